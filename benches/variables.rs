@@ -0,0 +1,114 @@
+// Variable lookup benchmark: the same scripts run by name and resolved
+//
+// `cargo bench --bench variables` runs each workload unresolved, where every
+// variable is searched for by name scope by scope, and after
+// resolver::resolve, where locals are read from their slots, and prints the
+// median time of each and the speedup.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use arcscript::interpreter::Interpreter;
+use arcscript::lexer::Lexer;
+use arcscript::parser::Parser;
+use arcscript::resolver;
+
+const RUNS: usize = 15;
+
+const WORKLOADS: &[(&str, &str)] = &[
+    (
+        "top-level loops",
+        "var sum = 0;
+         for i = 1, 300, 1 do {
+             for j = 1, 100, 1 do {
+                 sum = sum + i * j;
+             } end
+         } end
+         var n = 0;
+         while n < 20000 do { n = n + 1; } end",
+    ),
+    (
+        "function locals",
+        "func work(size): {
+             var sum = 0;
+             for i = 1, size, 1 do {
+                 var row = 0;
+                 for j = 1, size, 1 do {
+                     row = row + i * j;
+                 } end
+                 sum = sum + row;
+             } end
+             var n = 0;
+             while n < size * size do { n = n + 1; } end
+             return sum;
+         } end
+         var result = work(150);",
+    ),
+    (
+        "nested blocks",
+        "func simulate(steps): {
+             var x = 0.0;
+             var speed = 1.5;
+             var bounces = 0;
+             for step = 1, steps, 1 do {
+                 var next = x + speed;
+                 if next > 100 or next < 0 then {
+                     speed = -speed;
+                     bounces = bounces + 1;
+                 } else {
+                     x = next;
+                 } end
+             } end
+             return bounces;
+         } end
+         var bounces = simulate(30000);",
+    ),
+    (
+        "calls",
+        "func fib(n): {
+             if n < 2 then { return n; } end
+             return fib(n - 1) + fib(n - 2);
+         } end
+         func adder(n): {
+             func add(x): { return x + n; } end
+             return add;
+         } end
+         var total = fib(18);
+         for i = 1, 3000, 1 do {
+             var add = adder(i);
+             total = add(total);
+         } end",
+    ),
+];
+
+fn median(source: &str, resolved: bool) -> Duration {
+    let mut program = Parser::new(Lexer::new(source)).parse_program().expect("workload parses");
+    if resolved {
+        resolver::resolve(&mut program).expect("workload resolves");
+    }
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let mut interp = Interpreter::new();
+            let start = Instant::now();
+            interp.eval_program(black_box(&program)).expect("workload runs");
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+fn main() {
+    println!("{:<16}  {:>10}  {:>10}  {:>8}", "workload", "by name", "resolved", "speedup");
+    for (name, source) in WORKLOADS {
+        let by_name = median(source, false);
+        let resolved = median(source, true);
+        println!(
+            "{:<16}  {:>8.2}ms  {:>8.2}ms  {:>7.2}x",
+            name,
+            by_name.as_secs_f64() * 1000.0,
+            resolved.as_secs_f64() * 1000.0,
+            by_name.as_secs_f64() / resolved.as_secs_f64(),
+        );
+    }
+}
@@ -1,6 +1,6 @@
 // ArcScript AST (MVP subset)
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Int(i64),
     Float(f64),
@@ -9,10 +9,15 @@ pub enum Literal {
     Nil,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Literal(Literal),
     Ident(String),
+    /// A variable the resolver found in a local scope
+    Local {
+        name: String,
+        slot: Slot,
+    },
     Unary {
         op: UnaryOp,
         expr: Box<Expr>,
@@ -37,13 +42,13 @@ pub enum Expr {
     TableLiteral(Vec<TableField>),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UnaryOp {
     Negate,
     Not,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinaryOp {
     Add,
     Sub,
@@ -60,19 +65,56 @@ pub enum BinaryOp {
     Or,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TableField {
     KeyValue { key: String, value: Expr },
     Value(Expr),
+    Trivia(Trivia),
 }
 
-#[derive(Debug, Clone)]
+/// Source text with no effect on the program, kept by `Lexer::with_trivia`
+/// so the formatter can put it back. Ordinary parsing never produces it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    /// A comment on its own line(s), with its `//` or `/* */`
+    Comment(String),
+    /// A comment after code on the same line
+    TrailingComment(String),
+    /// One or more empty lines
+    BlankLine,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Param {
     pub name: String,
 }
 
-#[derive(Debug, Clone)]
-pub enum Stmt {
+/// Where a local variable lives: `index` in the scope `depth` scopes out
+/// from the innermost. Given out by the resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+/// A statement and the line it starts on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: usize,
+    /// The local variable a declaration or assignment writes, once resolved;
+    /// None for globals and unresolved code, which go by name
+    pub slot: Option<Slot>,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, line: usize) -> Self {
+        Self { kind, line, slot: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
     VarDecl {
         name: String,
         init: Expr,
@@ -81,6 +123,16 @@ pub enum Stmt {
         name: String,
         value: Expr,
     },
+    MemberAssignment {
+        object: Expr,
+        field: String,
+        value: Expr,
+    },
+    IndexAssignment {
+        object: Expr,
+        index: Expr,
+        value: Expr,
+    },
     Expr(Expr),
     Block(Vec<Stmt>),
     If {
@@ -103,38 +155,51 @@ pub enum Stmt {
     Break,
     Continue,
     Return(Option<Expr>),
+    Try {
+        body: Vec<Stmt>,
+        catch_name: Option<String>,
+        catch_body: Option<Vec<Stmt>>,
+        finally_body: Option<Vec<Stmt>>,
+    },
+    Throw {
+        value: Expr,
+    },
     FuncDecl(FuncDecl),
     ObjectDecl(ObjectDecl),
+    Trivia(Trivia),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FuncDecl {
     pub name: String,
     pub params: Vec<Param>,
-    pub body: Stmt,
+    pub body: Box<Stmt>,
+    pub line: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct EventDecl {
     pub name: String,
     pub params: Vec<Param>,
-    pub body: Stmt,
+    pub body: Box<Stmt>,
+    pub line: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ObjectDecl {
     pub name: String,
     pub members: Vec<ObjectMember>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ObjectMember {
     Var(Stmt),
     Method(FuncDecl),
     Event(EventDecl),
+    Trivia(Trivia),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub body: Vec<Stmt>,
 }
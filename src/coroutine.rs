@@ -0,0 +1,167 @@
+// ArcScript coroutines: function calls that can suspend and be resumed later
+//
+// The interpreter is a recursive tree walker, so a coroutine body runs on its own
+// thread with its own interpreter. Control is handed back and forth over channels
+// and exactly one side runs at any time, which keeps execution deterministic.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use crate::interpreter::{ErrorKind, RuntimeError, Value};
+use crate::scheduler::TimerClock;
+
+/// Coroutine bodies get the same stack as the main thread since they run full
+/// recursive interpreters.
+const COROUTINE_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+static NEXT_COROUTINE_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoroutineStatus {
+    /// Not started yet, or stopped at a `yield`
+    Suspended,
+    Running,
+    /// Stopped at `wait`/`wait_frames` until the scheduler wakes it
+    Waiting,
+    Dead,
+}
+
+impl CoroutineStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoroutineStatus::Suspended => "suspended",
+            CoroutineStatus::Running => "running",
+            CoroutineStatus::Waiting => "waiting",
+            CoroutineStatus::Dead => "dead",
+        }
+    }
+}
+
+/// Sent from a coroutine's thread back to whoever resumed it.
+pub(crate) enum CoroutineEvent {
+    Yielded(Value),
+    Waiting(TimerClock, f64),
+    Finished(Result<Value, RuntimeError>),
+}
+
+/// The coroutine's end of the channels, held by the interpreter running its body.
+pub(crate) struct CoroutineLink {
+    events: Sender<CoroutineEvent>,
+    resumes: Receiver<Vec<Value>>,
+}
+
+impl CoroutineLink {
+    /// Hands `event` to the resumer and blocks until the coroutine is resumed,
+    /// returning the resume arguments.
+    pub(crate) fn suspend(&self, event: CoroutineEvent) -> Result<Vec<Value>, RuntimeError> {
+        let dropped = || RuntimeError::with_kind(ErrorKind::CoroutineDropped, "coroutine was dropped while suspended");
+        self.events.send(event).map_err(|_| dropped())?;
+        self.resumes.recv().map_err(|_| dropped())
+    }
+}
+
+pub struct Coroutine {
+    id: u64,
+    status: Mutex<CoroutineStatus>,
+    resumes: Mutex<Sender<Vec<Value>>>,
+    events: Mutex<Receiver<CoroutineEvent>>,
+}
+
+impl Coroutine {
+    /// Starts the coroutine thread. `body` runs on the first resume with the
+    /// resume arguments; dropping the coroutine before then just ends the thread.
+    pub(crate) fn spawn<F>(body: F) -> Result<Arc<Self>, RuntimeError>
+    where
+        F: FnOnce(CoroutineLink, Vec<Value>) -> Result<Value, RuntimeError> + Send + 'static,
+    {
+        let id = NEXT_COROUTINE_ID.fetch_add(1, Ordering::Relaxed);
+        let (resume_tx, resume_rx) = channel::<Vec<Value>>();
+        let (event_tx, event_rx) = channel::<CoroutineEvent>();
+        let finished_tx = event_tx.clone();
+
+        thread::Builder::new()
+            .name(format!("arcscript-coroutine-{}", id))
+            .stack_size(COROUTINE_STACK_SIZE)
+            .spawn(move || {
+                let Ok(args) = resume_rx.recv() else {
+                    return;
+                };
+                let link = CoroutineLink { events: event_tx, resumes: resume_rx };
+                let result = body(link, args);
+                let _ = finished_tx.send(CoroutineEvent::Finished(result));
+            })
+            .map_err(|e| RuntimeError::new(&format!("failed to start coroutine: {}", e)))?;
+
+        Ok(Arc::new(Self {
+            id,
+            status: Mutex::new(CoroutineStatus::Suspended),
+            resumes: Mutex::new(resume_tx),
+            events: Mutex::new(event_rx),
+        }))
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn status(&self) -> CoroutineStatus {
+        *lock(&self.status)
+    }
+
+    /// Runs the coroutine until it yields, waits or finishes. Fails unless the
+    /// coroutine is currently in the `expected` state (suspended for script
+    /// resumes, waiting for scheduler wake-ups).
+    pub(crate) fn resume(
+        &self,
+        expected: CoroutineStatus,
+        args: Vec<Value>,
+    ) -> Result<CoroutineEvent, RuntimeError> {
+        {
+            let mut status = lock(&self.status);
+            if *status != expected {
+                return Err(RuntimeError::new(match *status {
+                    CoroutineStatus::Dead => "cannot resume dead coroutine",
+                    CoroutineStatus::Running => "cannot resume running coroutine",
+                    CoroutineStatus::Waiting => "cannot resume a coroutine that is waiting",
+                    CoroutineStatus::Suspended => "coroutine is not waiting",
+                }));
+            }
+            *status = CoroutineStatus::Running;
+        }
+
+        let events = lock(&self.events);
+        let event = lock(&self.resumes)
+            .send(args)
+            .ok()
+            .and_then(|_| events.recv().ok())
+            .unwrap_or_else(|| {
+                CoroutineEvent::Finished(Err(RuntimeError::new("coroutine thread exited unexpectedly")))
+            });
+
+        *lock(&self.status) = match &event {
+            CoroutineEvent::Yielded(_) => CoroutineStatus::Suspended,
+            CoroutineEvent::Waiting(..) => CoroutineStatus::Waiting,
+            CoroutineEvent::Finished(_) => CoroutineStatus::Dead,
+        };
+        Ok(event)
+    }
+}
+
+impl std::fmt::Debug for Coroutine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Coroutine({}, {})", self.id, self.status().as_str())
+    }
+}
+
+impl PartialEq for Coroutine {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A poisoned lock only means a coroutine thread panicked; the data is still usable
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
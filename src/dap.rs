@@ -0,0 +1,506 @@
+// ArcScript debug adapter: editor debugging over stdio (`arcscript dap`)
+//
+// Speaks the Debug Adapter Protocol, framed like the language server. A reader
+// thread queues client messages so pause, setBreakpoints and disconnect work
+// while the script runs; while it's paused the debug hook waits on the queue.
+// Scripts have one thread, with id 1.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::ast::Program;
+use crate::debugger::{self, DebugEvent, DebugHook, Resume, Stepper};
+use crate::interpreter::{Interpreter, Value};
+use crate::lexer::Lexer;
+use crate::limits::Limits;
+use crate::lsp::{self, field, int_field, str_field, Json};
+use crate::parser::Parser;
+use crate::resolver;
+use crate::stdlib::{json, list_items};
+
+const THREAD_ID: i64 = 1;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Serves one debugging session until the client disconnects or closes the
+/// input.
+pub fn run(input: impl BufRead + Send + 'static, output: impl Write + Send + 'static) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(message)) = lsp::read_message(&mut input) {
+            // Anything that isn't JSON can't be answered, as it has no seq
+            if let Ok(message) = json::decode(&message) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let outbox = Arc::new(Mutex::new(Outbox { output: Box::new(output), seq: 0 }));
+    let session = Arc::new(Mutex::new(Session::new(outbox, receiver)));
+    loop {
+        let mut guard = lock(&session);
+        let Ok(message) = guard.messages.recv() else { break };
+        let action = guard.handle(&message, None);
+        drop(guard);
+        if let Action::Start = action {
+            run_program(&session);
+        }
+        if lock(&session).disconnected {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn run_program(session: &Arc<Mutex<Session>>) {
+    let (program, outbox) = {
+        let session = lock(session);
+        match &session.launch {
+            Some(launch) => (launch.program.clone(), Arc::clone(&session.outbox)),
+            None => return,
+        }
+    };
+    let mut interp = Interpreter::new();
+    interp.set_limits(Limits::recommended());
+    let printed = Arc::clone(&outbox);
+    interp.set_print_handler(Arc::new(move |text: &str| {
+        lock(&printed).event("output", Json::Object(vec![("category", Json::str("stdout")), ("output", Json::str(text))]));
+    }));
+    interp.set_debug_hook(Box::new(Hook(Arc::clone(session))));
+    let result = interp.eval_program(&program);
+
+    if lock(session).disconnected {
+        return;
+    }
+    let mut outbox = lock(&outbox);
+    let exit_code = match result {
+        Ok(()) => 0,
+        Err(e) => {
+            let text = format!("Runtime error: {}\n", e);
+            outbox.event("output", Json::Object(vec![("category", Json::str("stderr")), ("output", Json::String(text))]));
+            1
+        }
+    };
+    outbox.event("exited", Json::Object(vec![("exitCode", Json::Int(exit_code))]));
+    outbox.event("terminated", Json::Object(Vec::new()));
+}
+
+/// Numbers and writes messages to the client.
+struct Outbox {
+    output: Box<dyn Write + Send>,
+    seq: i64,
+}
+
+impl Outbox {
+    fn send(&mut self, mut fields: Vec<(&'static str, Json)>) {
+        self.seq += 1;
+        fields.insert(0, ("seq", Json::Int(self.seq)));
+        // If the client has gone, the reader thread sees the input close too
+        let _ = lsp::write_message(&mut self.output, &Json::Object(fields));
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(vec![("type", Json::str("event")), ("event", Json::str(event)), ("body", body)]);
+    }
+}
+
+/// What a request asks of the script.
+enum Action {
+    None,
+    /// Configuration is done; start the script
+    Start,
+    Resume,
+    Stop,
+}
+
+/// Something the client can expand in the variables view.
+enum Handle {
+    Locals(usize),
+    Globals,
+    Value(Value),
+}
+
+struct Launch {
+    path: String,
+    program: Program,
+    lines: BTreeSet<usize>,
+}
+
+struct Session {
+    outbox: Arc<Mutex<Outbox>>,
+    messages: Receiver<Value>,
+    launch: Option<Launch>,
+    stepper: Stepper,
+    /// What each variablesReference (an index plus one) stands for; only
+    /// valid until the script resumes
+    handles: Vec<Handle>,
+    paused: bool,
+    disconnected: bool,
+}
+
+impl Session {
+    fn new(outbox: Arc<Mutex<Outbox>>, messages: Receiver<Value>) -> Self {
+        Self {
+            outbox,
+            messages,
+            launch: None,
+            stepper: Stepper::new(false),
+            handles: Vec::new(),
+            paused: false,
+            disconnected: false,
+        }
+    }
+
+    /// Answers one request. `interp` is the running script, if any.
+    fn handle(&mut self, request: &Value, interp: Option<&mut Interpreter>) -> Action {
+        let command = str_field(request, "command").unwrap_or("");
+        let args = field(request, "arguments").unwrap_or(&Value::Nil);
+        let paused = if self.paused { interp } else { None };
+        let mut action = Action::None;
+        let result = match (command, paused) {
+            ("initialize", _) => Ok(capabilities()),
+            ("launch", _) => self.launch(args),
+            ("setBreakpoints", _) => Ok(self.set_breakpoints(args)),
+            ("configurationDone", _) => {
+                action = Action::Start;
+                Ok(Json::Null)
+            }
+            ("threads", _) => Ok(Json::Object(vec![(
+                "threads",
+                Json::Array(vec![Json::Object(vec![("id", Json::Int(THREAD_ID)), ("name", Json::str("main"))])]),
+            )])),
+            ("pause", _) => {
+                self.stepper.pause();
+                Ok(Json::Null)
+            }
+            ("disconnect" | "terminate", _) => {
+                self.disconnected = true;
+                action = Action::Stop;
+                Ok(Json::Null)
+            }
+            ("stackTrace", Some(interp)) => Ok(self.stack_trace(interp)),
+            ("scopes", Some(_)) => Ok(self.scopes(args)),
+            ("variables", Some(interp)) => self.variables(interp, args),
+            ("evaluate", Some(interp)) => self.evaluate(interp, args),
+            ("continue" | "next" | "stepIn" | "stepOut", Some(interp)) => {
+                let depth = interp.call_depth();
+                match command {
+                    "continue" => self.stepper.resume(),
+                    "next" => self.stepper.step_over(depth),
+                    "stepIn" => self.stepper.step_in(),
+                    _ => self.stepper.step_out(depth),
+                }
+                action = Action::Resume;
+                Ok(Json::Object(vec![("allThreadsContinued", Json::Bool(true))]))
+            }
+            ("stackTrace" | "scopes" | "variables" | "evaluate" | "continue" | "next" | "stepIn" | "stepOut", None) => {
+                Err("the script isn't paused".to_string())
+            }
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+
+        let mut fields = vec![
+            ("type", Json::str("response")),
+            ("request_seq", Json::Int(int_field(request, "seq").unwrap_or(0))),
+            ("success", Json::Bool(result.is_ok())),
+            ("command", Json::str(command)),
+        ];
+        match result {
+            Ok(Json::Null) => {}
+            Ok(body) => fields.push(("body", body)),
+            Err(message) => fields.push(("message", Json::String(message))),
+        }
+        let launched = command == "launch" && self.launch.is_some();
+        let mut outbox = lock(&self.outbox);
+        outbox.send(fields);
+        // Breakpoints are resolved against the program, so ask for them now
+        if launched {
+            outbox.event("initialized", Json::Object(Vec::new()));
+        }
+        action
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Json, String> {
+        let path = str_field(args, "program").ok_or("launch needs a program to debug")?;
+        let source = std::fs::read_to_string(path).map_err(|e| format!("could not read '{}': {}", path, e))?;
+        let mut program = Parser::new(Lexer::new(&source)).parse_program().map_err(|errors| {
+            let lines: Vec<String> = errors.iter().map(|e| format!("{}:{}: {}", e.line, e.column, e.message)).collect();
+            format!("parse errors in '{}':\n{}", path, lines.join("\n"))
+        })?;
+        resolver::resolve(&mut program).map_err(|errors| {
+            let lines: Vec<String> = errors.iter().map(|e| format!("{}: {}", e.line, e.message)).collect();
+            format!("errors in '{}':\n{}", path, lines.join("\n"))
+        })?;
+        self.stepper = Stepper::new(matches!(field(args, "stopOnEntry"), Some(Value::Bool(true))));
+        self.launch = Some(Launch { path: path.to_string(), lines: debugger::statement_lines(&program), program });
+        Ok(Json::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Json {
+        let requested: Vec<usize> = match field(args, "breakpoints") {
+            Some(Value::Table(list)) => list_items(list).iter().filter_map(|b| int_field(b, "line")).map(|l| l as usize).collect(),
+            _ => Vec::new(),
+        };
+        let path = str_field(args, "source.path").unwrap_or("");
+        let launch = self.launch.as_ref().filter(|launch| same_file(&launch.path, path));
+        self.stepper.clear_breakpoints();
+        let mut breakpoints = Vec::new();
+        for line in requested {
+            let resolved = launch.and_then(|launch| debugger::breakpoint_line(&launch.lines, line));
+            breakpoints.push(match resolved {
+                Some(at) => {
+                    self.stepper.add_breakpoint(at);
+                    Json::Object(vec![("verified", Json::Bool(true)), ("line", Json::Int(at as i64))])
+                }
+                None => Json::Object(vec![
+                    ("verified", Json::Bool(false)),
+                    ("line", Json::Int(line as i64)),
+                    ("message", Json::str(if launch.is_some() { "no statement on or after this line" } else { "not the program being debugged" })),
+                ]),
+            });
+        }
+        Json::Object(vec![("breakpoints", Json::Array(breakpoints))])
+    }
+
+    fn stack_trace(&self, interp: &Interpreter) -> Json {
+        let path = self.launch.as_ref().map_or("", |launch| launch.path.as_str());
+        let name = Path::new(path).file_name().map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+        let source = Json::Object(vec![("name", Json::String(name)), ("path", Json::str(path))]);
+        let frames: Vec<Json> = interp
+            .call_stack()
+            .into_iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                Json::Object(vec![
+                    ("id", Json::Int(i as i64 + 1)),
+                    ("name", Json::String(frame.name)),
+                    ("source", source.clone()),
+                    ("line", Json::Int(frame.line as i64)),
+                    ("column", Json::Int(1)),
+                ])
+            })
+            .collect();
+        let total = frames.len() as i64;
+        Json::Object(vec![("stackFrames", Json::Array(frames)), ("totalFrames", Json::Int(total))])
+    }
+
+    fn handle_for(&mut self, handle: Handle) -> i64 {
+        self.handles.push(handle);
+        self.handles.len() as i64
+    }
+
+    fn scopes(&mut self, args: &Value) -> Json {
+        let frame = (int_field(args, "frameId").unwrap_or(1).max(1) - 1) as usize;
+        let locals = self.handle_for(Handle::Locals(frame));
+        let globals = self.handle_for(Handle::Globals);
+        let scope = |name: &str, reference: i64| {
+            Json::Object(vec![
+                ("name", Json::str(name)),
+                ("variablesReference", Json::Int(reference)),
+                ("expensive", Json::Bool(false)),
+            ])
+        };
+        Json::Object(vec![("scopes", Json::Array(vec![scope("Locals", locals), scope("Globals", globals)]))])
+    }
+
+    fn variables(&mut self, interp: &Interpreter, args: &Value) -> Result<Json, String> {
+        let reference = int_field(args, "variablesReference").unwrap_or(0);
+        let entries = match self.handles.get((reference - 1).max(0) as usize).filter(|_| reference > 0) {
+            Some(Handle::Locals(frame)) => interp.frame_locals(*frame),
+            Some(Handle::Globals) => interp.user_globals(),
+            Some(Handle::Value(Value::Table(table))) => {
+                let mut entries: Vec<(String, Value)> = table.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                // List entries in order, then the named ones
+                entries.sort_by_key(|(key, _)| (key.parse::<i64>().map_err(|_| key.clone()), key.clone()));
+                entries
+            }
+            _ => return Err(format!("no variables with reference {}", reference)),
+        };
+        let variables = entries
+            .into_iter()
+            .map(|(name, value)| {
+                let (text, reference) = self.describe(value);
+                Json::Object(vec![
+                    ("name", Json::String(name)),
+                    ("value", Json::String(text)),
+                    ("variablesReference", Json::Int(reference)),
+                ])
+            })
+            .collect();
+        Ok(Json::Object(vec![("variables", Json::Array(variables))]))
+    }
+
+    /// How a value is shown, and the reference to expand it by if it's a
+    /// non-empty table.
+    fn describe(&mut self, value: Value) -> (String, i64) {
+        let text = value.repr();
+        match value {
+            Value::Table(ref table) if !table.is_empty() => (text, self.handle_for(Handle::Value(value))),
+            _ => (text, 0),
+        }
+    }
+
+    fn evaluate(&mut self, interp: &mut Interpreter, args: &Value) -> Result<Json, String> {
+        let expression = str_field(args, "expression").unwrap_or("");
+        let frame = (int_field(args, "frameId").unwrap_or(1).max(1) - 1) as usize;
+        let value = interp.eval_in_frame(frame, expression).map_err(|e| e.to_string())?;
+        let (text, reference) = self.describe(value);
+        Ok(Json::Object(vec![("result", Json::String(text)), ("variablesReference", Json::Int(reference))]))
+    }
+}
+
+fn capabilities() -> Json {
+    Json::Object(vec![
+        ("supportsConfigurationDoneRequest", Json::Bool(true)),
+        ("supportsEvaluateForHovers", Json::Bool(true)),
+        ("supportsTerminateRequest", Json::Bool(true)),
+    ])
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Lets the debug adapter drive a running script.
+struct Hook(Arc<Mutex<Session>>);
+
+impl DebugHook for Hook {
+    fn event(&mut self, interp: &mut Interpreter, event: &DebugEvent) -> Resume {
+        let DebugEvent::Statement { line } = *event else { return Resume::Continue };
+        let mut session = lock(&self.0);
+        // Requests sent while the script runs, like pause or new breakpoints
+        while let Ok(message) = session.messages.try_recv() {
+            if let Action::Stop = session.handle(&message, Some(interp)) {
+                return Resume::Stop;
+            }
+        }
+        let Some(reason) = session.stepper.should_pause(line, interp.call_depth()) else { return Resume::Continue };
+
+        session.paused = true;
+        lock(&session.outbox).event(
+            "stopped",
+            Json::Object(vec![
+                ("reason", Json::str(reason.as_str())),
+                ("threadId", Json::Int(THREAD_ID)),
+                ("allThreadsStopped", Json::Bool(true)),
+            ]),
+        );
+        loop {
+            // The client has gone if the queue is closed
+            let Ok(message) = session.messages.recv() else { return Resume::Stop };
+            match session.handle(&message, Some(interp)) {
+                Action::Resume => {
+                    session.paused = false;
+                    session.handles.clear();
+                    return Resume::Continue;
+                }
+                Action::Stop => return Resume::Stop,
+                Action::None | Action::Start => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Read};
+    use std::time::Duration;
+
+    /// One end of an in-memory pipe, so the test can talk to the adapter as
+    /// a client would: one message at a time, waiting for replies.
+    struct PipeReader {
+        chunks: Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    impl Read for PipeReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                match self.chunks.recv_timeout(Duration::from_secs(5)) {
+                    Ok(chunk) => self.pending = chunk,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "no reply from the adapter"))
+                    }
+                }
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    struct PipeWriter(mpsc::Sender<Vec<u8>>);
+
+    impl Write for PipeWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.0.send(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Whether `actual` matches `expected`, where the string "*" matches anything.
+    fn matches(expected: &Value, actual: &Value) -> bool {
+        match (expected, actual) {
+            (Value::String(s), _) if s == "*" => true,
+            (Value::Table(expected), Value::Table(actual)) => {
+                expected.len() == actual.len()
+                    && expected.iter().all(|(key, value)| actual.get(key).is_some_and(|other| matches(value, other)))
+            }
+            _ => expected == actual,
+        }
+    }
+
+    /// Replays a recorded session: `->` lines are sent to the adapter, and
+    /// each `<-` line must be the next message it sends back. `$PROGRAM`
+    /// stands for the path of tests/dap/damage.arc as a JSON string.
+    fn replay(transcript: &str) {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dap/damage.arc");
+        let program = Json::str(path).to_string();
+        let (to_adapter, adapter_input) = mpsc::channel();
+        let (adapter_output, from_adapter) = mpsc::channel();
+        let input = BufReader::new(PipeReader { chunks: adapter_input, pending: Vec::new() });
+        let adapter = std::thread::spawn(move || run(input, PipeWriter(adapter_output)));
+        let mut replies = BufReader::new(PipeReader { chunks: from_adapter, pending: Vec::new() });
+
+        for (number, line) in transcript.lines().enumerate() {
+            let line = line.replace("$PROGRAM", &program);
+            if let Some(message) = line.strip_prefix("-> ") {
+                to_adapter.send(format!("Content-Length: {}\r\n\r\n{}", message.len(), message).into_bytes()).unwrap();
+            } else if let Some(expected) = line.strip_prefix("<- ") {
+                let reply = lsp::read_message(&mut replies).unwrap().expect("the adapter closed the connection");
+                let matched = matches(&json::decode(expected).unwrap(), &json::decode(&reply).unwrap());
+                assert!(matched, "line {}: expected\n  {}\ngot\n  {}", number + 1, expected, reply);
+            }
+        }
+        drop(to_adapter);
+        adapter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_breakpoints_transcript() {
+        replay(include_str!("../tests/dap/breakpoints.transcript"));
+    }
+
+    #[test]
+    fn test_stepping_transcript() {
+        replay(include_str!("../tests/dap/stepping.transcript"));
+    }
+}
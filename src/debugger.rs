@@ -0,0 +1,506 @@
+// Debugging support: the interpreter's hook, breakpoint and step logic shared
+// by the front ends, and the terminal debugger (`arcscript debug`)
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::{BufRead, Write};
+
+use crate::ast::{ObjectMember, Program, Stmt, StmtKind};
+use crate::interpreter::{Interpreter, Value};
+
+/// What the interpreter is about to do, as reported to a `DebugHook`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugEvent<'a> {
+    /// About to run the statement on `line`
+    Statement { line: usize },
+    /// Entered a script function, before its first statement
+    Call { name: &'a str },
+    /// A script function finished without an error
+    Return { name: &'a str },
+}
+
+/// How the run continues after a hook returns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    Continue,
+    /// Fail the run with `ErrorKind::Interrupted`
+    Stop,
+}
+
+/// Installed with `Interpreter::set_debug_hook`. Pausing is just not
+/// returning: the hook can inspect the interpreter (`call_stack`,
+/// `frame_locals`, `eval_in_frame`, ...) until the user resumes.
+pub trait DebugHook: Send {
+    fn event(&mut self, interp: &mut Interpreter, event: &DebugEvent) -> Resume;
+}
+
+/// Why execution paused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Entry,
+    Breakpoint,
+    Step,
+    /// The front end asked to pause while the script was running
+    Pause,
+}
+
+impl StopReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopReason::Entry => "entry",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+            StopReason::Pause => "pause",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Run,
+    Pause,
+    /// Pause at the next statement anywhere
+    StepIn,
+    /// Pause at the next statement at or above this call depth
+    StepOver(usize),
+    /// Pause at the next statement above this call depth
+    StepOut(usize),
+}
+
+/// Breakpoints and the step command in progress. Front ends ask it before
+/// each statement whether to pause.
+#[derive(Debug, Clone)]
+pub struct Stepper {
+    breakpoints: HashSet<usize>,
+    mode: Mode,
+    stop_on_entry: bool,
+}
+
+impl Stepper {
+    pub fn new(stop_on_entry: bool) -> Self {
+        Self { breakpoints: HashSet::new(), mode: Mode::Run, stop_on_entry }
+    }
+
+    pub fn add_breakpoint(&mut self, line: usize) {
+        self.breakpoints.insert(line);
+    }
+
+    /// Returns whether there was a breakpoint on `line`.
+    pub fn remove_breakpoint(&mut self, line: usize) -> bool {
+        self.breakpoints.remove(&line)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = self.breakpoints.iter().copied().collect();
+        lines.sort_unstable();
+        lines
+    }
+
+    pub fn resume(&mut self) {
+        self.mode = Mode::Run;
+    }
+
+    /// Pauses at the next statement.
+    pub fn pause(&mut self) {
+        self.mode = Mode::Pause;
+    }
+
+    pub fn step_in(&mut self) {
+        self.mode = Mode::StepIn;
+    }
+
+    /// Steps to the next statement without stopping inside calls; `depth` is
+    /// the current `Interpreter::call_depth`.
+    pub fn step_over(&mut self, depth: usize) {
+        self.mode = Mode::StepOver(depth);
+    }
+
+    /// Runs until the current function returns to its caller.
+    pub fn step_out(&mut self, depth: usize) {
+        self.mode = if depth == 0 { Mode::Run } else { Mode::StepOut(depth) };
+    }
+
+    /// Whether to pause before the statement on `line`, run at call depth
+    /// `depth`.
+    pub fn should_pause(&mut self, line: usize, depth: usize) -> Option<StopReason> {
+        if std::mem::take(&mut self.stop_on_entry) {
+            return Some(StopReason::Entry);
+        }
+        let stepped = match self.mode {
+            Mode::Run => false,
+            Mode::Pause => {
+                self.mode = Mode::Run;
+                return Some(StopReason::Pause);
+            }
+            Mode::StepIn => true,
+            Mode::StepOver(from) => depth <= from,
+            Mode::StepOut(from) => depth < from,
+        };
+        if stepped {
+            self.mode = Mode::Run;
+            Some(StopReason::Step)
+        } else if self.breakpoints.contains(&line) {
+            self.mode = Mode::Run;
+            Some(StopReason::Breakpoint)
+        } else {
+            None
+        }
+    }
+}
+
+/// Every line a statement starts on, including inside functions, methods
+/// and handlers; the lines a breakpoint can stop at.
+pub fn statement_lines(program: &Program) -> BTreeSet<usize> {
+    fn visit(stmt: &Stmt, lines: &mut BTreeSet<usize>) {
+        match &stmt.kind {
+            StmtKind::Block(stmts) => stmts.iter().for_each(|s| visit(s, lines)),
+            StmtKind::Trivia(_) => return,
+            _ => {
+                lines.insert(stmt.line);
+            }
+        }
+        match &stmt.kind {
+            StmtKind::If { then_branch, elif_branches, else_branch, .. } => {
+                visit(then_branch, lines);
+                elif_branches.iter().for_each(|(_, branch)| visit(branch, lines));
+                if let Some(branch) = else_branch {
+                    visit(branch, lines);
+                }
+            }
+            StmtKind::While { body, .. } | StmtKind::For { body, .. } => visit(body, lines),
+            StmtKind::Try { body, catch_body, finally_body, .. } => {
+                for stmts in [Some(body), catch_body.as_ref(), finally_body.as_ref()].into_iter().flatten() {
+                    stmts.iter().for_each(|s| visit(s, lines));
+                }
+            }
+            StmtKind::FuncDecl(func) => visit(&func.body, lines),
+            StmtKind::ObjectDecl(obj) => {
+                for member in &obj.members {
+                    match member {
+                        ObjectMember::Method(func) => visit(&func.body, lines),
+                        ObjectMember::Event(event) => visit(&event.body, lines),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut lines = BTreeSet::new();
+    program.body.iter().for_each(|stmt| visit(stmt, &mut lines));
+    lines
+}
+
+/// Where a breakpoint asked for on `line` will stop: the first statement on
+/// or after it.
+pub fn breakpoint_line(lines: &BTreeSet<usize>, line: usize) -> Option<usize> {
+    lines.range(line..).next().copied()
+}
+
+const HELP: &str = "\
+Commands:
+  break LINE (b)      stop before the statement on LINE
+  delete LINE         remove the breakpoint on LINE
+  breakpoints         list breakpoints
+  continue (c)        run to the next breakpoint
+  step (s)            run one statement, stepping into calls
+  next (n)            run one statement, stepping over calls
+  finish (out)        run until the current function returns
+  stack (bt)          list the calls in progress
+  frame N (f)         inspect stack frame N (0 is the innermost)
+  locals              variables of the selected frame
+  globals             variables defined by the script
+  print EXPR (p)      evaluate EXPR in the selected frame; statements such
+                      as x = 1 change variables
+  list (l)            show the source around the current line
+  quit (q)            stop the script
+";
+
+/// The terminal debugger behind `arcscript debug`. Pauses before the first
+/// statement, then reads commands from `input` whenever execution stops.
+pub struct Console {
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+    source: Vec<String>,
+    lines: BTreeSet<usize>,
+    stepper: Stepper,
+    /// Stack frame that locals and print look at
+    frame: usize,
+}
+
+impl Console {
+    pub fn new(source: &str, program: &Program, input: Box<dyn BufRead + Send>, output: Box<dyn Write + Send>) -> Self {
+        Self {
+            input,
+            output,
+            source: source.lines().map(String::from).collect(),
+            lines: statement_lines(program),
+            stepper: Stepper::new(true),
+            frame: 0,
+        }
+    }
+
+    fn show_line(&mut self, line: usize, marker: &str) {
+        let text = self.source.get(line.wrapping_sub(1)).map_or("", String::as_str);
+        let _ = writeln!(self.output, "{}{:>4} | {}", marker, line, text);
+    }
+
+    /// Runs one command; `Some` ends the pause.
+    fn command(&mut self, interp: &mut Interpreter, input: &str) -> Option<Resume> {
+        let (command, argument) = input.split_once(' ').map_or((input, ""), |(c, a)| (c, a.trim()));
+        let depth = interp.call_depth();
+        let out = &mut self.output;
+        match command {
+            "" => {}
+            "c" | "continue" => {
+                self.stepper.resume();
+                return Some(Resume::Continue);
+            }
+            "s" | "step" => {
+                self.stepper.step_in();
+                return Some(Resume::Continue);
+            }
+            "n" | "next" => {
+                self.stepper.step_over(depth);
+                return Some(Resume::Continue);
+            }
+            "out" | "finish" => {
+                self.stepper.step_out(depth);
+                return Some(Resume::Continue);
+            }
+            "q" | "quit" => return Some(Resume::Stop),
+            "b" | "break" => match argument.parse::<usize>() {
+                Ok(line) => match breakpoint_line(&self.lines, line) {
+                    Some(at) => {
+                        self.stepper.add_breakpoint(at);
+                        let _ = writeln!(out, "Breakpoint set at line {}", at);
+                    }
+                    None => {
+                        let _ = writeln!(out, "No statement on or after line {}", line);
+                    }
+                },
+                Err(_) => {
+                    let _ = writeln!(out, "Usage: break LINE");
+                }
+            },
+            "delete" => match argument.parse::<usize>() {
+                Ok(line) if self.stepper.remove_breakpoint(line) => {
+                    let _ = writeln!(out, "Removed the breakpoint at line {}", line);
+                }
+                _ => {
+                    let _ = writeln!(out, "No breakpoint at line {}", argument);
+                }
+            },
+            "breakpoints" => {
+                let lines = self.stepper.breakpoints();
+                if lines.is_empty() {
+                    let _ = writeln!(out, "No breakpoints");
+                }
+                for line in lines {
+                    let _ = writeln!(out, "line {}", line);
+                }
+            }
+            "bt" | "stack" => {
+                for (i, frame) in interp.call_stack().iter().enumerate() {
+                    let marker = if i == self.frame { "*" } else { " " };
+                    let _ = writeln!(out, "{}#{} {} at line {}", marker, i, frame.name, frame.line);
+                }
+            }
+            "f" | "frame" => {
+                let stack = interp.call_stack();
+                match argument.parse::<usize>().ok().filter(|&n| n < stack.len()) {
+                    Some(n) => {
+                        self.frame = n;
+                        let _ = writeln!(out, "#{} {} at line {}", n, stack[n].name, stack[n].line);
+                    }
+                    None => {
+                        let _ = writeln!(out, "Usage: frame N, where N is 0 to {}", stack.len() - 1);
+                    }
+                }
+            }
+            "locals" => print_variables(out, &interp.frame_locals(self.frame)),
+            "globals" => print_variables(out, &interp.user_globals()),
+            "p" | "print" => match interp.eval_in_frame(self.frame, argument) {
+                Ok(value) => {
+                    let _ = writeln!(out, "{}", value.repr());
+                }
+                Err(e) => {
+                    let _ = writeln!(out, "Error: {}", e);
+                }
+            },
+            "l" | "list" => {
+                let current = interp.call_stack().get(self.frame).map_or(0, |frame| frame.line);
+                for line in current.saturating_sub(3).max(1)..=current + 3 {
+                    if line <= self.source.len() {
+                        let marker = if line == current { "> " } else { "  " };
+                        self.show_line(line, marker);
+                    }
+                }
+            }
+            "h" | "help" => {
+                let _ = write!(out, "{}", HELP);
+            }
+            _ => {
+                let _ = writeln!(out, "Unknown command '{}'; type help for a list", command);
+            }
+        }
+        None
+    }
+}
+
+fn print_variables(out: &mut Box<dyn Write + Send>, variables: &[(String, Value)]) {
+    if variables.is_empty() {
+        let _ = writeln!(out, "(none)");
+    }
+    for (name, value) in variables {
+        let _ = writeln!(out, "{} = {}", name, value.repr());
+    }
+}
+
+impl DebugHook for Console {
+    fn event(&mut self, interp: &mut Interpreter, event: &DebugEvent) -> Resume {
+        let DebugEvent::Statement { line } = *event else { return Resume::Continue };
+        let Some(reason) = self.stepper.should_pause(line, interp.call_depth()) else { return Resume::Continue };
+        self.frame = 0;
+        let _ = match reason {
+            StopReason::Entry => writeln!(self.output, "Paused at line {}; type help for commands", line),
+            StopReason::Breakpoint => writeln!(self.output, "Breakpoint at line {}", line),
+            StopReason::Step | StopReason::Pause => Ok(()),
+        };
+        self.show_line(line, "");
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+            let mut input = String::new();
+            // Nobody left to type commands, so don't leave the script hanging
+            if self.input.read_line(&mut input).map_or(true, |n| n == 0) {
+                return Resume::Stop;
+            }
+            if let Some(resume) = self.command(interp, input.trim()) {
+                return resume;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::sync::{Arc, Mutex};
+
+    const SCRIPT: &str = "var health = 10;
+func damage(amount): {
+    var left = health - amount;
+    return left;
+} end
+
+health = damage(3);
+for i = 1, 2 do {
+    health = health + i;
+} end
+";
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs SCRIPT under the console with `commands`, returning what it printed.
+    fn debug(commands: &str) -> (String, Interpreter) {
+        let program = Parser::new(Lexer::new(SCRIPT)).parse_program().expect("parse failed");
+        let output = Shared::default();
+        let console = Console::new(
+            SCRIPT,
+            &program,
+            Box::new(std::io::Cursor::new(commands.to_string())),
+            Box::new(output.clone()),
+        );
+        let mut interp = Interpreter::new();
+        interp.set_debug_hook(Box::new(console));
+        let _ = interp.eval_program(&program);
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        (text, interp)
+    }
+
+    #[test]
+    fn test_breakpoints_stack_and_variables() {
+        let (output, interp) = debug("break 3\nc\nstack\nlocals\nprint health * 2\nframe 1\nlocals\nglobals\nc\n");
+        assert_eq!(
+            output,
+            "Paused at line 1; type help for commands
+   1 | var health = 10;
+(debug) Breakpoint set at line 3
+(debug) Breakpoint at line 3
+   3 |     var left = health - amount;
+(debug) *#0 damage at line 3
+ #1 <main> at line 7
+(debug) amount = 3
+(debug) 20
+(debug) #1 <main> at line 7
+(debug) (none)
+(debug) damage = <function damage>
+health = 10
+(debug) "
+        );
+        assert_eq!(interp.get_global("health"), Some(Value::Int(10)));
+    }
+
+    #[test]
+    fn test_stepping() {
+        // step enters damage(), out returns to the top level, next skips the call
+        let (output, _) = debug("n\nn\ns\ns\nout\nn\nn\nq\n");
+        let paused: Vec<&str> = output.lines().filter(|l| l.contains(" | ")).map(|l| l.trim_start_matches("(debug) ")).collect();
+        assert_eq!(
+            paused,
+            [
+                "   1 | var health = 10;",
+                "   2 | func damage(amount): {",
+                "   7 | health = damage(3);",
+                "   3 |     var left = health - amount;",
+                "   4 |     return left;",
+                "   8 | for i = 1, 2 do {",
+                "   9 |     health = health + i;",
+                "   9 |     health = health + i;",
+            ]
+        );
+
+        // print can change variables, and quit stops the run
+        let (output, interp) = debug("print health = 99\nprint health\nq\n");
+        assert!(output.contains("(debug) nil\n(debug) 99\n"));
+        assert_eq!(interp.get_global("health"), Some(Value::Int(99)));
+    }
+
+    #[test]
+    fn test_stepper_and_statement_lines() {
+        let program = Parser::new(Lexer::new(SCRIPT)).parse_program().unwrap();
+        let lines = statement_lines(&program);
+        assert_eq!(lines.iter().copied().collect::<Vec<_>>(), [1, 2, 3, 4, 7, 8, 9]);
+        assert_eq!(breakpoint_line(&lines, 5), Some(7));
+        assert_eq!(breakpoint_line(&lines, 10), None);
+
+        let mut stepper = Stepper::new(false);
+        stepper.add_breakpoint(4);
+        assert_eq!(stepper.should_pause(1, 0), None);
+        assert_eq!(stepper.should_pause(4, 1), Some(StopReason::Breakpoint));
+        stepper.step_out(1);
+        assert_eq!(stepper.should_pause(3, 1), None);
+        assert_eq!(stepper.should_pause(8, 0), Some(StopReason::Step));
+        stepper.step_over(0);
+        assert_eq!(stepper.should_pause(3, 1), None);
+        assert_eq!(stepper.should_pause(9, 0), Some(StopReason::Step));
+    }
+}
@@ -0,0 +1,677 @@
+// ArcScript formatter: re-emits a program in the canonical style
+//
+// Comments and blank lines survive because the formatter parses with
+// `Lexer::with_trivia`. Used by `arcscript fmt`.
+
+use std::fmt;
+
+use crate::ast::{BinaryOp, EventDecl, Expr, FuncDecl, Literal, ObjectMember, Program, Stmt, StmtKind, TableField, Trivia, UnaryOp};
+use crate::lexer::{Lexer, TokenKind};
+use crate::parser::Parser;
+
+const INDENT: &str = "    ";
+
+/// Tables longer than this are split one field per line.
+const MAX_INLINE_TABLE: usize = 72;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatError {
+    pub message: String,
+}
+
+impl FormatError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Formats `source`, or explains why it can't be formatted safely. The result
+/// is checked to parse to the same program and to keep every comment.
+pub fn format_source(source: &str) -> Result<String, FormatError> {
+    check_annotations(source)?;
+    let program = parse(source, true)?;
+    let output = print(&program, compound_assignments(source));
+
+    let before = comments(source);
+    let after = comments(&output);
+    if let Some((text, line)) = before.iter().zip(after.iter().map(Some).chain(std::iter::repeat(None))).find_map(
+        |((text, line), kept)| match kept {
+            Some((kept, _)) if kept == text => None,
+            _ => Some((text, line)),
+        },
+    ) {
+        return Err(FormatError::new(format!(
+            "line {}: can't keep the comment `{}` in this position; move it onto its own line",
+            line,
+            first_line(text)
+        )));
+    }
+
+    let reparsed = parse(&output, false).map_err(|e| FormatError::new(format!("formatted code doesn't parse: {}", e)))?;
+    if shape(&parse(source, false)?) != shape(&reparsed) {
+        return Err(FormatError::new("formatting would change the meaning of the program".to_string()));
+    }
+    Ok(output)
+}
+
+/// Formats a parsed program. Comments and blank lines are only present if it
+/// was parsed with `Lexer::with_trivia`.
+pub fn format_program(program: &Program) -> String {
+    print(program, Vec::new())
+}
+
+fn print(program: &Program, compound: Vec<bool>) -> String {
+    let mut printer = Printer { lines: Vec::new(), indent: 0, compound: compound.into_iter() };
+    printer.stmts(&program.body);
+    if printer.lines.is_empty() {
+        return String::new();
+    }
+    printer.lines.join("\n") + "\n"
+}
+
+fn parse(source: &str, keep_trivia: bool) -> Result<Program, FormatError> {
+    let lexer = if keep_trivia { Lexer::with_trivia(source) } else { Lexer::new(source) };
+    Parser::new(lexer).parse_program().map_err(|errors| {
+        let lines: Vec<String> =
+            errors.iter().map(|e| format!("parse error at {}:{}: {}", e.line, e.column, e.message)).collect();
+        FormatError::new(lines.join("\n"))
+    })
+}
+
+/// The parser accepts and drops `var x: T` and `func f(): T:` annotations, so
+/// formatting would silently delete them.
+fn check_annotations(source: &str) -> Result<(), FormatError> {
+    let mut lexer = Lexer::new(source);
+    let mut recent: Vec<(TokenKind, usize)> = Vec::new();
+    loop {
+        let token = lexer.next_token();
+        if token.kind == TokenKind::Eof {
+            return Ok(());
+        }
+        recent.push((token.kind, token.line));
+        let kinds: Vec<TokenKind> = recent.iter().rev().take(4).map(|(kind, _)| kind.clone()).collect();
+        let annotated = matches!(kinds[..], [TokenKind::Colon, TokenKind::Identifier, TokenKind::KwVar, ..])
+            || matches!(kinds[..], [TokenKind::Colon, TokenKind::Identifier, TokenKind::Colon, TokenKind::RParen]);
+        if annotated {
+            let line = recent[recent.len() - 2].1;
+            return Err(FormatError::new(format!("line {}: type annotations aren't supported by the formatter", line)));
+        }
+        if recent.len() > 4 {
+            recent.remove(0);
+        }
+    }
+}
+
+/// Whether each assignment in `source` used `+=`-style syntax, in source
+/// order. Both spellings parse to `x = x + 1`, so this is the only record.
+fn compound_assignments(source: &str) -> Vec<bool> {
+    let mut lexer = Lexer::new(source);
+    let mut found = Vec::new();
+    let mut recent = [TokenKind::Eof, TokenKind::Eof];
+    loop {
+        let token = lexer.next_token();
+        match token.kind {
+            TokenKind::Eof => return found,
+            TokenKind::PlusEqual | TokenKind::MinusEqual | TokenKind::StarEqual | TokenKind::SlashEqual => found.push(true),
+            // `var x =` and `for i =` aren't assignments
+            TokenKind::Equal if !matches!(recent, [TokenKind::KwVar | TokenKind::KwFor, TokenKind::Identifier]) => {
+                found.push(false)
+            }
+            _ => {}
+        }
+        recent = [recent[1].clone(), token.kind];
+    }
+}
+
+/// Every comment in `source` with the line of the token that follows it.
+fn comments(source: &str) -> Vec<(String, usize)> {
+    let mut lexer = Lexer::with_trivia(source);
+    let mut found = Vec::new();
+    loop {
+        let token = lexer.next_token();
+        for trivia in &token.trivia {
+            if let Trivia::Comment(text) | Trivia::TrailingComment(text) = trivia {
+                found.push((text.clone(), token.line));
+            }
+        }
+        if token.kind == TokenKind::Eof {
+            return found;
+        }
+    }
+}
+
+fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or("")
+}
+
+/// A comparable rendering of a program, ignoring source line numbers.
+fn shape(program: &Program) -> String {
+    let debug = format!("{:?}", program.body);
+    let mut shape = String::with_capacity(debug.len());
+    let mut rest = debug.as_str();
+    while let Some(at) = rest.find("line: ") {
+        shape.push_str(&rest[..at + 6]);
+        rest = rest[at + 6..].trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+    shape.push_str(rest);
+    shape
+}
+
+struct Printer {
+    lines: Vec<String>,
+    indent: usize,
+    /// From `compound_assignments`; assignments print with `=` once it runs out
+    compound: std::vec::IntoIter<bool>,
+}
+
+impl Printer {
+    fn line(&mut self, text: &str) {
+        self.lines.push(format!("{}{}", INDENT.repeat(self.indent), text));
+    }
+
+    /// Appends a comment to the line before it, where it was in the source.
+    fn trailing(&mut self, comment: &str) {
+        match self.lines.last_mut() {
+            Some(last) => {
+                last.push(' ');
+                last.push_str(comment);
+            }
+            None => self.line(comment),
+        }
+    }
+
+    /// Prints a sequence of nodes with their trivia. Runs of blank lines
+    /// become one, and blank lines at the start or end are dropped.
+    fn items<T>(&mut self, items: &[T], trivia: impl Fn(&T) -> Option<&Trivia>, mut node: impl FnMut(&mut Self, &T)) {
+        let start = self.lines.len();
+        for (i, item) in items.iter().enumerate() {
+            match trivia(item) {
+                Some(Trivia::BlankLine) => {
+                    let more = items[i + 1..].iter().any(|rest| trivia(rest) != Some(&Trivia::BlankLine));
+                    if self.lines.len() > start && more && self.lines.last().is_some_and(|last| !last.is_empty()) {
+                        self.lines.push(String::new());
+                    }
+                }
+                Some(Trivia::Comment(text)) => self.line(text),
+                Some(Trivia::TrailingComment(text)) => self.trailing(text),
+                None => node(self, item),
+            }
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        self.items(stmts, stmt_trivia, |printer, stmt| printer.stmt(stmt));
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::VarDecl { name, init } => {
+                let text = format!("var {} = {};", name, self.expr(init));
+                self.line(&text);
+            }
+            StmtKind::Assignment { name, value } => {
+                let compound = self.compound.next().unwrap_or(false);
+                let text = self.assignment(&Expr::Ident(name.clone()), value, compound);
+                self.line(&text);
+            }
+            StmtKind::MemberAssignment { object, field, value } => {
+                let target = Expr::Member { object: Box::new(object.clone()), field: field.clone() };
+                let compound = self.compound.next().unwrap_or(false);
+                let text = self.assignment(&target, value, compound);
+                self.line(&text);
+            }
+            StmtKind::IndexAssignment { object, index, value } => {
+                let target = Expr::Index { object: Box::new(object.clone()), index: Box::new(index.clone()) };
+                let compound = self.compound.next().unwrap_or(false);
+                let text = self.assignment(&target, value, compound);
+                self.line(&text);
+            }
+            StmtKind::Expr(expr) => {
+                let mut text = self.expr(expr);
+                // A leading '{' would start a block instead of a table
+                if text.starts_with('{') {
+                    text = format!("({})", text);
+                }
+                self.line(&format!("{};", text));
+            }
+            StmtKind::Block(stmts) => self.clauses(&[(String::new(), stmts.as_slice())], ""),
+            StmtKind::If { condition, then_branch, elif_branches, else_branch } => {
+                let mut clauses = vec![(format!("if {} then", self.expr(condition)), body(then_branch))];
+                for (cond, branch) in elif_branches {
+                    clauses.push((format!("elif {} then", self.expr(cond)), body(branch)));
+                }
+                if let Some(branch) = else_branch {
+                    clauses.push(("else".to_string(), body(branch)));
+                }
+                self.clauses(&clauses, "end");
+            }
+            StmtKind::While { condition, body: loop_body } => {
+                let header = format!("while {} do", self.expr(condition));
+                self.clauses(&[(header, body(loop_body))], "end");
+            }
+            StmtKind::For { var_name, start, end, step, body: loop_body } => {
+                let mut header = format!("for {} = {}, {}", var_name, self.expr(start), self.expr(end));
+                if let Some(step) = step {
+                    header.push_str(&format!(", {}", self.expr(step)));
+                }
+                header.push_str(" do");
+                self.clauses(&[(header, body(loop_body))], "end");
+            }
+            StmtKind::Break => self.line("break;"),
+            StmtKind::Continue => self.line("continue;"),
+            StmtKind::Return(None) => self.line("return;"),
+            StmtKind::Return(Some(value)) => {
+                let text = format!("return {};", self.expr(value));
+                self.line(&text);
+            }
+            StmtKind::Try { body: try_body, catch_name, catch_body, finally_body } => {
+                let mut clauses = vec![("try".to_string(), try_body.as_slice())];
+                if let Some(catch_body) = catch_body {
+                    let header = match catch_name {
+                        Some(name) => format!("catch {}", name),
+                        None => "catch".to_string(),
+                    };
+                    clauses.push((header, catch_body.as_slice()));
+                }
+                if let Some(finally_body) = finally_body {
+                    clauses.push(("finally".to_string(), finally_body.as_slice()));
+                }
+                self.clauses(&clauses, "end");
+            }
+            StmtKind::Throw { value } => {
+                let text = format!("throw {};", self.expr(value));
+                self.line(&text);
+            }
+            StmtKind::FuncDecl(func) => self.func(func),
+            StmtKind::ObjectDecl(obj) => {
+                let header = format!("object {}: {{", obj.name);
+                if is_empty(&obj.members, member_trivia) {
+                    self.line(&format!("{} }} end", header));
+                    return;
+                }
+                self.line(&header);
+                self.indent += 1;
+                self.items(&obj.members, member_trivia, |printer, member| match member {
+                    ObjectMember::Var(stmt) => printer.stmt(stmt),
+                    ObjectMember::Method(func) => printer.func(func),
+                    ObjectMember::Event(event) => printer.event(event),
+                    ObjectMember::Trivia(_) => {}
+                });
+                self.indent -= 1;
+                self.line("} end");
+            }
+            StmtKind::Trivia(_) => {}
+        }
+    }
+
+    fn func(&mut self, func: &FuncDecl) {
+        let params: Vec<&str> = func.params.iter().map(|p| p.name.as_str()).collect();
+        let header = format!("func {}({}):", func.name, params.join(", "));
+        self.clauses(&[(header, body(&func.body))], "end");
+    }
+
+    fn event(&mut self, event: &EventDecl) {
+        let params: Vec<&str> = event.params.iter().map(|p| p.name.as_str()).collect();
+        let header = format!("on {}({}):", event.name, params.join(", "));
+        self.clauses(&[(header, body(&event.body))], "end");
+    }
+
+    /// Prints `header { body } header { body } ... tail`, with each `}` sharing
+    /// a line with the next header. Empty bodies stay on one line as `{ }`.
+    fn clauses(&mut self, clauses: &[(String, &[Stmt])], tail: &str) {
+        let mut current = String::new();
+        for (header, stmts) in clauses {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            if !header.is_empty() {
+                current.push_str(header);
+                current.push(' ');
+            }
+            current.push('{');
+            if is_empty(stmts, stmt_trivia) {
+                current.push_str(" }");
+                continue;
+            }
+            self.line(&current);
+            self.indent += 1;
+            self.stmts(stmts);
+            self.indent -= 1;
+            current = "}".to_string();
+        }
+        if !tail.is_empty() {
+            current.push(' ');
+            current.push_str(tail);
+        }
+        self.line(&current);
+    }
+
+    /// Prints `x += 1` back in its compound form when it was written that way.
+    fn assignment(&self, target: &Expr, value: &Expr, compound: bool) -> String {
+        let target_text = self.expr(target);
+        if let (true, Expr::Binary { left, op, right }) = (compound, value) {
+            let symbol = match op {
+                BinaryOp::Add => Some("+="),
+                BinaryOp::Sub => Some("-="),
+                BinaryOp::Mul => Some("*="),
+                BinaryOp::Div => Some("/="),
+                _ => None,
+            };
+            if let Some(symbol) = symbol {
+                if format!("{:?}", left) == format!("{:?}", target) {
+                    return format!("{} {} {};", target_text, symbol, self.expr(right));
+                }
+            }
+        }
+        format!("{} = {};", target_text, self.expr(value))
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Literal(literal) => format_literal(literal),
+            Expr::Ident(name) | Expr::Local { name, .. } => name.clone(),
+            Expr::Unary { op, expr: operand } => {
+                let parenthesize = precedence(operand) < UNARY;
+                let operand = self.operand(operand, parenthesize);
+                // Two minus signs in a row would print -(-a) as --a
+                let operand = if matches!(op, UnaryOp::Negate) && operand.starts_with('-') {
+                    format!("({})", operand)
+                } else {
+                    operand
+                };
+                match op {
+                    UnaryOp::Negate => format!("-{}", operand),
+                    UnaryOp::Not => format!("not {}", operand),
+                }
+            }
+            Expr::Binary { left, op, right } => {
+                let level = binary_precedence(op);
+                // Operators are left-associative, so an equal-precedence right
+                // side needs parentheses: a - (b - c)
+                let left = self.operand(left, precedence(left) < level);
+                let right = self.operand(right, precedence(right) <= level);
+                format!("{} {} {}", left, binary_symbol(op), right)
+            }
+            Expr::Call { callee, args } => {
+                let args: Vec<String> = args.iter().map(|arg| self.expr(arg)).collect();
+                format!("{}({})", self.operand(callee, precedence(callee) < POSTFIX), args.join(", "))
+            }
+            Expr::Member { object, field } => {
+                format!("{}.{}", self.operand(object, precedence(object) < POSTFIX), field)
+            }
+            Expr::Index { object, index } => {
+                format!("{}[{}]", self.operand(object, precedence(object) < POSTFIX), self.expr(index))
+            }
+            Expr::TableLiteral(fields) => self.table(fields),
+        }
+    }
+
+    fn operand(&self, expr: &Expr, parenthesize: bool) -> String {
+        if parenthesize {
+            format!("({})", self.expr(expr))
+        } else {
+            self.expr(expr)
+        }
+    }
+
+    fn table(&self, fields: &[TableField]) -> String {
+        if fields.is_empty() {
+            return "{}".to_string();
+        }
+        let has_trivia = fields.iter().any(|field| matches!(field, TableField::Trivia(_)));
+        if !has_trivia {
+            let inline: Vec<String> = fields.iter().map(|field| self.field(field)).collect();
+            let text = format!("{{{}}}", inline.join(", "));
+            if !text.contains('\n') && text.len() <= MAX_INLINE_TABLE {
+                return text;
+            }
+        }
+
+        // One field per line, with the trailing comment slot after '{'
+        let mut inner = Printer { lines: vec!["{".to_string()], indent: self.indent + 1, compound: Vec::new().into_iter() };
+        inner.items(fields, field_trivia, |printer, field| {
+            let text = format!("{},", printer.field(field));
+            printer.line(&text);
+        });
+        inner.indent -= 1;
+        inner.line("}");
+        inner.lines.join("\n")
+    }
+
+    fn field(&self, field: &TableField) -> String {
+        match field {
+            TableField::KeyValue { key, value } => format!("{}: {}", key, self.expr(value)),
+            // `{x + 1}` would parse `x` as a field on its own
+            TableField::Value(value) if starts_with_ident(value) && !matches!(value, Expr::Ident(_) | Expr::Local { .. }) => {
+                format!("({})", self.expr(value))
+            }
+            TableField::Value(value) => self.expr(value),
+            TableField::Trivia(_) => String::new(),
+        }
+    }
+}
+
+fn body(stmt: &Stmt) -> &[Stmt] {
+    match &stmt.kind {
+        StmtKind::Block(stmts) => stmts,
+        _ => std::slice::from_ref(stmt),
+    }
+}
+
+fn is_empty<T>(items: &[T], trivia: impl Fn(&T) -> Option<&Trivia>) -> bool {
+    items.iter().all(|item| trivia(item) == Some(&Trivia::BlankLine))
+}
+
+fn stmt_trivia(stmt: &Stmt) -> Option<&Trivia> {
+    match &stmt.kind {
+        StmtKind::Trivia(trivia) => Some(trivia),
+        _ => None,
+    }
+}
+
+fn member_trivia(member: &ObjectMember) -> Option<&Trivia> {
+    match member {
+        ObjectMember::Trivia(trivia) => Some(trivia),
+        _ => None,
+    }
+}
+
+fn field_trivia(field: &TableField) -> Option<&Trivia> {
+    match field {
+        TableField::Trivia(trivia) => Some(trivia),
+        _ => None,
+    }
+}
+
+fn starts_with_ident(expr: &Expr) -> bool {
+    match expr {
+        Expr::Ident(_) | Expr::Local { .. } => true,
+        Expr::Call { callee: inner, .. } | Expr::Member { object: inner, .. } | Expr::Index { object: inner, .. } => {
+            starts_with_ident(inner)
+        }
+        Expr::Binary { left, .. } => starts_with_ident(left),
+        _ => false,
+    }
+}
+
+// Binding strength, loosest first; mirrors the parser's descent
+const OR: u8 = 1;
+const AND: u8 = 2;
+const EQUALITY: u8 = 3;
+const COMPARISON: u8 = 4;
+const TERM: u8 = 5;
+const FACTOR: u8 = 6;
+const UNARY: u8 = 7;
+const POSTFIX: u8 = 8;
+const PRIMARY: u8 = 9;
+
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary { op, .. } => binary_precedence(op),
+        Expr::Unary { .. } => UNARY,
+        Expr::Call { .. } | Expr::Member { .. } | Expr::Index { .. } => POSTFIX,
+        Expr::Literal(_) | Expr::Ident(_) | Expr::Local { .. } | Expr::TableLiteral(_) => PRIMARY,
+    }
+}
+
+fn binary_precedence(op: &BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => OR,
+        BinaryOp::And => AND,
+        BinaryOp::Equal | BinaryOp::NotEqual => EQUALITY,
+        BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => COMPARISON,
+        BinaryOp::Add | BinaryOp::Sub => TERM,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => FACTOR,
+    }
+}
+
+fn binary_symbol(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Mod => "%",
+        BinaryOp::Equal => "==",
+        BinaryOp::NotEqual => "!=",
+        BinaryOp::Less => "<",
+        BinaryOp::LessEqual => "<=",
+        BinaryOp::Greater => ">",
+        BinaryOp::GreaterEqual => ">=",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+    }
+}
+
+fn format_literal(literal: &Literal) -> String {
+    match literal {
+        Literal::Int(i) => i.to_string(),
+        Literal::Float(f) => {
+            let text = f.to_string();
+            if text.contains('.') {
+                text
+            } else {
+                text + ".0"
+            }
+        }
+        Literal::Bool(b) => b.to_string(),
+        Literal::Nil => "nil".to_string(),
+        Literal::String(s) => {
+            let mut text = String::from("\"");
+            for c in s.chars() {
+                match c {
+                    '\n' => text.push_str("\\n"),
+                    '\t' => text.push_str("\\t"),
+                    '\r' => text.push_str("\\r"),
+                    '\\' => text.push_str("\\\\"),
+                    '"' => text.push_str("\\\""),
+                    _ => text.push(c),
+                }
+            }
+            text.push('"');
+            text
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_layout() {
+        let source = r#"
+func  greet(name,greeting):{
+if name=="" then { return nil; } elif greeting==nil then {
+  greeting="hi";
+  }
+  else{ } end
+    var count=count+1
+  count = count * 2;   count/=2;
+return greeting+" "+name;
+} end
+object Player:{ var hp=10; func hit(n):{ hp-=n; } end } end
+while not done and lives>0 do {} end
+"#;
+        let expected = r#"func greet(name, greeting): {
+    if name == "" then {
+        return nil;
+    } elif greeting == nil then {
+        greeting = "hi";
+    } else { } end
+    var count = count + 1;
+    count = count * 2;
+    count /= 2;
+    return greeting + " " + name;
+} end
+object Player: {
+    var hp = 10;
+    func hit(n): {
+        hp -= n;
+    } end
+} end
+while not done and lives > 0 do { } end
+"#;
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_keeps_comments_and_blank_lines() {
+        let source = "// header\n\n\n\nvar a = 1; // one\n/* block\n   comment */\nvar t = { // stats\n  hp: 10,\n\n  mp: 5 // magic\n};\nfunc f(): { // body\n\n    return a;\n\n} end\n";
+        let expected = "// header\n\nvar a = 1; // one\n/* block\n   comment */\nvar t = { // stats\n    hp: 10,\n\n    mp: 5, // magic\n};\nfunc f(): { // body\n    return a;\n} end\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parentheses_follow_precedence() {
+        let cases = [
+            ("var x = (a + b) * c;", "var x = (a + b) * c;"),
+            ("var x = a - (b - c);", "var x = a - (b - c);"),
+            ("var x = (a - b) - c;", "var x = a - b - c;"),
+            ("var x = -(y + 1);", "var x = -(y + 1);"),
+            ("var x = -(-a);", "var x = -(-a);"),
+            ("var x = -(-1);", "var x = -(-1);"),
+            ("var x = -(-a).b;", "var x = -(-a).b;"),
+            ("var x = not (a and b) or c;", "var x = not (a and b) or c;"),
+            ("var x = (t).items[(1)](2.50);", "var x = t.items[1](2.5);"),
+            ("var x = {(y + 1), y, \"q\\\"\\n\"};", "var x = {(y + 1), y, \"q\\\"\\n\"};"),
+            ("({a: 1}).a;", "({a: 1}.a);"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(format_source(source).unwrap(), format!("{}\n", expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_refuses_lossy_input() {
+        let err = format_source("var hp: int = 10;").unwrap_err();
+        assert!(err.message.contains("type annotations"), "{}", err);
+        let err = format_source("print(1, // first\n 2);").unwrap_err();
+        assert!(err.message.starts_with("line 2: can't keep the comment `// first`"), "{}", err);
+        let err = format_source("var = 1;").unwrap_err();
+        assert!(err.message.starts_with("parse error at 1:5"), "{}", err);
+    }
+
+    #[test]
+    fn test_examples_format_idempotently() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "arc") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            let formatted = format_source(&source).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            assert_eq!(format_source(&formatted).unwrap(), formatted, "{}", path.display());
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+}
@@ -0,0 +1,156 @@
+// ArcScript input state pushed in by the host each frame
+//
+// Scripts never talk to a windowing library. The host translates its engine's
+// events into `press`/`release`/`set_axis`/`set_mouse` calls, and scripts query
+// the result through the `input` namespace. Feeding synthetic input is all a
+// headless test needs.
+
+use std::collections::{HashMap, HashSet};
+
+/// Buttons, axes and mouse position as seen by scripts during the current frame.
+///
+/// Button names are whatever the host chooses ("space", "mouse_left",
+/// "pad_a"). Actions map a gameplay name like "jump" onto one or more
+/// buttons, and every query accepts either an action or a button name.
+#[derive(Debug, Clone, Default)]
+pub struct InputState {
+    held: HashSet<String>,
+    // Edges since the last end_frame, so a press and release within one
+    // frame still register as both
+    pressed: HashSet<String>,
+    released: HashSet<String>,
+    axes: HashMap<String, f64>,
+    mouse: (f64, f64),
+    actions: HashMap<String, Vec<String>>,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks `button` as down. Pressing a button that is already held is ignored.
+    pub fn press(&mut self, button: &str) {
+        if self.held.insert(button.to_string()) {
+            self.pressed.insert(button.to_string());
+        }
+    }
+
+    /// Marks `button` as up. Releasing a button that isn't held is ignored.
+    pub fn release(&mut self, button: &str) {
+        if self.held.remove(button) {
+            self.released.insert(button.to_string());
+        }
+    }
+
+    /// Sets an analog axis, clamped to -1.0..=1.0.
+    pub fn set_axis(&mut self, axis: &str, value: f64) {
+        self.axes.insert(axis.to_string(), value.clamp(-1.0, 1.0));
+    }
+
+    pub fn set_mouse(&mut self, x: f64, y: f64) {
+        self.mouse = (x, y);
+    }
+
+    pub fn mouse(&self) -> (f64, f64) {
+        self.mouse
+    }
+
+    /// Adds `button` to the buttons that trigger `action`.
+    pub fn bind(&mut self, action: &str, button: &str) {
+        let buttons = self.actions.entry(action.to_string()).or_default();
+        if !buttons.iter().any(|b| b == button) {
+            buttons.push(button.to_string());
+        }
+    }
+
+    /// Removes every binding for `action`.
+    pub fn unbind(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    pub fn held(&self, name: &str) -> bool {
+        self.any_button(name, &self.held)
+    }
+
+    /// True on the frame `name` went down.
+    pub fn pressed(&self, name: &str) -> bool {
+        self.any_button(name, &self.pressed)
+    }
+
+    /// True on the frame `name` went up.
+    pub fn released(&self, name: &str) -> bool {
+        self.any_button(name, &self.released)
+    }
+
+    /// Value of an analog axis, or 0.0 if the host never set it.
+    pub fn axis(&self, axis: &str) -> f64 {
+        self.axes.get(axis).copied().unwrap_or(0.0)
+    }
+
+    /// Clears this frame's press/release edges. Held buttons, axes and the
+    /// mouse position carry over.
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+
+    /// Releases everything and zeroes the axes, e.g. when the game window loses
+    /// focus. Action bindings are kept.
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.pressed.clear();
+        self.released.clear();
+        self.axes.clear();
+        self.mouse = (0.0, 0.0);
+    }
+
+    fn any_button(&self, name: &str, set: &HashSet<String>) -> bool {
+        match self.actions.get(name) {
+            Some(buttons) => buttons.iter().any(|b| set.contains(b)),
+            None => set.contains(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_press_and_release_edges() {
+        let mut input = InputState::new();
+        input.press("space");
+        assert!(input.pressed("space") && input.held("space"));
+
+        input.end_frame();
+        assert!(!input.pressed("space") && input.held("space"));
+
+        input.release("space");
+        assert!(input.released("space") && !input.held("space"));
+        input.end_frame();
+        assert!(!input.released("space"));
+    }
+
+    #[test]
+    fn test_tap_within_one_frame() {
+        let mut input = InputState::new();
+        input.press("space");
+        input.release("space");
+        assert!(input.pressed("space") && input.released("space"));
+        assert!(!input.held("space"));
+    }
+
+    #[test]
+    fn test_actions_map_to_any_bound_button() {
+        let mut input = InputState::new();
+        input.bind("jump", "space");
+        input.bind("jump", "pad_a");
+        input.press("pad_a");
+        assert!(input.pressed("jump"));
+        assert!(!input.held("space"));
+
+        input.unbind("jump");
+        assert!(!input.held("jump"));
+    }
+}
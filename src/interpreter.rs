@@ -1,13 +1,73 @@
 // ArcScript interpreter (MVP subset with basic functions)
 
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use crate::ast::{BinaryOp, Expr, FuncDecl, Literal, Program, Stmt, UnaryOp};
+use crate::ast::{BinaryOp, EventDecl, Expr, FuncDecl, Literal, Program, Slot, Stmt, StmtKind, UnaryOp};
+use crate::coroutine::{Coroutine, CoroutineEvent, CoroutineLink, CoroutineStatus};
+use crate::debugger::{DebugEvent, DebugHook, Resume};
+use crate::input::InputState;
+use crate::lexer::Lexer;
+use crate::limits::{Budget, Exceeded, InterruptHandle, Limits};
+use crate::memory::{Allocation, MemoryTracker, MemoryUsage};
+use crate::parser::Parser;
+use crate::profiler::{self, Profiler};
+use crate::reload::ReloadReport;
+use crate::sandbox::Sandbox;
+use crate::stdlib;
+use crate::stdlib::random::Rng;
+use crate::scheduler::{Scheduler, Timer, TimerClock};
+use crate::snapshot::{self, Saver, Snapshot, SnapshotError};
+
+/// Distinguishes ordinary script errors from the host-imposed execution limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Runtime,
+    /// Raised by the script itself with `throw` or `error()`
+    Thrown,
+    /// A failed `assert` or `assert_eq`
+    Assertion,
+    StepLimit,
+    CallDepth,
+    Timeout,
+    Interrupted,
+    MemoryLimit,
+    /// A suspended coroutine's body unwinding because nothing can resume it
+    CoroutineDropped,
+}
+
+impl ErrorKind {
+    /// The name scripts see in a caught error's `kind` field.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Runtime => "runtime",
+            ErrorKind::Thrown => "thrown",
+            ErrorKind::Assertion => "assertion",
+            ErrorKind::StepLimit => "step_limit",
+            ErrorKind::CallDepth => "call_depth",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Interrupted => "interrupted",
+            ErrorKind::MemoryLimit => "memory_limit",
+            ErrorKind::CoroutineDropped => "coroutine_dropped",
+        }
+    }
+
+    /// Whether `try`/`catch` can handle it. Limits are the host's decision, so
+    /// a script can't catch its way past them, and a dropped coroutine's body
+    /// must unwind rather than keep running on its own thread.
+    pub fn is_catchable(&self) -> bool {
+        matches!(self, ErrorKind::Runtime | ErrorKind::Thrown | ErrorKind::Assertion)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct RuntimeError {
     pub message: String,
     pub line: Option<usize>,
+    pub kind: ErrorKind,
+    /// The value passed to `throw` or `error()`, if the script raised it, or
+    /// the `left` and `right` of a failed `assert_eq`
+    pub value: Option<Value>,
 }
 
 impl RuntimeError {
@@ -15,6 +75,8 @@ impl RuntimeError {
         Self { 
             message: msg.to_string(),
             line: None,
+            kind: ErrorKind::Runtime,
+            value: None,
         }
     }
     
@@ -22,7 +84,35 @@ impl RuntimeError {
         Self {
             message: msg.to_string(),
             line: Some(line),
+            kind: ErrorKind::Runtime,
+            value: None,
+        }
+    }
+
+    pub fn with_kind(kind: ErrorKind, msg: &str) -> Self {
+        Self {
+            message: msg.to_string(),
+            line: None,
+            kind,
+            value: None,
+        }
+    }
+
+    /// The table a `catch` block receives: `message`, `kind` and, when known,
+    /// `line`. A thrown table keeps its own fields; any other thrown value is
+    /// kept under `value`.
+    pub fn to_value(&self) -> Value {
+        let mut table = match &self.value {
+            Some(Value::Table(table)) => table.clone(),
+            Some(value) => HashMap::from([("value".to_string(), value.clone())]),
+            None => HashMap::new(),
+        };
+        table.entry("message".to_string()).or_insert_with(|| Value::String(self.message.clone()));
+        table.entry("kind".to_string()).or_insert_with(|| Value::String(self.kind.as_str().to_string()));
+        if let Some(line) = self.line {
+            table.entry("line".to_string()).or_insert(Value::Int(line as i64));
         }
+        Value::Table(table)
     }
 }
 
@@ -45,27 +135,190 @@ pub enum Value {
     Bool(bool),
     String(String),
     Function {
-        decl: FuncDecl,
-        closure: Option<Box<Environment>>,
+        decl: Arc<FuncDecl>,
+        closure: Option<Arc<Environment>>,
     },
     Table(HashMap<String, Value>),
+    Vec2(f64, f64),
+    Vec3(f64, f64, f64),
+    Coroutine(Arc<Coroutine>),
     BuiltinFunction(String), // Built-in function by name
     Nil,
 }
 
-#[derive(Clone, Default)]
+/// Approximate bookkeeping cost of one table entry besides its key and value contents.
+const TABLE_ENTRY_BYTES: usize = std::mem::size_of::<(String, Value)>();
+
+impl Value {
+    /// The name `type()` reports for this value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Function { .. } => "function",
+            Value::Table(_) => "table",
+            Value::Vec2(..) => "vec2",
+            Value::Vec3(..) => "vec3",
+            Value::Coroutine(_) => "coroutine",
+            Value::BuiltinFunction(_) => "builtin_function",
+            Value::Nil => "nil",
+        }
+    }
+
+    /// Approximate heap bytes owned by this value: string contents, table
+    /// entries and the environment a closure captured.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Value::String(s) => s.len(),
+            Value::Table(map) => map
+                .iter()
+                .map(|(key, value)| TABLE_ENTRY_BYTES + key.len() + value.heap_size())
+                .sum(),
+            Value::Function { closure: Some(env), .. } => env.heap_size(),
+            _ => 0,
+        }
+    }
+
+    /// Source-like text for messages and tooling: strings are quoted, floats
+    /// keep their decimal point and tables show their entries in key order.
+    pub fn repr(&self) -> String {
+        let mut out = String::new();
+        self.write_repr(&mut out, None);
+        out
+    }
+
+    /// Like `repr`, with each table entry on its own indented line.
+    pub fn repr_pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_repr(&mut out, Some(0));
+        out
+    }
+
+    fn write_repr(&self, out: &mut String, indent: Option<usize>) {
+        match self {
+            Value::Int(i) => out.push_str(&i.to_string()),
+            Value::Float(f) => out.push_str(&format!("{:?}", f)),
+            Value::Bool(b) => out.push_str(&b.to_string()),
+            Value::String(s) => out.push_str(&format!("{:?}", s)),
+            Value::Function { decl, .. } => out.push_str(&format!("<function {}>", decl.name)),
+            Value::BuiltinFunction(name) => out.push_str(&format!("<builtin: {}>", name)),
+            Value::Coroutine(co) => out.push_str(&format!("<coroutine: {}>", co.status().as_str())),
+            Value::Vec2(x, y) => out.push_str(&format!("vec2({:?}, {:?})", x, y)),
+            Value::Vec3(x, y, z) => out.push_str(&format!("vec3({:?}, {:?}, {:?})", x, y, z)),
+            Value::Nil => out.push_str("nil"),
+            Value::Table(table) if table.is_empty() => out.push_str("{}"),
+            Value::Table(table) => {
+                // Positional entries first, without keys, then the rest sorted
+                let positional = (0..).take_while(|i: &usize| table.contains_key(&i.to_string())).count();
+                let mut named: Vec<&String> =
+                    table.keys().filter(|k| k.parse::<usize>().map_or(true, |i| i >= positional)).collect();
+                named.sort();
+                let inner = indent.map(|depth| depth + 1);
+                let entries = (0..positional).map(|i| (None, &table[&i.to_string()]));
+                let entries = entries.chain(named.into_iter().map(|k| (Some(k), &table[k])));
+                out.push('{');
+                for (n, (key, value)) in entries.enumerate() {
+                    match inner {
+                        Some(depth) => {
+                            out.push_str(if n > 0 { ",\n" } else { "\n" });
+                            out.push_str(&"  ".repeat(depth));
+                        }
+                        None if n > 0 => out.push_str(", "),
+                        None => {}
+                    }
+                    if let Some(key) = key {
+                        let plain = key.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+                            && key.chars().all(|c| c.is_alphanumeric() || c == '_');
+                        if plain {
+                            out.push_str(key);
+                        } else {
+                            out.push_str(&format!("{:?}", key));
+                        }
+                        out.push_str(": ");
+                    }
+                    value.write_repr(out, inner);
+                }
+                if let Some(depth) = indent {
+                    out.push('\n');
+                    out.push_str(&"  ".repeat(depth));
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Environment {
     values: HashMap<String, Value>,
-    parent: Option<Box<Environment>>,
+    // Locals the resolver gave a slot, named so the debugger and unresolved
+    // code can still find them
+    slots: Vec<(String, Value)>,
+    parent: Option<Parent>,
+}
+
+/// The scope an environment chains to: its own until a closure captures it,
+/// then shared with the closure, and copied before a write if still shared.
+#[derive(Debug, Clone, PartialEq)]
+enum Parent {
+    Owned(Box<Environment>),
+    Shared(Arc<Environment>),
+}
+
+impl Parent {
+    fn into_env(self) -> Environment {
+        match self {
+            Parent::Owned(env) => *env,
+            Parent::Shared(env) => Arc::unwrap_or_clone(env),
+        }
+    }
 }
 
 impl Environment {
     pub fn new() -> Self {
-        Self { values: HashMap::new(), parent: None }
+        Self { values: HashMap::new(), slots: Vec::new(), parent: None }
     }
 
     pub fn with_parent(parent: Option<Box<Environment>>) -> Self {
-        Self { values: HashMap::new(), parent }
+        Self { values: HashMap::new(), slots: Vec::new(), parent: parent.map(Parent::Owned) }
+    }
+
+    fn with_shared_parent(parent: Arc<Environment>) -> Self {
+        Self { values: HashMap::new(), slots: Vec::new(), parent: Some(Parent::Shared(parent)) }
+    }
+
+    fn parent(&self) -> Option<&Environment> {
+        match self.parent.as_ref()? {
+            Parent::Owned(env) => Some(env),
+            Parent::Shared(env) => Some(env),
+        }
+    }
+
+    fn parent_mut(&mut self) -> Option<&mut Environment> {
+        match self.parent.as_mut()? {
+            Parent::Owned(env) => Some(env),
+            Parent::Shared(env) => Some(Arc::make_mut(env)),
+        }
+    }
+
+    /// This scope as a closure made in it holds it. The scopes it chains to
+    /// are shared with the closure from now on instead of copied; only this
+    /// one is copied.
+    pub(crate) fn capture(&mut self) -> Arc<Environment> {
+        self.share_parents();
+        Arc::new(self.clone())
+    }
+
+    fn share_parents(&mut self) {
+        self.parent = match self.parent.take() {
+            Some(Parent::Owned(mut parent)) => {
+                parent.share_parents();
+                Some(Parent::Shared(Arc::new(*parent)))
+            }
+            parent => parent,
+        };
     }
 
     pub fn define(&mut self, name: String, value: Value) {
@@ -75,158 +328,607 @@ impl Environment {
     pub fn get(&self, name: &str) -> Option<&Value> {
         if let Some(v) = self.values.get(name) {
             Some(v)
-        } else if let Some(parent) = &self.parent {
+        } else if let Some((_, v)) = self.slots.iter().rev().find(|(slot_name, _)| slot_name == name) {
+            Some(v)
+        } else if let Some(parent) = self.parent() {
             parent.get(name)
         } else {
             None
         }
     }
+
+    /// Updates the nearest existing binding of `name`, or defines it in this
+    /// scope if there is none.
+    pub fn assign(&mut self, name: &str, value: Value) {
+        match self.get_mut(name) {
+            Some(slot) => *slot = value,
+            None => self.define(name.to_string(), value),
+        }
+    }
+
+    /// Approximate heap bytes held by this scope and the scopes it chains to.
+    pub fn heap_size(&self) -> usize {
+        let own: usize = self
+            .values
+            .iter()
+            .chain(self.slots.iter().map(|(name, value)| (name, value)))
+            .map(|(name, value)| TABLE_ENTRY_BYTES + name.len() + value.heap_size())
+            .sum();
+        own + self.parent().map_or(0, |parent| parent.heap_size())
+    }
+
+    /// Sets slot `index` of this scope. Slots skipped over are left unnamed,
+    /// so lookups by name pass them by.
+    pub(crate) fn define_slot(&mut self, index: usize, name: &str, value: Value) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            if slot.0 != name {
+                slot.0 = name.to_string();
+            }
+            slot.1 = value;
+            return;
+        }
+        self.slots.resize_with(index, || (String::new(), Value::Nil));
+        self.slots.push((name.to_string(), value));
+    }
+
+    /// The local at `slot`, if the scope has it. Scopes built at runtime,
+    /// like a restored snapshot's closures, have no slots; callers then look
+    /// the name up instead.
+    pub(crate) fn get_slot(&self, slot: Slot, name: &str) -> Option<&Value> {
+        let mut env = self;
+        for _ in 0..slot.depth {
+            env = env.parent()?;
+        }
+        let (slot_name, value) = env.slots.get(slot.index)?;
+        debug_assert_eq!(slot_name, name, "slot {:?} holds another variable", slot);
+        Some(value)
+    }
+
+    /// Updates the local at `slot` like get_slot finds it, handing the value
+    /// back if it isn't there.
+    pub(crate) fn assign_slot(&mut self, slot: Slot, name: &str, value: Value) -> Result<(), Value> {
+        let mut env = self;
+        for _ in 0..slot.depth {
+            match env.parent_mut() {
+                Some(parent) => env = parent,
+                None => return Err(value),
+            }
+        }
+        match env.slots.get_mut(slot.index) {
+            Some((slot_name, current)) => {
+                debug_assert_eq!(slot_name, name, "slot {:?} holds another variable", slot);
+                *current = value;
+                Ok(())
+            }
+            None => Err(value),
+        }
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        if let Some(v) = self.values.get_mut(name) {
+            Some(v)
+        } else if let Some((_, v)) = self.slots.iter_mut().rev().find(|(slot_name, _)| slot_name == name) {
+            Some(v)
+        } else {
+            match self.parent.as_mut()? {
+                Parent::Owned(parent) => parent.get_mut(name),
+                Parent::Shared(parent) => {
+                    // Only copy a shared scope if the variable is there
+                    parent.get(name)?;
+                    Arc::make_mut(parent).get_mut(name)
+                }
+            }
+        }
+    }
+}
+
+/// A call in progress, as listed by `Interpreter::call_stack`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    pub name: String,
+    /// The line running in this frame: the current statement for the
+    /// innermost frame, the call site for the others
+    pub line: usize,
+}
+
+/// Whether a global is one `register_builtins` defined and nothing replaced.
+fn is_builtin_global(name: &str, value: &Value) -> bool {
+    match value {
+        Value::BuiltinFunction(builtin) => builtin == name,
+        Value::Table(table) => {
+            !table.is_empty()
+                && table.iter().all(|(key, v)| matches!(v, Value::BuiltinFunction(b) if *b == format!("{}.{}", name, key)))
+        }
+        Value::Float(f) => stdlib::math::CONSTANTS.iter().any(|(constant, c)| *constant == name && c == f),
+        _ => false,
+    }
+}
+
+/// How a statement finished: normally, or by unwinding to the enclosing loop
+/// or function.
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// An integer arithmetic result, or an error if it overflowed.
+fn int_result(value: Option<i64>) -> Result<Value, RuntimeError> {
+    value.map(Value::Int).ok_or_else(|| RuntimeError::new("integer overflow"))
+}
+
+fn memory_limit_error(cap: usize) -> RuntimeError {
+    RuntimeError::with_kind(ErrorKind::MemoryLimit, &format!("memory limit exceeded ({} bytes)", cap))
+}
+
+fn is_callable(value: &Value) -> bool {
+    matches!(value, Value::Function { .. } | Value::BuiltinFunction(_))
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+fn vec_component(vector: &Value, field: &str) -> Result<Value, RuntimeError> {
+    match (vector, field) {
+        (Value::Vec2(x, _), "x") | (Value::Vec3(x, _, _), "x") => Ok(Value::Float(*x)),
+        (Value::Vec2(_, y), "y") | (Value::Vec3(_, y, _), "y") => Ok(Value::Float(*y)),
+        (Value::Vec3(_, _, z), "z") => Ok(Value::Float(*z)),
+        _ => Err(RuntimeError::new(&format!("vector has no member '{}'", field))),
+    }
+}
+
+fn vec_with_component(vector: Value, field: &str, value: &Value) -> Result<Value, RuntimeError> {
+    let n = as_number(value)
+        .ok_or_else(|| RuntimeError::new(&format!("vector member '{}' must be a number", field)))?;
+    match (vector, field) {
+        (Value::Vec2(_, y), "x") => Ok(Value::Vec2(n, y)),
+        (Value::Vec2(x, _), "y") => Ok(Value::Vec2(x, n)),
+        (Value::Vec3(_, y, z), "x") => Ok(Value::Vec3(n, y, z)),
+        (Value::Vec3(x, _, z), "y") => Ok(Value::Vec3(x, n, z)),
+        (Value::Vec3(x, y, _), "z") => Ok(Value::Vec3(x, y, n)),
+        _ => Err(RuntimeError::new(&format!("vector has no member '{}'", field))),
+    }
+}
+
+/// Returns the unit vector in the same direction; zero vectors are returned unchanged.
+fn vec_normalize(vector: &Value) -> Value {
+    match *vector {
+        Value::Vec2(x, y) => {
+            let len = x.hypot(y);
+            if len == 0.0 { Value::Vec2(x, y) } else { Value::Vec2(x / len, y / len) }
+        }
+        Value::Vec3(x, y, z) => {
+            let len = (x * x + y * y + z * z).sqrt();
+            if len == 0.0 { Value::Vec3(x, y, z) } else { Value::Vec3(x / len, y / len, z / len) }
+        }
+        _ => vector.clone(),
+    }
+}
+
+/// Rotates a vec3 around `axis` by `angle` radians (Rodrigues' rotation formula).
+fn vec3_rotate(vector: &Value, axis: &Value, angle: f64) -> Value {
+    let (Value::Vec3(vx, vy, vz), Value::Vec3(kx, ky, kz)) = (vector, vec_normalize(axis)) else {
+        return vector.clone();
+    };
+    let (sin, cos) = angle.sin_cos();
+    let dot = kx * vx + ky * vy + kz * vz;
+    let (cx, cy, cz) = (ky * vz - kz * vy, kz * vx - kx * vz, kx * vy - ky * vx);
+    Value::Vec3(
+        vx * cos + cx * sin + kx * dot * (1.0 - cos),
+        vy * cos + cy * sin + ky * dot * (1.0 - cos),
+        vz * cos + cz * sin + kz * dot * (1.0 - cos),
+    )
+}
+
+/// Unsigned angle in radians between two vec3s (0 if either is zero-length).
+fn vec3_angle_between(a: &Value, b: &Value) -> f64 {
+    let (Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz)) = (a, b) else {
+        return 0.0;
+    };
+    let lengths = (ax * ax + ay * ay + az * az).sqrt() * (bx * bx + by * by + bz * bz).sqrt();
+    if lengths == 0.0 {
+        return 0.0;
+    }
+    ((ax * bx + ay * by + az * bz) / lengths).clamp(-1.0, 1.0).acos()
 }
 
 pub struct Interpreter {
     env: Environment,
+    // Shared with coroutine interpreters so timers they create reach the host's tick
+    scheduler: Arc<Mutex<Scheduler>>,
+    // Pushed in by the host; shared with coroutine interpreters like the scheduler
+    input: Arc<Mutex<InputState>>,
+    limits: Limits,
+    // Steps, deadline and interrupt flag for the current run, shared with coroutines
+    budget: Arc<Budget>,
+    // Nested function calls on this interpreter's stack
+    depth: usize,
+    // Callers' environments while a function runs, so memory measurement can reach them
+    frames: Vec<Environment>,
+    // Shared with coroutines; each interpreter reports its own reachable size under memory_id
+    memory: Arc<MemoryTracker>,
+    memory_id: u64,
+    // Kept to explain why a builtin is missing
+    sandbox: Sandbox,
+    // One generator shared with coroutines so a seed fixes every roll
+    rng: Arc<Mutex<Rng>>,
+    // Set when this interpreter is running a coroutine body
+    coroutine: Option<CoroutineLink>,
+    // Consulted before each statement and on calls and returns
+    debug_hook: Option<Box<dyn DebugHook>>,
+    // Name and call-site line of each call made while a hook is set
+    call_sites: Vec<(String, usize)>,
+    // Line of the statement being run, kept up to date while a hook is set
+    line: usize,
+    // Receives print/println output instead of stdout; shared with coroutines
+    print_handler: Option<PrintHandler>,
+    // Times calls and counts allocations while installed
+    profiler: Option<Profiler>,
+    // Objects' `on` handlers in declaration order, one per object and event
+    handlers: Vec<Handler>,
+}
+
+/// An object's `on` handler, run by `Interpreter::emit`.
+struct Handler {
+    object: String,
+    event: String,
+    function: Value,
+}
+
+/// Receives what scripts print; see `Interpreter::set_print_handler`.
+pub type PrintHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_sandbox(Sandbox::all())
+    }
+
+    /// Creates an interpreter that only exposes the builtins `sandbox` allows.
+    pub fn with_sandbox(sandbox: Sandbox) -> Self {
+        let memory = Arc::new(MemoryTracker::default());
         let mut env = Environment::new();
         // Register built-in functions
-        Self::register_builtins(&mut env);
-        Self { env }
+        Self::register_builtins(&mut env, &sandbox);
+        Self {
+            env,
+            scheduler: Arc::new(Mutex::new(Scheduler::new())),
+            input: Arc::new(Mutex::new(InputState::new())),
+            limits: Limits::default(),
+            budget: Arc::new(Budget::default()),
+            depth: 0,
+            frames: Vec::new(),
+            memory: Arc::clone(&memory),
+            memory_id: memory.register(),
+            sandbox,
+            rng: Arc::new(Mutex::new(Rng::default())),
+            coroutine: None,
+            debug_hook: None,
+            call_sites: Vec::new(),
+            line: 0,
+            print_handler: None,
+            profiler: None,
+            handlers: Vec::new(),
+        }
     }
 
-    fn register_builtins(env: &mut Environment) {
+    fn register_builtins(env: &mut Environment, sandbox: &Sandbox) {
+        // Only builtins the sandbox allows are visible to scripts
+        let mut define = |name: &str| {
+            if sandbox.allows(name) {
+                env.define(name.to_string(), Value::BuiltinFunction(name.to_string()));
+            }
+        };
+
         // Output functions
-        env.define("print".to_string(), Value::BuiltinFunction("print".to_string()));
-        env.define("println".to_string(), Value::BuiltinFunction("println".to_string()));
+        define("print");
+        define("println");
+
+        // Errors and assertions
+        define("error");
+        define("assert");
+        define("assert_eq");
         
         // Type operations
-        env.define("type".to_string(), Value::BuiltinFunction("type".to_string()));
-        env.define("len".to_string(), Value::BuiltinFunction("len".to_string()));
+        define("type");
+        define("len");
         
         // Type conversions
-        env.define("str".to_string(), Value::BuiltinFunction("str".to_string()));
-        env.define("int".to_string(), Value::BuiltinFunction("int".to_string()));
-        env.define("float".to_string(), Value::BuiltinFunction("float".to_string()));
+        define("str");
+        define("int");
+        define("float");
         
         // Math functions
-        env.define("abs".to_string(), Value::BuiltinFunction("abs".to_string()));
-        env.define("min".to_string(), Value::BuiltinFunction("min".to_string()));
-        env.define("max".to_string(), Value::BuiltinFunction("max".to_string()));
-        env.define("floor".to_string(), Value::BuiltinFunction("floor".to_string()));
-        env.define("ceil".to_string(), Value::BuiltinFunction("ceil".to_string()));
-        env.define("round".to_string(), Value::BuiltinFunction("round".to_string()));
-        env.define("sqrt".to_string(), Value::BuiltinFunction("sqrt".to_string()));
-        env.define("pow".to_string(), Value::BuiltinFunction("pow".to_string()));
+        define("abs");
+        define("min");
+        define("max");
+        define("floor");
+        define("ceil");
+        define("round");
+        define("sqrt");
+        define("pow");
+        for name in stdlib::math::FUNCTIONS {
+            define(name);
+        }
         
         // String functions
-        env.define("substring".to_string(), Value::BuiltinFunction("substring".to_string()));
-        env.define("contains".to_string(), Value::BuiltinFunction("contains".to_string()));
-        env.define("toUpper".to_string(), Value::BuiltinFunction("toUpper".to_string()));
-        env.define("toLower".to_string(), Value::BuiltinFunction("toLower".to_string()));
-    }
-
-    fn eval_function_body(&mut self, body: &Stmt) -> Result<Option<Value>, RuntimeError> {
-        match body {
-            Stmt::Block(stmts) => {
-                for s in stmts {
-                    match self.eval_stmt(s) {
-                        Ok(Some(v)) => return Ok(Some(v)),
-                        Ok(None) => continue,
-                        Err(e) => return Err(e),
-                    }
-                }
-                Ok(None)
+        define("substring");
+        define("contains");
+        define("toUpper");
+        define("toLower");
+
+        // Vector functions
+        define("vec2");
+        define("vec3");
+        define("length");
+        define("normalize");
+        define("dot");
+        define("cross");
+        define("distance");
+        define("lerp");
+        define("rotate");
+        define("angle");
+
+        // Timer functions
+        define("after");
+        define("every");
+        define("after_frames");
+        define("every_frames");
+        define("cancel");
+
+        // Coroutine functions
+        define("coroutine");
+        define("resume");
+        define("yield");
+        define("status");
+        define("wait");
+        define("wait_frames");
+
+        // Namespaces: input.pressed("jump"), string.split(s, ","), ...
+        let input = ["pressed", "held", "released", "axis", "mouse", "bind", "unbind"];
+        Self::define_namespace(env, sandbox, "input", &input);
+        Self::define_namespace(env, sandbox, "string", stdlib::string::FUNCTIONS);
+        Self::define_namespace(env, sandbox, "random", stdlib::random::FUNCTIONS);
+        Self::define_namespace(env, sandbox, "table", stdlib::table::FUNCTIONS);
+        Self::define_namespace(env, sandbox, "json", stdlib::json::FUNCTIONS);
+
+        // Math constants
+        for (name, value) in stdlib::math::CONSTANTS {
+            env.define(name.to_string(), Value::Float(*value));
+        }
+    }
+
+    /// Defines a global table of the `namespace.function` builtins the sandbox allows.
+    fn define_namespace(env: &mut Environment, sandbox: &Sandbox, namespace: &str, functions: &[&str]) {
+        let mut table = HashMap::new();
+        for function in functions {
+            let builtin = format!("{}.{}", namespace, function);
+            if sandbox.allows(&builtin) {
+                table.insert(function.to_string(), Value::BuiltinFunction(builtin));
+            }
+        }
+        if !table.is_empty() && sandbox.allows(namespace) {
+            env.define(namespace.to_string(), Value::Table(table));
+        }
+    }
+
+    fn eval_function_body(&mut self, body: &Stmt) -> Result<Value, RuntimeError> {
+        let flow = match &body.kind {
+            StmtKind::Block(stmts) => self.eval_stmts(stmts)?,
+            _ => self.eval_stmt(body)?,
+        };
+        match flow {
+            Flow::Return(v) => Ok(v),
+            Flow::Normal => Ok(Value::Nil),
+            Flow::Break | Flow::Continue => Err(RuntimeError::new("break or continue outside of a loop")),
+        }
+    }
+
+    /// Runs statements in the current scope until one of them unwinds.
+    fn eval_stmts(&mut self, stmts: &[Stmt]) -> Result<Flow, RuntimeError> {
+        for stmt in stmts {
+            match self.eval_stmt(stmt)? {
+                Flow::Normal => continue,
+                flow => return Ok(flow),
             }
-            _ => self.eval_stmt(body),
         }
+        Ok(Flow::Normal)
+    }
+
+    /// Finds a variable by name, from the innermost scope out.
+    fn lookup(&self, name: &str) -> Result<Value, RuntimeError> {
+        if let Some(v) = self.env.get(name) {
+            Ok(v.clone())
+        } else if let Some(denial) = self.sandbox.denial(name) {
+            Err(RuntimeError::new(&denial))
+        } else {
+            Err(RuntimeError::new(&format!("Undefined identifier '{}'", name)))
+        }
+    }
+
+    /// Defines a variable in the current scope, in its slot if the resolver
+    /// gave it one.
+    fn declare(&mut self, slot: Option<Slot>, name: &str, value: Value) {
+        match slot {
+            Some(slot) => self.env.define_slot(slot.index, name, value),
+            None => self.env.define(name.to_string(), value),
+        }
+    }
+
+    /// Updates a variable through its slot, falling back to the nearest
+    /// variable of that name, or a new one in the current scope.
+    fn assign_variable(&mut self, slot: Option<Slot>, name: &str, value: Value) {
+        let value = match slot {
+            Some(slot) => match self.env.assign_slot(slot, name, value) {
+                Ok(()) => return,
+                Err(value) => value,
+            },
+            None => value,
+        };
+        self.env.assign(name, value);
+    }
+
+    /// Runs `f` in a new scope chained to the current one. Assignments to outer
+    /// variables made inside the scope are kept.
+    fn in_scope<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+        let outer = std::mem::take(&mut self.env);
+        self.env = Environment::with_parent(Some(Box::new(outer)));
+        let result = f(self);
+        if let Some(outer) = self.env.parent.take() {
+            self.env = outer.into_env();
+        }
+        result
     }
 
     fn eval_call(&mut self, callee: &Expr, args: &[Expr]) -> Result<Value, RuntimeError> {
         let callee_val = self.eval_expr(callee)?;
-        
-        // Handle built-in functions
-        if let Value::BuiltinFunction(name) = callee_val {
-            return self.call_builtin(&name, args);
+        let mut arg_vals = Vec::with_capacity(args.len());
+        for arg in args {
+            arg_vals.push(self.eval_expr(arg)?);
         }
-        
-        let (func, closure) = match callee_val {
-            Value::Function { decl, closure } => (decl, closure),
-            _ => {
-                return Err(RuntimeError::new("attempt to call non-function"));
+        self.call_value(callee_val, arg_vals)
+    }
+
+    /// Calls any callable value (function, builtin or table with `__call`) with
+    /// already-evaluated arguments.
+    pub(crate) fn call_value(&mut self, callee: Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::BuiltinFunction(name) => {
+                let result = self.call_builtin(&name, &args)?;
+                self.charge(result.heap_size())?;
+                Ok(result)
             }
-        };
+            Value::Function { decl, closure } => self.call_function(decl, closure, args),
+            // Calling a coroutine resumes it
+            Value::Coroutine(co) => self.resume_coroutine(&co, CoroutineStatus::Suspended, args),
+            Value::Table(_) => {
+                // Callable tables dispatch to their __call metamethod with the table first
+                let handler = match self.metamethod(&callee, "__call") {
+                    Some(handler) => handler,
+                    None => return Err(RuntimeError::new("attempt to call non-function")),
+                };
+                let mut call_args = Vec::with_capacity(args.len() + 1);
+                call_args.push(callee);
+                call_args.extend(args);
+                self.call_value(handler, call_args)
+            }
+            _ => Err(RuntimeError::new("attempt to call non-function")),
+        }
+    }
 
+    fn call_function(
+        &mut self,
+        func: Arc<FuncDecl>,
+        closure: Option<Arc<Environment>>,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
         // Create new environment: if function has closure, chain to that; otherwise chain to current env
-        let parent = if let Some(captured) = closure {
-            Some(captured)
-        } else {
-            Some(Box::new(self.env.clone()))
-        };
-        let mut call_env = Environment::with_parent(parent);
+        let parent = closure.unwrap_or_else(|| self.env.capture());
+        // Slot 0 is the function itself, so it can call itself, and the
+        // parameters follow, as the resolver numbers them
+        let mut call_env = Environment::with_shared_parent(Arc::clone(&parent));
+        let function = Value::Function { decl: Arc::clone(&func), closure: Some(parent) };
+        call_env.define_slot(0, &func.name, function);
 
         // Bind parameters to argument values (extra args ignored, missing args become Nil)
+        let mut args = args.into_iter();
         for (i, param) in func.params.iter().enumerate() {
-            let value = if let Some(arg_expr) = args.get(i) {
-                self.eval_expr(arg_expr)?
-            } else {
-                Value::Nil
-            };
-            call_env.define(param.name.clone(), value);
+            call_env.define_slot(i + 1, &param.name, args.next().unwrap_or(Value::Nil));
+        }
+
+        if matches!(self.limits.max_call_depth, Some(max) if self.depth >= max) {
+            return Err(RuntimeError::with_kind(
+                ErrorKind::CallDepth,
+                &format!("maximum call depth exceeded ({})", self.depth),
+            ));
         }
 
         // Swap in call environment, execute, and restore previous environment
-        let saved_env = std::mem::replace(&mut self.env, call_env);
-        let result_opt = self.eval_function_body(&func.body)?;
-        self.env = saved_env;
-        Ok(result_opt.unwrap_or(Value::Nil))
+        self.frames.push(std::mem::replace(&mut self.env, call_env));
+        self.depth += 1;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(&func.name, func.line);
+        }
+        let traced = self.debug_hook.is_some();
+        let mut result = Ok(Value::Nil);
+        if traced {
+            self.call_sites.push((func.name.clone(), self.line));
+            result = self.debug_event(DebugEvent::Call { name: &func.name }).map(|_| Value::Nil);
+        }
+        if result.is_ok() {
+            result = self.eval_function_body(&func.body);
+        }
+        if traced {
+            if result.is_ok() {
+                result = self.debug_event(DebugEvent::Return { name: &func.name }).and(result);
+            }
+            self.call_sites.pop();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+        self.depth -= 1;
+        if let Some(saved_env) = self.frames.pop() {
+            self.env = saved_env;
+        }
+        result
+    }
+
+    /// Runs a builtin if the sandbox allows it. Builtin values normally only
+    /// reach scripts through the globals the sandbox let in, but one restored
+    /// or passed in by the host is checked here too.
+    pub(crate) fn call_builtin(&mut self, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+        if let Some(denial) = self.sandbox.denial(name) {
+            return Err(RuntimeError::new(&denial));
+        }
+        self.run_builtin(name, args)
     }
 
-    fn call_builtin(&mut self, name: &str, args: &[Expr]) -> Result<Value, RuntimeError> {
+    /// Runs a builtin without checking the sandbox; for namespaced builtins
+    /// that forward to a global one after their own name was checked.
+    pub(crate) fn run_builtin(&mut self, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
         match name {
-            "print" => {
+            "print" | "println" => {
+                let mut text = String::new();
                 for (i, arg) in args.iter().enumerate() {
                     if i > 0 {
-                        print!(" ");
+                        text.push(' ');
                     }
-                    print!("{}", self.value_to_string(&self.eval_expr(arg)?));
+                    text.push_str(&self.value_to_string(arg)?);
                 }
-                Ok(Value::Nil)
-            }
-            "println" => {
-                for (i, arg) in args.iter().enumerate() {
-                    if i > 0 {
-                        print!(" ");
-                    }
-                    print!("{}", self.value_to_string(&self.eval_expr(arg)?));
+                if name == "println" {
+                    text.push('\n');
+                }
+                match &self.print_handler {
+                    Some(handler) => handler(&text),
+                    None => print!("{}", text),
                 }
-                println!();
                 Ok(Value::Nil)
             }
             "type" => {
                 if args.is_empty() {
                     return Err(RuntimeError::new("type() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
-                let type_name = match val {
-                    Value::Int(_) => "int",
-                    Value::Float(_) => "float",
-                    Value::Bool(_) => "bool",
-                    Value::String(_) => "string",
-                    Value::Function { .. } => "function",
-                    Value::Table(_) => "table",
-                    Value::BuiltinFunction(_) => "builtin_function",
-                    Value::Nil => "nil",
-                };
-                Ok(Value::String(type_name.to_string()))
+                Ok(Value::String(args[0].type_name().to_string()))
             }
             "len" => {
                 if args.is_empty() {
                     return Err(RuntimeError::new("len() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
-                    Value::String(s) => Ok(Value::Int(s.len() as i64)),
+                    Value::String(s) => Ok(Value::Int(s.chars().count() as i64)),
                     Value::Table(t) => Ok(Value::Int(t.len() as i64)),
                     _ => Err(RuntimeError::new("len() requires string or table argument")),
                 }
@@ -235,14 +937,13 @@ impl Interpreter {
                 if args.is_empty() {
                     return Err(RuntimeError::new("str() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
-                Ok(Value::String(self.value_to_string(&val)))
+                Ok(Value::String(self.value_to_string(&args[0])?))
             }
             "int" => {
                 if args.is_empty() {
                     return Err(RuntimeError::new("int() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
                     Value::Int(i) => Ok(Value::Int(i)),
                     Value::Float(f) => Ok(Value::Int(f as i64)),
@@ -259,7 +960,7 @@ impl Interpreter {
                 if args.is_empty() {
                     return Err(RuntimeError::new("float() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
                     Value::Int(i) => Ok(Value::Float(i as f64)),
                     Value::Float(f) => Ok(Value::Float(f)),
@@ -276,46 +977,18 @@ impl Interpreter {
                 if args.is_empty() {
                     return Err(RuntimeError::new("abs() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
-                    Value::Int(i) => Ok(Value::Int(i.abs())),
+                    Value::Int(i) => int_result(i.checked_abs()),
                     Value::Float(f) => Ok(Value::Float(f.abs())),
                     _ => Err(RuntimeError::new("abs() requires numeric argument")),
                 }
             }
-            "min" => {
-                if args.len() < 2 {
-                    return Err(RuntimeError::new("min() requires 2 arguments"));
-                }
-                let a = self.eval_expr(&args[0])?;
-                let b = self.eval_expr(&args[1])?;
-                match (a, b) {
-                    (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x.min(y))),
-                    (Value::Float(x), Value::Float(y)) => Ok(Value::Float(x.min(y))),
-                    (Value::Int(x), Value::Float(y)) => Ok(Value::Float((x as f64).min(y))),
-                    (Value::Float(x), Value::Int(y)) => Ok(Value::Float(x.min(y as f64))),
-                    _ => Err(RuntimeError::new("min() requires numeric arguments")),
-                }
-            }
-            "max" => {
-                if args.len() < 2 {
-                    return Err(RuntimeError::new("max() requires 2 arguments"));
-                }
-                let a = self.eval_expr(&args[0])?;
-                let b = self.eval_expr(&args[1])?;
-                match (a, b) {
-                    (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x.max(y))),
-                    (Value::Float(x), Value::Float(y)) => Ok(Value::Float(x.max(y))),
-                    (Value::Int(x), Value::Float(y)) => Ok(Value::Float((x as f64).max(y))),
-                    (Value::Float(x), Value::Int(y)) => Ok(Value::Float(x.max(y as f64))),
-                    _ => Err(RuntimeError::new("max() requires numeric arguments")),
-                }
-            }
             "floor" => {
                 if args.is_empty() {
                     return Err(RuntimeError::new("floor() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
                     Value::Float(f) => Ok(Value::Int(f.floor() as i64)),
                     Value::Int(i) => Ok(Value::Int(i)),
@@ -326,7 +999,7 @@ impl Interpreter {
                 if args.is_empty() {
                     return Err(RuntimeError::new("ceil() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
                     Value::Float(f) => Ok(Value::Int(f.ceil() as i64)),
                     Value::Int(i) => Ok(Value::Int(i)),
@@ -337,7 +1010,7 @@ impl Interpreter {
                 if args.is_empty() {
                     return Err(RuntimeError::new("round() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
                     Value::Float(f) => Ok(Value::Int(f.round() as i64)),
                     Value::Int(i) => Ok(Value::Int(i)),
@@ -348,7 +1021,7 @@ impl Interpreter {
                 if args.is_empty() {
                     return Err(RuntimeError::new("sqrt() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
                     Value::Float(f) => {
                         if f < 0.0 {
@@ -371,8 +1044,8 @@ impl Interpreter {
                 if args.len() < 2 {
                     return Err(RuntimeError::new("pow() requires 2 arguments"));
                 }
-                let base = self.eval_expr(&args[0])?;
-                let exp = self.eval_expr(&args[1])?;
+                let base = args[0].clone();
+                let exp = args[1].clone();
                 match (base, exp) {
                     (Value::Float(b), Value::Float(e)) => Ok(Value::Float(b.powf(e))),
                     (Value::Float(b), Value::Int(e)) => Ok(Value::Float(b.powi(e as i32))),
@@ -392,9 +1065,9 @@ impl Interpreter {
                 if args.len() < 3 {
                     return Err(RuntimeError::new("substring() requires 3 arguments (string, start, end)"));
                 }
-                let s = self.eval_expr(&args[0])?;
-                let start = self.eval_expr(&args[1])?;
-                let end = self.eval_expr(&args[2])?;
+                let s = args[0].clone();
+                let start = args[1].clone();
+                let end = args[2].clone();
                 
                 match (s, start, end) {
                     (Value::String(s), Value::Int(start), Value::Int(end)) => {
@@ -412,8 +1085,8 @@ impl Interpreter {
                 if args.len() < 2 {
                     return Err(RuntimeError::new("contains() requires 2 arguments"));
                 }
-                let s = self.eval_expr(&args[0])?;
-                let substr = self.eval_expr(&args[1])?;
+                let s = args[0].clone();
+                let substr = args[1].clone();
                 
                 match (s, substr) {
                     (Value::String(s), Value::String(substr)) => {
@@ -426,7 +1099,7 @@ impl Interpreter {
                 if args.is_empty() {
                     return Err(RuntimeError::new("toUpper() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
                     Value::String(s) => Ok(Value::String(s.to_uppercase())),
                     _ => Err(RuntimeError::new("toUpper() requires string argument")),
@@ -436,103 +1109,694 @@ impl Interpreter {
                 if args.is_empty() {
                     return Err(RuntimeError::new("toLower() requires 1 argument"));
                 }
-                let val = self.eval_expr(&args[0])?;
+                let val = args[0].clone();
                 match val {
                     Value::String(s) => Ok(Value::String(s.to_lowercase())),
                     _ => Err(RuntimeError::new("toLower() requires string argument")),
                 }
             }
-            _ => Err(RuntimeError::new(&format!("unknown built-in function: {}", name))),
-        }
-    }
-
-    fn value_to_string(&self, val: &Value) -> String {
-        match val {
-            Value::Int(i) => i.to_string(),
-            Value::Float(f) => f.to_string(),
-            Value::Bool(b) => b.to_string(),
-            Value::String(s) => s.clone(),
-            Value::Function { .. } => "<function>".to_string(),
-            Value::Table(_) => "<table>".to_string(),
-            Value::BuiltinFunction(name) => format!("<builtin: {}>", name),
-            Value::Nil => "nil".to_string(),
-        }
-    }
-
-    pub fn eval_program(&mut self, program: &Program) -> Result<(), RuntimeError> {
-        for stmt in &program.body {
-            self.eval_stmt(stmt)?;
-        }
-        Ok(())
-    }
-
-    fn eval_stmt(&mut self, stmt: &Stmt) -> Result<Option<Value>, RuntimeError> {
-        match stmt {
-            Stmt::VarDecl { name, init } => {
-                let v = self.eval_expr(init)?;
-                self.env.define(name.clone(), v);
-                Ok(None)
-            }
-            Stmt::Assignment { name, value } => {
-                let v = self.eval_expr(value)?;
-                // For assignment, we update existing variable (or create if not exists)
-                self.env.define(name.clone(), v);
-                Ok(None)
-            }
-            Stmt::Expr(expr) => {
-                let _ = self.eval_expr(expr)?;
-                Ok(None)
-            }
-            Stmt::Block(stmts) => {
-                // Create a new scope for the block that chains to the current environment
-                let prev_env = self.env.clone();
-                let block_env = Environment::with_parent(Some(Box::new(prev_env.clone())));
-                let saved_env = std::mem::replace(&mut self.env, block_env);
-                for s in stmts {
-                    match self.eval_stmt(s) {
-                        Ok(Some(v)) => { self.env = saved_env; return Ok(Some(v)); }
-                        Ok(None) => continue,
-                        Err(e) => { self.env = saved_env; return Err(e); }
+            // Vector functions
+            "vec2" => match args {
+                [x, y, ..] => match (as_number(x), as_number(y)) {
+                    (Some(x), Some(y)) => Ok(Value::Vec2(x, y)),
+                    _ => Err(RuntimeError::new("vec2() requires numeric arguments")),
+                },
+                _ => Err(RuntimeError::new("vec2() requires 2 arguments")),
+            },
+            "vec3" => match args {
+                [x, y, z, ..] => match (as_number(x), as_number(y), as_number(z)) {
+                    (Some(x), Some(y), Some(z)) => Ok(Value::Vec3(x, y, z)),
+                    _ => Err(RuntimeError::new("vec3() requires numeric arguments")),
+                },
+                _ => Err(RuntimeError::new("vec3() requires 3 arguments")),
+            },
+            "length" => match args.first() {
+                Some(Value::Vec2(x, y)) => Ok(Value::Float(x.hypot(*y))),
+                Some(Value::Vec3(x, y, z)) => Ok(Value::Float((x * x + y * y + z * z).sqrt())),
+                _ => Err(RuntimeError::new("length() requires a vector argument")),
+            },
+            "normalize" => match args.first() {
+                Some(v @ (Value::Vec2(..) | Value::Vec3(..))) => Ok(vec_normalize(v)),
+                _ => Err(RuntimeError::new("normalize() requires a vector argument")),
+            },
+            "dot" => match args {
+                [Value::Vec2(ax, ay), Value::Vec2(bx, by), ..] => Ok(Value::Float(ax * bx + ay * by)),
+                [Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz), ..] => {
+                    Ok(Value::Float(ax * bx + ay * by + az * bz))
+                }
+                _ => Err(RuntimeError::new("dot() requires two vectors of the same size")),
+            },
+            "cross" => match args {
+                // The 2D cross product is the z component of the 3D one
+                [Value::Vec2(ax, ay), Value::Vec2(bx, by), ..] => Ok(Value::Float(ax * by - ay * bx)),
+                [Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz), ..] => Ok(Value::Vec3(
+                    ay * bz - az * by,
+                    az * bx - ax * bz,
+                    ax * by - ay * bx,
+                )),
+                _ => Err(RuntimeError::new("cross() requires two vectors of the same size")),
+            },
+            "distance" => match args {
+                [Value::Vec2(ax, ay), Value::Vec2(bx, by), ..] => Ok(Value::Float((bx - ax).hypot(by - ay))),
+                [Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz), ..] => {
+                    let (dx, dy, dz) = (bx - ax, by - ay, bz - az);
+                    Ok(Value::Float((dx * dx + dy * dy + dz * dz).sqrt()))
+                }
+                _ => Err(RuntimeError::new("distance() requires two vectors of the same size")),
+            },
+            "lerp" => {
+                if args.len() < 3 {
+                    return Err(RuntimeError::new("lerp() requires 3 arguments (a, b, t)"));
+                }
+                let t = as_number(&args[2])
+                    .ok_or_else(|| RuntimeError::new("lerp() requires a numeric t"))?;
+                match (&args[0], &args[1]) {
+                    (Value::Vec2(ax, ay), Value::Vec2(bx, by)) => {
+                        Ok(Value::Vec2(ax + (bx - ax) * t, ay + (by - ay) * t))
                     }
+                    (Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz)) => Ok(Value::Vec3(
+                        ax + (bx - ax) * t,
+                        ay + (by - ay) * t,
+                        az + (bz - az) * t,
+                    )),
+                    (a, b) => match (as_number(a), as_number(b)) {
+                        (Some(a), Some(b)) => Ok(Value::Float(a + (b - a) * t)),
+                        _ => Err(RuntimeError::new("lerp() requires two numbers or two vectors of the same size")),
+                    },
                 }
-                // restore previous environment
-                self.env = saved_env;
-                Ok(None)
             }
-            Stmt::If { condition, then_branch, elif_branches, else_branch } => {
-                if self.truthy(&self.eval_expr(condition)?) {
-                    self.eval_stmt(then_branch)?;
-                } else {
-                    let mut handled = false;
-                    for (cond, block) in elif_branches {
-                        if self.truthy(&self.eval_expr(cond)?) {
-                            self.eval_stmt(block)?;
-                            handled = true;
-                            break;
-                        }
-                    }
-                    if !handled {
-                        if let Some(else_b) = else_branch {
-                            self.eval_stmt(else_b)?;
-                        }
-                    }
+            "rotate" => match args {
+                [Value::Vec2(x, y), angle, ..] => {
+                    let angle = as_number(angle)
+                        .ok_or_else(|| RuntimeError::new("rotate() requires a numeric angle"))?;
+                    let (sin, cos) = angle.sin_cos();
+                    Ok(Value::Vec2(x * cos - y * sin, x * sin + y * cos))
                 }
-                Ok(None)
+                [v @ Value::Vec3(..), axis @ Value::Vec3(..), angle, ..] => {
+                    let angle = as_number(angle)
+                        .ok_or_else(|| RuntimeError::new("rotate() requires a numeric angle"))?;
+                    Ok(vec3_rotate(v, axis, angle))
+                }
+                _ => Err(RuntimeError::new("rotate() requires (vec2, angle) or (vec3, axis, angle)")),
+            },
+            "angle" => match args {
+                [Value::Vec2(ax, ay), Value::Vec2(bx, by), ..] => {
+                    // Signed angle from a to b
+                    Ok(Value::Float((ax * by - ay * bx).atan2(ax * bx + ay * by)))
+                }
+                [a @ Value::Vec3(..), b @ Value::Vec3(..), ..] => Ok(Value::Float(vec3_angle_between(a, b))),
+                [Value::Vec2(x, y)] => Ok(Value::Float(y.atan2(*x))),
+                _ => Err(RuntimeError::new("angle() requires (vec2), (vec2, vec2) or (vec3, vec3)")),
+            },
+            // Timer functions
+            "after" => self.schedule_timer(name, TimerClock::Seconds, false, args),
+            "every" => self.schedule_timer(name, TimerClock::Seconds, true, args),
+            "after_frames" => self.schedule_timer(name, TimerClock::Frames, false, args),
+            "every_frames" => self.schedule_timer(name, TimerClock::Frames, true, args),
+            "cancel" => match args.first() {
+                Some(Value::Int(handle)) => Ok(Value::Bool(self.scheduler().cancel(*handle))),
+                _ => Err(RuntimeError::new("cancel() requires a timer handle")),
+            },
+            // Errors and assertions
+            "error" => {
+                let value = args.first().cloned().unwrap_or(Value::Nil);
+                Err(self.thrown(value, None)?)
             }
-            Stmt::While { condition, body } => {
-                while self.truthy(&self.eval_expr(condition)?) {
-                    match self.eval_stmt(body)? {
-                        Some(Value::Nil) if matches!(body.as_ref(), Stmt::Break) => break,
-                        Some(v) => return Ok(Some(v)), // return from function
-                        None => continue,
-                    }
+            "assert" => {
+                let ok = args.first().is_some_and(|cond| self.truthy(cond));
+                if ok {
+                    return Ok(Value::Nil);
                 }
-                Ok(None)
+                let message = match args.get(1) {
+                    Some(Value::Nil) | None => "assertion failed".to_string(),
+                    Some(msg) => format!("assertion failed: {}", self.value_to_string(msg)?),
+                };
+                Err(RuntimeError::with_kind(ErrorKind::Assertion, &message))
             }
-            Stmt::For { var_name, start, end, step, body } => {
-                let start_val = self.eval_expr(start)?;
-                let end_val = self.eval_expr(end)?;
-                let step_val = if let Some(step_expr) = step {
+            "assert_eq" => {
+                if args.len() < 2 {
+                    return Err(RuntimeError::new("assert_eq() requires 2 arguments"));
+                }
+                let (left, right) = (&args[0], &args[1]);
+                if left == right {
+                    return Ok(Value::Nil);
+                }
+                let mut message = format!("assert_eq failed: {} != {}", left.repr(), right.repr());
+                if let Some(msg) = args.get(2).filter(|msg| !matches!(msg, Value::Nil)) {
+                    message = format!("{}: {}", message, self.value_to_string(msg)?);
+                }
+                // Keep both sides so test runners and catch blocks can show a diff
+                let mut err = RuntimeError::with_kind(ErrorKind::Assertion, &message);
+                err.value = Some(Value::Table(HashMap::from([
+                    ("message".to_string(), Value::String(message.clone())),
+                    ("left".to_string(), left.clone()),
+                    ("right".to_string(), right.clone()),
+                ])));
+                Err(err)
+            }
+            // Coroutine functions
+            "coroutine" => match args.first() {
+                Some(function) if is_callable(function) => self.spawn_coroutine(function.clone()),
+                _ => Err(RuntimeError::new("coroutine() requires a function")),
+            },
+            "resume" => match args.split_first() {
+                Some((Value::Coroutine(co), rest)) => {
+                    self.resume_coroutine(co, CoroutineStatus::Suspended, rest.to_vec())
+                }
+                _ => Err(RuntimeError::new("resume() requires a coroutine")),
+            },
+            "status" => match args.first() {
+                Some(Value::Coroutine(co)) => Ok(Value::String(co.status().as_str().to_string())),
+                _ => Err(RuntimeError::new("status() requires a coroutine")),
+            },
+            "yield" => {
+                let link = self.coroutine.as_ref()
+                    .ok_or_else(|| RuntimeError::new("yield() can only be called inside a coroutine"))?;
+                let value = args.first().cloned().unwrap_or(Value::Nil);
+                let resumed = link.suspend(CoroutineEvent::Yielded(value))?;
+                Ok(resumed.into_iter().next().unwrap_or(Value::Nil))
+            }
+            "wait" | "wait_frames" => {
+                let link = self.coroutine.as_ref()
+                    .ok_or_else(|| RuntimeError::new(&format!("{}() can only be called inside a coroutine", name)))?;
+                let wait = match (name, args.first()) {
+                    ("wait", Some(seconds @ (Value::Int(_) | Value::Float(_)))) => {
+                        (TimerClock::Seconds, as_number(seconds).unwrap_or_default())
+                    }
+                    ("wait_frames", Some(Value::Int(frames))) => (TimerClock::Frames, *frames as f64),
+                    ("wait", _) => return Err(RuntimeError::new("wait() requires a number of seconds")),
+                    _ => return Err(RuntimeError::new("wait_frames() requires an integer frame count")),
+                };
+                link.suspend(CoroutineEvent::Waiting(wait.0, wait.1.max(0.0)))?;
+                Ok(Value::Nil)
+            }
+            "input.pressed" | "input.held" | "input.released" => {
+                let query = &name["input.".len()..];
+                let button = match args.first() {
+                    Some(Value::String(button)) => button,
+                    _ => return Err(RuntimeError::new(&format!("input.{}() requires an action or button name", query))),
+                };
+                let input = self.input();
+                Ok(Value::Bool(match query {
+                    "pressed" => input.pressed(button),
+                    "held" => input.held(button),
+                    _ => input.released(button),
+                }))
+            }
+            "input.axis" => match args {
+                // Digital axis from two buttons: -1 for negative, 1 for positive, 0 for both or neither
+                [Value::String(negative), Value::String(positive), ..] => {
+                    let input = self.input();
+                    let value = input.held(positive) as i64 - input.held(negative) as i64;
+                    Ok(Value::Float(value as f64))
+                }
+                [Value::String(axis)] => Ok(Value::Float(self.input().axis(axis))),
+                _ => Err(RuntimeError::new("input.axis() requires an axis name or two button names")),
+            },
+            "input.mouse" => {
+                let (x, y) = self.input().mouse();
+                Ok(Value::Vec2(x, y))
+            }
+            "input.bind" => match args {
+                [Value::String(action), buttons @ ..] if !buttons.is_empty() => {
+                    let mut input = self.input();
+                    for button in buttons {
+                        match button {
+                            Value::String(button) => input.bind(action, button),
+                            _ => return Err(RuntimeError::new("input.bind() requires button names")),
+                        }
+                    }
+                    Ok(Value::Nil)
+                }
+                _ => Err(RuntimeError::new("input.bind() requires an action name and at least one button")),
+            },
+            "input.unbind" => match args.first() {
+                Some(Value::String(action)) => {
+                    self.input().unbind(action);
+                    Ok(Value::Nil)
+                }
+                _ => Err(RuntimeError::new("input.unbind() requires an action name")),
+            },
+            _ if name.starts_with("string.") => stdlib::string::call(self, &name["string.".len()..], args),
+            _ if name.starts_with("random.") => stdlib::random::call(self, &name["random.".len()..], args),
+            _ if name.starts_with("table.") => stdlib::table::call(self, &name["table.".len()..], args),
+            _ if name.starts_with("json.") => stdlib::json::call(&name["json.".len()..], args),
+            _ if stdlib::math::FUNCTIONS.contains(&name) => stdlib::math::call(name, args),
+            _ => Err(RuntimeError::new(&format!("unknown built-in function: {}", name))),
+        }
+    }
+
+    fn schedule_timer(
+        &mut self,
+        name: &str,
+        clock: TimerClock,
+        repeat: bool,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let (delay, callback) = match args {
+            [delay, callback, ..] => (delay, callback),
+            _ => return Err(RuntimeError::new(&format!("{}() requires 2 arguments (delay, function)", name))),
+        };
+        let delay = match (clock, delay) {
+            (TimerClock::Seconds, Value::Int(_) | Value::Float(_)) => as_number(delay).unwrap_or_default(),
+            (TimerClock::Frames, Value::Int(frames)) => *frames as f64,
+            (TimerClock::Seconds, _) => return Err(RuntimeError::new(&format!("{}() requires a numeric delay", name))),
+            (TimerClock::Frames, _) => return Err(RuntimeError::new(&format!("{}() requires an integer frame count", name))),
+        };
+        // A repeating timer with no interval would fire forever within one tick
+        if delay < 0.0 || (repeat && delay <= 0.0) {
+            return Err(RuntimeError::new(&format!("{}() requires a positive delay", name)));
+        }
+        if !is_callable(callback) && self.metamethod(callback, "__call").is_none() {
+            return Err(RuntimeError::new(&format!("{}() requires a function", name)));
+        }
+        Ok(Value::Int(self.scheduler().schedule(clock, delay, repeat, callback.clone())))
+    }
+
+    fn scheduler(&self) -> MutexGuard<'_, Scheduler> {
+        self.scheduler.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn rng(&self) -> MutexGuard<'_, Rng> {
+        self.rng.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Reseeds the generator behind the `random` namespace, e.g. before
+    /// replaying a recorded session.
+    pub fn seed_random(&self, seed: u64) {
+        self.rng().seed(seed);
+    }
+
+    /// Input state the host pushes button, axis and mouse changes into.
+    /// Press/release edges last until the end of the next `tick`.
+    pub fn input(&self) -> MutexGuard<'_, InputState> {
+        self.input.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn spawn_coroutine(&mut self, function: Value) -> Result<Value, RuntimeError> {
+        // The body runs in its own interpreter that shares this one's scheduler
+        let mut runner = Interpreter {
+            env: self.env.clone(),
+            scheduler: Arc::clone(&self.scheduler),
+            input: Arc::clone(&self.input),
+            limits: self.limits,
+            budget: Arc::clone(&self.budget),
+            depth: 0,
+            frames: Vec::new(),
+            memory: Arc::clone(&self.memory),
+            memory_id: self.memory.register(),
+            sandbox: self.sandbox.clone(),
+            rng: Arc::clone(&self.rng),
+            coroutine: None,
+            debug_hook: None,
+            call_sites: Vec::new(),
+            line: 0,
+            print_handler: self.print_handler.clone(),
+            profiler: None,
+            handlers: Vec::new(),
+        };
+        let co = Coroutine::spawn(move |link, args| {
+            runner.coroutine = Some(link);
+            runner.call_value(function, args)
+        })?;
+        Ok(Value::Coroutine(co))
+    }
+
+    /// Resumes `co` until its next yield, wait or return. Waiting coroutines
+    /// are handed to the scheduler, which wakes them from `tick`.
+    fn resume_coroutine(
+        &mut self,
+        co: &Arc<Coroutine>,
+        expected: CoroutineStatus,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        // The body runs unprofiled in its own interpreter, so its time is
+        // counted under <coroutine> rather than as the resumer's own
+        self.profile_enter(profiler::COROUTINE);
+        let event = co.resume(expected, args);
+        self.profile_exit();
+        match event? {
+            CoroutineEvent::Yielded(value) => Ok(value),
+            CoroutineEvent::Waiting(clock, delay) => {
+                self.scheduler().schedule(clock, delay, false, Value::Coroutine(Arc::clone(co)));
+                Ok(Value::Nil)
+            }
+            CoroutineEvent::Finished(result) => result,
+        }
+    }
+
+    pub(crate) fn value_to_string(&mut self, val: &Value) -> Result<String, RuntimeError> {
+        if let Some(handler) = self.metamethod(val, "__tostring") {
+            return match self.call_value(handler, vec![val.clone()])? {
+                Value::String(s) => Ok(s),
+                _ => Err(RuntimeError::new("__tostring must return a string")),
+            };
+        }
+        Ok(match val {
+            Value::Int(i) => i.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::String(s) => s.clone(),
+            Value::Function { .. } => "<function>".to_string(),
+            Value::Table(_) => "<table>".to_string(),
+            Value::Vec2(x, y) => format!("vec2({}, {})", x, y),
+            Value::Vec3(x, y, z) => format!("vec3({}, {}, {})", x, y, z),
+            Value::Coroutine(co) => format!("<coroutine: {}>", co.status().as_str()),
+            Value::BuiltinFunction(name) => format!("<builtin: {}>", name),
+            Value::Nil => "nil".to_string(),
+        })
+    }
+
+    /// Builds the error for a `throw` or `error()`. A thrown table's `message`
+    /// field becomes the error message.
+    fn thrown(&mut self, value: Value, line: Option<usize>) -> Result<RuntimeError, RuntimeError> {
+        let message = match &value {
+            Value::Table(table) => match table.get("message") {
+                Some(Value::String(message)) => message.clone(),
+                _ => self.value_to_string(&value)?,
+            },
+            _ => self.value_to_string(&value)?,
+        };
+        let mut err = RuntimeError::with_kind(ErrorKind::Thrown, &message);
+        err.line = line;
+        err.value = Some(value);
+        Ok(err)
+    }
+
+    /// Looks up a metamethod (e.g. `__add`) defined directly on a table value.
+    fn metamethod(&self, value: &Value, name: &str) -> Option<Value> {
+        match value {
+            Value::Table(map) => map.get(name).filter(|m| is_callable(m)).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Dispatches a binary operator to a table metamethod, returning `None` when
+    /// neither operand defines one.
+    fn binary_metamethod(
+        &mut self,
+        op: &BinaryOp,
+        left: &Value,
+        right: &Value,
+    ) -> Result<Option<Value>, RuntimeError> {
+        use BinaryOp::*;
+        // (metamethod, swap operands, negate result)
+        let (name, swap, negate) = match op {
+            Add => ("__add", false, false),
+            Sub => ("__sub", false, false),
+            Mul => ("__mul", false, false),
+            Div => ("__div", false, false),
+            Mod => ("__mod", false, false),
+            Equal => ("__eq", false, false),
+            NotEqual => ("__eq", false, true),
+            Less => ("__lt", false, false),
+            Greater => ("__lt", true, false),
+            LessEqual => ("__le", false, false),
+            GreaterEqual => ("__le", true, false),
+            And | Or => return Ok(None),
+        };
+        // Equality metamethods only apply when both operands are tables
+        if name == "__eq" && !(matches!(left, Value::Table(_)) && matches!(right, Value::Table(_))) {
+            return Ok(None);
+        }
+
+        let (a, b) = if swap { (right, left) } else { (left, right) };
+        let result = match self.call_metamethod(name, a, b)? {
+            Some(v) => v,
+            // a <= b falls back to not (b < a) when only __lt is defined
+            None if name == "__le" => match self.call_metamethod("__lt", b, a)? {
+                Some(v) => Value::Bool(!self.truthy(&v)),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        match op {
+            Add | Sub | Mul | Div | Mod => Ok(Some(result)),
+            _ => Ok(Some(Value::Bool(self.truthy(&result) != negate))),
+        }
+    }
+
+    /// Calls the named metamethod of `a` (or else `b`) with both operands.
+    fn call_metamethod(&mut self, name: &str, a: &Value, b: &Value) -> Result<Option<Value>, RuntimeError> {
+        match self.metamethod(a, name).or_else(|| self.metamethod(b, name)) {
+            Some(handler) => self.call_value(handler, vec![a.clone(), b.clone()]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads `key` from a table, falling back to its `__index` metamethod
+    /// (a table or a function) when the key is missing.
+    fn table_get(&mut self, table: Value, key: &str) -> Result<Value, RuntimeError> {
+        let fallback = match &table {
+            Value::Table(map) => match map.get(key) {
+                Some(v) => return Ok(v.clone()),
+                None => map.get("__index").cloned(),
+            },
+            _ => return Err(RuntimeError::new("cannot index non-table")),
+        };
+        match fallback {
+            Some(proto @ Value::Table(_)) => self.table_get(proto, key),
+            Some(handler) if is_callable(&handler) => {
+                self.call_value(handler, vec![table, Value::String(key.to_string())])
+            }
+            _ => Ok(Value::Nil),
+        }
+    }
+
+    /// Stores `value` under `key` in the table produced by `target`. Tables are
+    /// held by value, so the updated table is written back to `target` itself.
+    /// Missing keys are routed to a `__newindex` metamethod when one is defined.
+    fn assign_field(&mut self, target: &Expr, key: String, value: Value) -> Result<(), RuntimeError> {
+        let mut map = match self.eval_expr(target)? {
+            Value::Table(map) => map,
+            vector @ (Value::Vec2(..) | Value::Vec3(..)) => {
+                let updated = vec_with_component(vector, &key, &value)?;
+                return self.store(target, updated);
+            }
+            _ => return Err(RuntimeError::new(&format!("cannot assign member '{}' on non-table", key))),
+        };
+        if !map.contains_key(&key) {
+            if let Some(handler) = map.get("__newindex").filter(|h| is_callable(h)).cloned() {
+                self.call_value(handler, vec![Value::Table(map), Value::String(key), value])?;
+                return Ok(());
+            }
+        }
+        let entry = if map.contains_key(&key) { 0 } else { TABLE_ENTRY_BYTES + key.len() };
+        self.charge(entry + value.heap_size())?;
+        map.insert(key, value);
+        self.store(target, Value::Table(map))
+    }
+
+    /// Writes a value back to an assignable expression (variable, member or index).
+    fn store(&mut self, target: &Expr, value: Value) -> Result<(), RuntimeError> {
+        match target {
+            Expr::Ident(name) => {
+                self.env.assign(name, value);
+                Ok(())
+            }
+            Expr::Local { name, slot } => {
+                self.assign_variable(Some(*slot), name, value);
+                Ok(())
+            }
+            Expr::Member { object, field } => self.assign_field(object, field.clone(), value),
+            Expr::Index { object, index } => {
+                let key = self.eval_table_key(index)?;
+                self.assign_field(object, key, value)
+            }
+            _ => Err(RuntimeError::new("invalid assignment target")),
+        }
+    }
+
+    fn eval_table_key(&mut self, index: &Expr) -> Result<String, RuntimeError> {
+        match self.eval_expr(index)? {
+            Value::String(key) => Ok(key),
+            // Positional entries of table literals live under "0", "1", ...
+            Value::Int(i) => Ok(i.to_string()),
+            _ => Err(RuntimeError::new("table index must be a string or integer")),
+        }
+    }
+
+    pub fn eval_program(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        self.budget.start(&self.limits);
+        self.profile_enter(profiler::MAIN);
+        let result = self.eval_top_level(&program.body);
+        self.profile_exit();
+        result.map(|_| ())
+    }
+
+    /// Runs a program like eval_program, returning the value of its last
+    /// statement if that's a bare expression (`1 + 2`, `player.hp`), as the
+    /// REPL echoes it.
+    pub fn eval_program_value(&mut self, program: &Program) -> Result<Option<Value>, RuntimeError> {
+        self.budget.start(&self.limits);
+        let Some((Stmt { kind: StmtKind::Expr(expr), .. }, rest)) = program.body.split_last() else {
+            self.eval_top_level(&program.body)?;
+            return Ok(None);
+        };
+        if !self.eval_top_level(rest)? {
+            return Ok(None);
+        }
+        self.step()?;
+        self.eval_expr(expr).map(Some)
+    }
+
+    /// Evaluates an expression of literals and operators, as the optimizer
+    /// folds them.
+    pub(crate) fn eval_constant(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        self.eval_expr(expr)
+    }
+
+    /// Runs top-level statements; false if a `return` ended the script.
+    fn eval_top_level(&mut self, stmts: &[Stmt]) -> Result<bool, RuntimeError> {
+        for stmt in stmts {
+            match self.eval_stmt(stmt)? {
+                Flow::Break | Flow::Continue => {
+                    return Err(RuntimeError::new("break or continue outside of a loop"));
+                }
+                // A top-level return ends the script
+                Flow::Return(_) => return Ok(false),
+                Flow::Normal => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// Records a new string or table allocation, failing if it would exceed
+    /// the memory cap.
+    fn charge(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.allocated(bytes);
+        }
+        let cap = self.limits.max_memory;
+        match self.memory.allocate(self.memory_id, bytes, cap) {
+            Allocation::Granted => Ok(()),
+            Allocation::Measure => {
+                self.measure_memory();
+                self.memory.allocate(self.memory_id, bytes, None);
+                Ok(())
+            }
+            Allocation::OverLimit => {
+                // The estimate includes garbage; only fail if what's reachable is too big
+                self.measure_memory();
+                match self.memory.allocate(self.memory_id, bytes, cap) {
+                    Allocation::OverLimit => Err(memory_limit_error(cap.unwrap_or_default())),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Fails if a builtin result of `bytes` wouldn't fit under the memory cap,
+    /// so large strings are refused before they're built. Nothing is recorded;
+    /// the result is charged once the builtin returns it.
+    pub(crate) fn reserve_memory(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        let Some(cap) = self.limits.max_memory else {
+            return Ok(());
+        };
+        let fits = |interp: &Self| interp.memory.usage().current.saturating_add(bytes) <= cap;
+        if fits(self) {
+            return Ok(());
+        }
+        self.measure_memory();
+        if fits(self) { Ok(()) } else { Err(memory_limit_error(cap)) }
+    }
+
+    fn measure_memory(&self) {
+        let live = self.env.heap_size() + self.frames.iter().map(Environment::heap_size).sum::<usize>();
+        self.memory.measured(self.memory_id, live);
+    }
+
+    /// Charges one step to the current run's budget.
+    fn step(&self) -> Result<(), RuntimeError> {
+        self.budget.step(&self.limits).map_err(|exceeded| match exceeded {
+            Exceeded::Steps(max) => RuntimeError::with_kind(
+                ErrorKind::StepLimit,
+                &format!("execution step limit exceeded ({} steps)", max),
+            ),
+            Exceeded::Deadline => RuntimeError::with_kind(ErrorKind::Timeout, "execution timed out"),
+            Exceeded::Interrupted => RuntimeError::with_kind(ErrorKind::Interrupted, "execution interrupted"),
+        })
+    }
+
+    fn eval_stmt(&mut self, stmt: &Stmt) -> Result<Flow, RuntimeError> {
+        // Expressions and builtins don't know their line; the innermost
+        // statement fills it in so caught and uncaught errors both report it
+        self.eval_stmt_kind(stmt).map_err(|mut err| {
+            err.line.get_or_insert(stmt.line);
+            err
+        })
+    }
+
+    fn eval_stmt_kind(&mut self, stmt: &Stmt) -> Result<Flow, RuntimeError> {
+        self.step()?;
+        // Blocks only group statements, so the debugger sees what's inside
+        if self.debug_hook.is_some() && !matches!(stmt.kind, StmtKind::Block(_) | StmtKind::Trivia(_)) {
+            self.line = stmt.line;
+            self.debug_event(DebugEvent::Statement { line: stmt.line })?;
+        }
+        match &stmt.kind {
+            StmtKind::VarDecl { name, init } => {
+                let v = self.eval_expr(init)?;
+                self.declare(stmt.slot, name, v);
+                Ok(Flow::Normal)
+            }
+            StmtKind::Assignment { name, value } => {
+                let v = self.eval_expr(value)?;
+                // For assignment, we update existing variable (or create if not exists)
+                self.assign_variable(stmt.slot, name, v);
+                Ok(Flow::Normal)
+            }
+            StmtKind::MemberAssignment { object, field, value } => {
+                let v = self.eval_expr(value)?;
+                self.assign_field(object, field.clone(), v)?;
+                Ok(Flow::Normal)
+            }
+            StmtKind::IndexAssignment { object, index, value } => {
+                let key = self.eval_table_key(index)?;
+                let v = self.eval_expr(value)?;
+                self.assign_field(object, key, v)?;
+                Ok(Flow::Normal)
+            }
+            StmtKind::Expr(expr) => {
+                let _ = self.eval_expr(expr)?;
+                Ok(Flow::Normal)
+            }
+            StmtKind::Block(stmts) => self.in_scope(|interp| interp.eval_stmts(stmts)),
+            StmtKind::If { condition, then_branch, elif_branches, else_branch } => {
+                let v = self.eval_expr(condition)?;
+                if self.truthy(&v) {
+                    return self.eval_stmt(then_branch);
+                }
+                for (cond, block) in elif_branches {
+                    let v = self.eval_expr(cond)?;
+                    if self.truthy(&v) {
+                        return self.eval_stmt(block);
+                    }
+                }
+                match else_branch {
+                    Some(else_b) => self.eval_stmt(else_b),
+                    None => Ok(Flow::Normal),
+                }
+            }
+            StmtKind::While { condition, body } => {
+                loop {
+                    let v = self.eval_expr(condition)?;
+                    if !self.truthy(&v) {
+                        break;
+                    }
+                    match self.eval_stmt(body)? {
+                        Flow::Break => break,
+                        Flow::Return(v) => return Ok(Flow::Return(v)),
+                        Flow::Normal | Flow::Continue => continue,
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+            StmtKind::For { var_name, start, end, step, body } => {
+                let start_val = self.eval_expr(start)?;
+                let end_val = self.eval_expr(end)?;
+                let step_val = if let Some(step_expr) = step {
                     self.eval_expr(step_expr)?
                 } else {
                     Value::Int(1)
@@ -554,116 +1818,132 @@ impl Interpreter {
                 }
                 
                 // Create new scope for the loop
-                let prev_env = self.env.clone();
-                let loop_env = Environment::with_parent(Some(Box::new(prev_env.clone())));
-                let saved_env = std::mem::replace(&mut self.env, loop_env);
-                
-                let mut i = start_num;
-                let result = loop {
+                self.in_scope(|interp| {
+                    let mut i = start_num;
                     // Check loop condition based on step direction
-                    let should_continue = if step_num > 0 {
-                        i <= end_num
-                    } else {
-                        i >= end_num
-                    };
-                    
-                    if !should_continue {
-                        break Ok(None);
-                    }
-                    
-                    // Define/update loop variable
-                    self.env.define(var_name.clone(), Value::Int(i));
-                    
-                    // Execute body
-                    match self.eval_stmt(body)? {
-                        Some(v) if matches!(body.as_ref(), Stmt::Break) => break Ok(None),
-                        Some(v) => break Ok(Some(v)), // return from function
-                        None => {}
+                    while (step_num > 0 && i <= end_num) || (step_num < 0 && i >= end_num) {
+                        // Define/update loop variable, the scope's only slot
+                        interp.env.define_slot(0, var_name, Value::Int(i));
+                        match interp.eval_stmt(body)? {
+                            Flow::Break => break,
+                            Flow::Return(v) => return Ok(Flow::Return(v)),
+                            Flow::Normal | Flow::Continue => {}
+                        }
+                        // Stepping past i64's range means stepping past the end
+                        match i.checked_add(step_num) {
+                            Some(next) => i = next,
+                            None => break,
+                        }
                     }
-                    
-                    i += step_num;
-                };
-                
-                self.env = saved_env;
-                result
+                    Ok(Flow::Normal)
+                })
             }
-            Stmt::Break => {
-                // Break is handled by the loop that contains it
-                Ok(Some(Value::Nil))
+            StmtKind::Break => Ok(Flow::Break),
+            StmtKind::Continue => Ok(Flow::Continue),
+            StmtKind::Return(expr_opt) => {
+                let v = match expr_opt {
+                    Some(e) => self.eval_expr(e)?,
+                    None => Value::Nil,
+                };
+                Ok(Flow::Return(v))
             }
-            Stmt::Continue => {
-                // Continue is handled by the loop that contains it
-                Ok(None)
+            StmtKind::Throw { value } => {
+                let v = self.eval_expr(value)?;
+                Err(self.thrown(v, Some(stmt.line))?)
             }
-            Stmt::Return(expr_opt) => {
-                if let Some(e) = expr_opt {
-                    let v = self.eval_expr(e)?;
-                    Ok(Some(v))
-                } else {
-                    Ok(Some(Value::Nil))
+            StmtKind::Try { body, catch_name, catch_body, finally_body } => {
+                let mut result = self.in_scope(|interp| interp.eval_stmts(body));
+                if let (Err(err), Some(handler)) = (&result, catch_body) {
+                    if err.kind.is_catchable() {
+                        let error = err.to_value();
+                        result = self.in_scope(|interp| {
+                            if let Some(name) = catch_name {
+                                interp.env.define_slot(0, name, error);
+                            }
+                            interp.eval_stmts(handler)
+                        });
+                    }
+                }
+                // finally runs on the way out of the try, unless a limit was hit:
+                // the budget is spent and the host wants control back
+                if let Some(finally) = finally_body {
+                    if !matches!(&result, Err(err) if !err.kind.is_catchable()) {
+                        match self.in_scope(|interp| interp.eval_stmts(finally))? {
+                            Flow::Normal => {}
+                            flow => return Ok(flow),
+                        }
+                    }
                 }
+                result
             }
-            Stmt::FuncDecl(func) => {
+            StmtKind::FuncDecl(func) => {
                 // Capture current environment when defining the function (closure)
-                let closure = Some(Box::new(self.env.clone()));
-                self.env.define(
-                    func.name.clone(),
+                let closure = Some(self.env.capture());
+                self.declare(
+                    stmt.slot,
+                    &func.name,
                     Value::Function {
-                        decl: func.clone(),
+                        decl: Arc::new(func.clone()),
                         closure,
                     },
                 );
-                Ok(None)
+                Ok(Flow::Normal)
             }
-            Stmt::ObjectDecl(obj) => {
+            StmtKind::ObjectDecl(obj) => {
                 // Create a table for the object with its members
                 let mut table = HashMap::new();
                 for member in &obj.members {
                     match member {
                         crate::ast::ObjectMember::Var(var_stmt) => {
-                            if let Stmt::VarDecl { name, init } = var_stmt {
+                            if let StmtKind::VarDecl { name, init } = &var_stmt.kind {
                                 let val = self.eval_expr(init)?;
                                 table.insert(name.clone(), val);
                             }
                         }
                         crate::ast::ObjectMember::Method(func) => {
                             // Methods also capture environment as closures
-                            let closure = Some(Box::new(self.env.clone()));
+                            let closure = Some(self.env.capture());
                             table.insert(
                                 func.name.clone(),
                                 Value::Function {
-                                    decl: func.clone(),
+                                    decl: Arc::new(func.clone()),
                                     closure,
                                 },
                             );
                         }
-                        crate::ast::ObjectMember::Event(_event) => {
-                            // Events not yet implemented; skip for now
+                        crate::ast::ObjectMember::Event(event) => {
+                            self.install_handler(&obj.name, event);
                         }
+                        crate::ast::ObjectMember::Trivia(_) => {}
                     }
                 }
-                self.env.define(obj.name.clone(), Value::Table(table));
-                Ok(None)
+                self.declare(stmt.slot, &obj.name, Value::Table(table));
+                Ok(Flow::Normal)
             }
+            StmtKind::Trivia(_) => Ok(Flow::Normal),
         }
     }
 
     fn eval_expr(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        self.step()?;
         match expr {
             Expr::Literal(lit) => Ok(self.eval_literal(lit)),
-            Expr::Ident(name) => {
-                if let Some(v) = self.env.get(name) {
-                    Ok(v.clone())
-                } else {
-                    Err(RuntimeError::new(&format!("Undefined identifier '{}'", name)))
-                }
-            }
+            Expr::Ident(name) => self.lookup(name),
+            Expr::Local { name, slot } => match self.env.get_slot(*slot, name) {
+                Some(v) => Ok(v.clone()),
+                None => self.lookup(name),
+            },
             Expr::Unary { op, expr } => {
                 let v = self.eval_expr(expr)?;
+                if let (UnaryOp::Negate, Some(handler)) = (op, self.metamethod(&v, "__unm")) {
+                    return self.call_value(handler, vec![v]);
+                }
                 match op {
                     UnaryOp::Negate => match v {
-                        Value::Int(i) => Ok(Value::Int(-i)),
+                        Value::Int(i) => int_result(i.checked_neg()),
                         Value::Float(f) => Ok(Value::Float(-f)),
+                        Value::Vec2(x, y) => Ok(Value::Vec2(-x, -y)),
+                        Value::Vec3(x, y, z) => Ok(Value::Vec3(-x, -y, -z)),
                         _ => Err(RuntimeError::new("type error: unary - on non-number")),
                     },
                     UnaryOp::Not => Ok(Value::Bool(!self.truthy(&v))),
@@ -680,9 +1960,17 @@ impl Interpreter {
             Expr::Member { object, field } => {
                 let obj_val = self.eval_expr(object)?;
                 match obj_val {
-                    Value::Table(map) => {
-                        Ok(map.get(field).cloned().unwrap_or(Value::Nil))
+                    Value::Table(_) => {
+                        let value = self.table_get(obj_val, field)?;
+                        // A namespaced builtin like input.held may have been withheld by the sandbox
+                        if let (Value::Nil, Expr::Ident(namespace)) = (&value, object.as_ref()) {
+                            if let Some(denial) = self.sandbox.denial(&format!("{}.{}", namespace, field)) {
+                                return Err(RuntimeError::new(&denial));
+                            }
+                        }
+                        Ok(value)
                     }
+                    Value::Vec2(..) | Value::Vec3(..) => vec_component(&obj_val, field),
                     _ => Err(RuntimeError::new(&format!("cannot access member '{}' on non-table", field))),
                 }
             }
@@ -690,16 +1978,17 @@ impl Interpreter {
                 let obj_val = self.eval_expr(object)?;
                 let index_val = self.eval_expr(index)?;
                 match (obj_val, index_val) {
-                    (Value::Table(map), Value::String(key)) => {
-                        Ok(map.get(&key).cloned().unwrap_or(Value::Nil))
-                    }
-                    (Value::Table(_), _) => Err(RuntimeError::new("table index must be a string")),
+                    (obj_val @ Value::Table(_), Value::String(key)) => self.table_get(obj_val, &key),
+                    (obj_val @ Value::Table(_), Value::Int(i)) => self.table_get(obj_val, &i.to_string()),
+                    (Value::Table(_), _) => Err(RuntimeError::new("table index must be a string or integer")),
                     _ => Err(RuntimeError::new("cannot index non-table")),
                 }
             }
             Expr::TableLiteral(fields) => {
                 let mut map = HashMap::new();
-                for (idx, field) in fields.iter().enumerate() {
+                // Comments kept for the formatter don't take a position
+                let fields = fields.iter().filter(|field| !matches!(field, crate::ast::TableField::Trivia(_)));
+                for (idx, field) in fields.enumerate() {
                     match field {
                         crate::ast::TableField::KeyValue { key, value } => {
                             let val = self.eval_expr(value)?;
@@ -709,8 +1998,10 @@ impl Interpreter {
                             let val = self.eval_expr(expr)?;
                             map.insert(idx.to_string(), val);
                         }
+                        crate::ast::TableField::Trivia(_) => {}
                     }
                 }
+                self.charge(map.keys().map(|key| TABLE_ENTRY_BYTES + key.len()).sum())?;
                 Ok(Value::Table(map))
             }
         }
@@ -734,18 +2025,21 @@ impl Interpreter {
         }
     }
 
-    fn negate(&self, v: Value) -> Value {
-        match v {
-            Value::Int(i) => Value::Int(-i),
-            Value::Float(f) => Value::Float(-f),
-            _ => Value::Nil,
-        }
-    }
-
-    fn apply_binary(&self, op: &BinaryOp, left: Value, right: Value) -> Result<Value, RuntimeError> {
+    fn apply_binary(&mut self, op: &BinaryOp, left: Value, right: Value) -> Result<Value, RuntimeError> {
         use BinaryOp::*;
+        if matches!(left, Value::Table(_)) || matches!(right, Value::Table(_)) {
+            if let Some(result) = self.binary_metamethod(op, &left, &right)? {
+                return Ok(result);
+            }
+        }
         match op {
-            Add => self.add(left, right),
+            Add => {
+                let result = self.add(left, right)?;
+                if let Value::String(s) = &result {
+                    self.charge(s.len())?;
+                }
+                Ok(result)
+            }
             Sub => self.sub(left, right),
             Mul => self.mul(left, right),
             Div => self.div(left, right),
@@ -760,39 +2054,69 @@ impl Interpreter {
             Or => Ok(self.apply_or(left, right)),
         }
     }
-            GreaterEqual => self.cmp(|a, b| a >= b, left, right),
-            And => Ok(Value::Bool(self.truthy(&left) && self.truthy(&right))),
-            Or => Ok(Value::Bool(self.truthy(&left) || self.truthy(&right))),
+
+    /// `and` gives its left operand if that's falsy, otherwise its right.
+    fn apply_and(&self, left: Value, right: Value) -> Value {
+        if self.truthy(&left) {
+            right
+        } else {
+            left
+        }
+    }
+
+    /// `or` gives its left operand if that's truthy, otherwise its right.
+    fn apply_or(&self, left: Value, right: Value) -> Value {
+        if self.truthy(&left) {
+            left
+        } else {
+            right
         }
     }
 
     fn add(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a + b)),
+            (Value::Int(a), Value::Int(b)) => int_result(a.checked_add(b)),
             (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a + b)),
             (Value::Int(a), Value::Float(b)) => Ok(Value::Float(a as f64 + b)),
             (Value::Float(a), Value::Int(b)) => Ok(Value::Float(a + b as f64)),
             (Value::String(a), Value::String(b)) => Ok(Value::String(a + &b)),
+            (Value::Vec2(ax, ay), Value::Vec2(bx, by)) => Ok(Value::Vec2(ax + bx, ay + by)),
+            (Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz)) => Ok(Value::Vec3(ax + bx, ay + by, az + bz)),
             _ => Err(RuntimeError::new("type error: cannot add the given operands")),
         }
     }
 
     fn sub(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a - b)),
+            (Value::Int(a), Value::Int(b)) => int_result(a.checked_sub(b)),
             (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a - b)),
             (Value::Int(a), Value::Float(b)) => Ok(Value::Float(a as f64 - b)),
             (Value::Float(a), Value::Int(b)) => Ok(Value::Float(a - b as f64)),
+            (Value::Vec2(ax, ay), Value::Vec2(bx, by)) => Ok(Value::Vec2(ax - bx, ay - by)),
+            (Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz)) => Ok(Value::Vec3(ax - bx, ay - by, az - bz)),
             _ => Err(RuntimeError::new("type error: cannot subtract the given operands")),
         }
     }
 
     fn mul(&self, left: Value, right: Value) -> Result<Value, RuntimeError> {
         match (left, right) {
-            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a * b)),
+            (Value::Int(a), Value::Int(b)) => int_result(a.checked_mul(b)),
             (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a * b)),
             (Value::Int(a), Value::Float(b)) => Ok(Value::Float(a as f64 * b)),
             (Value::Float(a), Value::Int(b)) => Ok(Value::Float(a * b as f64)),
+            // Vectors scale by numbers and multiply component-wise with each other
+            (Value::Vec2(x, y), n @ (Value::Int(_) | Value::Float(_)))
+            | (n @ (Value::Int(_) | Value::Float(_)), Value::Vec2(x, y)) => {
+                let n = as_number(&n).unwrap_or_default();
+                Ok(Value::Vec2(x * n, y * n))
+            }
+            (Value::Vec3(x, y, z), n @ (Value::Int(_) | Value::Float(_)))
+            | (n @ (Value::Int(_) | Value::Float(_)), Value::Vec3(x, y, z)) => {
+                let n = as_number(&n).unwrap_or_default();
+                Ok(Value::Vec3(x * n, y * n, z * n))
+            }
+            (Value::Vec2(ax, ay), Value::Vec2(bx, by)) => Ok(Value::Vec2(ax * bx, ay * by)),
+            (Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz)) => Ok(Value::Vec3(ax * bx, ay * by, az * bz)),
             _ => Err(RuntimeError::new("type error: cannot multiply the given operands")),
         }
     }
@@ -803,7 +2127,7 @@ impl Interpreter {
                 if b == 0 {
                     Err(RuntimeError::new("division by zero"))
                 } else {
-                    Ok(Value::Int(a / b))
+                    int_result(a.checked_div(b))
                 }
             }
             (Value::Float(a), Value::Float(b)) => {
@@ -815,6 +2139,24 @@ impl Interpreter {
             (Value::Float(a), Value::Int(b)) => {
                 if b == 0 { Err(RuntimeError::new("division by zero")) } else { Ok(Value::Float(a / b as f64)) }
             }
+            (Value::Vec2(x, y), n @ (Value::Int(_) | Value::Float(_))) => {
+                let n = as_number(&n).unwrap_or_default();
+                if n == 0.0 { Err(RuntimeError::new("division by zero")) } else { Ok(Value::Vec2(x / n, y / n)) }
+            }
+            (Value::Vec3(x, y, z), n @ (Value::Int(_) | Value::Float(_))) => {
+                let n = as_number(&n).unwrap_or_default();
+                if n == 0.0 { Err(RuntimeError::new("division by zero")) } else { Ok(Value::Vec3(x / n, y / n, z / n)) }
+            }
+            (Value::Vec2(ax, ay), Value::Vec2(bx, by)) => {
+                if bx == 0.0 || by == 0.0 { Err(RuntimeError::new("division by zero")) } else { Ok(Value::Vec2(ax / bx, ay / by)) }
+            }
+            (Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz)) => {
+                if bx == 0.0 || by == 0.0 || bz == 0.0 {
+                    Err(RuntimeError::new("division by zero"))
+                } else {
+                    Ok(Value::Vec3(ax / bx, ay / by, az / bz))
+                }
+            }
             _ => Err(RuntimeError::new("type error: cannot divide the given operands")),
         }
     }
@@ -825,7 +2167,7 @@ impl Interpreter {
                 if b == 0 {
                     Err(RuntimeError::new("modulo by zero"))
                 } else {
-                    Ok(Value::Int(a % b))
+                    int_result(a.checked_rem(b))
                 }
             }
             (Value::Float(a), Value::Float(b)) => {
@@ -860,6 +2202,8 @@ impl Interpreter {
             (Value::Bool(a), Value::Bool(b)) => a == b,
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Nil, Value::Nil) => true,
+            (Value::Vec2(ax, ay), Value::Vec2(bx, by)) => ax == bx && ay == by,
+            (Value::Vec3(ax, ay, az), Value::Vec3(bx, by, bz)) => ax == bx && ay == by && az == bz,
             _ => false,
         }
     }
@@ -881,64 +2225,593 @@ impl Interpreter {
     pub fn get_global(&self, name: &str) -> Option<Value> {
         self.env.get(name).cloned()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::lexer::Lexer;
-    use crate::parser::Parser;
+    /// Every name defined in the global scope, sorted: builtins, namespaces
+    /// and whatever scripts or the host have defined.
+    pub fn global_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.env.values.keys().cloned().collect();
+        names.sort();
+        names
+    }
 
-    #[test]
-    fn test_function_sees_global() {
-        let src = r#"
-            var g = 10;
-            func f(): {
-                return g;
-            } end
-            var r = f();
-        "#;
-        let lexer = Lexer::new(src);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program().expect("parse failed");
-        let mut interp = Interpreter::new();
-        interp.eval_program(&program).expect("runtime error");
-        assert_eq!(interp.get_global("r"), Some(Value::Int(10)));
+    /// Limits applied to each run (`eval_program`, `call_global` or `tick`).
+    /// Coroutines created afterwards inherit them.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
     }
 
-    #[test]
-    fn test_block_scoping_shadowing() {
-        let src = r#"
-            var x = 1;
-            {
-                var x = 2;
-            }
-        "#;
-        let lexer = Lexer::new(src);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program().expect("parse failed");
-        let mut interp = Interpreter::new();
-        interp.eval_program(&program).expect("runtime error");
-        assert_eq!(interp.get_global("x"), Some(Value::Int(1)));
+    pub fn limits(&self) -> Limits {
+        self.limits
     }
 
-    #[test]
-    fn test_string_escapes() {
-        let src = r#"var s = "line1\nline2";"#;
-        let lexer = Lexer::new(src);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program().expect("parse failed");
-        let mut interp = Interpreter::new();
-        interp.eval_program(&program).expect("runtime error");
-        assert_eq!(interp.get_global("s"), Some(Value::String("line1\nline2".to_string())));
+    /// Approximate bytes held by script strings and tables, including any
+    /// coroutines. Re-measures this interpreter's environment first.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.measure_memory();
+        self.memory.usage()
     }
 
-    #[test]
-    fn test_division_by_zero_error() {
-        let src = r#"var x = 1 / 0;"#;
-        let lexer = Lexer::new(src);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program().expect("parse failed");
+    /// Starts peak tracking over from the current usage.
+    pub fn reset_memory_peak(&self) {
+        self.measure_memory();
+        self.memory.reset_peak();
+    }
+
+    /// Handle another thread can use to stop the script that is running.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.budget.interrupt.clone()
+    }
+
+    /// Sends what `print` and `println` write to `handler` instead of stdout,
+    /// e.g. to show it in a game console. Applies to coroutines created
+    /// afterwards too.
+    pub fn set_print_handler(&mut self, handler: PrintHandler) {
+        self.print_handler = Some(handler);
+    }
+
+    /// Installs a debugger, called before each statement and when script
+    /// functions are entered and return. Coroutine bodies run in their own
+    /// interpreter without it, so they aren't traced or stopped in.
+    pub fn set_debug_hook(&mut self, hook: Box<dyn DebugHook>) {
+        self.debug_hook = Some(hook);
+    }
+
+    pub fn take_debug_hook(&mut self) -> Option<Box<dyn DebugHook>> {
+        self.debug_hook.take()
+    }
+
+    /// Installs a profiler, which from now on times every script function
+    /// call and counts the strings and tables each one creates. Coroutine
+    /// bodies aren't profiled.
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Opens a profiler frame that isn't a script function, like <main>.
+    fn profile_enter(&mut self, name: &str) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(name, 0);
+        }
+    }
+
+    fn profile_exit(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+    }
+
+    /// Hands `event` to the debug hook, which may pause or stop the run.
+    fn debug_event(&mut self, event: DebugEvent) -> Result<(), RuntimeError> {
+        let Some(mut hook) = self.debug_hook.take() else { return Ok(()) };
+        // Taken out while it runs, so code it evaluates isn't traced
+        let resume = hook.event(self, &event);
+        if self.debug_hook.is_none() {
+            self.debug_hook = Some(hook);
+        }
+        match resume {
+            Resume::Continue => Ok(()),
+            Resume::Stop => Err(RuntimeError::with_kind(ErrorKind::Interrupted, "stopped by the debugger")),
+        }
+    }
+
+    /// Line of the statement running now, or that last ran. Only tracked
+    /// while a debug hook is set.
+    pub fn current_line(&self) -> usize {
+        self.line
+    }
+
+    /// Number of script function calls in progress.
+    pub fn call_depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The calls in progress, innermost first, ending with the top level as
+    /// `<main>`. Only complete for calls made while a debug hook is set.
+    pub fn call_stack(&self) -> Vec<StackFrame> {
+        let mut stack = Vec::with_capacity(self.call_sites.len() + 1);
+        let mut line = self.line;
+        for (name, call_line) in self.call_sites.iter().rev() {
+            stack.push(StackFrame { name: name.clone(), line });
+            line = *call_line;
+        }
+        stack.push(StackFrame { name: "<main>".to_string(), line });
+        stack
+    }
+
+    /// The scope chain of stack frame `frame` (0 is the innermost).
+    fn frame_env(&self, frame: usize) -> Option<&Environment> {
+        match frame {
+            0 => Some(&self.env),
+            _ => self.frames.len().checked_sub(frame).map(|index| &self.frames[index]),
+        }
+    }
+
+    /// Variables visible in a stack frame outside the global scope, sorted
+    /// by name: a function's parameters and locals, or the top level's
+    /// block variables.
+    pub fn frame_locals(&self, frame: usize) -> Vec<(String, Value)> {
+        // Calls bind the function's own name for recursion; that isn't a local
+        let function = self.call_sites.len().checked_sub(frame + 1).map(|i| self.call_sites[i].0.as_str());
+        let mut locals: HashMap<&String, &Value> = HashMap::new();
+        let mut scope = self.frame_env(frame);
+        while let Some(env) = scope {
+            if env.parent.is_none() {
+                break;
+            }
+            // Later slots shadow earlier ones; unnamed slots were skipped over
+            let slots = env.slots.iter().rev().filter(|(name, _)| !name.is_empty()).map(|(name, value)| (name, value));
+            for (name, value) in env.values.iter().chain(slots) {
+                let recursive = Some(name.as_str()) == function && matches!(value, Value::Function { decl, .. } if decl.name == *name);
+                if !recursive {
+                    locals.entry(name).or_insert(value);
+                }
+            }
+            scope = env.parent();
+        }
+        let mut locals: Vec<(String, Value)> = locals.into_iter().map(|(name, value)| (name.clone(), value.clone())).collect();
+        locals.sort_by(|a, b| a.0.cmp(&b.0));
+        locals
+    }
+
+    /// Globals defined by the script or host, sorted by name; builtins are
+    /// left out unless something replaced them.
+    pub fn user_globals(&self) -> Vec<(String, Value)> {
+        let mut env = self.frames.first().unwrap_or(&self.env);
+        while let Some(parent) = env.parent() {
+            env = parent;
+        }
+        let mut globals: Vec<(String, Value)> = env
+            .values
+            .iter()
+            .filter(|(name, value)| !is_builtin_global(name, value))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Evaluates `source` in stack frame `frame` (0 is the innermost): the
+    /// value of a single expression, or nil after running statements, which
+    /// may change variables.
+    pub fn eval_in_frame(&mut self, frame: usize, source: &str) -> Result<Value, RuntimeError> {
+        let mut text = source.trim().to_string();
+        if !text.ends_with(';') && !text.ends_with("end") {
+            text.push(';');
+        }
+        let program = Parser::new(Lexer::new(&text)).parse_program().map_err(|errors| {
+            let message = errors.first().map_or("could not parse", |e| e.message.as_str());
+            RuntimeError::new(message)
+        })?;
+        let index = match frame {
+            0 => None,
+            _ => match self.frames.len().checked_sub(frame) {
+                Some(index) => Some(index),
+                None => return Err(RuntimeError::new(&format!("no stack frame {}", frame))),
+            },
+        };
+        if let Some(index) = index {
+            std::mem::swap(&mut self.env, &mut self.frames[index]);
+        }
+        let result = match program.body.as_slice() {
+            [Stmt { kind: StmtKind::Expr(expr), .. }] => self.eval_expr(expr),
+            stmts => self.eval_stmts(stmts).map(|_| Value::Nil),
+        };
+        if let Some(index) = index {
+            std::mem::swap(&mut self.env, &mut self.frames[index]);
+        }
+        result
+    }
+
+    /// Swaps a changed version of the script in. Functions, object methods
+    /// and `on` handlers are rebuilt in source order, as a fresh run would
+    /// define them, so they capture the current globals. Variables and object
+    /// fields keep their values; only ones the old script didn't have are
+    /// evaluated. Other top-level statements aren't run again.
+    pub fn reload_program(&mut self, program: &Program) -> Result<ReloadReport, RuntimeError> {
+        self.budget.start(&self.limits);
+        let mut report = ReloadReport::default();
+        for stmt in &program.body {
+            match &stmt.kind {
+                StmtKind::FuncDecl(func) => {
+                    let existed = matches!(self.env.get(&func.name), Some(Value::Function { .. }));
+                    self.eval_stmt(stmt)?;
+                    report.record(existed, func.name.clone());
+                }
+                StmtKind::VarDecl { name, .. } if self.env.get(name).is_none() => {
+                    self.eval_stmt(stmt)?;
+                    report.added.push(name.clone());
+                }
+                StmtKind::ObjectDecl(obj) => {
+                    let Some(Value::Table(mut table)) = self.env.get(&obj.name).cloned() else {
+                        self.eval_stmt(stmt)?;
+                        report.added.push(obj.name.clone());
+                        continue;
+                    };
+                    for member in &obj.members {
+                        match member {
+                            crate::ast::ObjectMember::Var(Stmt { kind: StmtKind::VarDecl { name, init }, .. })
+                                if !table.contains_key(name) =>
+                            {
+                                let val = self.eval_expr(init)?;
+                                table.insert(name.clone(), val);
+                                report.added.push(format!("{}.{}", obj.name, name));
+                            }
+                            crate::ast::ObjectMember::Method(func) => {
+                                let existed = matches!(table.get(&func.name), Some(Value::Function { .. }));
+                                let closure = Some(self.env.capture());
+                                table.insert(func.name.clone(), Value::Function { decl: Arc::new(func.clone()), closure });
+                                report.record(existed, format!("{}.{}", obj.name, func.name));
+                            }
+                            crate::ast::ObjectMember::Event(event) => {
+                                let existed = self.install_handler(&obj.name, event);
+                                report.record(existed, format!("{}.on {}", obj.name, event.name));
+                            }
+                            _ => {}
+                        }
+                    }
+                    self.env.define(obj.name.clone(), Value::Table(table));
+                }
+                _ => {}
+            }
+        }
+        Ok(report)
+    }
+
+    /// Saves the script's state: its globals, pending timers, the scheduler
+    /// clock and the random generator. See the snapshot module for the format.
+    pub fn snapshot(&self) -> Result<Snapshot, SnapshotError> {
+        let mut saver = Saver::new();
+        let mut globals = Vec::new();
+        for (name, value) in self.user_globals() {
+            let saved = saver
+                .save(&value, &is_builtin_global)
+                .map_err(|e| SnapshotError::new(format!("global '{}': {}", name, e.message)))?;
+            globals.push((name, saved));
+        }
+        let scheduler = self.scheduler();
+        let mut timers = Vec::new();
+        for timer in scheduler.timers() {
+            let callback = saver
+                .save(&timer.callback, &is_builtin_global)
+                .map_err(|e| SnapshotError::new(format!("timer {}: {}", timer.id, e.message)))?;
+            timers.push(snapshot::saved_timer(timer, callback));
+        }
+        Ok(Snapshot {
+            time: scheduler.time(),
+            frame: scheduler.frame(),
+            next_timer: scheduler.next_handle(),
+            random_state: self.rng().state(),
+            objects: saver.finish(),
+            globals,
+            timers,
+        })
+    }
+
+    /// Puts a snapshot's state back into an interpreter that has run the
+    /// script, whose declarations `program` holds; saved functions get
+    /// today's code. Saved globals replace the current ones and globals the
+    /// save doesn't have (from a newer script) are kept. Nothing changes if
+    /// the snapshot doesn't fit the script.
+    pub fn restore(&mut self, saved: &Snapshot, program: &Program) -> Result<(), SnapshotError> {
+        let mut builtins = Environment::new();
+        for (name, value) in &self.env.values {
+            if is_builtin_global(name, value) {
+                builtins.define(name.clone(), value.clone());
+            }
+        }
+        // A snapshot is data from outside, so it can't hand the script a
+        // builtin the sandbox withholds
+        for name in saved.builtins() {
+            if !self.sandbox.allows(name) {
+                let reason = self.sandbox.denial(name).unwrap_or_else(|| format!("'{}' is not a builtin", name));
+                return Err(SnapshotError::new(reason));
+            }
+        }
+        let objects = snapshot::rebuild_objects(saved, program, &builtins)?;
+        let mut globals = Vec::with_capacity(saved.globals.len());
+        for (name, value) in &saved.globals {
+            globals.push((name.clone(), snapshot::load(value, &objects)?));
+        }
+        let mut timers = Vec::with_capacity(saved.timers.len());
+        for timer in &saved.timers {
+            let callback = snapshot::load(&timer.callback, &objects)?;
+            timers.push(Timer { id: timer.handle, clock: timer.clock, due: timer.due, interval: timer.interval, callback });
+        }
+
+        for (name, value) in globals {
+            self.env.define(name, value);
+        }
+        self.scheduler().restore(saved.time, saved.frame, saved.next_timer, timers);
+        self.seed_random(saved.random_state);
+        Ok(())
+    }
+
+    /// Calls the global function `name`, e.g. a script's `update(dt)` from the
+    /// host's game loop.
+    pub fn call_global(&mut self, name: &str, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        self.budget.start(&self.limits);
+        match self.get_global(name) {
+            Some(callee) => self.call_value(callee, args),
+            None => Err(RuntimeError::new(&format!("Undefined identifier '{}'", name))),
+        }
+    }
+
+    /// Calls every object's `on` handler for `event` with `args`, in the order
+    /// the objects were declared. Returns the number of handlers that ran.
+    pub fn emit(&mut self, event: &str, args: Vec<Value>) -> Result<usize, RuntimeError> {
+        self.budget.start(&self.limits);
+        let handlers: Vec<Value> =
+            self.handlers.iter().filter(|h| h.event == event).map(|h| h.function.clone()).collect();
+        for handler in &handlers {
+            self.call_value(handler.clone(), args.clone())?;
+        }
+        Ok(handlers.len())
+    }
+
+    /// Installs `object`'s handler for `event`, replacing the one it had for
+    /// that event. Returns whether there was one.
+    fn install_handler(&mut self, object: &str, event: &EventDecl) -> bool {
+        let decl = FuncDecl {
+            name: event.name.clone(),
+            params: event.params.clone(),
+            body: event.body.clone(),
+            line: event.line,
+        };
+        let function = Value::Function { decl: Arc::new(decl), closure: Some(self.env.capture()) };
+        match self.handlers.iter_mut().find(|h| h.object == object && h.event == event.name) {
+            Some(handler) => {
+                handler.function = function;
+                true
+            }
+            None => {
+                self.handlers.push(Handler { object: object.to_string(), event: event.name.clone(), function });
+                false
+            }
+        }
+    }
+
+    /// Advances the scheduler clock by `dt` seconds and one frame, then runs
+    /// every timer that became due. Timers created by those callbacks wait for
+    /// the next tick. Returns the number of callbacks that ran.
+    ///
+    /// Ends the input frame: buttons pressed or released before this tick stop
+    /// reporting `pressed`/`released` afterwards.
+    pub fn tick(&mut self, dt: f64) -> Result<usize, RuntimeError> {
+        self.profile_enter(profiler::TICK);
+        let result = self.run_due_timers(dt);
+        self.profile_exit();
+        // Edges are only visible for one frame, even if a callback failed
+        self.input().end_frame();
+        result
+    }
+
+    fn run_due_timers(&mut self, dt: f64) -> Result<usize, RuntimeError> {
+        self.budget.start(&self.limits);
+        self.scheduler().advance(dt);
+        let created_before = self.scheduler().next_handle();
+        let mut ran = 0;
+        loop {
+            // Release the scheduler lock before running script code
+            let next = self.scheduler().pop_due(created_before);
+            match next {
+                Some(Value::Coroutine(co)) if co.status() == CoroutineStatus::Waiting => {
+                    self.resume_coroutine(&co, CoroutineStatus::Waiting, Vec::new())?;
+                }
+                Some(callback) => {
+                    self.call_value(callback, Vec::new())?;
+                }
+                None => break,
+            }
+            ran += 1;
+        }
+        Ok(ran)
+    }
+
+    /// Seconds of scheduler time elapsed across all ticks.
+    pub fn time(&self) -> f64 {
+        self.scheduler().time()
+    }
+
+    /// Number of ticks the host has run.
+    pub fn frame(&self) -> u64 {
+        self.scheduler().frame()
+    }
+
+    pub fn pending_timers(&self) -> usize {
+        self.scheduler().pending()
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.memory.unregister(self.memory_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_function_sees_global() {
+        let src = r#"
+            var g = 10;
+            func f(): {
+                return g;
+            } end
+            var r = f();
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("r"), Some(Value::Int(10)));
+    }
+
+    #[test]
+    fn test_block_scoping_shadowing() {
+        let src = r#"
+            var x = 1;
+            {
+                var x = 2;
+            }
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("x"), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_assignment_updates_outer_variables() {
+        let src = r#"
+            var i = 0;
+            while i < 3 do {
+                i += 1;
+            } end
+            var total = 0;
+            for k = 1, 4 do {
+                total = total + k;
+            } end
+            var x = 1;
+            {
+                x = 2;
+                var inner = 3;
+            }
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("i"), Some(Value::Int(3)));
+        assert_eq!(interp.get_global("total"), Some(Value::Int(10)));
+        assert_eq!(interp.get_global("x"), Some(Value::Int(2)));
+        // Declarations stay inside their block and loop scopes
+        assert_eq!(interp.get_global("inner"), None);
+        assert_eq!(interp.get_global("k"), None);
+    }
+
+    #[test]
+    fn test_control_flow_unwinds_through_nested_blocks() {
+        let src = r#"
+            func find(limit): {
+                var n = 0;
+                while true do {
+                    n += 1;
+                    if n > 2 then {
+                        if n * n > limit then {
+                            return n;
+                        } end
+                    } end
+                } end
+            } end
+            var found = find(20);
+            var skipped = 0;
+            var last = 0;
+            for j = 1, 10 do {
+                if j % 2 == 0 then {
+                    skipped += 1;
+                    continue;
+                } end
+                if j > 6 then {
+                    break;
+                } end
+                last = j;
+            } end
+            return;
+            var unreached = 1;
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("found"), Some(Value::Int(5)));
+        assert_eq!(interp.get_global("skipped"), Some(Value::Int(3)));
+        assert_eq!(interp.get_global("last"), Some(Value::Int(5)));
+        // A top-level return ends the script
+        assert_eq!(interp.get_global("unreached"), None);
+
+        let lexer = Lexer::new("func f(): { break; } end f();");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let err = interp.eval_program(&program).expect_err("expected error");
+        assert_eq!(err.message, "break or continue outside of a loop");
+    }
+
+    #[test]
+    fn test_nested_function_calls_itself() {
+        let src = r#"
+            func outer(): {
+                func fact(n): {
+                    if n <= 1 then {
+                        return 1;
+                    } end
+                    return n * fact(n - 1);
+                } end
+                return fact(5);
+            } end
+            var r = outer();
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("r"), Some(Value::Int(120)));
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let src = r#"var s = "line1\nline2";"#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("s"), Some(Value::String("line1\nline2".to_string())));
+    }
+
+    #[test]
+    fn test_division_by_zero_error() {
+        let src = r#"var x = 1 / 0;"#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
         let mut interp = Interpreter::new();
         let res = interp.eval_program(&program);
         assert!(res.is_err());
@@ -946,6 +2819,36 @@ mod tests {
         assert!(err.message.contains("division by zero"));
     }
 
+    #[test]
+    fn test_integer_overflow_is_an_error() {
+        let overflows = [
+            "var x = 9223372036854775807 + 1;",
+            "var x = -9223372036854775807 - 2;",
+            "var x = 4611686018427387904 * 2;",
+            "var x = (-9223372036854775807 - 1) / -1;",
+            "var x = (-9223372036854775807 - 1) % -1;",
+            "var x = -(-9223372036854775807 - 1);",
+            "var x = abs(-9223372036854775807 - 1);",
+        ];
+        for src in overflows {
+            let lexer = Lexer::new(src);
+            let mut parser = Parser::new(lexer);
+            let program = parser.parse_program().expect("parse failed");
+            let mut interp = Interpreter::new();
+            let err = interp.eval_program(&program).expect_err(src);
+            assert_eq!(err.message, "integer overflow", "{}", src);
+        }
+
+        // A loop ending at the top of the range stops instead of overflowing
+        let src = "var n = 0; for i = 9223372036854775806, 9223372036854775807 do { n += 1; } end";
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("n"), Some(Value::Int(2)));
+    }
+
     #[test]
     fn test_table_literal() {
         let src = r#"var t = {x: 10, y: 20};"#;
@@ -1026,4 +2929,831 @@ mod tests {
         interp.eval_program(&program).expect("runtime error");
         assert_eq!(interp.get_global("result"), Some(Value::Int(15)));
     }
+
+    #[test]
+    fn test_closures_share_scopes_without_seeing_later_writes() {
+        // Closures hold the scope as it was when they were made, and calls
+        // don't keep what they write to it, resolved or not
+        let src = r#"
+            func counter(): {
+                var count = 0;
+                func bump(): {
+                    count = count + 1;
+                    return count;
+                } end
+                count = 100;
+                return bump;
+            } end
+            var bump = counter();
+            var first = bump();
+            var second = bump();
+        "#;
+        for resolved in [false, true] {
+            let mut program = Parser::new(Lexer::new(src)).parse_program().expect("parse failed");
+            if resolved {
+                crate::resolver::resolve(&mut program).expect("resolve failed");
+            }
+            let mut interp = Interpreter::new();
+            interp.eval_program(&program).expect("runtime error");
+            assert_eq!(interp.get_global("first"), Some(Value::Int(1)));
+            assert_eq!(interp.get_global("second"), Some(Value::Int(1)));
+        }
+    }
+
+    #[test]
+    fn test_method_name_refers_to_the_method() {
+        let src = r#"
+            func countdown(self, n): { return "global"; } end
+            object Timer: {
+                func countdown(self, n): {
+                    if n == 0 then { return "done"; } end
+                    return countdown(self, n - 1);
+                } end
+            } end
+            var result = Timer.countdown(Timer, 3);
+        "#;
+        for resolved in [false, true] {
+            let mut program = Parser::new(Lexer::new(src)).parse_program().expect("parse failed");
+            if resolved {
+                crate::resolver::resolve(&mut program).expect("resolve failed");
+            }
+            let mut interp = Interpreter::new();
+            interp.eval_program(&program).expect("runtime error");
+            assert_eq!(interp.get_global("result"), Some(Value::String("done".to_string())));
+        }
+    }
+
+    #[test]
+    fn test_member_and_index_assignment() {
+        let src = r#"
+            var t = {hp: 1, inner: {mp: 2}};
+            t.hp = 5;
+            t.inner.mp += 10;
+            t["name"] = "orc";
+            var hp = t.hp;
+            var mp = t.inner.mp;
+            var name = t.name;
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("hp"), Some(Value::Int(5)));
+        assert_eq!(interp.get_global("mp"), Some(Value::Int(12)));
+        assert_eq!(interp.get_global("name"), Some(Value::String("orc".to_string())));
+    }
+
+    #[test]
+    fn test_arithmetic_metamethods() {
+        let src = r#"
+            func addVec(a, b): {
+                return {x: a.x + b.x, y: a.y + b.y};
+            } end
+            func negVec(a): {
+                return {x: -a.x, y: -a.y};
+            } end
+            var a = {x: 1, y: 2, __add: addVec, __unm: negVec};
+            var b = {x: 3, y: 4};
+            var c = a + b;
+            var d = -a;
+            var cx = c.x;
+            var cy = c.y;
+            var dy = d.y;
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("cx"), Some(Value::Int(4)));
+        assert_eq!(interp.get_global("cy"), Some(Value::Int(6)));
+        assert_eq!(interp.get_global("dy"), Some(Value::Int(-2)));
+    }
+
+    #[test]
+    fn test_comparison_metamethods() {
+        let src = r#"
+            func sameHp(a, b): {
+                return a.hp == b.hp;
+            } end
+            func lessHp(a, b): {
+                return a.hp < b.hp;
+            } end
+            var a = {hp: 10, __eq: sameHp, __lt: lessHp};
+            var b = {hp: 10, __eq: sameHp, __lt: lessHp};
+            var c = {hp: 20, __eq: sameHp, __lt: lessHp};
+            var eq = a == b;
+            var ne = a != c;
+            var lt = a < c;
+            var gt = c > a;
+            var le = a <= b;
+            var ge = a >= c;
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("eq"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("ne"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("lt"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("gt"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("le"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("ge"), Some(Value::Bool(false)));
+    }
+
+    #[test]
+    fn test_index_and_newindex_metamethods() {
+        let src = r#"
+            func ignore(t, key, value): {
+                return nil;
+            } end
+            var defaults = {speed: 5};
+            var t = {name: "orc", __index: defaults, __newindex: ignore};
+            var speed = t.speed;
+            var name = t["name"];
+            var missing = t.armor;
+            t.armor = 3;
+            t.name = "goblin";
+            var armor = t.armor;
+            var renamed = t.name;
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("speed"), Some(Value::Int(5)));
+        assert_eq!(interp.get_global("name"), Some(Value::String("orc".to_string())));
+        assert_eq!(interp.get_global("missing"), Some(Value::Nil));
+        assert_eq!(interp.get_global("armor"), Some(Value::Nil));
+        assert_eq!(interp.get_global("renamed"), Some(Value::String("goblin".to_string())));
+    }
+
+    #[test]
+    fn test_call_and_tostring_metamethods() {
+        let src = r#"
+            func describe(self): {
+                return "Enemy(" + self.name + ")";
+            } end
+            func double(self, n): {
+                return n * 2;
+            } end
+            var e = {name: "orc", __tostring: describe, __call: double};
+            var s = str(e);
+            var r = e(21);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("s"), Some(Value::String("Enemy(orc)".to_string())));
+        assert_eq!(interp.get_global("r"), Some(Value::Int(42)));
+    }
+
+    #[test]
+    fn test_vector_arithmetic_and_members() {
+        let src = r#"
+            var a = vec2(1, 2);
+            var b = vec2(3, 4.5);
+            var sum = a + b;
+            var diff = b - a;
+            var scaled = a * 2;
+            var halved = 2 * vec3(1, 2, 3) / 4;
+            var neg = -a;
+            var same = vec2(1, 2) == a;
+            var x = sum.x;
+            a.y = 10;
+            var ay = a.y;
+            var kind = type(b);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("sum"), Some(Value::Vec2(4.0, 6.5)));
+        assert_eq!(interp.get_global("diff"), Some(Value::Vec2(2.0, 2.5)));
+        assert_eq!(interp.get_global("scaled"), Some(Value::Vec2(2.0, 4.0)));
+        assert_eq!(interp.get_global("halved"), Some(Value::Vec3(0.5, 1.0, 1.5)));
+        assert_eq!(interp.get_global("neg"), Some(Value::Vec2(-1.0, -2.0)));
+        assert_eq!(interp.get_global("same"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("x"), Some(Value::Float(4.0)));
+        assert_eq!(interp.get_global("ay"), Some(Value::Float(10.0)));
+        assert_eq!(interp.get_global("kind"), Some(Value::String("vec2".to_string())));
+    }
+
+    #[test]
+    fn test_vector_builtins() {
+        let src = r#"
+            var len = length(vec2(3, 4));
+            var unit = normalize(vec3(0, 0, 5));
+            var d = dot(vec2(1, 2), vec2(3, 4));
+            var c = cross(vec3(1, 0, 0), vec3(0, 1, 0));
+            var dist = distance(vec2(0, 0), vec2(6, 8));
+            var mid = lerp(vec2(0, 0), vec2(10, 20), 0.5);
+            var n = lerp(0, 10, 0.25);
+            var r = rotate(vec2(1, 0), 0);
+            var heading = angle(vec2(0, 0));
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("len"), Some(Value::Float(5.0)));
+        assert_eq!(interp.get_global("unit"), Some(Value::Vec3(0.0, 0.0, 1.0)));
+        assert_eq!(interp.get_global("d"), Some(Value::Float(11.0)));
+        assert_eq!(interp.get_global("c"), Some(Value::Vec3(0.0, 0.0, 1.0)));
+        assert_eq!(interp.get_global("dist"), Some(Value::Float(10.0)));
+        assert_eq!(interp.get_global("mid"), Some(Value::Vec2(5.0, 10.0)));
+        assert_eq!(interp.get_global("n"), Some(Value::Float(2.5)));
+        assert_eq!(interp.get_global("r"), Some(Value::Vec2(1.0, 0.0)));
+        assert_eq!(interp.get_global("heading"), Some(Value::Float(0.0)));
+    }
+
+    #[test]
+    fn test_vector_rotation_and_angles() {
+        let src = r#"
+            var quarter = 3.141592653589793 / 2;
+            var r2 = rotate(vec2(1, 0), quarter);
+            var r3 = rotate(vec3(1, 0, 0), vec3(0, 0, 1), quarter);
+            var between = angle(vec2(1, 0), vec2(0, 1));
+            var between3 = angle(vec3(1, 0, 0), vec3(0, 0, 2));
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        let quarter = std::f64::consts::FRAC_PI_2;
+        match interp.get_global("r2") {
+            Some(Value::Vec2(x, y)) => assert!(x.abs() < 1e-9 && (y - 1.0).abs() < 1e-9),
+            other => panic!("expected vec2, got {:?}", other),
+        }
+        match interp.get_global("r3") {
+            Some(Value::Vec3(x, y, z)) => assert!(x.abs() < 1e-9 && (y - 1.0).abs() < 1e-9 && z.abs() < 1e-9),
+            other => panic!("expected vec3, got {:?}", other),
+        }
+        assert_eq!(interp.get_global("between"), Some(Value::Float(quarter)));
+        assert_eq!(interp.get_global("between3"), Some(Value::Float(quarter)));
+    }
+
+    #[test]
+    fn test_timers_fire_on_tick() {
+        let src = r#"
+            func noop(): {
+                return nil;
+            } end
+            var once = after(1.0, noop);
+            var repeating = every(0.5, noop);
+            var framed = after_frames(2, noop);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.pending_timers(), 3);
+
+        assert_eq!(interp.tick(0.4).expect("tick failed"), 0);
+        assert_eq!(interp.tick(0.4).expect("tick failed"), 2); // every + after_frames
+        assert_eq!(interp.tick(0.4).expect("tick failed"), 2); // every + after
+        assert_eq!(interp.pending_timers(), 1);
+        assert_eq!(interp.frame(), 3);
+    }
+
+    #[test]
+    fn test_cancel_timer() {
+        let src = r#"
+            func fail(): {
+                return 1 / 0;
+            } end
+            var handle = every(1, fail);
+            var cancelled = cancel(handle);
+            var again = cancel(handle);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("cancelled"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("again"), Some(Value::Bool(false)));
+        assert_eq!(interp.tick(2.0).expect("tick failed"), 0);
+    }
+
+    #[test]
+    fn test_timer_callback_error_surfaces_from_tick() {
+        let src = r#"
+            func fail(): {
+                return 1 / 0;
+            } end
+            after(0.5, fail);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        let err = interp.tick(1.0).expect_err("expected callback error");
+        assert!(err.message.contains("division by zero"));
+        assert_eq!(interp.pending_timers(), 0);
+    }
+
+    #[test]
+    fn test_coroutine_yield_and_resume() {
+        let src = r#"
+            func counter(start): {
+                var next = yield(start + 1);
+                var last = yield(next * 2);
+                return last + 100;
+            } end
+            var co = coroutine(counter);
+            var r1 = resume(co, 1);
+            var s1 = status(co);
+            var r2 = resume(co, 5);
+            var r3 = co(7);
+            var s3 = status(co);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("r1"), Some(Value::Int(2)));
+        assert_eq!(interp.get_global("s1"), Some(Value::String("suspended".to_string())));
+        assert_eq!(interp.get_global("r2"), Some(Value::Int(10)));
+        assert_eq!(interp.get_global("r3"), Some(Value::Int(107)));
+        assert_eq!(interp.get_global("s3"), Some(Value::String("dead".to_string())));
+    }
+
+    #[test]
+    fn test_coroutine_wait_resumes_on_tick() {
+        let src = r#"
+            func cutscene(): {
+                wait(1.0);
+                wait_frames(2);
+                return nil;
+            } end
+            var co = coroutine(cutscene);
+            resume(co);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        let co = match interp.get_global("co") {
+            Some(Value::Coroutine(co)) => co,
+            other => panic!("expected coroutine, got {:?}", other),
+        };
+        assert_eq!(co.status(), CoroutineStatus::Waiting);
+
+        interp.tick(0.6).expect("tick failed");
+        assert_eq!(co.status(), CoroutineStatus::Waiting);
+        assert_eq!(interp.tick(0.6).expect("tick failed"), 1);
+        assert_eq!(co.status(), CoroutineStatus::Waiting); // now waiting on frames
+        interp.tick(0.1).expect("tick failed");
+        assert_eq!(co.status(), CoroutineStatus::Waiting);
+        interp.tick(0.1).expect("tick failed");
+        assert_eq!(co.status(), CoroutineStatus::Dead);
+        assert_eq!(interp.pending_timers(), 0);
+    }
+
+    #[test]
+    fn test_coroutine_errors() {
+        let src = r#"
+            func quick(): {
+                return 1;
+            } end
+            var co = coroutine(quick);
+            resume(co);
+            resume(co);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        let err = interp.eval_program(&program).expect_err("expected error");
+        assert!(err.message.contains("dead coroutine"));
+
+        let lexer = Lexer::new("wait(1);");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let err = interp.eval_program(&program).expect_err("expected error");
+        assert!(err.message.contains("inside a coroutine"));
+    }
+
+    #[test]
+    fn test_dropped_coroutine_unwinds_through_catch() {
+        let src = r#"
+            func stubborn(): {
+                while true do {
+                    try {
+                        yield(1);
+                    } catch e {
+                        println("caught", e.kind);
+                    } end
+                } end
+            } end
+            var co = coroutine(stubborn);
+            resume(co);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let output = Arc::new(Mutex::new(String::new()));
+        let sink = Arc::clone(&output);
+        let mut interp = Interpreter::new();
+        interp.set_print_handler(Arc::new(move |text: &str| sink.lock().unwrap().push_str(text)));
+        interp.eval_program(&program).expect("runtime error");
+
+        // Dropping the last reference ends the body instead of letting its
+        // catch swallow the error and loop on the coroutine's thread
+        let lexer = Lexer::new("co = nil;");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        interp.eval_program(&program).expect("runtime error");
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(output.lock().unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn test_input_fed_by_host() {
+        let src = r#"
+            input.bind("jump", "space", "pad_a");
+            func update(): {
+                return input.pressed("jump");
+            } end
+            func steer(): {
+                return vec2(input.axis("left", "right"), input.axis("throttle"));
+            } end
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+
+        assert_eq!(interp.call_global("update", vec![]).unwrap(), Value::Bool(false));
+        interp.input().press("pad_a");
+        assert_eq!(interp.call_global("update", vec![]).unwrap(), Value::Bool(true));
+        interp.tick(0.016).expect("tick failed");
+        // Still held, but the press edge belonged to the previous frame
+        assert_eq!(interp.call_global("update", vec![]).unwrap(), Value::Bool(false));
+
+        interp.input().press("right");
+        interp.input().set_axis("throttle", 2.0);
+        assert_eq!(interp.call_global("steer", vec![]).unwrap(), Value::Vec2(1.0, 1.0));
+    }
+
+    #[test]
+    fn test_input_queries() {
+        let mut interp = Interpreter::new();
+        interp.input().press("space");
+        interp.input().set_mouse(320.0, 240.0);
+        let src = r#"
+            var down = input.held("space");
+            var up = input.released("space");
+            var cursor = input.mouse();
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("down"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("up"), Some(Value::Bool(false)));
+        assert_eq!(interp.get_global("cursor"), Some(Value::Vec2(320.0, 240.0)));
+
+        let lexer = Lexer::new("input.pressed(42);");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let err = interp.eval_program(&program).expect_err("expected error");
+        assert!(err.message.contains("action or button name"));
+    }
+
+    #[test]
+    fn test_recursion_and_loop_control_flow() {
+        let src = r#"
+            func fibonacci(n): {
+                if n <= 1 then {
+                    return n;
+                } end
+                return fibonacci(n - 1) + fibonacci(n - 2);
+            } end
+            func firstOver(limit): {
+                for i = 1, 100 do {
+                    if i * i > limit then {
+                        return i;
+                    } end
+                } end
+                return nil;
+            } end
+            var fib = fibonacci(10);
+            var over = firstOver(50);
+            var count = 0;
+            var odds = 0;
+            while true do {
+                count += 1;
+                if count > 10 then {
+                    break;
+                } end
+                if count % 2 == 0 then {
+                    continue;
+                } end
+                odds += 1;
+            } end
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("fib"), Some(Value::Int(55)));
+        assert_eq!(interp.get_global("over"), Some(Value::Int(8)));
+        assert_eq!(interp.get_global("count"), Some(Value::Int(11)));
+        assert_eq!(interp.get_global("odds"), Some(Value::Int(5)));
+    }
+
+    #[test]
+    fn test_step_and_depth_limits() {
+        let lexer = Lexer::new("while true do { } end");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits { max_steps: Some(10_000), ..Limits::default() });
+        let err = interp.eval_program(&program).expect_err("expected step limit");
+        assert_eq!(err.kind, ErrorKind::StepLimit);
+
+        let src = r#"
+            func forever(n): {
+                return forever(n + 1);
+            } end
+            func shallow(n): {
+                return n;
+            } end
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        // Test threads have small stacks, so keep the depth low
+        interp.set_limits(Limits { max_call_depth: Some(20), ..Limits::default() });
+        interp.eval_program(&program).expect("runtime error");
+        let err = interp.call_global("forever", vec![Value::Int(0)]).expect_err("expected depth limit");
+        assert_eq!(err.kind, ErrorKind::CallDepth);
+        // The interpreter is still usable after hitting a limit
+        assert_eq!(interp.call_global("shallow", vec![Value::Int(3)]).unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn test_timeout_and_interrupt() {
+        let lexer = Lexer::new("while true do { } end");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits { timeout: Some(std::time::Duration::from_millis(20)), ..Limits::default() });
+        let err = interp.eval_program(&program).expect_err("expected timeout");
+        assert_eq!(err.kind, ErrorKind::Timeout);
+
+        interp.set_limits(Limits::default());
+        let handle = interp.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            handle.interrupt();
+        });
+        let err = interp.eval_program(&program).expect_err("expected interrupt");
+        assert_eq!(err.kind, ErrorKind::Interrupted);
+        interrupter.join().unwrap();
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let src = r#"
+            var s = "ab";
+            while true do {
+                s = s + s;
+            } end
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits { max_memory: Some(1 << 20), ..Limits::default() });
+        let err = interp.eval_program(&program).expect_err("expected memory limit");
+        assert_eq!(err.kind, ErrorKind::MemoryLimit);
+
+        let src = r#"
+            var t = {};
+            var i = 0;
+            while true do {
+                t["key" + str(i)] = i;
+                i += 1;
+            } end
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits { max_memory: Some(64 * 1024), ..Limits::default() });
+        let err = interp.eval_program(&program).expect_err("expected memory limit");
+        assert_eq!(err.kind, ErrorKind::MemoryLimit);
+    }
+
+    #[test]
+    fn test_memory_usage_ignores_garbage() {
+        let src = r#"
+            var i = 0;
+            while i < 2000 do {
+                var temp = "temporary string number " + str(i);
+                i += 1;
+            } end
+            var kept = "x" + "yz";
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        let baseline = interp.memory_usage().current;
+        // Far less than the 2000 temporary strings add up to
+        interp.set_limits(Limits { max_memory: Some(baseline + 16 * 1024), ..Limits::default() });
+        interp.eval_program(&program).expect("runtime error");
+
+        let usage = interp.memory_usage();
+        assert!(usage.current >= baseline + 3);
+        assert!(usage.current < baseline + 1024);
+        assert!(usage.peak >= usage.current);
+    }
+
+    #[test]
+    fn test_sandbox_capabilities() {
+        use crate::sandbox::Capability;
+
+        let sandbox = Sandbox::new(&[Capability::Pure, Capability::Host]).deny("input.mouse");
+        let mut interp = Interpreter::with_sandbox(sandbox);
+        let run = |interp: &mut Interpreter, src: &str| {
+            let lexer = Lexer::new(src);
+            let mut parser = Parser::new(lexer);
+            let program = parser.parse_program().expect("parse failed");
+            interp.eval_program(&program)
+        };
+
+        run(&mut interp, "var root = sqrt(16); var down = input.held(\"space\");").expect("runtime error");
+        assert_eq!(interp.get_global("root"), Some(Value::Float(4.0)));
+
+        let err = run(&mut interp, "println(\"hi\");").expect_err("expected capability error");
+        assert_eq!(err.message, "capability not granted: 'println' requires 'io'");
+        let err = run(&mut interp, "var at = input.mouse();").expect_err("expected capability error");
+        assert_eq!(err.message, "capability not granted: 'input.mouse' is denied by the host");
+        let err = run(&mut interp, "missing();").expect_err("expected undefined error");
+        assert!(err.message.contains("Undefined identifier"));
+
+        // A withheld builtin handed in by the host still can't run
+        run(&mut interp, "func apply(f): { return f(\"hi\"); } end").expect("runtime error");
+        let err = interp
+            .call_global("apply", vec![Value::BuiltinFunction("println".to_string())])
+            .expect_err("expected capability error");
+        assert_eq!(err.message, "capability not granted: 'println' requires 'io'");
+        assert_eq!(
+            interp.call_global("apply", vec![Value::BuiltinFunction("string.toUpper".to_string())]).unwrap(),
+            Value::String("HI".to_string())
+        );
+    }
+
+    #[test]
+    fn test_try_catch_and_finally() {
+        let src = r#"
+            var log = "";
+            var runtime_kind = nil;
+            var thrown_kind = nil;
+            var thrown_value = nil;
+            var code = nil;
+            try {
+                var x = 1 / 0;
+                log = log + "unreachable";
+            } catch e {
+                log = log + "caught;";
+                runtime_kind = e.kind;
+            } finally {
+                log = log + "finally;";
+            } end
+
+            var thrown_line = nil;
+            try {
+                throw "out of mana";
+            } catch e {
+                thrown_kind = e.kind;
+                thrown_value = e.value;
+                thrown_line = e.line;
+            } end
+
+            try {
+                error({message: "bad spawn", code: 7});
+            } catch e {
+                code = e.code;
+            } end
+
+            func risky(): {
+                try {
+                    return "from try";
+                } finally {
+                    log = log + "cleanup;";
+                } end
+            } end
+            var returned = risky();
+
+            var rethrown = "";
+            try {
+                try {
+                    throw "inner";
+                } catch e {
+                    throw e;
+                } end
+            } catch outer {
+                rethrown = outer.message;
+            } end
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        let string = |s: &str| Some(Value::String(s.to_string()));
+        assert_eq!(interp.get_global("log"), string("caught;finally;"));
+        assert_eq!(interp.get_global("runtime_kind"), string("runtime"));
+        assert_eq!(interp.get_global("thrown_kind"), string("thrown"));
+        assert_eq!(interp.get_global("thrown_value"), string("out of mana"));
+        assert_eq!(interp.get_global("thrown_line"), Some(Value::Int(19)));
+        assert_eq!(interp.get_global("code"), Some(Value::Int(7)));
+        assert_eq!(interp.get_global("returned"), string("from try"));
+        assert_eq!(interp.get_global("rethrown"), string("inner"));
+    }
+
+    #[test]
+    fn test_caught_runtime_errors_report_their_line() {
+        let src = r#"
+            var x = 0;
+            var direct = nil;
+            try {
+                println(1 / x);
+            } catch e {
+                direct = e.line;
+            } end
+            func divide(a): {
+                return a / x;
+            } end
+            var nested = nil;
+            try {
+                divide(1);
+            } catch e {
+                nested = e.line;
+            } end
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).expect("runtime error");
+        assert_eq!(interp.get_global("direct"), Some(Value::Int(5)));
+        // The line of the statement that failed, not of the call
+        assert_eq!(interp.get_global("nested"), Some(Value::Int(10)));
+    }
+
+    #[test]
+    fn test_uncaught_throws_and_limits() {
+        let run = |interp: &mut Interpreter, src: &str| {
+            let lexer = Lexer::new(src);
+            let mut parser = Parser::new(lexer);
+            let program = parser.parse_program().expect("parse failed");
+            interp.eval_program(&program)
+        };
+        let mut interp = Interpreter::new();
+
+        let err = run(&mut interp, "\nthrow {message: \"no save slot\", slot: 3};").expect_err("expected throw");
+        assert_eq!(err.kind, ErrorKind::Thrown);
+        assert_eq!(err.message, "no save slot");
+        assert_eq!(err.line, Some(2));
+        match err.value {
+            Some(Value::Table(table)) => assert_eq!(table.get("slot"), Some(&Value::Int(3))),
+            other => panic!("thrown value is not a table: {:?}", other),
+        }
+
+        // Execution limits go straight past catch and finally
+        interp.set_limits(Limits { max_steps: Some(10_000), ..Limits::default() });
+        let src = r#"
+            var handled = false;
+            try {
+                while true do { } end
+            } catch e {
+                handled = true;
+            } finally {
+                handled = true;
+            } end
+        "#;
+        let err = run(&mut interp, src).expect_err("expected step limit");
+        assert_eq!(err.kind, ErrorKind::StepLimit);
+        assert_eq!(interp.get_global("handled"), Some(Value::Bool(false)));
+    }
 }
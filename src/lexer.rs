@@ -1,5 +1,7 @@
 // ArcScript lexer (MVP subset)
 
+use crate::ast::Trivia;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TokenKind {
     // Identifiers & literals
@@ -30,6 +32,10 @@ pub enum TokenKind {
     KwAnd,
     KwOr,
     KwNot,
+    KwTry,
+    KwCatch,
+    KwFinally,
+    KwThrow,
 
     // Operators
     Plus,
@@ -71,6 +77,9 @@ pub struct Token {
     pub lexeme: String,
     pub line: usize,
     pub column: usize,
+    /// Comments and blank lines just before this token; only collected by
+    /// `Lexer::with_trivia`
+    pub trivia: Vec<Trivia>,
 }
 
 pub struct Lexer<'a> {
@@ -78,6 +87,9 @@ pub struct Lexer<'a> {
     pub line: usize,
     pub column: usize,
     pos: usize,
+    keep_trivia: bool,
+    trivia: Vec<Trivia>,
+    seen_token: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -87,9 +99,18 @@ impl<'a> Lexer<'a> {
             line: 1,
             column: 1,
             pos: 0,
+            keep_trivia: false,
+            trivia: Vec::new(),
+            seen_token: false,
         }
     }
 
+    /// A lexer that keeps comments and blank lines as trivia on the next
+    /// token, for tools that need to reproduce the source (the formatter).
+    pub fn with_trivia(source: &'a str) -> Self {
+        Self { keep_trivia: true, ..Self::new(source) }
+    }
+
     fn peek(&self) -> Option<u8> {
         self.source.get(self.pos).copied()
     }
@@ -119,6 +140,13 @@ impl<'a> Lexer<'a> {
 
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace_and_comments();
+        let mut token = self.lex_token();
+        token.trivia = std::mem::take(&mut self.trivia);
+        self.seen_token = true;
+        token
+    }
+
+    fn lex_token(&mut self) -> Token {
 
         let start_line = self.line;
         let start_column = self.column;
@@ -131,6 +159,7 @@ impl<'a> Lexer<'a> {
                     lexeme: String::new(),
                     line: start_line,
                     column: start_column,
+                    trivia: Vec::new(),
                 }
             }
         };
@@ -211,6 +240,7 @@ impl<'a> Lexer<'a> {
                 lexeme: String::new(),
                 line: start_line,
                 column: start_column,
+                trivia: Vec::new(),
             },
         }
     }
@@ -221,17 +251,24 @@ impl<'a> Lexer<'a> {
             lexeme: lexeme.to_string(),
             line,
             column,
+            trivia: Vec::new(),
         }
     }
 
     fn skip_whitespace_and_comments(&mut self) {
+        // Newlines since the previous token or comment, for trivia
+        let mut newlines = 0;
         loop {
             let c = self.peek();
             match c {
                 Some(b' ' | b'\r' | b'\t' | b'\n') => {
+                    if c == Some(b'\n') {
+                        newlines += 1;
+                    }
                     self.advance();
                 }
                 Some(b'/') => {
+                    let start = self.pos;
                     if self.source.get(self.pos + 1) == Some(&b'/') {
                         // line comment
                         while let Some(ch) = self.peek() {
@@ -255,10 +292,32 @@ impl<'a> Lexer<'a> {
                     } else {
                         break;
                     }
+                    if self.keep_trivia {
+                        let text = String::from_utf8_lossy(&self.source[start..self.pos]).trim_end().to_string();
+                        self.push_trivia_gap(newlines);
+                        if newlines == 0 && self.seen_token {
+                            self.trivia.push(Trivia::TrailingComment(text));
+                        } else {
+                            self.trivia.push(Trivia::Comment(text));
+                        }
+                        newlines = 0;
+                    }
                 }
                 _ => break,
             }
         }
+        if self.keep_trivia {
+            self.push_trivia_gap(newlines);
+        }
+    }
+
+    /// Records a blank line if `newlines` line breaks separated the previous
+    /// token or comment from what follows. Several blank lines count as one.
+    fn push_trivia_gap(&mut self, newlines: usize) {
+        let after_something = self.seen_token || !self.trivia.is_empty();
+        if newlines >= 2 && after_something && self.trivia.last() != Some(&Trivia::BlankLine) {
+            self.trivia.push(Trivia::BlankLine);
+        }
     }
 
     fn lex_identifier_or_keyword(&mut self, first: u8, line: usize, column: usize) -> Token {
@@ -295,9 +354,13 @@ impl<'a> Lexer<'a> {
             "and" => TokenKind::KwAnd,
             "or" => TokenKind::KwOr,
             "not" => TokenKind::KwNot,
+            "try" => TokenKind::KwTry,
+            "catch" => TokenKind::KwCatch,
+            "finally" => TokenKind::KwFinally,
+            "throw" => TokenKind::KwThrow,
             _ => TokenKind::Identifier,
         };
-        Token { kind, lexeme: s, line, column }
+        Token { kind, lexeme: s, line, column, trivia: Vec::new() }
     }
 
     fn lex_number(&mut self, first: u8, line: usize, column: usize) -> Token {
@@ -320,7 +383,7 @@ impl<'a> Lexer<'a> {
 
         let s = String::from_utf8(buf).unwrap_or_default();
         let kind = if is_float { TokenKind::Float } else { TokenKind::Int };
-        Token { kind, lexeme: s, line, column }
+        Token { kind, lexeme: s, line, column, trivia: Vec::new() }
     }
 
     fn lex_string(&mut self, line: usize, column: usize) -> Token {
@@ -348,6 +411,6 @@ impl<'a> Lexer<'a> {
             }
         }
         let s = String::from_utf8(buf).unwrap_or_default();
-        Token { kind: TokenKind::String, lexeme: s, line, column }
+        Token { kind: TokenKind::String, lexeme: s, line, column, trivia: Vec::new() }
     }
 }
@@ -0,0 +1,25 @@
+// ArcScript library: embed the lexer, parser and interpreter in a host application
+
+pub mod ast;
+pub mod coroutine;
+pub mod dap;
+pub mod debugger;
+pub mod formatter;
+pub mod input;
+pub mod interpreter;
+pub mod lexer;
+pub mod limits;
+pub mod lint;
+pub mod lsp;
+pub mod memory;
+pub mod optimizer;
+pub mod parser;
+pub mod profiler;
+pub mod reload;
+pub mod repl;
+pub mod resolver;
+pub mod sandbox;
+pub mod scheduler;
+pub mod snapshot;
+pub mod stdlib;
+pub mod testing;
@@ -0,0 +1,146 @@
+// ArcScript execution budgets: step, call depth, wall-clock and memory limits
+//
+// Scripts come from modders, so the host needs a way to stop a runaway loop or
+// unbounded recursion without hanging or crashing the game. Every limit
+// produces a RuntimeError with its own ErrorKind.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A suggested `max_call_depth`: deep enough for ordinary recursive scripts
+/// while staying well inside the main thread's stack in debug builds.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 200;
+
+/// Deadlines and interrupts are checked once per this many steps so the clock
+/// isn't read on every expression.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Per-run limits. A run is one host entry point: `eval_program`,
+/// `call_global` or `tick`. Every limit is off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Statements and expressions evaluated before the run is stopped
+    pub max_steps: Option<u64>,
+    /// Nested function calls before the run is stopped
+    pub max_call_depth: Option<usize>,
+    /// Wall-clock time before the run is stopped
+    pub timeout: Option<Duration>,
+    /// Approximate bytes of strings and tables scripts may hold at once.
+    /// Unlike the other limits this is not reset between runs.
+    pub max_memory: Option<usize>,
+}
+
+impl Limits {
+    /// What the arcscript command-line tools and test runner use: only the
+    /// suggested call depth, so runaway recursion stops with an error instead
+    /// of overflowing the stack.
+    pub fn recommended() -> Self {
+        Self { max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH), ..Self::default() }
+    }
+}
+
+/// Stops a running script from another thread. The script fails with
+/// `ErrorKind::Interrupted` at its next check; an interrupt requested while
+/// nothing is running stops the next run.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true (and clears the request) if an interrupt is pending.
+    pub(crate) fn take(&self) -> bool {
+        self.flag.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// What a run has used so far. Shared with coroutine interpreters so a
+/// coroutine's steps count against whoever resumed it.
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    steps: AtomicU64,
+    deadline: Mutex<Option<Instant>>,
+    pub(crate) interrupt: InterruptHandle,
+}
+
+/// Which limit a step ran into.
+pub(crate) enum Exceeded {
+    Steps(u64),
+    Deadline,
+    Interrupted,
+}
+
+impl Budget {
+    /// Resets the step count and arms the deadline for a new run.
+    pub(crate) fn start(&self, limits: &Limits) {
+        self.steps.store(0, Ordering::Relaxed);
+        *self.deadline() = limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Counts one step and reports the first limit it exceeds.
+    pub(crate) fn step(&self, limits: &Limits) -> Result<(), Exceeded> {
+        let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(max) = limits.max_steps {
+            if steps > max {
+                return Err(Exceeded::Steps(max));
+            }
+        }
+        if steps.is_multiple_of(CHECK_INTERVAL) {
+            if self.interrupt.take() {
+                return Err(Exceeded::Interrupted);
+            }
+            if matches!(*self.deadline(), Some(deadline) if Instant::now() >= deadline) {
+                return Err(Exceeded::Deadline);
+            }
+        }
+        Ok(())
+    }
+
+    fn deadline(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.deadline.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_limit() {
+        let limits = Limits { max_steps: Some(3), ..Limits::default() };
+        let budget = Budget::default();
+        budget.start(&limits);
+        for _ in 0..3 {
+            assert!(budget.step(&limits).is_ok());
+        }
+        assert!(matches!(budget.step(&limits), Err(Exceeded::Steps(3))));
+
+        budget.start(&limits);
+        assert!(budget.step(&limits).is_ok());
+    }
+
+    #[test]
+    fn test_limits_are_off_by_default() {
+        let limits = Limits::default();
+        assert_eq!(limits.max_call_depth, None);
+        let budget = Budget::default();
+        budget.start(&limits);
+        assert!((0..CHECK_INTERVAL * 2).all(|_| budget.step(&limits).is_ok()));
+    }
+
+    #[test]
+    fn test_interrupt_is_consumed() {
+        let limits = Limits::default();
+        let budget = Budget::default();
+        budget.start(&limits);
+        budget.interrupt.clone().interrupt();
+        let stopped = (0..CHECK_INTERVAL).any(|_| budget.step(&limits).is_err());
+        assert!(stopped);
+        assert!(!budget.interrupt.take());
+    }
+}
@@ -0,0 +1,735 @@
+// ArcScript linter: finds likely bugs in a parsed program without running it
+//
+// Used by `arcscript lint`. Scoping follows the interpreter: a function only
+// sees names declared before it, and object members can't see each other.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{Expr, FuncDecl, ObjectDecl, ObjectMember, Program, Stmt, StmtKind, TableField};
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::{ParseError, Parser};
+use crate::stdlib::signatures;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The rule is off
+    Allow,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Allow => "allow",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+pub struct Rule {
+    pub name: &'static str,
+    pub default: Severity,
+    pub description: &'static str,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule { name: "undefined_variable", default: Severity::Error, description: "a name that isn't defined where it's used" },
+    Rule { name: "unused_variable", default: Severity::Warning, description: "a local var that is never read" },
+    Rule { name: "unreachable_code", default: Severity::Warning, description: "statements after return, break, continue or throw" },
+    Rule { name: "shadowing", default: Severity::Warning, description: "a var that hides a variable or builtin from an outer scope" },
+    Rule { name: "undeclared_assignment", default: Severity::Warning, description: "assigning to a name that was never declared with var" },
+    Rule { name: "duplicate_member", default: Severity::Error, description: "an object member or table key defined twice" },
+    Rule { name: "builtin_arity", default: Severity::Error, description: "a builtin called with the wrong number of arguments" },
+    Rule { name: "unknown_event", default: Severity::Warning, description: "an `on` handler for an event the host doesn't send" },
+    Rule { name: "unknown_rule", default: Severity::Warning, description: "an `arc:allow` comment naming a rule that doesn't exist" },
+];
+
+/// Events handlers may be declared for unless the host adds its own.
+pub const DEFAULT_EVENTS: &[&str] = &["update", "collision"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}[{}]: {}", self.line, self.severity.as_str(), self.rule, self.message)
+    }
+}
+
+/// Rule severities plus what the host provides beyond the builtins.
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    severities: HashMap<&'static str, Severity>,
+    /// Globals the host defines before running scripts
+    pub globals: Vec<String>,
+    /// Events the host sends to `on` handlers
+    pub events: Vec<String>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            severities: RULES.iter().map(|rule| (rule.name, rule.default)).collect(),
+            globals: Vec::new(),
+            events: DEFAULT_EVENTS.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Changes a rule's severity; `Severity::Allow` turns it off.
+    pub fn set(&mut self, rule: &str, severity: Severity) -> Result<(), String> {
+        match RULES.iter().find(|r| r.name == rule) {
+            Some(r) => {
+                self.severities.insert(r.name, severity);
+                Ok(())
+            }
+            None => Err(format!("unknown lint rule '{}'", rule)),
+        }
+    }
+
+    pub fn severity(&self, rule: &str) -> Severity {
+        self.severities.get(rule).copied().unwrap_or(Severity::Allow)
+    }
+}
+
+/// Lints `source`, honouring `// arc:allow(rule)` comments: one after code
+/// covers its own line, one on a line by itself covers the next line.
+pub fn lint_source(source: &str, config: &LintConfig) -> Result<Vec<Diagnostic>, Vec<ParseError>> {
+    let program = Parser::new(Lexer::new(source)).parse_program()?;
+    let Suppressions { allowed, unknown } = suppressions(source);
+    let mut diagnostics = lint_program(&program, config);
+    let severity = config.severity("unknown_rule");
+    if severity != Severity::Allow {
+        for (line, rule) in unknown {
+            let mut message = format!("arc:allow names unknown lint rule '{}'", rule);
+            if let Some(similar) = similar_rule(&rule) {
+                message.push_str(&format!("; did you mean '{}'?", similar));
+            }
+            diagnostics.push(Diagnostic { rule: "unknown_rule", severity, line, message });
+        }
+        diagnostics.sort_by_key(|d| d.line);
+    }
+    diagnostics.retain(|d| !allowed.get(&d.line).is_some_and(|rules| rules.contains(d.rule)));
+    Ok(diagnostics)
+}
+
+/// Lints a parsed program, sorted by line.
+pub fn lint_program(program: &Program, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut linter = Linter::new(config);
+    linter.stmts(&program.body);
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|d| d.line);
+    diagnostics
+}
+
+/// What the `arc:allow(...)` comments in a source file ask for.
+#[derive(Default)]
+struct Suppressions {
+    /// The rules allowed on each line
+    allowed: HashMap<usize, HashSet<String>>,
+    /// The line of each comment naming a rule that doesn't exist, with the name
+    unknown: Vec<(usize, String)>,
+}
+
+fn suppressions(source: &str) -> Suppressions {
+    let mut found = Suppressions::default();
+    for (index, text) in source.lines().enumerate() {
+        let comment_start = match (text.find("//"), text.find("/*")) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => continue,
+        };
+        let comment = &text[comment_start..];
+        let Some(start) = comment.find("arc:allow(") else { continue };
+        let Some(len) = comment[start..].find(')') else { continue };
+        let rules = &comment[start + "arc:allow(".len()..start + len];
+        // Line numbers are 1-based; a comment alone on its line covers the next
+        let line = if text[..comment_start].trim().is_empty() { index + 2 } else { index + 1 };
+        for rule in rules.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            if !RULES.iter().any(|r| r.name == rule) {
+                found.unknown.push((index + 1, rule.to_string()));
+            }
+            found.allowed.entry(line).or_default().insert(rule.to_string());
+        }
+    }
+    found
+}
+
+/// The rule a misspelled `arc:allow` most likely meant.
+fn similar_rule(name: &str) -> Option<&'static str> {
+    RULES.iter()
+        .map(|rule| (edit_distance(name, rule.name), rule.name))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, rule)| rule)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Builtin,
+    /// Defined by the host (`LintConfig::globals`)
+    Host,
+    Var,
+    Param,
+    Function,
+    Object,
+    LoopVar,
+    CatchVar,
+}
+
+struct Binding {
+    kind: Kind,
+    line: usize,
+    used: bool,
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    /// Innermost last; the first holds builtins and host globals, the second
+    /// the script's globals
+    scopes: Vec<HashMap<String, Binding>>,
+    /// Line of the statement being checked
+    line: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(config: &'a LintConfig) -> Self {
+        let mut root = HashMap::new();
+        for name in Interpreter::new().global_names() {
+            root.insert(name, Binding { kind: Kind::Builtin, line: 0, used: true });
+        }
+        for name in &config.globals {
+            root.insert(name.clone(), Binding { kind: Kind::Host, line: 0, used: true });
+        }
+        Self { config, scopes: vec![root, HashMap::new()], line: 0, diagnostics: Vec::new() }
+    }
+
+    fn report(&mut self, rule: &'static str, line: usize, message: String) {
+        let severity = self.config.severity(rule);
+        if severity != Severity::Allow {
+            self.diagnostics.push(Diagnostic { rule, severity, line, message });
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+    }
+
+    fn declare(&mut self, name: &str, kind: Kind, line: usize) {
+        let outer = self.scopes[..self.scopes.len() - 1].iter().rev().find_map(|scope| scope.get(name));
+        let in_scope = self.scopes.last().is_some_and(|scope| scope.contains_key(name));
+        if !in_scope && matches!(kind, Kind::Var | Kind::Function | Kind::LoopVar | Kind::CatchVar) {
+            let message = match outer.map(|b| (b.kind, b.line)) {
+                Some((Kind::Builtin, _)) => Some(format!("'{}' shadows the builtin of the same name", name)),
+                Some((Kind::Host, _)) => Some(format!("'{}' shadows a global defined by the host", name)),
+                Some((_, outer_line)) => Some(format!("'{}' shadows the variable declared on line {}", name, outer_line)),
+                None => None,
+            };
+            if let Some(message) = message {
+                self.report("shadowing", line, message);
+            }
+        }
+        // Anything but a plain local var is exempt from the unused check
+        let used = kind != Kind::Var || name.starts_with('_');
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Binding { kind, line, used });
+        }
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else { return };
+        // Globals may be read by the host, so only locals count as unused
+        if self.scopes.len() < 2 {
+            return;
+        }
+        let mut unused: Vec<(&String, &Binding)> = scope.iter().filter(|(_, b)| !b.used).collect();
+        unused.sort_by_key(|(name, b)| (b.line, name.to_string()));
+        for (name, binding) in unused {
+            let message = format!("variable '{}' is never used; prefix it with _ if that's intended", name);
+            self.report("unused_variable", binding.line, message);
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        let mut exit: Option<&str> = None;
+        let mut reported = false;
+        for stmt in stmts {
+            if matches!(stmt.kind, StmtKind::Trivia(_)) {
+                continue;
+            }
+            if let (Some(word), false) = (exit, reported) {
+                self.report("unreachable_code", stmt.line, format!("unreachable code after '{}'", word));
+                reported = true;
+            }
+            self.stmt(stmt);
+            if exit.is_none() {
+                exit = exits(stmt);
+            }
+        }
+    }
+
+    /// The statements of a block in a new scope.
+    fn block(&mut self, body: &Stmt) {
+        self.push_scope();
+        match &body.kind {
+            StmtKind::Block(stmts) => self.stmts(stmts),
+            _ => self.stmt(body),
+        }
+        self.pop_scope();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        self.line = stmt.line;
+        match &stmt.kind {
+            StmtKind::VarDecl { name, init } => {
+                self.expr(init);
+                self.declare(name, Kind::Var, stmt.line);
+            }
+            StmtKind::Assignment { name, value } => {
+                if self.lookup(name).is_none() {
+                    let message = format!("assignment to undeclared variable '{}'; declare it with var", name);
+                    self.report("undeclared_assignment", stmt.line, message);
+                    // Assignment creates it, so later uses are fine
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.insert(name.clone(), Binding { kind: Kind::Var, line: stmt.line, used: true });
+                    }
+                }
+                self.expr(value);
+            }
+            StmtKind::MemberAssignment { object, value, .. } => {
+                self.expr(object);
+                self.expr(value);
+            }
+            StmtKind::IndexAssignment { object, index, value } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Block(_) => self.block(stmt),
+            StmtKind::If { condition, then_branch, elif_branches, else_branch } => {
+                self.expr(condition);
+                self.block(then_branch);
+                for (cond, branch) in elif_branches {
+                    self.line = stmt.line;
+                    self.expr(cond);
+                    self.block(branch);
+                }
+                if let Some(branch) = else_branch {
+                    self.block(branch);
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.expr(condition);
+                self.block(body);
+            }
+            StmtKind::For { var_name, start, end, step, body } => {
+                self.expr(start);
+                self.expr(end);
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+                self.push_scope();
+                self.declare(var_name, Kind::LoopVar, stmt.line);
+                self.block(body);
+                self.pop_scope();
+            }
+            StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            StmtKind::Throw { value } => self.expr(value),
+            StmtKind::Try { body, catch_name, catch_body, finally_body } => {
+                self.push_scope();
+                self.stmts(body);
+                self.pop_scope();
+                if let Some(catch_body) = catch_body {
+                    self.push_scope();
+                    if let Some(name) = catch_name {
+                        self.declare(name, Kind::CatchVar, stmt.line);
+                    }
+                    self.stmts(catch_body);
+                    self.pop_scope();
+                }
+                if let Some(finally_body) = finally_body {
+                    self.push_scope();
+                    self.stmts(finally_body);
+                    self.pop_scope();
+                }
+            }
+            StmtKind::FuncDecl(func) => {
+                self.declare(&func.name, Kind::Function, func.line);
+                self.function(func);
+            }
+            StmtKind::ObjectDecl(obj) => {
+                self.object(obj);
+                self.declare(&obj.name, Kind::Object, stmt.line);
+            }
+            StmtKind::Trivia(_) => {}
+        }
+    }
+
+    /// A function body sees what's declared so far, its own name and its
+    /// parameters.
+    fn function(&mut self, func: &FuncDecl) {
+        self.push_scope();
+        if let Some(scope) = self.scopes.last_mut() {
+            for param in &func.params {
+                scope.insert(param.name.clone(), Binding { kind: Kind::Param, line: func.line, used: true });
+            }
+        }
+        self.block(&func.body);
+        self.pop_scope();
+    }
+
+    fn object(&mut self, obj: &ObjectDecl) {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for member in &obj.members {
+            let (key, line) = match member {
+                ObjectMember::Var(stmt) => match &stmt.kind {
+                    StmtKind::VarDecl { name, init } => {
+                        self.line = stmt.line;
+                        self.expr(init);
+                        (name.clone(), stmt.line)
+                    }
+                    _ => continue,
+                },
+                ObjectMember::Method(func) => {
+                    self.function(func);
+                    (func.name.clone(), func.line)
+                }
+                ObjectMember::Event(event) => {
+                    if !self.config.events.iter().any(|e| e == &event.name) {
+                        let message = format!("'{}' is not an event the host sends (known: {})", event.name, self.config.events.join(", "));
+                        self.report("unknown_event", event.line, message);
+                    }
+                    self.push_scope();
+                    if let Some(scope) = self.scopes.last_mut() {
+                        for param in &event.params {
+                            scope.insert(param.name.clone(), Binding { kind: Kind::Param, line: event.line, used: true });
+                        }
+                    }
+                    self.block(&event.body);
+                    self.pop_scope();
+                    (format!("on {}", event.name), event.line)
+                }
+                ObjectMember::Trivia(_) => continue,
+            };
+            if let Some(first) = seen.get(&key) {
+                let message = format!("'{}' is already defined in object '{}' on line {}", key, obj.name, first);
+                self.report("duplicate_member", line, message);
+            } else {
+                seen.insert(key, line);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(_) => {}
+            Expr::Ident(name) | Expr::Local { name, .. } => match self.lookup(name) {
+                Some(binding) => binding.used = true,
+                None => {
+                    let mut message = format!("undefined variable '{}'", name);
+                    if let Some(similar) = self.similar_name(name) {
+                        message.push_str(&format!("; did you mean '{}'?", similar));
+                    }
+                    self.report("undefined_variable", self.line, message);
+                }
+            },
+            Expr::Unary { expr, .. } => self.expr(expr),
+            Expr::Binary { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                if let Some(name) = self.builtin_name(callee) {
+                    self.check_arity(&name, args.len());
+                }
+            }
+            Expr::Member { object, field } => {
+                self.expr(object);
+                if let Expr::Ident(namespace) = object.as_ref() {
+                    if self.is_builtin(namespace) && is_namespace(namespace) {
+                        let name = format!("{}.{}", namespace, field);
+                        if signatures::lookup(&name).is_none() {
+                            self.report("undefined_variable", self.line, format!("'{}' has no function '{}'", namespace, field));
+                        }
+                    }
+                }
+            }
+            Expr::Index { object, index } => {
+                self.expr(object);
+                self.expr(index);
+            }
+            Expr::TableLiteral(fields) => {
+                let mut keys = HashSet::new();
+                for field in fields {
+                    match field {
+                        TableField::KeyValue { key, value } => {
+                            if !keys.insert(key.as_str()) {
+                                self.report("duplicate_member", self.line, format!("key '{}' appears twice in this table", key));
+                            }
+                            self.expr(value);
+                        }
+                        TableField::Value(value) => self.expr(value),
+                        TableField::Trivia(_) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_builtin(&mut self, name: &str) -> bool {
+        self.lookup(name).is_some_and(|b| b.kind == Kind::Builtin)
+    }
+
+    /// The builtin a call goes to, unless the script has redefined the name.
+    fn builtin_name(&mut self, callee: &Expr) -> Option<String> {
+        match callee {
+            Expr::Ident(name) if self.is_builtin(name) => Some(name.clone()),
+            Expr::Member { object, field } => match object.as_ref() {
+                Expr::Ident(namespace) if self.is_builtin(namespace) => Some(format!("{}.{}", namespace, field)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn check_arity(&mut self, name: &str, count: usize) {
+        let Some(signature) = signatures::lookup(name) else { return };
+        let (min, max) = signature.arity();
+        if count < min || max.is_some_and(|max| count > max) {
+            let expected = match max {
+                Some(max) if max == min => format!("{}", min),
+                Some(max) => format!("{} to {}", min, max),
+                None => format!("at least {}", min),
+            };
+            let plural = if max == Some(1) || (max.is_none() && min == 1) { "" } else { "s" };
+            let message = format!("{} takes {} argument{} but was given {}", signature.display(), expected, plural, count);
+            self.report("builtin_arity", self.line, message);
+        }
+    }
+
+    /// A visible name within two edits of `name`, for typo hints.
+    fn similar_name(&self, name: &str) -> Option<String> {
+        let mut best: Option<(usize, &String)> = None;
+        for scope in &self.scopes {
+            for candidate in scope.keys() {
+                let distance = edit_distance(name, candidate);
+                if distance <= 2 && distance < name.len() && best.is_none_or(|(d, c)| (distance, candidate) < (d, c)) {
+                    best = Some((distance, candidate));
+                }
+            }
+        }
+        best.map(|(_, name)| name.clone())
+    }
+}
+
+/// The keyword that makes everything after `stmt` unreachable, if any.
+fn exits(stmt: &Stmt) -> Option<&'static str> {
+    match &stmt.kind {
+        StmtKind::Return(_) => Some("return"),
+        StmtKind::Break => Some("break"),
+        StmtKind::Continue => Some("continue"),
+        StmtKind::Throw { .. } => Some("throw"),
+        StmtKind::Block(stmts) => stmts.iter().find_map(exits),
+        // Only if every branch exits
+        StmtKind::If { then_branch, elif_branches, else_branch: Some(else_branch), .. } => {
+            let word = exits(then_branch)?;
+            for (_, branch) in elif_branches {
+                exits(branch)?;
+            }
+            exits(else_branch)?;
+            Some(word)
+        }
+        _ => None,
+    }
+}
+
+fn is_namespace(name: &str) -> bool {
+    signatures::SIGNATURES.iter().any(|sig| sig.name.split_once('.').is_some_and(|(ns, _)| ns == name))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb { previous } else { 1 + previous.min(row[j]).min(current) };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(source: &str) -> Vec<String> {
+        let diagnostics = lint_source(source, &LintConfig::new()).expect("parse failed");
+        diagnostics.iter().map(|d| format!("{}:{}", d.line, d.rule)).collect()
+    }
+
+    #[test]
+    fn test_scope_rules() {
+        let source = r#"
+var health = 10;
+func heal(amount): {
+    var unused = 1;
+    var _ignored = 2;
+    healht = health + amount;
+    return helth;
+} end
+func early(): {
+    return later;
+} end
+var later = 1;
+for i = 1, 3 do {
+    var health = i;
+    println(health);
+} end
+try { throw "x"; } catch e { var max = 1; println(max); } end
+"#;
+        assert_eq!(
+            lint(source),
+            [
+                "4:unused_variable",
+                "6:undeclared_assignment",
+                "7:undefined_variable",
+                "10:undefined_variable",
+                "14:shadowing",
+                "17:shadowing",
+            ]
+        );
+        let diagnostics = lint_source(source, &LintConfig::new()).unwrap();
+        assert_eq!(diagnostics[2].message, "undefined variable 'helth'; did you mean 'health'?");
+        assert_eq!(diagnostics[2].severity, Severity::Error);
+        assert_eq!(diagnostics[4].message, "'health' shadows the variable declared on line 2");
+    }
+
+    #[test]
+    fn test_structure_rules() {
+        let source = r#"
+func f(x): {
+    if x then { return 1; } else { throw "no"; } end
+    println("never");
+    return 2;
+} end
+while true do { break; continue; } end
+object Enemy: {
+    var hp = 10;
+    var hp = 20;
+    func hit(): { } end
+    on update(dt): { } end
+    on spawn(): { } end
+} end
+var t = {a: 1, b: 2, a: 3};
+println(max());
+println(string.split("a b"), string.spilt("a"), input.mouse(1));
+func max(a): { return a; } end
+max();
+"#;
+        assert_eq!(
+            lint(source),
+            [
+                "4:unreachable_code",
+                "7:unreachable_code",
+                "10:duplicate_member",
+                "13:unknown_event",
+                "15:duplicate_member",
+                "16:builtin_arity",
+                "17:undefined_variable",
+                "17:builtin_arity",
+                "18:shadowing",
+            ]
+        );
+        let diagnostics = lint_source(source, &LintConfig::new()).unwrap();
+        assert_eq!(diagnostics[5].message, "max(value, values...) takes at least 1 argument but was given 0");
+        assert_eq!(diagnostics[7].message, "input.mouse() takes 0 arguments but was given 1");
+    }
+
+    #[test]
+    fn test_config_and_suppressions() {
+        let source = "var a = 1;\nfunc f(): {\n    var b = 2; // arc:allow(unused_variable)\n    // arc:allow(undefined_variable, unused_variable)\n    var c = spawn();\n    return d;\n} end\non_hit();\n";
+        assert_eq!(lint(source), ["6:undefined_variable", "8:undefined_variable"]);
+
+        let mut config = LintConfig::new();
+        config.globals.push("on_hit".to_string());
+        config.set("undefined_variable", Severity::Warning).unwrap();
+        let diagnostics = lint_source(source, &config).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].to_string(), "line 6: warning[undefined_variable]: undefined variable 'd'");
+
+        config.set("undefined_variable", Severity::Allow).unwrap();
+        assert!(lint_source(source, &config).unwrap().is_empty());
+        assert!(config.set("no_such_rule", Severity::Error).is_err());
+
+        let source = "// arc:allow(unused_varible, no_such_rule)\nvar a = 1;\nvar b = 2; // arc:allow(unknown_rule, nope)\n";
+        assert_eq!(lint(source), ["1:unknown_rule", "1:unknown_rule"]);
+        let diagnostics = lint_source(source, &LintConfig::new()).unwrap();
+        assert_eq!(
+            diagnostics[0].message,
+            "arc:allow names unknown lint rule 'unused_varible'; did you mean 'unused_variable'?"
+        );
+        assert_eq!(diagnostics[1].message, "arc:allow names unknown lint rule 'no_such_rule'");
+    }
+
+    #[test]
+    fn test_signatures_cover_every_builtin() {
+        let interp = Interpreter::new();
+        for name in interp.global_names() {
+            match interp.get_global(&name) {
+                Some(crate::interpreter::Value::BuiltinFunction(builtin)) => {
+                    assert!(signatures::lookup(&builtin).is_some(), "no signature for {}", builtin);
+                }
+                Some(crate::interpreter::Value::Table(table)) => {
+                    for value in table.values() {
+                        if let crate::interpreter::Value::BuiltinFunction(builtin) = value {
+                            assert!(signatures::lookup(builtin).is_some(), "no signature for {}", builtin);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for signature in signatures::SIGNATURES {
+            let (namespace, function) = signature.name.split_once('.').unwrap_or(("", signature.name));
+            let defined = match interp.get_global(if namespace.is_empty() { function } else { namespace }) {
+                Some(crate::interpreter::Value::Table(table)) => table.contains_key(function),
+                Some(_) => namespace.is_empty(),
+                None => false,
+            };
+            assert!(defined, "signature for missing builtin {}", signature.name);
+        }
+    }
+}
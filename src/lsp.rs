@@ -0,0 +1,962 @@
+// ArcScript language server: editor support over stdio (`arcscript lsp`)
+//
+// Speaks JSON-RPC with Content-Length framing. Documents are synced in full on
+// every change. Diagnostics come from the parser and the linter; hover,
+// definitions, symbols and completion come from a token-level index that
+// follows the interpreter's scoping.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::interpreter::{Interpreter, Value};
+use crate::lexer::{Lexer, Token, TokenKind};
+use crate::lint::{self, LintConfig, Severity};
+use crate::stdlib::{json, list_items, signatures};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+const KEYWORDS: &[&str] = &[
+    "var", "func", "object", "if", "then", "elif", "else", "end", "while", "do", "for", "return", "break",
+    "continue", "true", "false", "nil", "and", "or", "not", "on", "try", "catch", "finally", "throw",
+];
+
+/// A JSON value sent to the client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    pub(crate) fn str(s: impl Into<String>) -> Json {
+        Json::String(s.into())
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(i) => write!(f, "{}", i),
+            Json::String(s) => {
+                let mut out = String::new();
+                json::write_string(&mut out, s);
+                write!(f, "{}", out)
+            }
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::str(*key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Reads one framed message, or `None` at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length header"));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Serves one client until it sends `exit` or closes the input. Returns the
+/// process exit code: 0 if the client asked for a shutdown first.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<i32> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(&mut input)? {
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(if server.shutdown_requested { 0 } else { 1 })
+}
+
+pub struct Server {
+    documents: HashMap<String, Document>,
+    /// Builtin globals by name, for hover and completion
+    builtins: HashMap<String, Value>,
+    /// Applied to the diagnostics of every open document
+    pub lint: LintConfig,
+    shutdown_requested: bool,
+    exited: bool,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        let interp = Interpreter::new();
+        let builtins = interp
+            .global_names()
+            .into_iter()
+            .filter_map(|name| interp.get_global(&name).map(|value| (name, value)))
+            .collect();
+        Self { documents: HashMap::new(), builtins, lint: LintConfig::new(), shutdown_requested: false, exited: false }
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the client has sent `exit`.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Handles one message and returns what to send back: the response if it
+    /// was a request, and any notifications such as diagnostics.
+    pub fn handle(&mut self, message: &str) -> Vec<Json> {
+        let message = match json::decode(message) {
+            Ok(message) => message,
+            Err(e) => return vec![error_response(Json::Null, PARSE_ERROR, &e.to_string())],
+        };
+        // Messages without a method are responses to requests we never send
+        let Some(method) = str_field(&message, "method") else { return Vec::new() };
+        let params = field(&message, "params").unwrap_or(&Value::Nil);
+
+        let Some(id) = field(&message, "id") else {
+            return self.notification(method, params);
+        };
+        let id = match id {
+            Value::Int(i) => Json::Int(*i),
+            Value::String(s) => Json::str(s.as_str()),
+            _ => Json::Null,
+        };
+        if self.shutdown_requested {
+            return vec![error_response(id, INVALID_REQUEST, "the server is shutting down")];
+        }
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Json::Null
+            }
+            "textDocument/hover" => self.hover(params).unwrap_or(Json::Null),
+            "textDocument/definition" => self.definition(params).unwrap_or(Json::Null),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/completion" => self.completion(params),
+            _ => return vec![error_response(id, METHOD_NOT_FOUND, &format!("unknown method '{}'", method))],
+        };
+        vec![Json::Object(vec![("jsonrpc", Json::str("2.0")), ("id", id), ("result", result)])]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Json> {
+        let uri = str_field(params, "textDocument.uri").unwrap_or("").to_string();
+        match method {
+            "exit" => {
+                self.exited = true;
+                Vec::new()
+            }
+            "textDocument/didOpen" => {
+                let text = str_field(params, "textDocument.text").unwrap_or("");
+                self.update(uri, text)
+            }
+            "textDocument/didChange" => {
+                // Full sync, so the last change holds the whole text
+                let changes = match field(params, "contentChanges") {
+                    Some(Value::Table(changes)) => list_items(changes),
+                    _ => Vec::new(),
+                };
+                match changes.last().and_then(|change| str_field(change, "text")) {
+                    Some(text) => self.update(uri, text),
+                    None => Vec::new(),
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, Vec::new())]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn update(&mut self, uri: String, text: &str) -> Vec<Json> {
+        let document = Document::new(text);
+        let diagnostics = document.diagnostics(&self.lint);
+        let reply = publish_diagnostics(&uri, diagnostics);
+        self.documents.insert(uri, document);
+        vec![reply]
+    }
+
+    /// The open document and the 1-based line and byte column a request
+    /// points at.
+    fn locate<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a Document, (usize, usize))> {
+        let uri = str_field(params, "textDocument.uri")?;
+        let document = self.documents.get(uri)?;
+        let line = int_field(params, "position.line")? as usize;
+        let character = int_field(params, "position.character")? as usize;
+        Some((uri, document, document.source_position(line, character)))
+    }
+
+    fn hover(&self, params: &Value) -> Option<Json> {
+        let (_, document, pos) = self.locate(params)?;
+        let k = document.token_at(pos)?;
+        let text = match document.target(k, &self.builtins)? {
+            Target::Symbol(id) => {
+                let symbol = &document.index.symbols[id];
+                let mut text = format!("```arcscript\n{}\n```", document.index.describe(id));
+                if symbol.kind != SymbolKind::Parameter {
+                    text.push_str(&format!("\n\nDeclared on line {}", symbol.line));
+                }
+                text
+            }
+            Target::Builtin(name) => self.describe_builtin(&name)?,
+        };
+        let token = &document.tokens[k];
+        Some(Json::Object(vec![
+            ("contents", Json::Object(vec![("kind", Json::str("markdown")), ("value", Json::String(text))])),
+            ("range", document.range(token.line, token.column, token.lexeme.len())),
+        ]))
+    }
+
+    fn describe_builtin(&self, name: &str) -> Option<String> {
+        if let Some(signature) = signatures::lookup(name) {
+            return Some(format!("```arcscript\n{}\n```\n\n{}", signature.display(), signature.summary));
+        }
+        match self.builtins.get(name)? {
+            Value::Table(_) => {
+                let count = signatures::SIGNATURES.iter().filter(|sig| sig.name.starts_with(&format!("{}.", name))).count();
+                Some(format!("```arcscript\n(library) {}\n```\n\n{} functions", name, count))
+            }
+            value => Some(format!("```arcscript\n(constant) {} = {}\n```", name, value.repr())),
+        }
+    }
+
+    fn definition(&self, params: &Value) -> Option<Json> {
+        let (uri, document, pos) = self.locate(params)?;
+        let k = document.token_at(pos)?;
+        match document.target(k, &self.builtins)? {
+            Target::Symbol(id) => {
+                let symbol = &document.index.symbols[id];
+                Some(Json::Object(vec![
+                    ("uri", Json::str(uri)),
+                    ("range", document.range(symbol.line, symbol.column, symbol.name.len())),
+                ]))
+            }
+            Target::Builtin(_) => None,
+        }
+    }
+
+    fn document_symbols(&self, params: &Value) -> Json {
+        let document = str_field(params, "textDocument.uri").and_then(|uri| self.documents.get(uri));
+        match document {
+            Some(document) => Json::Array(document.symbols_under(None)),
+            None => Json::Array(Vec::new()),
+        }
+    }
+
+    fn completion(&self, params: &Value) -> Json {
+        let Some((_, document, pos)) = self.locate(params) else { return Json::Array(Vec::new()) };
+        let index = &document.index;
+        let tokens = &document.tokens;
+        // The token just before the word being typed, if any
+        let mut before = tokens.iter().rposition(|t| (t.line, t.column) < pos);
+        if let Some(k) = before {
+            let token = &tokens[k];
+            if token.kind == TokenKind::Identifier && token.line == pos.0 && token.column + token.lexeme.len() == pos.1 {
+                before = k.checked_sub(1);
+            }
+        }
+
+        // After `name.`, only members make sense
+        if let Some(k) = before.filter(|&k| k > 0 && tokens[k].kind == TokenKind::Dot) {
+            let base = &tokens[k - 1];
+            if base.kind != TokenKind::Identifier {
+                return Json::Array(Vec::new());
+            }
+            let base_pos = (base.line, base.column);
+            let items = match index.resolve(&base.lexeme, base_pos) {
+                Some(id) if index.symbols[id].kind == SymbolKind::Object => index
+                    .members(id)
+                    .map(|member| {
+                        let symbol = &index.symbols[member];
+                        let kind = if symbol.kind == SymbolKind::Function { 2 } else { 5 };
+                        completion_item(&symbol.name, kind, &index.describe(member), None)
+                    })
+                    .collect(),
+                None if matches!(self.builtins.get(&base.lexeme), Some(Value::Table(_))) => {
+                    let prefix = format!("{}.", base.lexeme);
+                    signatures::SIGNATURES
+                        .iter()
+                        .filter_map(|sig| {
+                            let function = sig.name.strip_prefix(&prefix)?;
+                            Some(completion_item(function, 3, &sig.display(), Some(sig.summary)))
+                        })
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+            return Json::Array(items);
+        }
+
+        let mut items = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        for id in index.visible(pos) {
+            let symbol = &index.symbols[id];
+            if seen.insert(symbol.name.clone()) {
+                let kind = match symbol.kind {
+                    SymbolKind::Function => 3,
+                    SymbolKind::Object => 7,
+                    _ => 6,
+                };
+                items.push(completion_item(&symbol.name, kind, &index.describe(id), None));
+            }
+        }
+        let mut builtins: Vec<(&String, &Value)> = self.builtins.iter().filter(|(name, _)| !seen.contains(*name)).collect();
+        builtins.sort_by_key(|(name, _)| name.as_str());
+        for (name, value) in builtins {
+            items.push(match value {
+                Value::BuiltinFunction(_) => match signatures::lookup(name) {
+                    Some(sig) => completion_item(name, 3, &sig.display(), Some(sig.summary)),
+                    None => completion_item(name, 3, name, None),
+                },
+                Value::Table(_) => completion_item(name, 9, &format!("(library) {}", name), None),
+                value => completion_item(name, 21, &value.repr(), None),
+            });
+        }
+        for keyword in KEYWORDS {
+            items.push(completion_item(keyword, 14, "keyword", None));
+        }
+        Json::Array(items)
+    }
+}
+
+fn capabilities() -> Json {
+    Json::Object(vec![
+        (
+            "capabilities",
+            Json::Object(vec![
+                // Full text on every change
+                ("textDocumentSync", Json::Int(1)),
+                ("hoverProvider", Json::Bool(true)),
+                ("definitionProvider", Json::Bool(true)),
+                ("documentSymbolProvider", Json::Bool(true)),
+                ("completionProvider", Json::Object(vec![("triggerCharacters", Json::Array(vec![Json::str(".")]))])),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::Object(vec![("name", Json::str("arcscript")), ("version", Json::str(env!("CARGO_PKG_VERSION")))]),
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: i64, message: &str) -> Json {
+    Json::Object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("id", id),
+        ("error", Json::Object(vec![("code", Json::Int(code)), ("message", Json::str(message))])),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::Object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/publishDiagnostics")),
+        ("params", Json::Object(vec![("uri", Json::str(uri)), ("diagnostics", Json::Array(diagnostics))])),
+    ])
+}
+
+fn completion_item(label: &str, kind: i64, detail: &str, documentation: Option<&str>) -> Json {
+    let mut fields = vec![("label", Json::str(label)), ("kind", Json::Int(kind)), ("detail", Json::str(detail))];
+    if let Some(documentation) = documentation {
+        fields.push(("documentation", Json::str(documentation)));
+    }
+    Json::Object(fields)
+}
+
+/// The value at a dotted path in a decoded message, e.g. "textDocument.uri".
+pub(crate) fn field<'v>(value: &'v Value, path: &str) -> Option<&'v Value> {
+    path.split('.').try_fold(value, |value, key| match value {
+        Value::Table(table) => table.get(key),
+        _ => None,
+    })
+}
+
+pub(crate) fn str_field<'v>(value: &'v Value, path: &str) -> Option<&'v str> {
+    match field(value, path)? {
+        Value::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+pub(crate) fn int_field(value: &Value, path: &str) -> Option<i64> {
+    match field(value, path)? {
+        Value::Int(i) => Some(*i),
+        Value::Float(f) => Some(*f as i64),
+        _ => None,
+    }
+}
+
+/// What a name under the cursor refers to.
+enum Target {
+    Symbol(usize),
+    /// A builtin, by the name scripts call it (e.g. "string.split")
+    Builtin(String),
+}
+
+struct Document {
+    text: String,
+    tokens: Vec<Token>,
+    index: Index,
+}
+
+impl Document {
+    fn new(text: &str) -> Self {
+        let mut lexer = Lexer::new(text);
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            tokens.push(token);
+        }
+        let index = Index::build(&tokens);
+        Self { text: text.to_string(), tokens, index }
+    }
+
+    fn line_text(&self, line: usize) -> &str {
+        self.text.lines().nth(line.saturating_sub(1)).unwrap_or("")
+    }
+
+    /// An LSP position (0-based line, UTF-16 offset) for a 1-based line and
+    /// byte column.
+    fn lsp_position(&self, line: usize, column: usize) -> Json {
+        let text = self.line_text(line);
+        let mut end = column.saturating_sub(1).min(text.len());
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+        let prefix = &text[..end];
+        Json::Object(vec![
+            ("line", Json::Int(line.saturating_sub(1) as i64)),
+            ("character", Json::Int(prefix.encode_utf16().count() as i64)),
+        ])
+    }
+
+    fn range(&self, line: usize, column: usize, len: usize) -> Json {
+        Json::Object(vec![("start", self.lsp_position(line, column)), ("end", self.lsp_position(line, column + len))])
+    }
+
+    /// The 1-based line and byte column of an LSP position.
+    fn source_position(&self, line: usize, character: usize) -> (usize, usize) {
+        let text = self.line_text(line + 1);
+        let mut units = 0;
+        for (byte, ch) in text.char_indices() {
+            if units >= character {
+                return (line + 1, byte + 1);
+            }
+            units += ch.len_utf16();
+        }
+        (line + 1, text.len() + 1)
+    }
+
+    /// Parse errors, or lint findings if it parses.
+    fn diagnostics(&self, config: &LintConfig) -> Vec<Json> {
+        match lint::lint_source(&self.text, config) {
+            Err(errors) => errors
+                .iter()
+                .map(|err| {
+                    let len = self.token_at((err.line, err.column)).map_or(1, |k| self.tokens[k].lexeme.len().max(1));
+                    diagnostic(self.range(err.line, err.column, len), 1, None, &err.message)
+                })
+                .collect(),
+            Ok(findings) => findings
+                .iter()
+                .map(|finding| {
+                    let text = self.line_text(finding.line);
+                    let indent = text.len() - text.trim_start().len();
+                    let range = self.range(finding.line, indent + 1, text.trim().len());
+                    let severity = if finding.severity == Severity::Error { 1 } else { 2 };
+                    diagnostic(range, severity, Some(finding.rule), &finding.message)
+                })
+                .collect(),
+        }
+    }
+
+    /// The identifier at or just before a position.
+    fn token_at(&self, pos: (usize, usize)) -> Option<usize> {
+        self.tokens.iter().position(|t| {
+            t.kind == TokenKind::Identifier && t.line == pos.0 && t.column <= pos.1 && pos.1 <= t.column + t.lexeme.len()
+        })
+    }
+
+    fn target(&self, k: usize, builtins: &HashMap<String, Value>) -> Option<Target> {
+        let token = &self.tokens[k];
+        let pos = (token.line, token.column);
+        let index = &self.index;
+
+        // A member: Enemy.hp or string.split
+        if k >= 2 && self.tokens[k - 1].kind == TokenKind::Dot && self.tokens[k - 2].kind == TokenKind::Identifier {
+            let base = &self.tokens[k - 2];
+            return match index.resolve(&base.lexeme, (base.line, base.column)) {
+                Some(id) if index.symbols[id].kind == SymbolKind::Object => {
+                    index.members(id).find(|&m| index.symbols[m].name == token.lexeme).map(Target::Symbol)
+                }
+                None if builtins.contains_key(&base.lexeme) => {
+                    let name = format!("{}.{}", base.lexeme, token.lexeme);
+                    signatures::lookup(&name).map(|_| Target::Builtin(name))
+                }
+                _ => None,
+            };
+        }
+
+        if let Some(id) = index.symbols.iter().position(|s| (s.line, s.column) == pos) {
+            return Some(Target::Symbol(id));
+        }
+        match index.resolve(&token.lexeme, pos) {
+            Some(id) => Some(Target::Symbol(id)),
+            None if builtins.contains_key(&token.lexeme) => Some(Target::Builtin(token.lexeme.clone())),
+            None => None,
+        }
+    }
+
+    /// Document symbols for the functions, objects and handlers declared
+    /// directly inside `parent`, or at the top level.
+    fn symbols_under(&self, parent: Option<usize>) -> Vec<Json> {
+        let index = &self.index;
+        let mut out = Vec::new();
+        for (id, symbol) in index.symbols.iter().enumerate() {
+            if !matches!(symbol.kind, SymbolKind::Function | SymbolKind::Object | SymbolKind::Event) {
+                continue;
+            }
+            if index.owner(symbol.scope) != parent {
+                continue;
+            }
+            let kind = match symbol.kind {
+                SymbolKind::Object => 5,
+                SymbolKind::Event => 24,
+                _ if index.member_of(id).is_some() => 6,
+                _ => 12,
+            };
+            let end = symbol.body.map_or((symbol.line, symbol.column + symbol.name.len()), |body| {
+                let (line, column) = index.scopes[body].end;
+                (line, column + 1)
+            });
+            let (start_line, start_column) = symbol.start;
+            let mut fields = vec![
+                ("name", Json::str(symbol.name.as_str())),
+                ("kind", Json::Int(kind)),
+                (
+                    "range",
+                    Json::Object(vec![("start", self.lsp_position(start_line, start_column)), ("end", self.lsp_position(end.0, end.1))]),
+                ),
+                ("selectionRange", self.range(symbol.line, symbol.column, symbol.name.len())),
+                ("children", Json::Array(self.symbols_under(Some(id)))),
+            ];
+            if symbol.kind != SymbolKind::Object {
+                fields.insert(2, ("detail", Json::String(format!("({})", symbol.params.join(", ")))));
+            }
+            out.push(Json::Object(fields));
+        }
+        out
+    }
+}
+
+fn diagnostic(range: Json, severity: i64, code: Option<&str>, message: &str) -> Json {
+    let mut fields = vec![("range", range), ("severity", Json::Int(severity))];
+    if let Some(code) = code {
+        fields.push(("code", Json::str(code)));
+    }
+    fields.push(("source", Json::str("arcscript")));
+    fields.push(("message", Json::str(message)));
+    Json::Object(fields)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Object,
+    Event,
+    LoopVariable,
+    CatchVariable,
+}
+
+struct Symbol {
+    name: String,
+    kind: SymbolKind,
+    /// Where the name is written
+    line: usize,
+    column: usize,
+    /// Where the declaration's keyword is
+    start: (usize, usize),
+    scope: usize,
+    /// The block of a function, object or handler
+    body: Option<usize>,
+    params: Vec<String>,
+}
+
+/// A `{ }` block. Table literals get one too, which does no harm as they
+/// declare nothing.
+struct Scope {
+    parent: Option<usize>,
+    start: (usize, usize),
+    end: (usize, usize),
+    /// The function, object or handler whose body this is
+    owner: Option<usize>,
+}
+
+struct Index {
+    symbols: Vec<Symbol>,
+    /// The first is the whole file
+    scopes: Vec<Scope>,
+}
+
+impl Index {
+    fn build(tokens: &[Token]) -> Self {
+        let mut index = Index {
+            symbols: Vec::new(),
+            scopes: vec![Scope { parent: None, start: (0, 0), end: (usize::MAX, usize::MAX), owner: None }],
+        };
+        let mut stack = vec![0];
+        // Parameters, loop and catch variables belong to the next block, as
+        // does the declaration that owns it
+        let mut pending: Vec<Symbol> = Vec::new();
+        let mut owner: Option<usize> = None;
+        let ident = |i: usize| tokens.get(i).filter(|t| t.kind == TokenKind::Identifier);
+
+        for (i, token) in tokens.iter().enumerate() {
+            let scope = stack.last().copied().unwrap_or(0);
+            let start = (token.line, token.column);
+            let symbol = |name: &Token, kind| Symbol {
+                name: name.lexeme.clone(),
+                kind,
+                line: name.line,
+                column: name.column,
+                start,
+                scope,
+                body: None,
+                params: Vec::new(),
+            };
+            match token.kind {
+                TokenKind::KwVar => {
+                    if let Some(name) = ident(i + 1) {
+                        index.symbols.push(symbol(name, SymbolKind::Variable));
+                    }
+                }
+                TokenKind::KwFunc | TokenKind::KwOn => {
+                    let Some(name) = ident(i + 1) else { continue };
+                    let kind = if token.kind == TokenKind::KwFunc { SymbolKind::Function } else { SymbolKind::Event };
+                    let mut declared = symbol(name, kind);
+                    if tokens.get(i + 2).is_some_and(|t| t.kind == TokenKind::LParen) {
+                        for param in tokens[i + 3..].iter().take_while(|t| t.kind != TokenKind::RParen) {
+                            if param.kind == TokenKind::Identifier {
+                                declared.params.push(param.lexeme.clone());
+                                pending.push(symbol(param, SymbolKind::Parameter));
+                            }
+                        }
+                    }
+                    owner = Some(index.symbols.len());
+                    index.symbols.push(declared);
+                }
+                TokenKind::KwObject => {
+                    if let Some(name) = ident(i + 1) {
+                        owner = Some(index.symbols.len());
+                        index.symbols.push(symbol(name, SymbolKind::Object));
+                    }
+                }
+                TokenKind::KwFor | TokenKind::KwCatch => {
+                    if let Some(name) = ident(i + 1) {
+                        let kind = if token.kind == TokenKind::KwFor { SymbolKind::LoopVariable } else { SymbolKind::CatchVariable };
+                        pending.push(symbol(name, kind));
+                    }
+                }
+                TokenKind::LBrace => {
+                    let id = index.scopes.len();
+                    index.scopes.push(Scope { parent: Some(scope), start, end: (usize::MAX, usize::MAX), owner });
+                    if let Some(owner) = owner.take() {
+                        index.symbols[owner].body = Some(id);
+                    }
+                    for mut declared in pending.drain(..) {
+                        declared.scope = id;
+                        index.symbols.push(declared);
+                    }
+                    stack.push(id);
+                }
+                TokenKind::RBrace if stack.len() > 1 => {
+                    if let Some(id) = stack.pop() {
+                        index.scopes[id].end = start;
+                    }
+                }
+                _ => {}
+            }
+        }
+        index
+    }
+
+    /// The innermost block containing a position.
+    fn scope_at(&self, pos: (usize, usize)) -> usize {
+        (0..self.scopes.len())
+            .filter(|&id| self.scopes[id].start < pos && pos <= self.scopes[id].end)
+            .max_by_key(|&id| self.scopes[id].start)
+            .unwrap_or(0)
+    }
+
+    /// The function, object or handler a block belongs to, looking outwards
+    /// through plain blocks.
+    fn owner(&self, mut scope: usize) -> Option<usize> {
+        loop {
+            if let Some(owner) = self.scopes[scope].owner {
+                return Some(owner);
+            }
+            scope = self.scopes[scope].parent?;
+        }
+    }
+
+    /// The object a symbol is declared directly inside.
+    fn member_of(&self, id: usize) -> Option<usize> {
+        let owner = self.scopes[self.symbols[id].scope].owner?;
+        (self.symbols[owner].kind == SymbolKind::Object).then_some(owner)
+    }
+
+    fn members(&self, object: usize) -> impl Iterator<Item = usize> + '_ {
+        let body = self.symbols[object].body;
+        (0..self.symbols.len())
+            .filter(move |&id| Some(self.symbols[id].scope) == body && self.symbols[id].kind != SymbolKind::Event)
+    }
+
+    /// Symbols visible at a position, innermost first. Members of an object
+    /// aren't visible inside it, just as the interpreter doesn't see them.
+    fn visible(&self, pos: (usize, usize)) -> Vec<usize> {
+        let mut out = Vec::new();
+        let mut scope = Some(self.scope_at(pos));
+        while let Some(id) = scope {
+            let is_object = self.scopes[id].owner.is_some_and(|owner| self.symbols[owner].kind == SymbolKind::Object);
+            if !is_object {
+                let mut here: Vec<usize> = (0..self.symbols.len())
+                    .filter(|&s| self.symbols[s].scope == id && self.symbols[s].kind != SymbolKind::Event)
+                    .collect();
+                here.reverse();
+                out.extend(here);
+            }
+            scope = self.scopes[id].parent;
+        }
+        out
+    }
+
+    /// The declaration `name` refers to at a position: the closest one
+    /// before it, or failing that a later one in an enclosing block.
+    fn resolve(&self, name: &str, pos: (usize, usize)) -> Option<usize> {
+        let visible: Vec<usize> = self.visible(pos).into_iter().filter(|&id| self.symbols[id].name == name).collect();
+        let declared_before = |&&id: &&usize| (self.symbols[id].line, self.symbols[id].column) <= pos;
+        visible.iter().find(declared_before).or_else(|| visible.last()).copied()
+    }
+
+    /// How hover shows a symbol.
+    fn describe(&self, id: usize) -> String {
+        let symbol = &self.symbols[id];
+        let params = symbol.params.join(", ");
+        let object = self.member_of(id).map(|object| self.symbols[object].name.as_str());
+        match (symbol.kind, object) {
+            (SymbolKind::Variable, Some(object)) => format!("(member) {}.{}", object, symbol.name),
+            (SymbolKind::Variable, None) => format!("var {}", symbol.name),
+            (SymbolKind::Parameter, _) => format!("(parameter) {}", symbol.name),
+            (SymbolKind::Function, Some(object)) => format!("func {}.{}({})", object, symbol.name, params),
+            (SymbolKind::Function, None) => format!("func {}({})", symbol.name, params),
+            (SymbolKind::Object, _) => format!("object {}", symbol.name),
+            (SymbolKind::Event, _) => format!("on {}({})", symbol.name, params),
+            (SymbolKind::LoopVariable, _) => format!("(loop variable) {}", symbol.name),
+            (SymbolKind::CatchVariable, _) => format!("(catch variable) {}", symbol.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "var health = 10;
+func heal(amount): {
+    health = max(health + amount, 100);
+    return health;
+} end
+object Enemy: {
+    var hp = 50;
+    func attack(self): {
+        return self.hp;
+    } end
+    on update(dt): { } end
+} end
+Enemy.attack(Enemy);
+";
+
+    fn frame(message: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", message.len(), message)
+    }
+
+    fn open(server: &mut Server, text: &str) -> Value {
+        let params = Json::Object(vec![(
+            "textDocument",
+            Json::Object(vec![("uri", Json::str("file:///a.arc")), ("text", Json::str(text))]),
+        )]);
+        let message = format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{}}}"#, params);
+        json::decode(&server.handle(&message)[0].to_string()).unwrap()
+    }
+
+    /// Sends a request at a 0-based position and decodes the result.
+    fn request(server: &mut Server, method: &str, line: usize, character: usize) -> Value {
+        let message = format!(
+            r#"{{"jsonrpc":"2.0","id":7,"method":"{}","params":{{"textDocument":{{"uri":"file:///a.arc"}},"position":{{"line":{},"character":{}}}}}}}"#,
+            method, line, character
+        );
+        let reply = json::decode(&server.handle(&message)[0].to_string()).unwrap();
+        field(&reply, "result").cloned().unwrap_or(Value::Nil)
+    }
+
+    fn labels(items: &Value, key: &str) -> Vec<String> {
+        match items {
+            Value::Table(table) => list_items(table).iter().filter_map(|item| str_field(item, key).map(String::from)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_scripted_session() {
+        let messages = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.arc","languageId":"arcscript","version":1,"text":"var x = ;\n"}}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.arc","version":2},"contentChanges":[{"text":"var x = 1;\nprintln(x);\n"}]}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/formatting","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ];
+        let input: String = messages.iter().map(|m| frame(m)).collect();
+        let mut output = Vec::new();
+        let code = run(io::Cursor::new(input), &mut output).unwrap();
+        assert_eq!(code, 0);
+
+        let mut output = io::Cursor::new(output);
+        let mut replies = Vec::new();
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        assert_eq!(replies.len(), 5);
+        let init = json::decode(&replies[0]).unwrap();
+        assert_eq!(field(&init, "id"), Some(&Value::Int(1)));
+        assert_eq!(field(&init, "result.capabilities.hoverProvider"), Some(&Value::Bool(true)));
+        assert_eq!(
+            replies[1],
+            r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.arc","diagnostics":[{"range":{"start":{"line":0,"character":8},"end":{"line":0,"character":9}},"severity":1,"source":"arcscript","message":"unexpected token in primary expression: Semicolon"}]}}"#
+        );
+        // Fixing the error clears the diagnostics
+        assert!(replies[2].ends_with(r#""diagnostics":[]}}"#));
+        let unknown = json::decode(&replies[3]).unwrap();
+        assert_eq!(field(&unknown, "error.code"), Some(&Value::Int(METHOD_NOT_FOUND)));
+        assert_eq!(replies[4], r#"{"jsonrpc":"2.0","id":3,"result":null}"#);
+    }
+
+    #[test]
+    fn test_hover_and_definition() {
+        let mut server = Server::new();
+        open(&mut server, SCRIPT);
+
+        let hover = request(&mut server, "textDocument/hover", 2, 14);
+        assert_eq!(
+            str_field(&hover, "contents.value"),
+            Some("```arcscript\nmax(value, values...)\n```\n\nLargest of the values")
+        );
+        let hover = request(&mut server, "textDocument/hover", 2, 30);
+        assert_eq!(str_field(&hover, "contents.value"), Some("```arcscript\n(parameter) amount\n```"));
+        let hover = request(&mut server, "textDocument/hover", 6, 9);
+        assert_eq!(str_field(&hover, "contents.value"), Some("```arcscript\n(member) Enemy.hp\n```\n\nDeclared on line 7"));
+        let hover = request(&mut server, "textDocument/hover", 0, 5);
+        assert_eq!(str_field(&hover, "contents.value"), Some("```arcscript\nvar health\n```\n\nDeclared on line 1"));
+
+        // health inside heal() goes to the global
+        let definition = request(&mut server, "textDocument/definition", 3, 12);
+        assert_eq!(int_field(&definition, "range.start.line"), Some(0));
+        assert_eq!(int_field(&definition, "range.start.character"), Some(4));
+        // Enemy.attack goes to the method
+        let definition = request(&mut server, "textDocument/definition", 12, 8);
+        assert_eq!(int_field(&definition, "range.start.line"), Some(7));
+        assert_eq!(int_field(&definition, "range.start.character"), Some(9));
+        // Builtins have no source to go to
+        assert_eq!(request(&mut server, "textDocument/definition", 2, 14), Value::Nil);
+    }
+
+    #[test]
+    fn test_symbols_and_completion() {
+        let mut server = Server::new();
+        open(&mut server, SCRIPT);
+
+        let message = r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///a.arc"}}}"#;
+        let reply = json::decode(&server.handle(message)[0].to_string()).unwrap();
+        let symbols = field(&reply, "result").unwrap();
+        assert_eq!(labels(symbols, "name"), ["heal", "Enemy"]);
+        assert_eq!(str_field(symbols, "0.detail"), Some("(amount)"));
+        assert_eq!(int_field(symbols, "0.range.end.line"), Some(4));
+        assert_eq!(labels(field(symbols, "1.children").unwrap(), "name"), ["attack", "update"]);
+        assert_eq!(int_field(symbols, "1.children.0.kind"), Some(6));
+        assert_eq!(int_field(symbols, "1.children.1.kind"), Some(24));
+
+        // Members after a dot
+        open(&mut server, &format!("{}string.sp\nEnemy.\n", SCRIPT));
+        let items = labels(&request(&mut server, "textDocument/completion", 13, 9), "label");
+        assert!(items.contains(&"split".to_string()) && items.contains(&"format".to_string()));
+        assert!(!items.contains(&"max".to_string()));
+        assert_eq!(labels(&request(&mut server, "textDocument/completion", 14, 6), "label"), ["hp", "attack"]);
+
+        // Locals, then globals, builtins and keywords
+        let items = labels(&request(&mut server, "textDocument/completion", 3, 4), "label");
+        assert_eq!(items[..4], ["amount", "Enemy", "heal", "health"]);
+        assert!(items.contains(&"sqrt".to_string()) && items.contains(&"while".to_string()));
+        assert!(!items.contains(&"hp".to_string()));
+    }
+
+}
@@ -1,9 +1,6 @@
-mod lexer;
-mod parser;
-mod ast;
-mod interpreter;
+use arcscript::{dap, debugger, formatter, interpreter, lexer, limits, lint, lsp, optimizer, parser, profiler, reload, repl, resolver, testing};
 
-use std::io::{self, Write};
+use std::io;
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
@@ -11,7 +8,14 @@ fn main() {
     if args.len() > 1 {
         match args[1].as_str() {
             "repl" => run_repl(),
-            _ => run_file(&args[1]),
+            "run" => run_command(&args[2..]),
+            "test" => run_tests(&args[2..]),
+            "fmt" => run_fmt(&args[2..]),
+            "lint" => run_lint(&args[2..]),
+            "lsp" => run_lsp(),
+            "debug" => run_debug(&args[2..]),
+            "dap" => run_dap(),
+            _ => run_file(&args[1], false),
         }
     } else {
         run_demo();
@@ -42,6 +46,7 @@ fn run_demo() {
     };
 
     let mut interp = interpreter::Interpreter::new();
+    interp.set_limits(limits::Limits::recommended());
     match interp.eval_program(&program) {
         Ok(_) => println!("ArcScript demo script executed (function add(2, 3) was called)."),
         Err(e) => eprintln!("Runtime error: {}", e),
@@ -50,46 +55,455 @@ fn run_demo() {
 
 fn run_repl() {
     println!("ArcScript REPL v0.1.0");
-    println!("Type 'exit' or press Ctrl+C to quit.\n");
+    println!("Type :help for commands, exit or Ctrl+D to quit.\n");
 
+    let history = repl::History::default_path().map_or_else(repl::History::new, repl::History::load);
+    if let Err(e) = repl::run(io::stdin().lock(), io::stdout(), history) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    println!("Goodbye!");
+}
+
+fn run_file(path: &str, optimize: bool) {
+    let program = load_program(path, optimize);
     let mut interp = interpreter::Interpreter::new();
-    
+    interp.set_limits(limits::Limits::recommended());
+    if let Err(e) = interp.eval_program(&program) {
+        eprintln!("Runtime error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Reads, parses and resolves a script, and optimizes it if asked, exiting
+/// with the errors if that fails.
+fn load_program(path: &str, optimize: bool) -> arcscript::ast::Program {
+    let source = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error reading file '{}': {}", path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let lexer = lexer::Lexer::new(&source);
+    let mut parser = parser::Parser::new(lexer);
+    let mut program = match parser.parse_program() {
+        Ok(p) => p,
+        Err(errors) => {
+            eprintln!("Parse errors in '{}':", path);
+            for err in errors {
+                eprintln!("  {}:{}: {}", err.line, err.column, err.message);
+            }
+            std::process::exit(1);
+        }
+    };
+    if !resolve_program(path, &mut program) {
+        std::process::exit(1);
+    }
+    if optimize {
+        optimizer::optimize(&mut program);
+    }
+    program
+}
+
+/// Resolves a parsed script's variables, printing the errors if there are any.
+fn resolve_program(path: &str, program: &mut arcscript::ast::Program) -> bool {
+    match resolver::resolve(program) {
+        Ok(()) => true,
+        Err(errors) => {
+            eprintln!("Errors in '{}':", path);
+            for err in errors {
+                eprintln!("  {}: {}", err.line, err.message);
+            }
+            false
+        }
+    }
+}
+
+/// Prints the usage line and exits when a subcommand is given `--help`, so
+/// the flag isn't taken for a file path.
+fn exit_on_help(args: &[String], usage: &str) {
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        eprintln!("{}", usage);
+        std::process::exit(1);
+    }
+}
+
+fn run_command(args: &[String]) {
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let (watch, profile, optimize) = (flag("--watch"), flag("--profile"), flag("--optimize"));
+    let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) else {
+        eprintln!("Usage: arcscript run [--watch | --profile] [--optimize | --dump-ast] <file.arc>");
+        std::process::exit(1);
+    };
+    if flag("--dump-ast") {
+        return dump_ast(path);
+    }
+    match (watch, profile) {
+        (true, true) => {
+            eprintln!("--watch and --profile can't be used together");
+            std::process::exit(1);
+        }
+        // Reloads keep global values, which folded constants wouldn't follow
+        (true, false) if optimize => {
+            eprintln!("--watch and --optimize can't be used together");
+            std::process::exit(1);
+        }
+        (true, false) => run_watch(path),
+        (false, true) => run_profiled(path, optimize),
+        (false, false) => run_file(path, optimize),
+    }
+}
+
+/// Prints the optimized AST a statement at a time, as the REPL's :ast does,
+/// with what the optimizer changed on stderr. The script isn't run.
+fn dump_ast(path: &str) {
+    let mut program = load_program(path, false);
+    let report = optimizer::optimize(&mut program);
+    for stmt in &program.body {
+        println!("{:#?}", stmt.kind);
+    }
+    eprintln!("Optimized '{}': {}", path, report);
+}
+
+/// How many frames `run --profile` drives a script's `update(dt)` for, at
+/// PROFILE_DT seconds each: ten seconds of game time.
+const PROFILE_FRAMES: usize = 600;
+const PROFILE_DT: f64 = 1.0 / 60.0;
+
+/// Runs a script under the profiler, then its `update(dt)` and timers for
+/// PROFILE_FRAMES frames if it has one, as fast as they go. Prints the
+/// table to stderr and writes folded stacks next to the script.
+fn run_profiled(path: &str, optimize: bool) {
+    let program = load_program(path, optimize);
+    let mut interp = interpreter::Interpreter::new();
+    interp.set_limits(limits::Limits::recommended());
+    interp.set_profiler(profiler::Profiler::new());
+
+    let mut result = interp.eval_program(&program);
+    if let Some(interpreter::Value::Function { .. }) = interp.get_global("update") {
+        for _ in 0..PROFILE_FRAMES {
+            if result.is_err() {
+                break;
+            }
+            result = interp
+                .call_global("update", vec![interpreter::Value::Float(PROFILE_DT)])
+                .and_then(|_| interp.tick(PROFILE_DT))
+                .map(|_| ());
+        }
+    }
+
+    // A failed run is still worth profiling up to the error
+    let profiler = interp.take_profiler().unwrap_or_default();
+    eprint!("{}", profiler.table());
+    let folded_path = format!("{}.folded", path);
+    match std::fs::write(&folded_path, profiler.folded()) {
+        Ok(()) => eprintln!("Folded stacks written to {}", folded_path),
+        Err(e) => eprintln!("Error writing '{}': {}", folded_path, e),
+    }
+    if let Err(e) = result {
+        eprintln!("Runtime error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// How often `run --watch` checks the file and runs a frame.
+const WATCH_FRAME: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Runs a script, then keeps calling its `update(dt)`, `on update` handlers
+/// and timers each frame, reloading the file whenever it's saved. Errors are reported and the
+/// session carries on, so the next save can fix them.
+fn run_watch(path: &str) {
+    let mut watcher = reload::FileWatcher::new(path);
+    let mut interp = interpreter::Interpreter::new();
+    interp.set_limits(limits::Limits::recommended());
+    let mut loaded = false;
+    // After an error, frames stop until the next save rather than repeating it
+    let mut running = load_watched(&mut interp, path, &mut loaded);
+    println!("Watching {} (Ctrl+C to stop)", path);
+
     loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
-        
-        let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
-            break;
-        }
-        
-        let input = input.trim();
-        if input == "exit" || input.is_empty() {
-            break;
-        }
-        
-        let lexer = lexer::Lexer::new(input);
-        let mut parser = parser::Parser::new(lexer);
-        
-        match parser.parse_program() {
-            Ok(program) => {
-                match interp.eval_program(&program) {
-                    Ok(_) => {},
-                    Err(e) => eprintln!("Runtime error: {}", e),
-                }
+        std::thread::sleep(WATCH_FRAME);
+        if watcher.changed() {
+            running = load_watched(&mut interp, path, &mut loaded);
+        }
+        if !running {
+            continue;
+        }
+        let dt = WATCH_FRAME.as_secs_f64();
+        let update = match interp.get_global("update") {
+            Some(interpreter::Value::Function { .. }) => interp.call_global("update", vec![interpreter::Value::Float(dt)]).map(|_| ()),
+            _ => Ok(()),
+        };
+        let frame = update
+            .and_then(|_| interp.emit("update", vec![interpreter::Value::Float(dt)]))
+            .and_then(|_| interp.tick(dt));
+        if let Err(e) = frame {
+            eprintln!("Runtime error: {}", e);
+            running = false;
+        }
+    }
+}
+
+/// Runs the watched file the first time it parses, and reloads it after
+/// that. Returns whether frames should run.
+fn load_watched(interp: &mut interpreter::Interpreter, path: &str, loaded: &mut bool) -> bool {
+    let source = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error reading file '{}': {}", path, e);
+            return false;
+        }
+    };
+    if *loaded {
+        match reload::reload(interp, &source) {
+            Ok(report) => {
+                println!("Reloaded {}: {}", path, report);
+                true
+            }
+            Err(e) => {
+                eprintln!("Reload of '{}' failed, {}", path, e);
+                matches!(e, reload::ReloadError::Parse(_) | reload::ReloadError::Resolve(_))
             }
+        }
+    } else {
+        let mut program = match parser::Parser::new(lexer::Lexer::new(&source)).parse_program() {
+            Ok(p) => p,
             Err(errors) => {
+                eprintln!("Parse errors in '{}':", path);
                 for err in errors {
-                    eprintln!("Parse error at {}:{}: {}", err.line, err.column, err.message);
+                    eprintln!("  {}:{}: {}", err.line, err.column, err.message);
                 }
+                return false;
+            }
+        };
+        if !resolve_program(path, &mut program) {
+            return false;
+        }
+        *loaded = true;
+        match interp.eval_program(&program) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Runtime error: {}", e);
+                false
             }
         }
     }
-    
-    println!("\nGoodbye!");
 }
 
-fn run_file(path: &str) {
+fn run_tests(args: &[String]) {
+    exit_on_help(args, "Usage: arcscript test [files or directories...]");
+    let paths: Vec<std::path::PathBuf> = if args.is_empty() {
+        vec![".".into()]
+    } else {
+        args.iter().map(Into::into).collect()
+    };
+    let files = match testing::discover(&paths) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Error finding tests: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let start = std::time::Instant::now();
+    let (mut passed, mut failed) = (0, 0);
+    let mut failures = Vec::new();
+    for path in files {
+        let result = testing::run_file(&path);
+        if let Some(error) = &result.error {
+            println!("{}: could not run tests", path.display());
+            failures.push((path.display().to_string(), error.clone()));
+            failed += 1;
+            continue;
+        }
+        if result.tests.is_empty() {
+            continue;
+        }
+        println!("running {} tests in {}", result.tests.len(), path.display());
+        for test in &result.tests {
+            let status = if test.passed() { "ok" } else { "FAILED" };
+            println!("  {:<6}  {} ({:.1?})", status, test.name, test.duration);
+            match &test.outcome {
+                testing::Outcome::Passed => passed += 1,
+                testing::Outcome::Failed(report) => {
+                    failures.push((format!("{}::{}", path.display(), test.name), report.clone()));
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, report) in &failures {
+            println!("\n  {}", name);
+            for line in report.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+    let verdict = if failed == 0 { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed; finished in {:.1?}",
+        verdict,
+        passed,
+        failed,
+        start.elapsed()
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn run_fmt(args: &[String]) {
+    exit_on_help(args, "Usage: arcscript fmt [--check] [files or directories...]");
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<std::path::PathBuf> = args.iter().filter(|arg| *arg != "--check").map(Into::into).collect();
+    let paths = if paths.is_empty() { vec![".".into()] } else { paths };
+    let files = match testing::discover(&paths) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Error finding scripts: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let (mut changed, mut errors) = (0, 0);
+    for path in files {
+        let result = std::fs::read_to_string(&path)
+            .map_err(|e| format!("could not read file: {}", e))
+            .and_then(|source| formatter::format_source(&source).map(|formatted| (source, formatted)).map_err(|e| e.to_string()));
+        let (source, formatted) = match result {
+            Ok(pair) => pair,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                errors += 1;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        changed += 1;
+        if check {
+            println!("would reformat {}", path.display());
+        } else if let Err(e) = std::fs::write(&path, formatted) {
+            eprintln!("{}: could not write file: {}", path.display(), e);
+            errors += 1;
+        } else {
+            println!("formatted {}", path.display());
+        }
+    }
+
+    if errors > 0 || (check && changed > 0) {
+        std::process::exit(1);
+    }
+}
+
+fn run_lint(args: &[String]) {
+    exit_on_help(
+        args,
+        "Usage: arcscript lint [--allow | --warn | --deny <rule>] [--global <name>] [--event <name>] [files or directories...]",
+    );
+    let mut config = lint::LintConfig::new();
+    let mut paths: Vec<std::path::PathBuf> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let severity = match arg.as_str() {
+            "--allow" => Some(lint::Severity::Allow),
+            "--warn" => Some(lint::Severity::Warning),
+            "--deny" => Some(lint::Severity::Error),
+            "--global" | "--event" => None,
+            _ => {
+                paths.push(arg.into());
+                continue;
+            }
+        };
+        let Some(value) = args.next() else {
+            eprintln!("Error: {} needs a value", arg);
+            std::process::exit(1);
+        };
+        match severity {
+            Some(severity) => {
+                if let Err(e) = config.set(value, severity) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            None if arg == "--global" => config.globals.push(value.clone()),
+            None => config.events.push(value.clone()),
+        }
+    }
+    let paths = if paths.is_empty() { vec![".".into()] } else { paths };
+    let files = match testing::discover(&paths) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Error finding scripts: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let (mut warnings, mut errors) = (0, 0);
+    for path in files {
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("{}: could not read file: {}", path.display(), e);
+                errors += 1;
+                continue;
+            }
+        };
+        match lint::lint_source(&source, &config) {
+            Ok(diagnostics) => {
+                for d in diagnostics {
+                    println!("{}:{}: {}[{}]: {}", path.display(), d.line, d.severity.as_str(), d.rule, d.message);
+                    match d.severity {
+                        lint::Severity::Error => errors += 1,
+                        _ => warnings += 1,
+                    }
+                }
+            }
+            Err(errs) => {
+                for err in errs {
+                    println!("{}:{}: error[parse]: {}", path.display(), err.line, err.message);
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    println!("\nlint result: {} errors; {} warnings", errors, warnings);
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn run_lsp() {
+    match lsp::run(io::stdin().lock(), io::stdout().lock()) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_dap() {
+    if let Err(e) = dap::run(io::BufReader::new(io::stdin()), io::stdout()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run_debug(args: &[String]) {
+    const USAGE: &str = "Usage: arcscript debug <file.arc>";
+    exit_on_help(args, USAGE);
+    let Some(path) = args.first() else {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    };
     let source = match std::fs::read_to_string(path) {
         Ok(content) => content,
         Err(e) => {
@@ -97,10 +511,7 @@ fn run_file(path: &str) {
             std::process::exit(1);
         }
     };
-
-    let lexer = lexer::Lexer::new(&source);
-    let mut parser = parser::Parser::new(lexer);
-    let program = match parser.parse_program() {
+    let mut program = match parser::Parser::new(lexer::Lexer::new(&source)).parse_program() {
         Ok(p) => p,
         Err(errors) => {
             eprintln!("Parse errors in '{}':", path);
@@ -110,10 +521,20 @@ fn run_file(path: &str) {
             std::process::exit(1);
         }
     };
+    if !resolve_program(path, &mut program) {
+        std::process::exit(1);
+    }
 
+    let console = debugger::Console::new(&source, &program, Box::new(io::BufReader::new(io::stdin())), Box::new(io::stdout()));
     let mut interp = interpreter::Interpreter::new();
-    if let Err(e) = interp.eval_program(&program) {
-        eprintln!("Runtime error: {}", e);
-        std::process::exit(1);
+    interp.set_limits(limits::Limits::recommended());
+    interp.set_debug_hook(Box::new(console));
+    match interp.eval_program(&program) {
+        Ok(()) => println!("Program finished"),
+        Err(e) if e.kind == interpreter::ErrorKind::Interrupted => println!("Program stopped"),
+        Err(e) => {
+            eprintln!("Runtime error: {}", e);
+            std::process::exit(1);
+        }
     }
 }
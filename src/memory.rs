@@ -0,0 +1,141 @@
+// ArcScript memory accounting for strings and tables
+//
+// Values are plain Rust data with no allocation hooks, so usage is tracked the
+// way a garbage collector would: bytes allocated since the last measurement
+// are added to the live size that measurement found. Before refusing an
+// allocation, the interpreter re-measures what it can still reach, so garbage
+// never counts against the cap.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Allocations below this are never worth a re-measurement on their own.
+const MIN_MEASURE_BYTES: usize = 64 * 1024;
+
+static NEXT_ROOT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Approximate bytes held by script strings and tables.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub current: usize,
+    pub peak: usize,
+}
+
+/// Usage of one interpreter: the main one and each coroutine measure their own
+/// environments.
+#[derive(Debug, Default)]
+struct RootUsage {
+    live: usize,
+    allocated: usize,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    roots: HashMap<u64, RootUsage>,
+    peak: usize,
+}
+
+impl TrackerState {
+    fn current(&self) -> usize {
+        self.roots.values().map(|r| r.live + r.allocated).sum()
+    }
+}
+
+/// What the allocation needs from the interpreter before it can go ahead.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Allocation {
+    Granted,
+    /// Enough has been allocated since the last measurement that the live size
+    /// is stale; the allocation was still recorded
+    Measure,
+    /// The allocation would exceed the cap and was not recorded
+    OverLimit,
+}
+
+/// Shared by an interpreter and the coroutines it spawns.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryTracker {
+    state: Mutex<TrackerState>,
+}
+
+impl MemoryTracker {
+    /// Registers a new interpreter whose environments are measured separately.
+    pub(crate) fn register(&self) -> u64 {
+        let id = NEXT_ROOT_ID.fetch_add(1, Ordering::Relaxed);
+        self.state().roots.insert(id, RootUsage::default());
+        id
+    }
+
+    pub(crate) fn unregister(&self, id: u64) {
+        self.state().roots.remove(&id);
+    }
+
+    /// Records `bytes` allocated by interpreter `id` unless that would push
+    /// the total over `cap`.
+    pub(crate) fn allocate(&self, id: u64, bytes: usize, cap: Option<usize>) -> Allocation {
+        let mut state = self.state();
+        let current = state.current();
+        if matches!(cap, Some(cap) if current + bytes > cap) {
+            return Allocation::OverLimit;
+        }
+        state.peak = state.peak.max(current + bytes);
+        let root = state.roots.entry(id).or_default();
+        root.allocated += bytes;
+        if root.allocated > root.live.max(MIN_MEASURE_BYTES) {
+            Allocation::Measure
+        } else {
+            Allocation::Granted
+        }
+    }
+
+    /// Replaces interpreter `id`'s estimate with a fresh measurement of
+    /// everything it can reach.
+    pub(crate) fn measured(&self, id: u64, live: usize) {
+        let mut state = self.state();
+        state.roots.insert(id, RootUsage { live, allocated: 0 });
+        state.peak = state.peak.max(state.current());
+    }
+
+    pub(crate) fn usage(&self) -> MemoryUsage {
+        let state = self.state();
+        MemoryUsage { current: state.current(), peak: state.peak }
+    }
+
+    pub(crate) fn reset_peak(&self) {
+        let mut state = self.state();
+        state.peak = state.current();
+    }
+
+    fn state(&self) -> MutexGuard<'_, TrackerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cap_and_measurement() {
+        let tracker = MemoryTracker::default();
+        let id = tracker.register();
+        assert_eq!(tracker.allocate(id, 600, Some(1000)), Allocation::Granted);
+        assert_eq!(tracker.allocate(id, 600, Some(1000)), Allocation::OverLimit);
+
+        // Most of it turned out to be garbage
+        tracker.measured(id, 100);
+        assert_eq!(tracker.allocate(id, 600, Some(1000)), Allocation::Granted);
+        assert_eq!(tracker.usage(), MemoryUsage { current: 700, peak: 700 });
+
+        tracker.unregister(id);
+        assert_eq!(tracker.usage().current, 0);
+    }
+
+    #[test]
+    fn test_large_allocations_request_measurement() {
+        let tracker = MemoryTracker::default();
+        let id = tracker.register();
+        assert_eq!(tracker.allocate(id, MIN_MEASURE_BYTES + 1, None), Allocation::Measure);
+    }
+}
@@ -0,0 +1,547 @@
+// ArcScript optimizer: does the work that never changes once, before running
+//
+// An optional pass over a parsed program. Arithmetic, comparisons and string
+// concatenation on literals are folded into their result, worked out by the
+// interpreter's own operators so the value is exactly what running them
+// gives; anything that would fail, like 1 / 0, is left to fail at runtime.
+// Top-level variables set to a constant and never assigned again are folded
+// into the code after them, so `if DEBUG then` settles too. Ifs and whiles
+// with a constant condition lose the branches that can't run, and statements
+// after a return, break, continue or throw are dropped. Used by
+// `arcscript run --optimize` and `--dump-ast`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{Expr, Literal, ObjectMember, Param, Program, Stmt, StmtKind, TableField};
+use crate::interpreter::{Interpreter, Value};
+
+/// What one optimize run changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OptimizeReport {
+    /// Expressions replaced by their value
+    pub folded: usize,
+    /// If and while conditions that turned out constant
+    pub conditions: usize,
+    /// Statements after a return, break, continue or throw
+    pub removed: usize,
+}
+
+impl fmt::Display for OptimizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(
+            f,
+            "folded {} expression{}, settled {} condition{}, removed {} unreachable statement{}",
+            self.folded,
+            plural(self.folded),
+            self.conditions,
+            plural(self.conditions),
+            self.removed,
+            plural(self.removed),
+        )
+    }
+}
+
+/// Optimizes `program` in place. Run it after resolver::resolve, so code
+/// this drops is still checked. Top-level constants are taken to keep the
+/// value the script gives them: a host changing one with set_global, or a
+/// hot reload, won't reach the places it was folded into.
+pub fn optimize(program: &mut Program) -> OptimizeReport {
+    let mut declarations = HashMap::new();
+    let mut assigned = HashSet::new();
+    scan(&program.body, &mut declarations, &mut assigned);
+    let candidates = declarations
+        .into_iter()
+        .filter(|(name, count)| *count == 1 && !assigned.contains(name))
+        .map(|(name, _)| name)
+        .collect();
+
+    let mut optimizer = Optimizer {
+        interp: Interpreter::new(),
+        candidates,
+        constants: HashMap::new(),
+        report: OptimizeReport::default(),
+    };
+    optimizer.stmts(&mut program.body, true);
+    optimizer.report
+}
+
+struct Optimizer {
+    /// Evaluates the operators being folded
+    interp: Interpreter,
+    /// Names declared once in the whole program and never assigned
+    candidates: HashSet<String>,
+    /// Top-level constants declared so far, with their values
+    constants: HashMap<String, Literal>,
+    report: OptimizeReport,
+}
+
+impl Optimizer {
+    fn stmts(&mut self, stmts: &mut Vec<Stmt>, top_level: bool) {
+        let mut reachable = true;
+        let mut kept = Vec::with_capacity(stmts.len());
+        for mut stmt in std::mem::take(stmts) {
+            if !reachable {
+                // Comments stay, as they don't run either way
+                if !matches!(stmt.kind, StmtKind::Trivia(_)) {
+                    self.report.removed += 1;
+                    continue;
+                }
+            } else if !self.stmt(&mut stmt) {
+                continue;
+            }
+            if top_level {
+                if let StmtKind::VarDecl { name, init: Expr::Literal(value) } = &stmt.kind {
+                    if self.candidates.contains(name) {
+                        self.constants.insert(name.clone(), value.clone());
+                    }
+                }
+            }
+            if matches!(stmt.kind, StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue | StmtKind::Throw { .. }) {
+                reachable = false;
+            }
+            kept.push(stmt);
+        }
+        *stmts = kept;
+    }
+
+    /// Optimizes one statement, returning false if it can't do anything and
+    /// should go.
+    fn stmt(&mut self, stmt: &mut Stmt) -> bool {
+        match &mut stmt.kind {
+            StmtKind::VarDecl { init: expr, .. }
+            | StmtKind::Assignment { value: expr, .. }
+            | StmtKind::Expr(expr)
+            | StmtKind::Throw { value: expr }
+            | StmtKind::Return(Some(expr)) => self.expr(expr),
+            StmtKind::MemberAssignment { object, value, .. } => {
+                self.expr(object);
+                self.expr(value);
+            }
+            StmtKind::IndexAssignment { object, index, value } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::Block(stmts) => self.stmts(stmts, false),
+            StmtKind::If { condition, then_branch, elif_branches, else_branch } => {
+                self.expr(condition);
+                self.stmt(then_branch);
+                for (condition, branch) in elif_branches {
+                    self.expr(condition);
+                    self.stmt(branch);
+                }
+                if let Some(branch) = else_branch {
+                    self.stmt(branch);
+                }
+                return self.settle_if(stmt);
+            }
+            StmtKind::While { condition, body } => {
+                self.expr(condition);
+                if truthiness(condition) == Some(false) {
+                    self.report.conditions += 1;
+                    return false;
+                }
+                self.stmt(body);
+            }
+            StmtKind::For { start, end, step, body, .. } => {
+                self.expr(start);
+                self.expr(end);
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+                self.stmt(body);
+            }
+            StmtKind::Try { body, catch_body, finally_body, .. } => {
+                self.stmts(body, false);
+                if let Some(catch_body) = catch_body {
+                    self.stmts(catch_body, false);
+                }
+                if let Some(finally_body) = finally_body {
+                    self.stmts(finally_body, false);
+                }
+            }
+            StmtKind::FuncDecl(func) => {
+                self.stmt(&mut func.body);
+            }
+            StmtKind::ObjectDecl(obj) => {
+                for member in &mut obj.members {
+                    match member {
+                        ObjectMember::Var(var) => {
+                            self.stmt(var);
+                        }
+                        ObjectMember::Method(func) => {
+                            self.stmt(&mut func.body);
+                        }
+                        ObjectMember::Event(event) => {
+                            self.stmt(&mut event.body);
+                        }
+                        ObjectMember::Trivia(_) => {}
+                    }
+                }
+            }
+            StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue | StmtKind::Trivia(_) => {}
+        }
+        true
+    }
+
+    /// Drops the branches of an if that constant conditions rule out. The if
+    /// becomes its only remaining branch, or goes if there's none left.
+    fn settle_if(&mut self, stmt: &mut Stmt) -> bool {
+        let constant = |condition: &Expr| truthiness(condition).is_some();
+        match &stmt.kind {
+            StmtKind::If { condition, elif_branches, .. }
+                if constant(condition) || elif_branches.iter().any(|(condition, _)| constant(condition)) => {}
+            _ => return true,
+        }
+        let StmtKind::If { condition, then_branch, elif_branches, else_branch } = std::mem::replace(&mut stmt.kind, StmtKind::Break)
+        else {
+            unreachable!()
+        };
+
+        // Branches whose conditions are still tested at runtime
+        let mut tested = Vec::new();
+        let mut otherwise = else_branch.map(|branch| *branch);
+        for (condition, branch) in std::iter::once((condition, *then_branch)).chain(elif_branches) {
+            match truthiness(&condition) {
+                None => tested.push((condition, branch)),
+                Some(false) => self.report.conditions += 1,
+                Some(true) => {
+                    self.report.conditions += 1;
+                    otherwise = Some(branch);
+                    break;
+                }
+            }
+        }
+
+        let mut tested = tested.into_iter();
+        match (tested.next(), otherwise) {
+            (Some((condition, then_branch)), else_branch) => {
+                stmt.kind = StmtKind::If {
+                    condition,
+                    then_branch: Box::new(then_branch),
+                    elif_branches: tested.collect(),
+                    else_branch: else_branch.map(Box::new),
+                };
+                true
+            }
+            // The branch is a block, so its variables keep their own scope
+            (None, Some(branch)) => {
+                stmt.kind = branch.kind;
+                true
+            }
+            (None, None) => false,
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Literal(_) | Expr::Local { .. } => return,
+            Expr::Ident(name) => {
+                if let Some(value) = self.constants.get(name) {
+                    *expr = Expr::Literal(value.clone());
+                    self.report.folded += 1;
+                }
+                return;
+            }
+            Expr::Unary { expr: operand, .. } => {
+                self.expr(operand);
+                if !matches!(operand.as_ref(), Expr::Literal(_)) {
+                    return;
+                }
+            }
+            Expr::Binary { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+                if !matches!((left.as_ref(), right.as_ref()), (Expr::Literal(_), Expr::Literal(_))) {
+                    return;
+                }
+            }
+            Expr::Call { callee, args } => {
+                self.expr(callee);
+                args.iter_mut().for_each(|arg| self.expr(arg));
+                return;
+            }
+            Expr::Member { object, .. } => return self.expr(object),
+            Expr::Index { object, index } => {
+                self.expr(object);
+                self.expr(index);
+                return;
+            }
+            Expr::TableLiteral(fields) => {
+                for field in fields {
+                    match field {
+                        TableField::KeyValue { value, .. } | TableField::Value(value) => self.expr(value),
+                        TableField::Trivia(_) => {}
+                    }
+                }
+                return;
+            }
+        }
+        // An operator on literals. Errors are left for runtime to report
+        if let Some(value) = self.interp.eval_constant(expr).ok().and_then(literal) {
+            *expr = Expr::Literal(value);
+            self.report.folded += 1;
+        }
+    }
+}
+
+/// Whether a constant condition holds, or None if it isn't constant.
+fn truthiness(condition: &Expr) -> Option<bool> {
+    match condition {
+        Expr::Literal(Literal::Bool(b)) => Some(*b),
+        Expr::Literal(Literal::Nil) => Some(false),
+        Expr::Literal(_) => Some(true),
+        _ => None,
+    }
+}
+
+fn literal(value: Value) -> Option<Literal> {
+    match value {
+        Value::Int(i) => Some(Literal::Int(i)),
+        Value::Float(f) if f.is_finite() => Some(Literal::Float(f)),
+        Value::Bool(b) => Some(Literal::Bool(b)),
+        Value::String(s) => Some(Literal::String(s)),
+        Value::Nil => Some(Literal::Nil),
+        _ => None,
+    }
+}
+
+/// Counts the declarations of each name, in any scope, and collects the
+/// names that are assigned, or have a field or index assigned.
+fn scan(stmts: &[Stmt], declarations: &mut HashMap<String, usize>, assigned: &mut HashSet<String>) {
+    for stmt in stmts {
+        scan_stmt(stmt, declarations, assigned);
+    }
+}
+
+fn scan_stmt(stmt: &Stmt, declarations: &mut HashMap<String, usize>, assigned: &mut HashSet<String>) {
+    fn declare(declarations: &mut HashMap<String, usize>, name: &str) {
+        *declarations.entry(name.to_string()).or_insert(0) += 1;
+    }
+    match &stmt.kind {
+        StmtKind::VarDecl { name, .. } => declare(declarations, name),
+        StmtKind::Assignment { name, .. } => {
+            assigned.insert(name.clone());
+        }
+        StmtKind::MemberAssignment { object, .. } | StmtKind::IndexAssignment { object, .. } => {
+            if let Some(name) = root_name(object) {
+                assigned.insert(name.to_string());
+            }
+        }
+        StmtKind::Block(body) => scan(body, declarations, assigned),
+        StmtKind::If { then_branch, elif_branches, else_branch, .. } => {
+            scan_stmt(then_branch, declarations, assigned);
+            for (_, branch) in elif_branches {
+                scan_stmt(branch, declarations, assigned);
+            }
+            if let Some(branch) = else_branch {
+                scan_stmt(branch, declarations, assigned);
+            }
+        }
+        StmtKind::While { body, .. } => scan_stmt(body, declarations, assigned),
+        StmtKind::For { var_name, body, .. } => {
+            declare(declarations, var_name);
+            scan_stmt(body, declarations, assigned);
+        }
+        StmtKind::Try { body, catch_name, catch_body, finally_body } => {
+            if let Some(name) = catch_name {
+                declare(declarations, name);
+            }
+            for body in [Some(body), catch_body.as_ref(), finally_body.as_ref()].into_iter().flatten() {
+                scan(body, declarations, assigned);
+            }
+        }
+        StmtKind::FuncDecl(func) => {
+            declare(declarations, &func.name);
+            func.params.iter().for_each(|param| declare(declarations, &param.name));
+            scan_stmt(&func.body, declarations, assigned);
+        }
+        StmtKind::ObjectDecl(obj) => {
+            declare(declarations, &obj.name);
+            for member in &obj.members {
+                let (params, body): (&[Param], &Stmt) = match member {
+                    ObjectMember::Method(func) => (&func.params, &func.body),
+                    ObjectMember::Event(event) => (&event.params, &event.body),
+                    ObjectMember::Var(_) | ObjectMember::Trivia(_) => continue,
+                };
+                params.iter().for_each(|param| declare(declarations, &param.name));
+                scan_stmt(body, declarations, assigned);
+            }
+        }
+        StmtKind::Expr(_) | StmtKind::Return(_) | StmtKind::Throw { .. } | StmtKind::Break | StmtKind::Continue | StmtKind::Trivia(_) => {}
+    }
+}
+
+/// The variable an assignment target like `a.b[1]` starts from.
+fn root_name(target: &Expr) -> Option<&str> {
+    match target {
+        Expr::Ident(name) | Expr::Local { name, .. } => Some(name),
+        Expr::Member { object, .. } | Expr::Index { object, .. } => root_name(object),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatter::format_program;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::resolver;
+    use std::sync::{Arc, Mutex};
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source)).parse_program().unwrap()
+    }
+
+    fn optimized(source: &str) -> (String, OptimizeReport) {
+        let mut program = parse(source);
+        let report = optimize(&mut program);
+        (format_program(&program), report)
+    }
+
+    #[test]
+    fn test_folding_and_dead_code() {
+        let (source, report) = optimized(
+            "var speed = 60 * 2.5;
+             var DEBUG = false;
+             var title = \"Arc\" + \"Script\" + \" \" + str(2);
+             func update(dt): {
+                 if DEBUG then {
+                     println(\"debug\");
+                 } elif dt > speed / 100 then {
+                     println(\"slow frame\");
+                 } elif not DEBUG then {
+                     println(\"fine\");
+                 } else {
+                     println(\"never\");
+                 } end
+                 while DEBUG and 1 < 2 do { println(\"never\"); } end
+                 return dt * speed;
+                 println(\"unreachable\");
+             } end
+             func fail(): { return 1 / 0; } end",
+        );
+        assert_eq!(
+            source,
+            "var speed = 150.0;
+var DEBUG = false;
+var title = \"ArcScript \" + str(2);
+func update(dt): {
+    if dt > 1.5 then {
+        println(\"slow frame\");
+    } else {
+        println(\"fine\");
+    } end
+    return dt * 150.0;
+} end
+func fail(): {
+    return 1 / 0;
+} end
+"
+        );
+        assert_eq!(report, OptimizeReport { folded: 12, conditions: 3, removed: 1 });
+        assert_eq!(report.to_string(), "folded 12 expressions, settled 3 conditions, removed 1 unreachable statement");
+    }
+
+    #[test]
+    fn test_variables_that_may_change_are_not_constants() {
+        let (source, _) = optimized(
+            "func early(): { return LIVES; } end
+             var LIVES = 3;
+             var level = 1;
+             level = level + 1;
+             var hero = {hp: 10};
+             hero.hp = 5;
+             var step = 2;
+             func walk(step): { return step * 2; } end
+             println(early, LIVES, level, hero, step);",
+        );
+        assert_eq!(
+            source,
+            "func early(): {
+    return LIVES;
+} end
+var LIVES = 3;
+var level = 1;
+level = level + 1;
+var hero = {hp: 10};
+hero.hp = 5;
+var step = 2;
+func walk(step): {
+    return step * 2;
+} end
+println(early, 3, level, hero, step);
+"
+        );
+    }
+
+    #[test]
+    fn test_optimized_programs_run_the_same() {
+        let programs = [
+            "var RATE = 60 * 2.5;
+             var DEBUG = false;
+             var NAME = \"arc\" + \"script\";
+             func scale(x): {
+                 if DEBUG then { println(\"scaling\", x); } end
+                 if x < 0 then { return -x * RATE; } elif 1 == 1 then { return x * RATE; } end
+                 return 0;
+             } end
+             println(scale(2), scale(-1), NAME, 7 % 3, 2 >= 2.0, not nil, 1 != 2 or DEBUG);",
+            "var total = 0;
+             for i = 1, 10, 1 do {
+                 if i % 2 == 0 then { continue; total = total + 100; } end
+                 total = total + i * (2 + 3);
+             } end
+             while false do { total = 0; } end
+             println(total);",
+            "func safe(): {
+                 try {
+                     throw \"bad \" + \"input\";
+                     println(\"never\");
+                 } catch e {
+                     return e.message;
+                 } end
+             } end
+             println(safe());
+             var x = 1;
+             if true then { var x = 2; println(x); } end
+             println(x);
+             return;
+             println(\"after return\");",
+            "println(\"before\");
+             var zero = 0;
+             println(10 / (5 - 5));",
+            // Overflowing constants are left for the interpreter to reject
+            "func never(): { return 9223372036854775807 + 1; } end
+             func flip(): { return -(-9223372036854775807 - 1); } end
+             println(\"ok\");
+             println(flip());",
+        ];
+        let run = |source: &str, optimized: bool| {
+            let mut program = parse(source);
+            resolver::resolve(&mut program).unwrap();
+            if optimized {
+                optimize(&mut program);
+            }
+            let output = Arc::new(Mutex::new(String::new()));
+            let sink = Arc::clone(&output);
+            let mut interp = Interpreter::new();
+            interp.set_print_handler(Arc::new(move |text: &str| sink.lock().unwrap().push_str(text)));
+            let result = interp.eval_program(&program).map_err(|e| e.message);
+            let output = output.lock().unwrap().clone();
+            (output, result)
+        };
+        for source in programs {
+            assert_eq!(run(source, true), run(source, false), "{}", source);
+        }
+        assert_eq!(
+            run(programs[0], true),
+            ("300 150 arcscript 1 true true true\n".to_string(), Ok(()))
+        );
+        assert_eq!(run(programs[3], true), ("before\n".to_string(), Err("division by zero".to_string())));
+        assert_eq!(run(programs[4], true), ("ok\n".to_string(), Err("integer overflow".to_string())));
+    }
+}
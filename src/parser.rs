@@ -1,10 +1,9 @@
 // ArcScript parser skeleton
 
 use crate::ast::{
-    BinaryOp, Expr, FuncDecl, Literal, Param, Program, Stmt, UnaryOp,
+    BinaryOp, Expr, FuncDecl, Literal, Param, Program, Stmt, StmtKind, Trivia, UnaryOp,
 };
 
-use std::fmt;
 use crate::lexer::{Lexer, Token, TokenKind};
 
 #[derive(Debug, Clone)]
@@ -59,7 +58,11 @@ impl<'a> Parser<'a> {
 
     pub fn parse_program(&mut self) -> Result<Program, Vec<ParseError>> {
         let mut body = Vec::new();
-        while self.current.kind != TokenKind::Eof {
+        loop {
+            body.extend(self.trivia_stmts());
+            if self.current.kind == TokenKind::Eof {
+                break;
+            }
             match self.parse_statement() {
                 Ok(stmt) => body.push(stmt),
                 Err(e) => {
@@ -79,15 +82,24 @@ impl<'a> Parser<'a> {
         self.current = self.lexer.next_token();
     }
 
-    fn consume(&mut self, kind: TokenKind, msg: &str) {
-        if self.current.kind == kind {
-            self.advance();
-        } else {
-            panic!("Parse error: expected {:?}, got {:?}: {}", kind, self.current.kind, msg);
-        }
+    /// Comments and blank lines before the current token. Always empty unless
+    /// the lexer was made with `Lexer::with_trivia`.
+    fn take_trivia(&mut self) -> Vec<Trivia> {
+        std::mem::take(&mut self.current.trivia)
+    }
+
+    fn trivia_stmts(&mut self) -> Vec<Stmt> {
+        let line = self.current.line;
+        self.take_trivia().into_iter().map(|trivia| Stmt::new(StmtKind::Trivia(trivia), line)).collect()
     }
 
     fn parse_statement(&mut self) -> Result<Stmt, ParseError> {
+        let line = self.current.line;
+        let kind = self.parse_statement_kind()?;
+        Ok(Stmt::new(kind, line))
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<StmtKind, ParseError> {
         match self.current.kind {
             TokenKind::KwVar => self.parse_var_decl(),
             TokenKind::KwIf => self.parse_if_stmt(),
@@ -98,87 +110,93 @@ impl<'a> Parser<'a> {
                 if self.current.kind == TokenKind::Semicolon {
                     self.advance();
                 }
-                Ok(Stmt::Break)
+                Ok(StmtKind::Break)
             }
             TokenKind::KwContinue => {
                 self.advance();
                 if self.current.kind == TokenKind::Semicolon {
                     self.advance();
                 }
-                Ok(Stmt::Continue)
+                Ok(StmtKind::Continue)
             }
             TokenKind::KwReturn => self.parse_return_stmt(),
+            TokenKind::KwTry => self.parse_try_stmt(),
+            TokenKind::KwThrow => {
+                self.advance();
+                let value = self.parse_expression()?;
+                if self.current.kind == TokenKind::Semicolon {
+                    self.advance();
+                }
+                Ok(StmtKind::Throw { value })
+            }
             TokenKind::KwFunc => {
                 let func = self.parse_func_decl()?;
-                Ok(Stmt::FuncDecl(func))
+                Ok(StmtKind::FuncDecl(func))
             }
             TokenKind::KwObject => {
                 let obj = self.parse_object_decl()?;
-                Ok(Stmt::ObjectDecl(obj))
+                Ok(StmtKind::ObjectDecl(obj))
             }
             TokenKind::LBrace => {
                 let block = self.parse_block()?;
-                Ok(Stmt::Block(block))
+                Ok(StmtKind::Block(block))
             }
             _ => {
-                // Check for assignment operators (+=, -=, *=, /=)
-                // Parse identifier first to check for compound assignment
-                if let TokenKind::Identifier = self.current.kind {
-                    let name = self.current.lexeme.clone();
-                    self.advance();
-                    
-                    // Check for compound assignment
-                    let compound_op = match self.current.kind {
-                        TokenKind::PlusEqual => Some(BinaryOp::Add),
-                        TokenKind::MinusEqual => Some(BinaryOp::Sub),
-                        TokenKind::StarEqual => Some(BinaryOp::Mul),
-                        TokenKind::SlashEqual => Some(BinaryOp::Div),
-                        TokenKind::Equal => Some(BinaryOp::Add), // Just a marker, we'll handle = separately
-                        _ => None,
-                    };
-                    
-                    if let Some(op) = compound_op {
-                        if self.current.kind == TokenKind::Equal {
-                            // Regular assignment: x = expr
-                            self.advance();
-                            let rhs = self.parse_expression()?;
-                            if self.current.kind == TokenKind::Semicolon {
-                                self.advance();
-                            }
-                            return Ok(Stmt::Assignment { name, value: rhs });
-                        } else {
-                            // Compound assignment: x += expr
+                // Parse the left-hand side as an expression first so that member
+                // and index targets (obj.field = v, obj["key"] += v) are supported
+                let expr = self.parse_expression()?;
+
+                // Check for assignment operators (=, +=, -=, *=, /=)
+                let compound_op = match self.current.kind {
+                    TokenKind::Equal => None,
+                    TokenKind::PlusEqual => Some(BinaryOp::Add),
+                    TokenKind::MinusEqual => Some(BinaryOp::Sub),
+                    TokenKind::StarEqual => Some(BinaryOp::Mul),
+                    TokenKind::SlashEqual => Some(BinaryOp::Div),
+                    _ => {
+                        // Plain expression statement
+                        if self.current.kind == TokenKind::Semicolon {
                             self.advance();
-                            let rhs = self.parse_expression()?;
-                            if self.current.kind == TokenKind::Semicolon {
-                                self.advance();
-                            }
-                            // Desugar: x += 5  becomes  x = x + 5
-                            let new_value = Expr::Binary {
-                                left: Box::new(Expr::Ident(name.clone())),
-                                op,
-                                right: Box::new(rhs),
-                            };
-                            return Ok(Stmt::Assignment { name, value: new_value });
                         }
+                        return Ok(StmtKind::Expr(expr));
                     }
-                    // If we get here, it wasn't an assignment - this is an error in our parsing
-                    // We consumed the identifier but can't backtrack easily
-                    // For now, return an error
-                    return Err(self.error("unexpected token after identifier"));
-                }
-                
-                // Not an identifier, parse as expression
-                let expr = self.parse_expression()?;
+                };
+                let op_error = self.error("invalid assignment target");
+                self.advance();
+                let rhs = self.parse_expression()?;
                 if self.current.kind == TokenKind::Semicolon {
                     self.advance();
                 }
-                Ok(Stmt::Expr(expr))
+
+                // Desugar: x += 5  becomes  x = x + 5
+                let value = match compound_op {
+                    Some(op) => Expr::Binary {
+                        left: Box::new(expr.clone()),
+                        op,
+                        right: Box::new(rhs),
+                    },
+                    None => rhs,
+                };
+
+                match expr {
+                    Expr::Ident(name) => Ok(StmtKind::Assignment { name, value }),
+                    Expr::Member { object, field } => Ok(StmtKind::MemberAssignment {
+                        object: *object,
+                        field,
+                        value,
+                    }),
+                    Expr::Index { object, index } => Ok(StmtKind::IndexAssignment {
+                        object: *object,
+                        index: *index,
+                        value,
+                    }),
+                    _ => Err(op_error),
+                }
             }
         }
     }
 
-    fn parse_var_decl(&mut self) -> Result<Stmt, ParseError> {
+    fn parse_var_decl(&mut self) -> Result<StmtKind, ParseError> {
         self.advance(); // consume 'var'
         let name = if let TokenKind::Identifier = self.current.kind {
             let n = self.current.lexeme.clone();
@@ -201,14 +219,18 @@ impl<'a> Parser<'a> {
         if self.current.kind == TokenKind::Semicolon {
             self.advance();
         }
-        Ok(Stmt::VarDecl { name, init })
+        Ok(StmtKind::VarDecl { name, init })
     }
 
     fn parse_block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         // assumes current token is '{'
         self.consume(TokenKind::LBrace, "expected '{' to start block")?;
         let mut stmts = Vec::new();
-        while self.current.kind != TokenKind::RBrace && self.current.kind != TokenKind::Eof {
+        loop {
+            stmts.extend(self.trivia_stmts());
+            if self.current.kind == TokenKind::RBrace || self.current.kind == TokenKind::Eof {
+                break;
+            }
             match self.parse_statement() {
                 Ok(stmt) => stmts.push(stmt),
                 Err(e) => { self.errors.push(e); self.synchronize(); }
@@ -218,24 +240,30 @@ impl<'a> Parser<'a> {
         Ok(stmts)
     }
 
-    fn parse_if_stmt(&mut self) -> Result<Stmt, ParseError> {
+    /// A `{ ... }` body as a statement, e.g. of a loop or function.
+    fn parse_body(&mut self) -> Result<Stmt, ParseError> {
+        let line = self.current.line;
+        Ok(Stmt::new(StmtKind::Block(self.parse_block()?), line))
+    }
+
+    fn parse_if_stmt(&mut self) -> Result<StmtKind, ParseError> {
         self.consume(TokenKind::KwIf, "expected 'if'")?;
         let condition = self.parse_expression()?;
         self.consume(TokenKind::KwThen, "expected 'then' after if condition")?;
-        let then_block = Stmt::Block(self.parse_block()?);
+        let then_block = self.parse_body()?;
 
         let mut elifs = Vec::new();
         while self.current.kind == TokenKind::KwElif {
             self.advance();
             let cond = self.parse_expression()?;
             self.consume(TokenKind::KwThen, "expected 'then' after elif condition")?;
-            let block = Stmt::Block(self.parse_block()?);
+            let block = self.parse_body()?;
             elifs.push((cond, block));
         }
 
         let else_branch = if self.current.kind == TokenKind::KwElse {
             self.advance();
-            let block = Stmt::Block(self.parse_block()?);
+            let block = self.parse_body()?;
             Some(Box::new(block))
         } else {
             None
@@ -243,7 +271,7 @@ impl<'a> Parser<'a> {
 
         self.consume(TokenKind::KwEnd, "expected 'end' after if statement")?;
 
-        Ok(Stmt::If {
+        Ok(StmtKind::If {
             condition,
             then_branch: Box::new(then_block),
             elif_branches: elifs,
@@ -251,19 +279,19 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn parse_while_stmt(&mut self) -> Result<Stmt, ParseError> {
+    fn parse_while_stmt(&mut self) -> Result<StmtKind, ParseError> {
         self.consume(TokenKind::KwWhile, "expected 'while'")?;
         let condition = self.parse_expression()?;
         self.consume(TokenKind::KwDo, "expected 'do' after while condition")?;
-        let body = Stmt::Block(self.parse_block()?);
+        let body = self.parse_body()?;
         self.consume(TokenKind::KwEnd, "expected 'end' after while statement")?;
-        Ok(Stmt::While {
+        Ok(StmtKind::While {
             condition,
             body: Box::new(body),
         })
     }
 
-    fn parse_for_stmt(&mut self) -> Result<Stmt, ParseError> {
+    fn parse_for_stmt(&mut self) -> Result<StmtKind, ParseError> {
         self.consume(TokenKind::KwFor, "expected 'for'")?;
         
         // Parse: for var_name = start, end [, step] do
@@ -289,13 +317,43 @@ impl<'a> Parser<'a> {
         };
         
         self.consume(TokenKind::KwDo, "expected 'do' before for body")?;
-        let body = Stmt::Block(self.parse_block()?);
+        let body = self.parse_body()?;
         self.consume(TokenKind::KwEnd, "expected 'end' after for body")?;
         
-        Ok(Stmt::For { var_name, start, end, step, body: Box::new(body) })
+        Ok(StmtKind::For { var_name, start, end, step, body: Box::new(body) })
+    }
+
+    fn parse_try_stmt(&mut self) -> Result<StmtKind, ParseError> {
+        // Parse: try { ... } [catch [name] { ... }] [finally { ... }] end
+        self.consume(TokenKind::KwTry, "expected 'try'")?;
+        let body = self.parse_block()?;
+
+        let mut catch_name = None;
+        let mut catch_body = None;
+        if self.current.kind == TokenKind::KwCatch {
+            self.advance();
+            if let TokenKind::Identifier = self.current.kind {
+                catch_name = Some(self.current.lexeme.clone());
+                self.advance();
+            }
+            catch_body = Some(self.parse_block()?);
+        }
+
+        let finally_body = if self.current.kind == TokenKind::KwFinally {
+            self.advance();
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+
+        if catch_body.is_none() && finally_body.is_none() {
+            return Err(self.error("expected 'catch' or 'finally' after try block"));
+        }
+        self.consume(TokenKind::KwEnd, "expected 'end' after try statement")?;
+        Ok(StmtKind::Try { body, catch_name, catch_body, finally_body })
     }
 
-    fn parse_return_stmt(&mut self) -> Result<Stmt, ParseError> {
+    fn parse_return_stmt(&mut self) -> Result<StmtKind, ParseError> {
         self.consume(TokenKind::KwReturn, "expected 'return'")?;
         let expr = if self.current.kind != TokenKind::Semicolon {
             Some(self.parse_expression()?)
@@ -305,10 +363,11 @@ impl<'a> Parser<'a> {
         if self.current.kind == TokenKind::Semicolon {
             self.advance();
         }
-        Ok(Stmt::Return(expr))
+        Ok(StmtKind::Return(expr))
     }
 
     fn parse_func_decl(&mut self) -> Result<FuncDecl, ParseError> {
+        let line = self.current.line;
         self.consume(TokenKind::KwFunc, "expected 'func'")?;
         let name = if let TokenKind::Identifier = self.current.kind {
             let n = self.current.lexeme.clone();
@@ -339,27 +398,25 @@ impl<'a> Parser<'a> {
         }
         self.consume(TokenKind::RParen, "expected ')' after parameter list")?;
 
-        // Optional ':' return type is currently ignored
-        if self.current.kind == TokenKind::Colon {
+        // `func f(a): {` or, with a return type that is ignored, `func f(a): int: {`
+        self.consume(TokenKind::Colon, "expected ':' before function body")?;
+        if let TokenKind::Identifier = self.current.kind {
             self.advance();
-            if let TokenKind::Identifier = self.current.kind {
-                self.advance();
-            }
+            self.consume(TokenKind::Colon, "expected ':' before function body")?;
         }
-
-        self.consume(TokenKind::Colon, "expected ':' before function body")?;
-        let body_block = Stmt::Block(self.parse_block()?);
+        let body_block = self.parse_body()?;
         self.consume(TokenKind::KwEnd, "expected 'end' after function body")?;
 
         Ok(FuncDecl {
             name,
             params,
-            body: body_block,
+            body: Box::new(body_block),
+            line,
         })
     }
 
     fn parse_object_decl(&mut self) -> Result<crate::ast::ObjectDecl, ParseError> {
-        use crate::ast::{ObjectDecl, ObjectMember, EventDecl};
+        use crate::ast::{ObjectDecl, ObjectMember};
         
         self.consume(TokenKind::KwObject, "expected 'object'")?;
         let name = if let TokenKind::Identifier = self.current.kind {
@@ -374,11 +431,16 @@ impl<'a> Parser<'a> {
         self.consume(TokenKind::LBrace, "expected '{' to start object body")?;
 
         let mut members = Vec::new();
-        while self.current.kind != TokenKind::RBrace && self.current.kind != TokenKind::Eof {
+        loop {
+            members.extend(self.take_trivia().into_iter().map(ObjectMember::Trivia));
+            if self.current.kind == TokenKind::RBrace || self.current.kind == TokenKind::Eof {
+                break;
+            }
             match self.current.kind {
                 TokenKind::KwVar => {
+                    let line = self.current.line;
                     let var_stmt = self.parse_var_decl()?;
-                    members.push(ObjectMember::Var(var_stmt));
+                    members.push(ObjectMember::Var(Stmt::new(var_stmt, line)));
                 }
                 TokenKind::KwFunc => {
                     let func = self.parse_func_decl()?;
@@ -403,6 +465,7 @@ impl<'a> Parser<'a> {
     fn parse_event_decl(&mut self) -> Result<crate::ast::EventDecl, ParseError> {
         use crate::ast::EventDecl;
         
+        let line = self.current.line;
         self.consume(TokenKind::KwOn, "expected 'on'")?;
         let name = if let TokenKind::Identifier = self.current.kind {
             let n = self.current.lexeme.clone();
@@ -433,10 +496,10 @@ impl<'a> Parser<'a> {
         }
         self.consume(TokenKind::RParen, "expected ')' after parameter list")?;
         self.consume(TokenKind::Colon, "expected ':' before event body")?;
-        let body = Stmt::Block(self.parse_block()?);
+        let body = self.parse_body()?;
         self.consume(TokenKind::KwEnd, "expected 'end' after event body")?;
 
-        Ok(EventDecl { name, params, body })
+        Ok(EventDecl { name, params, body: Box::new(body), line })
     }
 
     fn parse_table_literal(&mut self) -> Result<Vec<crate::ast::TableField>, ParseError> {
@@ -445,7 +508,11 @@ impl<'a> Parser<'a> {
         self.consume(TokenKind::LBrace, "expected '{'")?;
         let mut fields = Vec::new();
 
-        while self.current.kind != TokenKind::RBrace && self.current.kind != TokenKind::Eof {
+        loop {
+            fields.extend(self.take_trivia().into_iter().map(TableField::Trivia));
+            if self.current.kind == TokenKind::RBrace || self.current.kind == TokenKind::Eof {
+                break;
+            }
             // Check if key:value or just value
             if self.current.kind == TokenKind::Identifier {
                 let key_or_val = self.current.lexeme.clone();
@@ -469,6 +536,7 @@ impl<'a> Parser<'a> {
             if self.current.kind == TokenKind::Comma {
                 self.advance();
             } else {
+                fields.extend(self.take_trivia().into_iter().map(TableField::Trivia));
                 break;
             }
         }
@@ -625,15 +693,6 @@ impl<'a> Parser<'a> {
             _ => self.parse_primary(),
         }
     }
-                let expr = self.parse_unary()?;
-                Ok(Expr::Unary {
-                    op: UnaryOp::Not,
-                    expr: Box::new(expr),
-                })
-            }
-            _ => self.parse_primary(),
-        }
-    }
 
     fn parse_primary(&mut self) -> Result<Expr, ParseError> {
         let mut expr = match &self.current.kind {
@@ -761,7 +820,24 @@ mod tests {
         let res = parser.parse_program();
         assert!(res.is_err());
         let errs = res.err().unwrap();
-        assert!(errs.len() >= 1);
+        assert!(!errs.is_empty());
+    }
+
+    #[test]
+    fn test_parse_assignment_targets() {
+        let src = r#"
+            player.hp = 80;
+            player["gold"] += 50;
+            println(player.hp);
+        "#;
+        let lexer = Lexer::new(src);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        assert!(matches!(program.body[0].kind, StmtKind::MemberAssignment { .. }));
+        assert!(matches!(
+            program.body[1].kind,
+            StmtKind::IndexAssignment { value: Expr::Binary { op: BinaryOp::Add, .. }, .. }
+        ));
+        assert!(matches!(program.body[2].kind, StmtKind::Expr(Expr::Call { .. })));
     }
-}
 }
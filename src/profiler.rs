@@ -0,0 +1,309 @@
+// ArcScript profiler: where a script's time and allocations go
+//
+// While a Profiler is installed (Interpreter::set_profiler), every call to a
+// script function is timed and the strings and tables it creates are counted.
+// Top-level code is recorded under <main>, timer callbacks run by tick
+// under <tick> and coroutine bodies, which run in their own interpreter,
+// under <coroutine> wherever they're resumed. Results come as a table sorted by self time, or as folded
+// stacks for flamegraph tools. Used by `arcscript run --profile`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Frame for a script's top-level statements.
+pub const MAIN: &str = "<main>";
+/// Frame for the timer callbacks one tick runs.
+pub const TICK: &str = "<tick>";
+/// Frame for a coroutine body, from resume until it yields, waits or ends.
+pub const COROUTINE: &str = "<coroutine>";
+
+/// Totals for one function, told apart by name and declaration line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionStats {
+    pub name: String,
+    /// 0 for <main>, <tick> and <coroutine>
+    pub line: usize,
+    pub calls: u64,
+    /// Time from call to return, counted once for recursive calls
+    pub inclusive: Duration,
+    /// Inclusive time minus the time spent in the functions it called
+    pub exclusive: Duration,
+    /// Strings and tables created while it was the innermost call
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+}
+
+impl FunctionStats {
+    /// How the function is written in tables and stacks: name:line.
+    pub fn label(&self) -> String {
+        match self.line {
+            0 => self.name.clone(),
+            line => format!("{}:{}", self.name, line),
+        }
+    }
+}
+
+struct Frame {
+    function: usize,
+    start: Instant,
+    /// Inclusive time of the calls it made
+    children: Duration,
+    /// Length of the folded stack path before this frame was added
+    path_len: usize,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    functions: Vec<FunctionStats>,
+    index: HashMap<(String, usize), usize>,
+    /// How many calls of each function are in progress, so recursion isn't
+    /// counted twice in inclusive time
+    active: Vec<u32>,
+    stack: Vec<Frame>,
+    /// Frames joined with ';' from the outermost, like "<main>;update:4"
+    path: String,
+    /// Exclusive time per stack path
+    folded: HashMap<String, Duration>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn enter(&mut self, name: &str, line: usize) {
+        let key = (name.to_string(), line);
+        let function = match self.index.get(&key) {
+            Some(&function) => function,
+            None => {
+                self.functions.push(FunctionStats {
+                    name: name.to_string(),
+                    line,
+                    calls: 0,
+                    inclusive: Duration::ZERO,
+                    exclusive: Duration::ZERO,
+                    allocations: 0,
+                    allocated_bytes: 0,
+                });
+                self.active.push(0);
+                self.index.insert(key, self.functions.len() - 1);
+                self.functions.len() - 1
+            }
+        };
+        self.functions[function].calls += 1;
+        self.active[function] += 1;
+
+        let path_len = self.path.len();
+        if !self.path.is_empty() {
+            self.path.push(';');
+        }
+        self.path.push_str(&self.functions[function].label());
+        self.stack.push(Frame { function, start: Instant::now(), children: Duration::ZERO, path_len });
+    }
+
+    pub(crate) fn exit(&mut self) {
+        let Some(frame) = self.stack.pop() else { return };
+        let elapsed = frame.start.elapsed();
+        let own = elapsed.saturating_sub(frame.children);
+
+        self.active[frame.function] -= 1;
+        let stats = &mut self.functions[frame.function];
+        if self.active[frame.function] == 0 {
+            stats.inclusive += elapsed;
+        }
+        stats.exclusive += own;
+        match self.folded.get_mut(&self.path) {
+            Some(time) => *time += own,
+            None => {
+                self.folded.insert(self.path.clone(), own);
+            }
+        }
+        self.path.truncate(frame.path_len);
+        if let Some(caller) = self.stack.last_mut() {
+            caller.children += elapsed;
+        }
+    }
+
+    pub(crate) fn allocated(&mut self, bytes: usize) {
+        if let Some(frame) = self.stack.last() {
+            let stats = &mut self.functions[frame.function];
+            stats.allocations += 1;
+            stats.allocated_bytes += bytes as u64;
+        }
+    }
+
+    /// Every function called so far, the most self time first.
+    pub fn functions(&self) -> Vec<FunctionStats> {
+        let mut functions = self.functions.clone();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then_with(|| a.label().cmp(&b.label())));
+        functions
+    }
+
+    /// Forgets everything recorded, e.g. to profile one level at a time.
+    /// Calls in progress carry on being timed.
+    pub fn reset(&mut self) {
+        for stats in &mut self.functions {
+            stats.calls = 0;
+            stats.inclusive = Duration::ZERO;
+            stats.exclusive = Duration::ZERO;
+            stats.allocations = 0;
+            stats.allocated_bytes = 0;
+        }
+        self.folded.clear();
+        let now = Instant::now();
+        for frame in &mut self.stack {
+            frame.start = now;
+            frame.children = Duration::ZERO;
+        }
+    }
+
+    /// The functions as a text table, the most self time first.
+    pub fn table(&self) -> String {
+        let functions = self.functions();
+        let width = functions.iter().map(|f| f.label().len()).max().unwrap_or(0).max("function".len());
+        let mut out = format!(
+            "{:<width$}  {:>8}  {:>12}  {:>12}  {:>8}  {:>10}\n",
+            "function", "calls", "total ms", "self ms", "allocs", "bytes",
+        );
+        for f in functions {
+            out.push_str(&format!(
+                "{:<width$}  {:>8}  {:>12.3}  {:>12.3}  {:>8}  {:>10}\n",
+                f.label(),
+                f.calls,
+                f.inclusive.as_secs_f64() * 1000.0,
+                f.exclusive.as_secs_f64() * 1000.0,
+                f.allocations,
+                f.allocated_bytes,
+            ));
+        }
+        out
+    }
+
+    /// Folded stacks: one "outer;inner;innermost microseconds" line per
+    /// stack, weighted by self time, the input flamegraph.pl and inferno
+    /// take. Stacks that took under a microsecond are left out.
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<(&String, u128)> = self
+            .folded
+            .iter()
+            .map(|(path, time)| (path, time.as_micros()))
+            .filter(|(_, micros)| *micros > 0)
+            .collect();
+        stacks.sort();
+        stacks.into_iter().map(|(path, micros)| format!("{} {}\n", path, micros)).collect()
+    }
+
+    /// Every stack recorded, outermost frame first, without timings.
+    pub fn stacks(&self) -> Vec<String> {
+        let mut stacks: Vec<String> = self.folded.keys().cloned().collect();
+        stacks.sort();
+        stacks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, Value};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn profile(source: &str, ticks: usize) -> Profiler {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let mut interp = Interpreter::new();
+        interp.set_profiler(Profiler::new());
+        interp.eval_program(&program).unwrap();
+        for _ in 0..ticks {
+            interp.call_global("update", vec![Value::Float(0.5)]).unwrap();
+            interp.tick(0.5).unwrap();
+        }
+        interp.take_profiler().unwrap()
+    }
+
+    #[test]
+    fn test_calls_allocations_and_stacks() {
+        let profiler = profile(
+            "func fib(n): {
+                 if n < 2 then { return n; } end
+                 return fib(n - 1) + fib(n - 2);
+             } end
+             func label(n): { return \"fib \" + str(fib(n)); } end
+             func update(dt): { label(5); } end
+             func spawn(): { var enemy = {hp: 10}; } end
+             every(1.0, spawn);
+             fib(3);",
+            4,
+        );
+        let calls: Vec<(String, u64, u64)> = {
+            let mut calls: Vec<_> = profiler.functions().into_iter().map(|f| (f.label(), f.calls, f.allocations)).collect();
+            calls.sort();
+            calls
+        };
+        assert_eq!(
+            calls,
+            vec![
+                ("<main>".to_string(), 1, 1),
+                ("<tick>".to_string(), 4, 0),
+                ("fib:1".to_string(), 5 + 4 * 15, 0),
+                ("label:5".to_string(), 4, 8),
+                ("spawn:7".to_string(), 2, 2),
+                ("update:6".to_string(), 4, 0),
+            ]
+        );
+        assert_eq!(
+            profiler.stacks(),
+            vec![
+                "<main>",
+                "<main>;fib:1",
+                "<main>;fib:1;fib:1",
+                "<main>;fib:1;fib:1;fib:1",
+                "<tick>",
+                "<tick>;spawn:7",
+                "update:6",
+                "update:6;label:5",
+                "update:6;label:5;fib:1",
+                "update:6;label:5;fib:1;fib:1",
+                "update:6;label:5;fib:1;fib:1;fib:1",
+                "update:6;label:5;fib:1;fib:1;fib:1;fib:1",
+                "update:6;label:5;fib:1;fib:1;fib:1;fib:1;fib:1",
+            ]
+        );
+        for f in profiler.functions() {
+            assert!(f.inclusive >= f.exclusive, "{:?}", f);
+        }
+    }
+
+    #[test]
+    fn test_coroutine_bodies_are_counted_under_their_own_frame() {
+        let profiler = profile(
+            "func walk(steps): {
+                 var i = 0;
+                 while i < steps do { i = i + 1; yield(i); } end
+             } end
+             var walker = coroutine(walk);
+             func update(dt): { resume(walker, 1000); } end",
+            3,
+        );
+        assert_eq!(profiler.stacks(), vec!["<main>", "<tick>", "update:6", "update:6;<coroutine>"]);
+        let coroutine = profiler.functions().into_iter().find(|f| f.name == COROUTINE).unwrap();
+        assert_eq!((coroutine.line, coroutine.calls), (0, 3));
+    }
+
+    #[test]
+    fn test_table_and_folded_output() {
+        let mut profiler = profile("func update(dt): { var s = \"\"; for i = 1, 200, 1 do { s = s + \"x\"; } end } end", 3);
+        let table = profiler.table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "function     calls      total ms       self ms    allocs       bytes");
+        assert!(lines[1].starts_with("update:1         3  "), "{}", table);
+        assert!(lines[1].ends_with("     600       60300"), "{}", table);
+
+        let folded = profiler.folded();
+        assert!(folded.lines().all(|line| line.rsplit_once(' ').is_some_and(|(_, n)| n.parse::<u64>().is_ok())), "{}", folded);
+        assert!(folded.contains("update:1 "), "{}", folded);
+
+        profiler.reset();
+        assert!(profiler.functions().iter().all(|f| f.calls == 0));
+        assert_eq!(profiler.folded(), "");
+    }
+}
@@ -0,0 +1,251 @@
+// ArcScript hot reload: swaps a changed script into a running interpreter
+//
+// Functions, object methods and `on` handlers get their new bodies; global
+// variables and object fields keep the values the game gave them. Used by
+// `arcscript run --watch`; hosts call reload() when their own asset watcher
+// sees a script change.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::lexer::Lexer;
+use crate::parser::{ParseError, Parser};
+use crate::resolver::{self, ResolveError};
+
+/// What a reload changed. Names of object members are written Enemy.attack,
+/// and handlers Enemy.on update.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReloadReport {
+    /// Functions and methods whose bodies were swapped in
+    pub replaced: Vec<String>,
+    /// Functions, variables, objects and members the old script didn't have
+    pub added: Vec<String>,
+}
+
+impl ReloadReport {
+    pub(crate) fn record(&mut self, existed: bool, name: String) {
+        if existed {
+            self.replaced.push(name);
+        } else {
+            self.added.push(name);
+        }
+    }
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.replaced.is_empty() {
+            parts.push(format!("replaced {}", self.replaced.join(", ")));
+        }
+        if !self.added.is_empty() {
+            parts.push(format!("added {}", self.added.join(", ")));
+        }
+        if parts.is_empty() {
+            write!(f, "nothing to swap")
+        } else {
+            write!(f, "{}", parts.join("; "))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReloadError {
+    /// The new source doesn't parse; nothing was changed
+    Parse(Vec<ParseError>),
+    /// The new source parses but the resolver rejects it; nothing was changed
+    Resolve(Vec<ResolveError>),
+    /// Evaluating a new variable or field failed; what came before it was
+    /// already swapped in
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReloadError::Parse(errors) => {
+                let lines: Vec<String> = errors.iter().map(|e| format!("{}:{}: {}", e.line, e.column, e.message)).collect();
+                write!(f, "parse errors:\n  {}", lines.join("\n  "))
+            }
+            ReloadError::Resolve(errors) => {
+                let lines: Vec<String> = errors.iter().map(|e| format!("{}: {}", e.line, e.message)).collect();
+                write!(f, "errors:\n  {}", lines.join("\n  "))
+            }
+            ReloadError::Runtime(e) => write!(f, "runtime error: {}", e),
+        }
+    }
+}
+
+/// Reloads `source` into `interp`. See Interpreter::reload_program for what
+/// is swapped and what is kept.
+pub fn reload(interp: &mut Interpreter, source: &str) -> Result<ReloadReport, ReloadError> {
+    let mut program = Parser::new(Lexer::new(source)).parse_program().map_err(ReloadError::Parse)?;
+    resolver::resolve(&mut program).map_err(ReloadError::Resolve)?;
+    interp.reload_program(&program).map_err(ReloadError::Runtime)
+}
+
+/// Notices when a file changes, by polling its modification time and size.
+pub struct FileWatcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+}
+
+impl FileWatcher {
+    /// Watches `path`, taking how it is now as unchanged.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let stamp = stamp(&path);
+        Self { path, stamp }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file changed since the last call (or since new()).
+    pub fn changed(&mut self) -> bool {
+        let stamp = stamp(&self.path);
+        // A file that's briefly missing mid-save isn't a change yet
+        if stamp.is_none() || stamp == self.stamp {
+            return false;
+        }
+        self.stamp = stamp;
+        true
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::interpreter::Value;
+
+    fn load(source: &str) -> Interpreter {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).unwrap();
+        interp
+    }
+
+    #[test]
+    fn test_reload_swaps_bodies_and_keeps_state() {
+        let mut interp = load(
+            "var score = 0;
+             func bonus(): { return 1; } end
+             func award(): { return bonus() * 10; } end
+             object Enemy: {
+                 var hp = 50;
+                 func hit(self, amount): { return self.hp - amount; } end
+             } end
+             score = 7;
+             Enemy.hp = 20;",
+        );
+        let report = reload(
+            &mut interp,
+            "var score = 0;
+             var level = 2;
+             func bonus(): { return 3; } end
+             func award(): { return bonus() * 10; } end
+             object Enemy: {
+                 var hp = 50;
+                 var armor = 5;
+                 func hit(self, amount): { return self.hp - amount + self.armor; } end
+             } end
+             score = 100;",
+        )
+        .unwrap();
+        assert_eq!(report.to_string(), "replaced bonus, award, Enemy.hit; added level, Enemy.armor");
+
+        assert_eq!(interp.get_global("score"), Some(Value::Int(7)));
+        assert_eq!(interp.get_global("level"), Some(Value::Int(2)));
+        assert_eq!(interp.call_global("award", Vec::new()).unwrap(), Value::Int(30));
+        let enemy = interp.get_global("Enemy").unwrap();
+        let hit = interp.eval_in_frame(0, "Enemy.hit(Enemy, 4)").unwrap();
+        assert_eq!(hit, Value::Int(21));
+        assert!(matches!(enemy, Value::Table(t) if t.get("hp") == Some(&Value::Int(20))));
+    }
+
+    #[test]
+    fn test_reload_swaps_handlers_by_event() {
+        let mut interp = load(
+            "object Enemy: {
+                 func hit(self): { return 1; } end
+                 on update(dt): { println(\"old\", dt); } end
+             } end
+             object Door: {
+                 on update(dt): { println(\"door\"); } end
+             } end
+             var copy = Enemy;",
+        );
+        let output = Arc::new(Mutex::new(String::new()));
+        let sink = Arc::clone(&output);
+        interp.set_print_handler(Arc::new(move |text: &str| sink.lock().unwrap().push_str(text)));
+        assert_eq!(interp.emit("update", vec![Value::Int(1)]).unwrap(), 2);
+
+        let report = reload(
+            &mut interp,
+            "object Enemy: {
+                 func hit(self): { return 2; } end
+                 on update(dt): { println(\"new\", dt); } end
+                 on collision(other): { println(\"hit by\", other); } end
+             } end
+             object Door: {
+                 on update(dt): { println(\"door\"); } end
+             } end
+             var copy = Enemy;",
+        )
+        .unwrap();
+        assert_eq!(
+            report.to_string(),
+            "replaced Enemy.hit, Enemy.on update, Door.on update; added Enemy.on collision"
+        );
+        // Each object keeps one handler per event, in declaration order
+        assert_eq!(interp.emit("update", vec![Value::Int(2)]).unwrap(), 2);
+        assert_eq!(interp.emit("collision", vec![Value::String("rock".to_string())]).unwrap(), 1);
+        assert_eq!(interp.emit("jump", Vec::new()).unwrap(), 0);
+        assert_eq!(*output.lock().unwrap(), "old 1\ndoor\nnew 2\ndoor\nhit by rock\n");
+
+        // Tables are values: the copy made before the reload keeps the old method
+        assert_eq!(interp.eval_in_frame(0, "Enemy.hit(Enemy)").unwrap(), Value::Int(2));
+        assert_eq!(interp.eval_in_frame(0, "copy.hit(copy)").unwrap(), Value::Int(1));
+    }
+
+    #[test]
+    fn test_reload_errors_leave_the_interpreter_running() {
+        let mut interp = load("var lives = 3; func speed(): { return 5; } end");
+        let err = reload(&mut interp, "func speed(): { return 9 } end func broken(: end").unwrap_err();
+        assert!(matches!(err, ReloadError::Parse(_)), "{}", err);
+        assert_eq!(interp.call_global("speed", Vec::new()).unwrap(), Value::Int(5));
+
+        let err = reload(&mut interp, "func speed(): { return boost; } end var boost = 9;").unwrap_err();
+        assert_eq!(err.to_string(), "errors:\n  1: 'boost' is used before its declaration on line 1");
+        assert_eq!(interp.call_global("speed", Vec::new()).unwrap(), Value::Int(5));
+
+        let err = reload(&mut interp, "func speed(): { return 9; } end var boom = 1 / 0;").unwrap_err();
+        assert_eq!(err.to_string(), "runtime error: Line 1: division by zero");
+        assert_eq!(interp.call_global("speed", Vec::new()).unwrap(), Value::Int(9));
+        assert_eq!(interp.get_global("lives"), Some(Value::Int(3)));
+    }
+
+    #[test]
+    fn test_file_watcher() {
+        let path = std::env::temp_dir().join(format!("arcscript_watch_{}.arc", std::process::id()));
+        fs::write(&path, "var a = 1;").unwrap();
+        let mut watcher = FileWatcher::new(&path);
+        assert!(!watcher.changed());
+        fs::write(&path, "var a = 12;").unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed());
+    }
+}
@@ -0,0 +1,397 @@
+// ArcScript REPL: reads entries line by line and runs them in one interpreter
+//
+// An entry is buffered until it parses, so a func ... end can be typed over
+// several lines. Bare expressions echo their value, and lines starting with
+// `:` are commands (see HELP). Used by `arcscript repl`.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::ast::Program;
+use crate::interpreter::{Interpreter, Value};
+use crate::lexer::Lexer;
+use crate::limits::Limits;
+use crate::parser::{ParseError, Parser};
+use crate::resolver;
+
+/// How many history lines are kept in the file.
+pub const MAX_HISTORY: usize = 1000;
+
+const HELP: &str = "\
+Enter statements or expressions; an entry continues until it's complete.
+An empty line runs an unfinished entry anyway, to show what's wrong.
+
+  :env           Variables you've defined
+  :load FILE     Run a script in this session
+  :reset         Forget every variable
+  :type EXPR     The type of EXPR's value
+  :time EXPR     Run EXPR and show how long it took
+  :ast EXPR      The syntax tree EXPR parses to
+  :history [N]   The last N lines entered (default 20)
+  :help          This help
+  :quit          Leave (so do exit and Ctrl+D)";
+
+/// What the REPL needs after a line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Ready,
+    /// The entry isn't complete yet
+    More,
+    Exit,
+}
+
+/// Lines entered, in this session and earlier ones.
+pub struct History {
+    path: Option<PathBuf>,
+    lines: Vec<String>,
+}
+
+impl History {
+    /// History kept only in memory.
+    pub fn new() -> Self {
+        Self { path: None, lines: Vec::new() }
+    }
+
+    /// History loaded from and appended to `path`; a missing file starts empty.
+    pub fn load(path: PathBuf) -> Self {
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        let mut lines: Vec<String> = text.lines().map(String::from).collect();
+        lines.drain(..lines.len().saturating_sub(MAX_HISTORY));
+        Self { path: Some(path), lines }
+    }
+
+    /// The history file: $ARCSCRIPT_HISTORY, or .arcscript_history in the
+    /// home directory.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("ARCSCRIPT_HISTORY") {
+            return Some(path.into());
+        }
+        let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+        Some(PathBuf::from(home).join(".arcscript_history"))
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    fn add(&mut self, line: &str) {
+        self.lines.push(line.to_string());
+        let Some(path) = &self.path else { return };
+        // History is a convenience; a read-only home shouldn't stop the REPL
+        if self.lines.len() > MAX_HISTORY * 2 {
+            self.lines.drain(..self.lines.len() - MAX_HISTORY);
+            let _ = fs::write(path, self.lines.join("\n") + "\n");
+        } else if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What an entry parses to so far.
+enum Entry {
+    Complete(Program),
+    Incomplete,
+    Errors(Vec<ParseError>),
+}
+
+pub struct Repl {
+    interp: Interpreter,
+    buffer: String,
+    history: History,
+}
+
+impl Repl {
+    pub fn new(history: History) -> Self {
+        Self { interp: new_interpreter(), buffer: String::new(), history }
+    }
+
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interp
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            "> "
+        } else {
+            ".. "
+        }
+    }
+
+    /// Takes one line of input, writing results and errors to `out`.
+    pub fn feed(&mut self, line: &str, out: &mut dyn Write) -> io::Result<Status> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        if !line.trim().is_empty() {
+            self.history.add(line);
+        }
+
+        if self.buffer.is_empty() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                return Ok(Status::Ready);
+            }
+            if trimmed == "exit" {
+                return Ok(Status::Exit);
+            }
+            if let Some(command) = trimmed.strip_prefix(':') {
+                return self.command(command, out);
+            }
+        } else if line.trim().is_empty() {
+            // Run what's there, so the parse error shows
+            let source = std::mem::take(&mut self.buffer);
+            match parse(&source) {
+                Entry::Complete(program) => self.run(program, out)?,
+                Entry::Incomplete | Entry::Errors(_) => report(&parse_errors(&source), out)?,
+            }
+            return Ok(Status::Ready);
+        }
+
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        match parse(&self.buffer) {
+            Entry::Incomplete => return Ok(Status::More),
+            Entry::Complete(program) => self.run(program, out)?,
+            Entry::Errors(errors) => report(&errors, out)?,
+        }
+        self.buffer.clear();
+        Ok(Status::Ready)
+    }
+
+    fn run(&mut self, mut program: Program, out: &mut dyn Write) -> io::Result<()> {
+        if !resolve(&mut program, out)? {
+            return Ok(());
+        }
+        match self.interp.eval_program_value(&program) {
+            Ok(Some(value)) if !matches!(value, Value::Nil) => writeln!(out, "{}", value.repr()),
+            Ok(_) => Ok(()),
+            Err(e) => writeln!(out, "Runtime error: {}", e),
+        }
+    }
+
+    fn command(&mut self, command: &str, out: &mut dyn Write) -> io::Result<Status> {
+        let (name, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let arg = arg.trim();
+        match name {
+            "help" | "h" => writeln!(out, "{}", HELP)?,
+            "quit" | "q" => return Ok(Status::Exit),
+            "env" => {
+                let globals = self.interp.user_globals();
+                if globals.is_empty() {
+                    writeln!(out, "(no variables)")?;
+                }
+                for (name, value) in globals {
+                    writeln!(out, "{} = {}", name, value.repr())?;
+                }
+            }
+            "load" if !arg.is_empty() => match fs::read_to_string(arg) {
+                Ok(source) => match Parser::new(Lexer::new(&source)).parse_program() {
+                    Ok(mut program) => {
+                        if resolve(&mut program, out)? {
+                            match self.interp.eval_program(&program) {
+                                Ok(()) => writeln!(out, "Loaded {}", arg)?,
+                                Err(e) => writeln!(out, "Runtime error in '{}': {}", arg, e)?,
+                            }
+                        }
+                    }
+                    Err(errors) => report(&errors, out)?,
+                },
+                Err(e) => writeln!(out, "Error reading file '{}': {}", arg, e)?,
+            },
+            "reset" => {
+                self.interp = new_interpreter();
+                writeln!(out, "Environment reset")?;
+            }
+            "type" | "time" | "ast" if !arg.is_empty() => {
+                let program = match parse(arg) {
+                    Entry::Complete(program) => program,
+                    Entry::Incomplete | Entry::Errors(_) => return report(&parse_errors(arg), out).map(|_| Status::Ready),
+                };
+                match name {
+                    "ast" => {
+                        for stmt in &program.body {
+                            writeln!(out, "{:#?}", stmt.kind)?;
+                        }
+                    }
+                    "type" => {
+                        let mut program = program;
+                        if resolve(&mut program, out)? {
+                            match self.interp.eval_program_value(&program) {
+                                Ok(value) => writeln!(out, "{}", value.map_or("nil", |v| v.type_name()))?,
+                                Err(e) => writeln!(out, "Runtime error: {}", e)?,
+                            }
+                        }
+                    }
+                    _ => {
+                        let start = Instant::now();
+                        self.run(program, out)?;
+                        writeln!(out, "time: {:.1?}", start.elapsed())?;
+                    }
+                }
+            }
+            "history" => {
+                let count = arg.parse().unwrap_or(20);
+                let lines = self.history.lines();
+                // Leave out this :history itself
+                let end = lines.len().saturating_sub(1);
+                let start = end.saturating_sub(count);
+                for (i, line) in lines[start..end].iter().enumerate() {
+                    writeln!(out, "{:>5}  {}", start + i + 1, line)?;
+                }
+            }
+            "load" | "type" | "time" | "ast" => writeln!(out, "Usage: :{} {}", name, if name == "load" { "FILE" } else { "EXPR" })?,
+            _ => writeln!(out, "Unknown command ':{}'; type :help for commands", name)?,
+        }
+        Ok(Status::Ready)
+    }
+}
+
+/// Parses an entry. A statement only missing its final `;` counts as
+/// complete, and errors at the very end mean more input may fix them.
+fn new_interpreter() -> Interpreter {
+    let mut interp = Interpreter::new();
+    interp.set_limits(Limits::recommended());
+    interp
+}
+
+fn parse(source: &str) -> Entry {
+    let errors = match Parser::new(Lexer::new(source)).parse_program() {
+        Ok(program) => return Entry::Complete(program),
+        Err(errors) => errors,
+    };
+    let end = end_position(source);
+    if !errors.iter().any(|e| (e.line, e.column) == end) {
+        return Entry::Errors(errors);
+    }
+    match Parser::new(Lexer::new(&format!("{};", source.trim_end()))).parse_program() {
+        Ok(program) => Entry::Complete(program),
+        Err(_) => Entry::Incomplete,
+    }
+}
+
+fn parse_errors(source: &str) -> Vec<ParseError> {
+    Parser::new(Lexer::new(source)).parse_program().err().unwrap_or_default()
+}
+
+/// Where the lexer's end-of-input token is: just past the last character.
+fn end_position(source: &str) -> (usize, usize) {
+    let line = source.matches('\n').count() + 1;
+    let column = source.len() - source.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+fn report(errors: &[ParseError], out: &mut dyn Write) -> io::Result<()> {
+    for err in errors {
+        writeln!(out, "Parse error at {}:{}: {}", err.line, err.column, err.message)?;
+    }
+    Ok(())
+}
+
+/// Resolves an entry's variables, writing the errors if that fails.
+fn resolve(program: &mut Program, out: &mut dyn Write) -> io::Result<bool> {
+    match resolver::resolve(program) {
+        Ok(()) => Ok(true),
+        Err(errors) => {
+            for err in errors {
+                writeln!(out, "Error on line {}: {}", err.line, err.message)?;
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Runs the REPL until `exit`, `:quit` or the end of input.
+pub fn run(mut input: impl BufRead, mut output: impl Write, history: History) -> io::Result<()> {
+    let mut repl = Repl::new(history);
+    loop {
+        write!(output, "{}", repl.prompt())?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        if repl.feed(&line, &mut output)? == Status::Exit {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds each line and returns the statuses and everything written.
+    fn session(lines: &[&str]) -> (Vec<Status>, String) {
+        let mut repl = Repl::new(History::new());
+        let mut out = Vec::new();
+        let statuses = lines.iter().map(|line| repl.feed(line, &mut out).unwrap()).collect();
+        (statuses, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_multi_line_entries_and_echo() {
+        let (statuses, out) = session(&[
+            "func double(x): {",
+            "    return x * 2;",
+            "} end",
+            "var hp = double(21)",
+            "hp",
+            "hp +",
+            "  1",
+            "println(\"no echo for nil\")",
+        ]);
+        use Status::*;
+        assert_eq!(statuses, vec![More, More, Ready, Ready, Ready, More, Ready, Ready]);
+        assert_eq!(out, "42\n43\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let (statuses, out) = session(&["var = 3;", "func f(): {", "", "missing + 1", "var x = 1 / 0", "{ var a = 1; var a = 2; }"]);
+        use Status::*;
+        assert_eq!(statuses, vec![Ready, More, Ready, Ready, Ready, Ready]);
+        assert_eq!(
+            out,
+            "Parse error at 1:5: expected identifier after 'var'\n\
+             Parse error at 2:1: expected RBrace: expected '}' to close block\n\
+             Runtime error: Undefined identifier 'missing'\n\
+             Runtime error: Line 1: division by zero\n\
+             Error on line 1: 'a' is already declared in this scope, on line 1\n"
+        );
+    }
+
+    #[test]
+    fn test_commands() {
+        let (statuses, out) = session(&[
+            "var name = \"Hero\";",
+            ":type name",
+            ":env",
+            ":ast 1 + 2",
+            ":reset",
+            ":env",
+            ":history 2",
+            ":bogus",
+            ":quit",
+        ]);
+        assert_eq!(statuses.last(), Some(&Status::Exit));
+        assert_eq!(
+            out,
+            "string\n\
+             name = \"Hero\"\n\
+             Expr(\n    Binary {\n        left: Literal(\n            Int(\n                1,\n            ),\n        ),\n        \
+             op: Add,\n        right: Literal(\n            Int(\n                2,\n            ),\n        ),\n    },\n)\n\
+             Environment reset\n\
+             (no variables)\n    \
+             5  :reset\n    \
+             6  :env\n\
+             Unknown command ':bogus'; type :help for commands\n"
+        );
+    }
+}
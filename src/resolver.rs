@@ -0,0 +1,496 @@
+// ArcScript resolver: numbers local variables before a program runs
+//
+// Runs between parsing and evaluation. Each variable declared in a function,
+// block, loop or catch gets a slot in its scope, and each read or assignment
+// of it records how many scopes out that scope is, so the interpreter indexes
+// straight into it instead of searching every scope by name. Top-level names
+// stay globals, found by name, since the host and the REPL add more of them
+// at runtime. Along the way, names used before their declaration and names
+// declared twice in one scope are reported.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{Expr, ObjectMember, Param, Program, Slot, Stmt, StmtKind, TableField};
+use crate::interpreter::Interpreter;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Resolves `program` in place, giving its locals slots. Errors are sorted
+/// by line; the program is still fully resolved, but it has mistakes that
+/// would fail or misbehave at runtime, so report them instead of running it.
+/// Unresolved programs run too, only slower.
+pub fn resolve(program: &mut Program) -> Result<(), Vec<ResolveError>> {
+    let mut resolver = Resolver {
+        scopes: vec![Scope::new(declared_later(&program.body))],
+        builtins: Interpreter::new().global_names().into_iter().collect(),
+        errors: Vec::new(),
+    };
+    resolver.stmts(&mut program.body);
+    let mut errors = resolver.errors;
+    if errors.is_empty() {
+        return Ok(());
+    }
+    errors.sort_by_key(|e| e.line);
+    Err(errors)
+}
+
+/// What a name refers to where it's used.
+enum Found {
+    Local(Slot),
+    /// A global, a builtin, or a name the host may define
+    Global,
+    /// Declared further down, on this line, and nothing outside has it
+    Later(usize),
+}
+
+struct Scope {
+    /// Slot and line of each name declared so far
+    declared: HashMap<String, (usize, usize)>,
+    /// Names declared further down, with the line of the first declaration
+    later: HashMap<String, usize>,
+    next_slot: usize,
+    /// A function's scope, whose slot 0 is the function itself
+    function: bool,
+}
+
+impl Scope {
+    fn new(later: HashMap<String, usize>) -> Self {
+        Self { declared: HashMap::new(), later, next_slot: 0, function: false }
+    }
+}
+
+struct Resolver {
+    /// Innermost last; the first is the top level, whose names are globals
+    scopes: Vec<Scope>,
+    builtins: HashSet<String>,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolver {
+    fn report(&mut self, line: usize, message: String) {
+        self.errors.push(ResolveError { line, message });
+    }
+
+    fn lookup(&self, name: &str) -> Found {
+        let innermost = self.scopes.len() - 1;
+        for i in (0..=innermost).rev() {
+            if let Some(&(index, _)) = self.scopes[i].declared.get(name) {
+                if i == 0 {
+                    return Found::Global;
+                }
+                return Found::Local(Slot { depth: innermost - i, index });
+            }
+        }
+        if self.builtins.contains(name) {
+            return Found::Global;
+        }
+        match self.scopes.iter().rev().find_map(|scope| scope.later.get(name)) {
+            Some(&line) => Found::Later(line),
+            None => Found::Global,
+        }
+    }
+
+    /// Declares `name` in the innermost scope, returning its slot, or None at
+    /// the top level.
+    fn declare(&mut self, name: &str, line: usize) -> Option<Slot> {
+        let top_level = self.scopes.len() == 1;
+        let scope = self.scopes.last_mut()?;
+        scope.later.remove(name);
+        if let Some(&(index, first)) = scope.declared.get(name) {
+            // A function's own name may be reused by its parameters and locals
+            if !(scope.function && index == 0) {
+                let message = format!("'{}' is already declared in this scope, on line {}", name, first);
+                self.report(line, message);
+                return (!top_level).then_some(Slot { depth: 0, index });
+            }
+        }
+        let index = scope.next_slot;
+        scope.next_slot += 1;
+        scope.declared.insert(name.to_string(), (index, line));
+        (!top_level).then_some(Slot { depth: 0, index })
+    }
+
+    fn stmts(&mut self, stmts: &mut [Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    /// Statements in a new scope, as a block runs them.
+    fn scoped(&mut self, stmts: &mut [Stmt]) {
+        self.scopes.push(Scope::new(declared_later(stmts)));
+        self.stmts(stmts);
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        let Stmt { kind, line, slot } = stmt;
+        let line = *line;
+        match kind {
+            StmtKind::VarDecl { name, init } => {
+                self.expr(init, line);
+                *slot = self.declare(name, line);
+            }
+            StmtKind::Assignment { name, value } => {
+                self.expr(value, line);
+                *slot = match self.lookup(name) {
+                    Found::Local(local) => Some(local),
+                    Found::Global => None,
+                    Found::Later(declared) => {
+                        self.report(line, format!("'{}' is assigned before its declaration on line {}", name, declared));
+                        None
+                    }
+                };
+            }
+            StmtKind::MemberAssignment { object, value, .. } => {
+                self.expr(object, line);
+                self.expr(value, line);
+            }
+            StmtKind::IndexAssignment { object, index, value } => {
+                self.expr(object, line);
+                self.expr(index, line);
+                self.expr(value, line);
+            }
+            StmtKind::Expr(expr) | StmtKind::Throw { value: expr } | StmtKind::Return(Some(expr)) => self.expr(expr, line),
+            StmtKind::Block(stmts) => self.scoped(stmts),
+            StmtKind::If { condition, then_branch, elif_branches, else_branch } => {
+                self.expr(condition, line);
+                self.stmt(then_branch);
+                for (condition, branch) in elif_branches {
+                    self.expr(condition, line);
+                    self.stmt(branch);
+                }
+                if let Some(branch) = else_branch {
+                    self.stmt(branch);
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.expr(condition, line);
+                self.stmt(body);
+            }
+            StmtKind::For { var_name, start, end, step, body } => {
+                self.expr(start, line);
+                self.expr(end, line);
+                if let Some(step) = step {
+                    self.expr(step, line);
+                }
+                // The loop's own scope holds just the variable, in slot 0
+                self.scopes.push(Scope::new(HashMap::new()));
+                self.declare(var_name, line);
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            StmtKind::Try { body, catch_name, catch_body, finally_body } => {
+                self.scoped(body);
+                if let Some(catch_body) = catch_body {
+                    // The caught error, if named, is slot 0
+                    self.scopes.push(Scope::new(HashMap::new()));
+                    if let Some(name) = catch_name {
+                        self.declare(name, line);
+                    }
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.later = declared_later(catch_body);
+                    }
+                    self.stmts(catch_body);
+                    self.scopes.pop();
+                }
+                if let Some(finally_body) = finally_body {
+                    self.scoped(finally_body);
+                }
+            }
+            StmtKind::FuncDecl(func) => {
+                self.function(&func.name, &func.params, &mut func.body, line);
+                *slot = self.declare(&func.name, line);
+            }
+            StmtKind::ObjectDecl(obj) => {
+                // Members see what's declared before the object, not each other
+                for member in &mut obj.members {
+                    match member {
+                        ObjectMember::Var(var) => {
+                            if let StmtKind::VarDecl { init, .. } = &mut var.kind {
+                                self.expr(init, var.line);
+                            }
+                        }
+                        ObjectMember::Method(func) => {
+                            self.function(&func.name, &func.params, &mut func.body, func.line);
+                        }
+                        ObjectMember::Event(event) => {
+                            self.function(&event.name, &event.params, &mut event.body, event.line);
+                        }
+                        ObjectMember::Trivia(_) => {}
+                    }
+                }
+                *slot = self.declare(&obj.name, line);
+            }
+            StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue | StmtKind::Trivia(_) => {}
+        }
+    }
+
+    /// A function body, in the scope a call creates: the function in slot 0,
+    /// then its parameters, then the body's locals.
+    fn function(&mut self, name: &str, params: &[Param], body: &mut Stmt, line: usize) {
+        let mut scope = Scope::new(HashMap::new());
+        scope.function = true;
+        scope.declared.insert(name.to_string(), (0, line));
+        scope.next_slot = 1;
+        self.scopes.push(scope);
+        for param in params {
+            self.declare(&param.name, line);
+        }
+        match &mut body.kind {
+            // Calls run the body's statements in the call's own scope
+            StmtKind::Block(stmts) => {
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.later = declared_later(stmts);
+                }
+                self.stmts(stmts);
+            }
+            _ => self.stmt(body),
+        }
+        self.scopes.pop();
+    }
+
+    fn expr(&mut self, expr: &mut Expr, line: usize) {
+        match expr {
+            Expr::Literal(_) => {}
+            Expr::Ident(name) | Expr::Local { name, .. } => {
+                let found = self.lookup(name);
+                let name = std::mem::take(name);
+                *expr = match found {
+                    Found::Local(slot) => Expr::Local { name, slot },
+                    Found::Global => Expr::Ident(name),
+                    Found::Later(declared) => {
+                        self.report(line, format!("'{}' is used before its declaration on line {}", name, declared));
+                        Expr::Ident(name)
+                    }
+                };
+            }
+            Expr::Unary { expr, .. } => self.expr(expr, line),
+            Expr::Binary { left, right, .. } => {
+                self.expr(left, line);
+                self.expr(right, line);
+            }
+            Expr::Call { callee, args } => {
+                self.expr(callee, line);
+                for arg in args {
+                    self.expr(arg, line);
+                }
+            }
+            Expr::Member { object, .. } => self.expr(object, line),
+            Expr::Index { object, index } => {
+                self.expr(object, line);
+                self.expr(index, line);
+            }
+            Expr::TableLiteral(fields) => {
+                for field in fields {
+                    match field {
+                        TableField::KeyValue { value, .. } | TableField::Value(value) => self.expr(value, line),
+                        TableField::Trivia(_) => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The names `stmts` declare directly, with the line of each one's first
+/// declaration.
+fn declared_later(stmts: &[Stmt]) -> HashMap<String, usize> {
+    let mut later = HashMap::new();
+    for stmt in stmts {
+        let name = match &stmt.kind {
+            StmtKind::VarDecl { name, .. } => name,
+            StmtKind::FuncDecl(func) => &func.name,
+            StmtKind::ObjectDecl(obj) => &obj.name,
+            _ => continue,
+        };
+        later.entry(name.clone()).or_insert(stmt.line);
+    }
+    later
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Value;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::sync::{Arc, Mutex};
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source)).parse_program().unwrap()
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        match resolve(&mut parse(source)) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    /// The statements of a block or function body.
+    fn body(stmt: &Stmt) -> &[Stmt] {
+        match &stmt.kind {
+            StmtKind::Block(stmts) => stmts,
+            StmtKind::FuncDecl(func) => body(&func.body),
+            other => panic!("no body: {:?}", other),
+        }
+    }
+
+    fn local(expr: &Expr) -> (&str, usize, usize) {
+        match expr {
+            Expr::Local { name, slot } => (name, slot.depth, slot.index),
+            other => panic!("not resolved to a local: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_slots() {
+        let mut program = parse(
+            "var g = 1;
+             func area(w, h): {
+                 var a = w * h;
+                 {
+                     var b = a + g;
+                     a = b;
+                 }
+                 return a;
+             } end
+             func count(n): {
+                 if n > 0 then { return count(n - 1); } end
+                 return 0;
+             } end",
+        );
+        resolve(&mut program).unwrap();
+        assert!(program.body[0].slot.is_none());
+
+        let area = body(&program.body[1]);
+        let StmtKind::VarDecl { init: Expr::Binary { left, right, .. }, .. } = &area[0].kind else { panic!() };
+        assert_eq!((local(left), local(right)), (("w", 0, 1), ("h", 0, 2)));
+        assert_eq!(area[0].slot, Some(Slot { depth: 0, index: 3 }));
+
+        let inner = body(&area[1]);
+        let StmtKind::VarDecl { init: Expr::Binary { left, right, .. }, .. } = &inner[0].kind else { panic!() };
+        assert_eq!(local(left), ("a", 1, 3));
+        assert!(matches!(right.as_ref(), Expr::Ident(name) if name == "g"));
+        assert_eq!(inner[0].slot, Some(Slot { depth: 0, index: 0 }));
+        assert_eq!(inner[1].slot, Some(Slot { depth: 1, index: 3 }));
+
+        // The function itself is slot 0 of its call's scope
+        let StmtKind::If { then_branch, .. } = &body(&program.body[2])[0].kind else { panic!() };
+        let StmtKind::Return(Some(Expr::Call { callee, .. })) = &body(then_branch)[0].kind else { panic!() };
+        assert_eq!(local(callee), ("count", 1, 0));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            errors(
+                "var speed = 2;
+                 func move(x, x): { return x + speed + later; } end
+                 var later = 1;
+                 var speed = 3;
+                 func tick(): {
+                     total = 1;
+                     var total = 0;
+                     { var inner = 1; var inner = 2; }
+                 } end
+                 object Enemy: {
+                     func spawn(): { return Enemy; } end
+                 } end"
+            ),
+            vec![
+                "line 2: 'x' is already declared in this scope, on line 2",
+                "line 2: 'later' is used before its declaration on line 3",
+                "line 4: 'speed' is already declared in this scope, on line 1",
+                "line 6: 'total' is assigned before its declaration on line 7",
+                "line 8: 'inner' is already declared in this scope, on line 8",
+                "line 11: 'Enemy' is used before its declaration on line 10",
+            ]
+        );
+        // Outer variables, builtins and names the host may define are fine
+        assert_eq!(
+            errors(
+                "var x = 1;
+                 func f(f): { var str = str(x); { print(str); var x = 2; } return f; } end
+                 host_score = host_score + 1;"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_resolved_programs_run_the_same() {
+        let source = "
+            func counter(): {
+                var count = 0;
+                func next(): {
+                    count = count + 1;
+                    return count;
+                } end
+                return next;
+            } end
+            func fib(n): {
+                if n < 2 then { return n; } end
+                return fib(n - 1) + fib(n - 2);
+            } end
+            object Bag: {
+                var items = {};
+                func add(self, item): {
+                    var items = self.items;
+                    items[len(items)] = item;
+                    self.items = items;
+                    return self;
+                } end
+            } end
+            var total = 0;
+            for i = 1, 10, 1 do {
+                var square = i * i;
+                if square % 2 == 0 then { total = total + square; } else { continue; } end
+            } end
+            var next = counter();
+            var caught = nil;
+            try {
+                var local = 1;
+                throw \"oops \" + str(local);
+            } catch err {
+                caught = err.message;
+            } end
+            var shadow = 1;
+            {
+                var shadow = 2;
+                shadow = shadow + 1;
+                println(shadow);
+            }
+            var bag = Bag.add(Bag, \"sword\");
+            println(total, next(), next(), fib(12), caught, shadow, len(bag.items));
+        ";
+        let run = |resolved: bool| {
+            let mut program = parse(source);
+            if resolved {
+                resolve(&mut program).unwrap();
+            }
+            let output = Arc::new(Mutex::new(String::new()));
+            let sink = Arc::clone(&output);
+            let mut interp = Interpreter::new();
+            interp.set_print_handler(Arc::new(move |text: &str| sink.lock().unwrap().push_str(text)));
+            interp.eval_program(&program).unwrap();
+            let frames = interp.call_global("fib", vec![Value::Int(10)]).unwrap();
+            let output = output.lock().unwrap().clone();
+            (output, frames)
+        };
+        assert_eq!(run(false), ("3\n220 1 1 144 oops 1 1 1\n".to_string(), Value::Int(55)));
+        assert_eq!(run(true), run(false));
+    }
+}
@@ -0,0 +1,150 @@
+// ArcScript sandbox: which builtins a host makes available to scripts
+//
+// Every builtin belongs to one capability. A host running untrusted mods builds
+// the interpreter with only the capabilities it approves and can deny
+// individual builtins on top of that.
+
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Computation with no side effects outside the script: math, strings,
+    /// types, vectors, coroutines and the string, random, table and json
+    /// namespaces. Not the same as deterministic: the random generator is
+    /// the interpreter's own, but seeded from the clock until the script
+    /// calls random.seed
+    Pure,
+    /// Output to the console
+    Io,
+    /// Checks for tests and debugging: assert and assert_eq
+    Debug,
+    /// Anything driven by the host game: timers, waiting and input
+    Host,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 4] = [Capability::Pure, Capability::Io, Capability::Debug, Capability::Host];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Pure => "pure",
+            Capability::Io => "io",
+            Capability::Debug => "debug",
+            Capability::Host => "host",
+        }
+    }
+
+    /// Parses a profile name as used in mod manifests ("pure", "io", ...).
+    pub fn from_name(name: &str) -> Option<Capability> {
+        Capability::ALL.into_iter().find(|cap| cap.as_str() == name)
+    }
+}
+
+/// The capability a builtin needs, or None if `name` isn't a builtin.
+pub fn capability_of(name: &str) -> Option<Capability> {
+    let cap = match name {
+        "print" | "println" => Capability::Io,
+        "type" | "len" | "str" | "int" | "float" | "error" => Capability::Pure,
+        "assert" | "assert_eq" => Capability::Debug,
+        "abs" | "min" | "max" | "floor" | "ceil" | "round" | "sqrt" | "pow" => Capability::Pure,
+        "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "atan2" | "exp" | "log" | "clamp" | "sign" => {
+            Capability::Pure
+        }
+        "substring" | "contains" | "toUpper" | "toLower" => Capability::Pure,
+        "vec2" | "vec3" | "length" | "normalize" | "dot" | "cross" | "distance" | "lerp" | "rotate" | "angle" => {
+            Capability::Pure
+        }
+        "coroutine" | "resume" | "yield" | "status" => Capability::Pure,
+        "string" | "random" | "table" | "json" => Capability::Pure,
+        _ if ["string.", "random.", "table.", "json."].iter().any(|ns| name.starts_with(ns)) => Capability::Pure,
+        "after" | "every" | "after_frames" | "every_frames" | "cancel" | "wait" | "wait_frames" => Capability::Host,
+        "input" => Capability::Host,
+        _ if name.starts_with("input.") => Capability::Host,
+        _ => return None,
+    };
+    Some(cap)
+}
+
+/// The builtins an interpreter exposes: every builtin whose capability is
+/// granted, minus the denied ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandbox {
+    granted: HashSet<Capability>,
+    denied: HashSet<String>,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl Sandbox {
+    /// Grants exactly `capabilities`.
+    pub fn new(capabilities: &[Capability]) -> Self {
+        Self { granted: capabilities.iter().copied().collect(), denied: HashSet::new() }
+    }
+
+    /// Every capability; what `Interpreter::new` uses.
+    pub fn all() -> Self {
+        Self::new(&Capability::ALL)
+    }
+
+    pub fn grant(mut self, capability: Capability) -> Self {
+        self.granted.insert(capability);
+        self
+    }
+
+    /// Hides one builtin even though its capability is granted.
+    pub fn deny(mut self, builtin: &str) -> Self {
+        self.denied.insert(builtin.to_string());
+        self
+    }
+
+    pub fn allows(&self, builtin: &str) -> bool {
+        match capability_of(builtin) {
+            Some(cap) => self.granted.contains(&cap) && !self.denied.contains(builtin),
+            None => false,
+        }
+    }
+
+    /// Why a script can't use `builtin`, or None if it can (or isn't a builtin).
+    pub fn denial(&self, builtin: &str) -> Option<String> {
+        let cap = capability_of(builtin)?;
+        if self.denied.contains(builtin) {
+            Some(format!("capability not granted: '{}' is denied by the host", builtin))
+        } else if !self.granted.contains(&cap) {
+            Some(format!("capability not granted: '{}' requires '{}'", builtin, cap.as_str()))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles_and_deny_list() {
+        let sandbox = Sandbox::new(&[Capability::Pure]).grant(Capability::Io).deny("println");
+        assert!(sandbox.allows("sqrt"));
+        assert!(sandbox.allows("print"));
+        assert!(!sandbox.allows("println"));
+        assert!(!sandbox.allows("after"));
+        assert!(!sandbox.allows("not_a_builtin"));
+
+        assert!(sandbox.denial("println").unwrap().contains("denied"));
+        assert!(sandbox.denial("input.held").unwrap().contains("requires 'host'"));
+        assert!(sandbox.denial("assert_eq").unwrap().contains("requires 'debug'"));
+        assert_eq!(sandbox.denial("sqrt"), None);
+    }
+
+    #[test]
+    fn test_capability_names() {
+        for cap in Capability::ALL {
+            assert_eq!(Capability::from_name(cap.as_str()), Some(cap));
+        }
+        assert_eq!(Capability::from_name("network"), None);
+    }
+}
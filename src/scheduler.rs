@@ -0,0 +1,189 @@
+// ArcScript timer scheduler driven by the host's tick
+
+use crate::interpreter::Value;
+
+pub type TimerHandle = i64;
+
+/// What a timer's delay is measured in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimerClock {
+    Seconds,
+    Frames,
+}
+
+#[derive(Debug, Clone)]
+pub struct Timer {
+    pub id: TimerHandle,
+    pub clock: TimerClock,
+    /// Absolute time (seconds or frame number) at which the timer fires next
+    pub due: f64,
+    /// Re-arm interval for repeating timers
+    pub interval: Option<f64>,
+    pub callback: Value,
+}
+
+/// Pending timers plus the clock the host advances with `advance`.
+///
+/// Due timers are popped in a deterministic order: frame timers before
+/// second timers, then by due time, then by creation order.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    timers: Vec<Timer>,
+    next_id: TimerHandle,
+    time: f64,
+    frame: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self { timers: Vec::new(), next_id: 1, time: 0.0, frame: 0 }
+    }
+
+    /// Seconds elapsed across all ticks.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Number of ticks so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn pending(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Handle the next scheduled timer will receive. Timers with a handle at or
+    /// above this value were created after it was read.
+    pub fn next_handle(&self) -> TimerHandle {
+        self.next_id
+    }
+
+    pub(crate) fn timers(&self) -> &[Timer] {
+        &self.timers
+    }
+
+    /// Replaces the clock and every timer, e.g. with a saved game's.
+    pub(crate) fn restore(&mut self, time: f64, frame: u64, next_id: TimerHandle, timers: Vec<Timer>) {
+        *self = Self { timers, next_id, time, frame };
+    }
+
+    pub fn schedule(&mut self, clock: TimerClock, delay: f64, repeat: bool, callback: Value) -> TimerHandle {
+        let id = self.next_handle();
+        self.next_id = id + 1;
+        let now = match clock {
+            TimerClock::Seconds => self.time,
+            TimerClock::Frames => self.frame as f64,
+        };
+        self.timers.push(Timer {
+            id,
+            clock,
+            due: now + delay,
+            interval: if repeat { Some(delay) } else { None },
+            callback,
+        });
+        id
+    }
+
+    pub fn cancel(&mut self, id: TimerHandle) -> bool {
+        let before = self.timers.len();
+        self.timers.retain(|t| t.id != id);
+        self.timers.len() != before
+    }
+
+    /// Moves the clock forward by `dt` seconds and one frame.
+    pub fn advance(&mut self, dt: f64) {
+        self.time += dt;
+        self.frame += 1;
+    }
+
+    /// Removes (or re-arms, for repeating timers) the next due timer created
+    /// before `created_before` and returns its callback.
+    pub fn pop_due(&mut self, created_before: TimerHandle) -> Option<Value> {
+        let (time, frame) = (self.time, self.frame as f64);
+        let index = self
+            .timers
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.id < created_before)
+            .filter(|(_, t)| match t.clock {
+                TimerClock::Seconds => t.due <= time,
+                TimerClock::Frames => t.due <= frame,
+            })
+            .min_by(|(_, a), (_, b)| {
+                let rank = |t: &Timer| if t.clock == TimerClock::Frames { 0 } else { 1 };
+                rank(a)
+                    .cmp(&rank(b))
+                    .then(a.due.total_cmp(&b.due))
+                    .then(a.id.cmp(&b.id))
+            })
+            .map(|(i, _)| i)?;
+
+        match self.timers[index].interval {
+            Some(interval) => {
+                let timer = &mut self.timers[index];
+                timer.due += interval;
+                Some(timer.callback.clone())
+            }
+            None => Some(self.timers.remove(index).callback),
+        }
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(scheduler: &mut Scheduler) -> Vec<Value> {
+        let limit = scheduler.next_handle();
+        std::iter::from_fn(|| scheduler.pop_due(limit)).collect()
+    }
+
+    #[test]
+    fn test_due_timers_run_in_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(TimerClock::Seconds, 1.0, false, Value::Int(1));
+        scheduler.schedule(TimerClock::Seconds, 0.5, false, Value::Int(2));
+        scheduler.schedule(TimerClock::Seconds, 0.5, false, Value::Int(3));
+        scheduler.schedule(TimerClock::Frames, 1.0, false, Value::Int(4));
+
+        scheduler.advance(0.25);
+        assert_eq!(drain(&mut scheduler), vec![Value::Int(4)]);
+        scheduler.advance(1.0);
+        assert_eq!(drain(&mut scheduler), vec![Value::Int(2), Value::Int(3), Value::Int(1)]);
+        assert_eq!(scheduler.pending(), 0);
+    }
+
+    #[test]
+    fn test_repeating_timer_catches_up_and_cancels() {
+        let mut scheduler = Scheduler::new();
+        let id = scheduler.schedule(TimerClock::Seconds, 0.5, true, Value::Int(1));
+
+        scheduler.advance(1.2);
+        assert_eq!(drain(&mut scheduler).len(), 2);
+        scheduler.advance(0.3);
+        assert_eq!(drain(&mut scheduler).len(), 1);
+
+        assert!(scheduler.cancel(id));
+        assert!(!scheduler.cancel(id));
+        scheduler.advance(5.0);
+        assert!(drain(&mut scheduler).is_empty());
+    }
+
+    #[test]
+    fn test_timers_created_during_tick_wait_for_next_tick() {
+        let mut scheduler = Scheduler::new();
+        scheduler.advance(1.0);
+        let limit = scheduler.next_handle();
+        scheduler.schedule(TimerClock::Seconds, 0.0, false, Value::Int(1));
+        assert_eq!(scheduler.pop_due(limit), None);
+        scheduler.advance(0.0);
+        assert_eq!(drain(&mut scheduler), vec![Value::Int(1)]);
+    }
+}
@@ -0,0 +1,1061 @@
+// ArcScript snapshots: saving script state to restore in a later session
+//
+// Interpreter::snapshot() captures the globals a script defined, pending
+// timers, the scheduler clock and the random generator; Interpreter::restore()
+// puts them back into an interpreter that has run the same (or a newer
+// version of the) script. Snapshots are written as text or as compact binary.
+//
+// Functions aren't saved as code. A function is saved as its identity, the
+// name and line of its declaration, plus the values its body refers to from
+// where it was defined; restore finds the declaration in the loaded program,
+// so a save made before a patch runs the patched code. Tables and functions
+// are stored once however many places hold them and refer to each other by
+// index. ArcScript tables are values, so each place gets its own copy back,
+// as before the save. Coroutines are paused mid-call on their own thread and
+// can't be saved.
+//
+// Text format, version 1 (one item per line; names and strings are JSON
+// strings; objects only refer to objects listed before them):
+//
+//   arcscript-snapshot 1
+//   clock <seconds> <frame> <next timer handle>
+//   random <generator state>
+//   table <index>                       followed by "  <key> <value>" lines
+//   function <index> <name> <line>      followed by "  <name> <value>" lines
+//   global <name> <value>
+//   timer <handle> seconds|frames <due> [every <interval>] <callback value>
+//
+// Values: nil, true, false, integers, floats (always with a '.', an exponent,
+// inf or NaN), strings, "vec2 <x> <y>", "vec3 <x> <y> <z>", "builtin <name>"
+// and "@<index>" for a table or function.
+//
+// The binary format has the same items in the same order: the magic bytes
+// "ARCSNAP" and a version byte, then the clock, the random state, the objects,
+// the globals and the timers, each list prefixed by its length. Counts,
+// lengths, frames and indexes are LEB128 varints, integers are zigzag
+// varints, floats are 8 little-endian bytes and strings are a length followed
+// by UTF-8. Each value starts with a tag byte (see Saved).
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::ast::{Expr, FuncDecl, ObjectMember, Program, Stmt, StmtKind, TableField};
+use crate::interpreter::{Environment, Value};
+use crate::scheduler::{Timer, TimerClock, TimerHandle};
+use crate::stdlib::json;
+
+/// The format version written, and the only one read.
+pub const VERSION: u32 = 1;
+
+const TEXT_MAGIC: &str = "arcscript-snapshot";
+const BINARY_MAGIC: &[u8] = b"ARCSNAP";
+
+/// Why state couldn't be saved, read back or restored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotError {
+    pub message: String,
+}
+
+impl SnapshotError {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into() }
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// A saved value. Tables and functions live in Snapshot::objects.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Saved {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Vec2(f64, f64),
+    Vec3(f64, f64, f64),
+    Builtin(String),
+    /// Index into Snapshot::objects
+    Ref(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    /// Entries sorted by key
+    Table(Vec<(String, Saved)>),
+    /// A function by the name and line of its declaration, with the values
+    /// its body uses from where it was defined
+    Function { name: String, line: usize, captured: Vec<(String, Saved)> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedTimer {
+    pub handle: TimerHandle,
+    pub clock: TimerClock,
+    pub due: f64,
+    pub interval: Option<f64>,
+    pub callback: Saved,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Scheduler seconds and frames elapsed
+    pub time: f64,
+    pub frame: u64,
+    /// Handle the next timer will get, so handles the script kept stay unique
+    pub next_timer: TimerHandle,
+    pub random_state: u64,
+    pub objects: Vec<Object>,
+    /// Sorted by name
+    pub globals: Vec<(String, Saved)>,
+    pub timers: Vec<SavedTimer>,
+}
+
+/// Saves values, storing each distinct table or function once.
+pub(crate) struct Saver {
+    objects: Vec<Object>,
+    /// Text form of each object, to find repeats
+    seen: HashMap<String, usize>,
+}
+
+impl Saver {
+    pub(crate) fn new() -> Self {
+        Self { objects: Vec::new(), seen: HashMap::new() }
+    }
+
+    /// `is_builtin` tells captured builtins apart, which aren't saved: the
+    /// restoring interpreter has its own.
+    pub(crate) fn save(&mut self, value: &Value, is_builtin: &dyn Fn(&str, &Value) -> bool) -> Result<Saved, SnapshotError> {
+        Ok(match value {
+            Value::Nil => Saved::Nil,
+            Value::Bool(b) => Saved::Bool(*b),
+            Value::Int(i) => Saved::Int(*i),
+            Value::Float(f) => Saved::Float(*f),
+            Value::String(s) => Saved::String(s.clone()),
+            Value::Vec2(x, y) => Saved::Vec2(*x, *y),
+            Value::Vec3(x, y, z) => Saved::Vec3(*x, *y, *z),
+            Value::BuiltinFunction(name) => Saved::Builtin(name.clone()),
+            Value::Coroutine(_) => return Err(SnapshotError::new("coroutines can't be saved")),
+            Value::Table(table) => {
+                let mut entries = Vec::with_capacity(table.len());
+                for (key, value) in table {
+                    entries.push((key.clone(), self.save(value, is_builtin)?));
+                }
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                self.intern(Object::Table(entries))
+            }
+            Value::Function { decl, closure } => {
+                let mut captured = Vec::new();
+                if let Some(env) = closure {
+                    for name in referenced_names(decl) {
+                        match env.get(&name) {
+                            Some(value) if !is_builtin(&name, value) => {
+                                let saved = self.save(value, is_builtin).map_err(|e| {
+                                    SnapshotError::new(format!("{} (captured as '{}' by {})", e.message, name, decl.name))
+                                })?;
+                                captured.push((name, saved));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                self.intern(Object::Function { name: decl.name.clone(), line: decl.line, captured })
+            }
+        })
+    }
+
+    fn intern(&mut self, object: Object) -> Saved {
+        // The text form, numbered 0, is the same for equal objects
+        let mut key = String::new();
+        write_object(&mut key, 0, &object);
+        if let Some(&index) = self.seen.get(&key) {
+            return Saved::Ref(index);
+        }
+        self.objects.push(object);
+        self.seen.insert(key, self.objects.len() - 1);
+        Saved::Ref(self.objects.len() - 1)
+    }
+
+    pub(crate) fn finish(self) -> Vec<Object> {
+        self.objects
+    }
+}
+
+/// Names a function's body reads or assigns, including from functions
+/// declared inside it: everything it could need from where it was defined.
+fn referenced_names(decl: &FuncDecl) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    stmt_names(&decl.body, &mut names);
+    for param in &decl.params {
+        names.remove(&param.name);
+    }
+    names
+}
+
+fn stmt_names(stmt: &Stmt, names: &mut BTreeSet<String>) {
+    match &stmt.kind {
+        StmtKind::VarDecl { init, .. } => expr_names(init, names),
+        StmtKind::Assignment { name, value } => {
+            names.insert(name.clone());
+            expr_names(value, names);
+        }
+        StmtKind::MemberAssignment { object, value, .. } => {
+            expr_names(object, names);
+            expr_names(value, names);
+        }
+        StmtKind::IndexAssignment { object, index, value } => {
+            expr_names(object, names);
+            expr_names(index, names);
+            expr_names(value, names);
+        }
+        StmtKind::Expr(expr) | StmtKind::Throw { value: expr } | StmtKind::Return(Some(expr)) => expr_names(expr, names),
+        StmtKind::Block(stmts) => stmts.iter().for_each(|s| stmt_names(s, names)),
+        StmtKind::If { condition, then_branch, elif_branches, else_branch } => {
+            expr_names(condition, names);
+            stmt_names(then_branch, names);
+            for (condition, branch) in elif_branches {
+                expr_names(condition, names);
+                stmt_names(branch, names);
+            }
+            if let Some(branch) = else_branch {
+                stmt_names(branch, names);
+            }
+        }
+        StmtKind::While { condition, body } => {
+            expr_names(condition, names);
+            stmt_names(body, names);
+        }
+        StmtKind::For { start, end, step, body, .. } => {
+            expr_names(start, names);
+            expr_names(end, names);
+            if let Some(step) = step {
+                expr_names(step, names);
+            }
+            stmt_names(body, names);
+        }
+        StmtKind::Try { body, catch_body, finally_body, .. } => {
+            for stmts in [Some(body), catch_body.as_ref(), finally_body.as_ref()].into_iter().flatten() {
+                stmts.iter().for_each(|s| stmt_names(s, names));
+            }
+        }
+        StmtKind::FuncDecl(func) => stmt_names(&func.body, names),
+        StmtKind::ObjectDecl(obj) => {
+            for member in &obj.members {
+                match member {
+                    ObjectMember::Var(stmt) => stmt_names(stmt, names),
+                    ObjectMember::Method(func) => stmt_names(&func.body, names),
+                    ObjectMember::Event(_) | ObjectMember::Trivia(_) => {}
+                }
+            }
+        }
+        StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue | StmtKind::Trivia(_) => {}
+    }
+}
+
+fn expr_names(expr: &Expr, names: &mut BTreeSet<String>) {
+    match expr {
+        Expr::Literal(_) => {}
+        Expr::Ident(name) | Expr::Local { name, .. } => {
+            names.insert(name.clone());
+        }
+        Expr::Unary { expr, .. } => expr_names(expr, names),
+        Expr::Binary { left, right, .. } => {
+            expr_names(left, names);
+            expr_names(right, names);
+        }
+        Expr::Call { callee, args } => {
+            expr_names(callee, names);
+            args.iter().for_each(|arg| expr_names(arg, names));
+        }
+        Expr::Member { object, .. } => expr_names(object, names),
+        Expr::Index { object, index } => {
+            expr_names(object, names);
+            expr_names(index, names);
+        }
+        Expr::TableLiteral(fields) => {
+            for field in fields {
+                match field {
+                    TableField::KeyValue { value, .. } | TableField::Value(value) => expr_names(value, names),
+                    TableField::Trivia(_) => {}
+                }
+            }
+        }
+    }
+}
+
+/// Every function declared in a program, nested ones and methods included.
+fn declarations(program: &Program) -> Vec<&FuncDecl> {
+    fn visit<'p>(stmt: &'p Stmt, found: &mut Vec<&'p FuncDecl>) {
+        match &stmt.kind {
+            StmtKind::FuncDecl(func) => {
+                found.push(func);
+                visit(&func.body, found);
+            }
+            StmtKind::ObjectDecl(obj) => {
+                for member in &obj.members {
+                    if let ObjectMember::Method(func) = member {
+                        found.push(func);
+                        visit(&func.body, found);
+                    }
+                }
+            }
+            StmtKind::Block(stmts) => stmts.iter().for_each(|s| visit(s, found)),
+            StmtKind::If { then_branch, elif_branches, else_branch, .. } => {
+                visit(then_branch, found);
+                elif_branches.iter().for_each(|(_, branch)| visit(branch, found));
+                if let Some(branch) = else_branch {
+                    visit(branch, found);
+                }
+            }
+            StmtKind::While { body, .. } | StmtKind::For { body, .. } => visit(body, found),
+            StmtKind::Try { body, catch_body, finally_body, .. } => {
+                for stmts in [Some(body), catch_body.as_ref(), finally_body.as_ref()].into_iter().flatten() {
+                    stmts.iter().for_each(|s| visit(s, found));
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut found = Vec::new();
+    program.body.iter().for_each(|stmt| visit(stmt, &mut found));
+    found
+}
+
+/// Rebuilds a snapshot's objects as values, against the declarations in
+/// `program`. Restored closures hold `base` (the builtins) plus what was
+/// captured.
+pub(crate) fn rebuild_objects(snapshot: &Snapshot, program: &Program, base: &Environment) -> Result<Vec<Value>, SnapshotError> {
+    let decls = declarations(program);
+    let mut values: Vec<Value> = Vec::with_capacity(snapshot.objects.len());
+    for object in &snapshot.objects {
+        let value = match object {
+            Object::Table(entries) => {
+                let mut table = HashMap::with_capacity(entries.len());
+                for (key, saved) in entries {
+                    table.insert(key.clone(), load(saved, &values)?);
+                }
+                Value::Table(table)
+            }
+            Object::Function { name, line, captured } => {
+                // The same place if the script hasn't changed; otherwise the
+                // only function with that name
+                let exact = decls.iter().find(|d| d.name == *name && d.line == *line);
+                let mut named = decls.iter().filter(|d| d.name == *name);
+                let decl = match (exact, named.next(), named.next()) {
+                    (Some(decl), _, _) | (None, Some(decl), None) => *decl,
+                    (None, None, _) => {
+                        return Err(SnapshotError::new(format!("function '{}' (line {}) isn't in the script", name, line)))
+                    }
+                    (None, Some(_), Some(_)) => {
+                        return Err(SnapshotError::new(format!(
+                            "function '{}' moved from line {} and the script has several",
+                            name, line
+                        )))
+                    }
+                };
+                let mut closure = base.clone();
+                for (name, saved) in captured {
+                    closure.define(name.clone(), load(saved, &values)?);
+                }
+                Value::Function { decl: Arc::new(decl.clone()), closure: Some(Arc::new(closure)) }
+            }
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+/// A saved value as a value; `objects` are the rebuilt objects so far.
+pub(crate) fn load(saved: &Saved, objects: &[Value]) -> Result<Value, SnapshotError> {
+    Ok(match saved {
+        Saved::Nil => Value::Nil,
+        Saved::Bool(b) => Value::Bool(*b),
+        Saved::Int(i) => Value::Int(*i),
+        Saved::Float(f) => Value::Float(*f),
+        Saved::String(s) => Value::String(s.clone()),
+        Saved::Vec2(x, y) => Value::Vec2(*x, *y),
+        Saved::Vec3(x, y, z) => Value::Vec3(*x, *y, *z),
+        Saved::Builtin(name) => Value::BuiltinFunction(name.clone()),
+        Saved::Ref(index) => match objects.get(*index) {
+            Some(value) => value.clone(),
+            None => return Err(SnapshotError::new(format!("object @{} is used before it's defined", index))),
+        },
+    })
+}
+
+pub(crate) fn saved_timer(timer: &Timer, callback: Saved) -> SavedTimer {
+    SavedTimer { handle: timer.id, clock: timer.clock, due: timer.due, interval: timer.interval, callback }
+}
+
+impl Snapshot {
+    /// Names of every builtin the snapshot refers to, so the restoring
+    /// interpreter can check them against its sandbox.
+    pub fn builtins(&self) -> Vec<&str> {
+        let mut saved: Vec<&Saved> = self.globals.iter().map(|(_, value)| value).collect();
+        saved.extend(self.timers.iter().map(|timer| &timer.callback));
+        for object in &self.objects {
+            let entries = match object {
+                Object::Table(entries) => entries,
+                Object::Function { captured, .. } => captured,
+            };
+            saved.extend(entries.iter().map(|(_, value)| value));
+        }
+        saved
+            .into_iter()
+            .filter_map(|value| match value {
+                Saved::Builtin(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{} {}\n", TEXT_MAGIC, VERSION);
+        out.push_str(&format!("clock {} {} {}\n", float_text(self.time), self.frame, self.next_timer));
+        out.push_str(&format!("random {}\n", self.random_state));
+        for (index, object) in self.objects.iter().enumerate() {
+            write_object(&mut out, index, object);
+        }
+        for (name, value) in &self.globals {
+            out.push_str("global ");
+            json::write_string(&mut out, name);
+            out.push(' ');
+            write_saved(&mut out, value);
+            out.push('\n');
+        }
+        for timer in &self.timers {
+            let clock = match timer.clock {
+                TimerClock::Seconds => "seconds",
+                TimerClock::Frames => "frames",
+            };
+            out.push_str(&format!("timer {} {} {} ", timer.handle, clock, float_text(timer.due)));
+            if let Some(interval) = timer.interval {
+                out.push_str(&format!("every {} ", float_text(interval)));
+            }
+            write_saved(&mut out, &timer.callback);
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, SnapshotError> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()).peekable();
+        let mut snapshot = Snapshot::empty();
+
+        let header = lines.next().map_or("", |(_, line)| line);
+        match header.split_once(' ') {
+            Some((TEXT_MAGIC, version)) => check_version(version.trim().parse().unwrap_or(0))?,
+            _ => return Err(SnapshotError::new("not an ArcScript snapshot")),
+        }
+
+        while let Some((number, line)) = lines.next() {
+            let at = |e: SnapshotError| SnapshotError::new(format!("line {}: {}", number + 1, e.message));
+            let mut tokens = Tokens::new(line).map_err(at)?;
+            match tokens.word().map_err(at)? {
+                "clock" => {
+                    snapshot.time = tokens.float().map_err(at)?;
+                    snapshot.frame = tokens.number().map_err(at)?;
+                    snapshot.next_timer = tokens.number().map_err(at)?;
+                }
+                "random" => snapshot.random_state = tokens.number().map_err(at)?,
+                kind @ ("table" | "function") => {
+                    let index: usize = tokens.number().map_err(at)?;
+                    if index != snapshot.objects.len() {
+                        return Err(at(SnapshotError::new(format!("expected object {}", snapshot.objects.len()))));
+                    }
+                    let header = if kind == "function" {
+                        Some((tokens.string().map_err(at)?, tokens.number().map_err(at)?))
+                    } else {
+                        None
+                    };
+                    tokens.end().map_err(at)?;
+                    let mut entries = Vec::new();
+                    while let Some((number, line)) = lines.next_if(|(_, line)| line.starts_with(' ')) {
+                        let at = |e: SnapshotError| SnapshotError::new(format!("line {}: {}", number + 1, e.message));
+                        let mut tokens = Tokens::new(line).map_err(at)?;
+                        let name = tokens.string().map_err(at)?;
+                        let value = tokens.value(index).map_err(at)?;
+                        tokens.end().map_err(at)?;
+                        entries.push((name, value));
+                    }
+                    snapshot.objects.push(match header {
+                        Some((name, line)) => Object::Function { name, line, captured: entries },
+                        None => Object::Table(entries),
+                    });
+                }
+                "global" => {
+                    let name = tokens.string().map_err(at)?;
+                    let value = tokens.value(snapshot.objects.len()).map_err(at)?;
+                    snapshot.globals.push((name, value));
+                }
+                "timer" => {
+                    let handle = tokens.number().map_err(at)?;
+                    let clock = match tokens.word().map_err(at)? {
+                        "seconds" => TimerClock::Seconds,
+                        "frames" => TimerClock::Frames,
+                        other => return Err(at(SnapshotError::new(format!("unknown timer clock '{}'", other)))),
+                    };
+                    let due = tokens.float().map_err(at)?;
+                    let interval = if tokens.peek() == Some("every") {
+                        tokens.word().map_err(at)?;
+                        Some(tokens.float().map_err(at)?)
+                    } else {
+                        None
+                    };
+                    let callback = tokens.value(snapshot.objects.len()).map_err(at)?;
+                    snapshot.timers.push(SavedTimer { handle, clock, due, interval, callback });
+                }
+                other => return Err(at(SnapshotError::new(format!("unknown item '{}'", other)))),
+            }
+            tokens.end().map_err(at)?;
+        }
+        Ok(snapshot)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = BINARY_MAGIC.to_vec();
+        out.push(VERSION as u8);
+        out.extend_from_slice(&self.time.to_le_bytes());
+        put_varint(&mut out, self.frame);
+        put_int(&mut out, self.next_timer);
+        put_varint(&mut out, self.random_state);
+        put_varint(&mut out, self.objects.len() as u64);
+        for object in &self.objects {
+            match object {
+                Object::Table(entries) => {
+                    out.push(0);
+                    put_entries(&mut out, entries);
+                }
+                Object::Function { name, line, captured } => {
+                    out.push(1);
+                    put_str(&mut out, name);
+                    put_varint(&mut out, *line as u64);
+                    put_entries(&mut out, captured);
+                }
+            }
+        }
+        put_entries(&mut out, &self.globals);
+        put_varint(&mut out, self.timers.len() as u64);
+        for timer in &self.timers {
+            put_int(&mut out, timer.handle);
+            out.push(match timer.clock {
+                TimerClock::Seconds => 0,
+                TimerClock::Frames => 1,
+            });
+            out.extend_from_slice(&timer.due.to_le_bytes());
+            match timer.interval {
+                Some(interval) => {
+                    out.push(1);
+                    out.extend_from_slice(&interval.to_le_bytes());
+                }
+                None => out.push(0),
+            }
+            put_saved(&mut out, &timer.callback);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(BINARY_MAGIC.len()).ok() != Some(BINARY_MAGIC) {
+            return Err(SnapshotError::new("not an ArcScript snapshot"));
+        }
+        check_version(reader.byte()? as u32)?;
+        let mut snapshot = Snapshot::empty();
+        snapshot.time = reader.float()?;
+        snapshot.frame = reader.varint()?;
+        snapshot.next_timer = reader.int()?;
+        snapshot.random_state = reader.varint()?;
+        let count = reader.count()?;
+        for index in 0..count {
+            let object = match reader.byte()? {
+                0 => Object::Table(reader.entries(index)?),
+                1 => {
+                    let name = reader.string()?;
+                    let line = reader.count()?;
+                    Object::Function { name, line, captured: reader.entries(index)? }
+                }
+                tag => return Err(reader.error(&format!("unknown object tag {}", tag))),
+            };
+            snapshot.objects.push(object);
+        }
+        snapshot.globals = reader.entries(count)?;
+        for _ in 0..reader.count()? {
+            let handle = reader.int()?;
+            let clock = match reader.byte()? {
+                0 => TimerClock::Seconds,
+                1 => TimerClock::Frames,
+                tag => return Err(reader.error(&format!("unknown timer clock {}", tag))),
+            };
+            let due = reader.float()?;
+            let interval = match reader.byte()? {
+                0 => None,
+                _ => Some(reader.float()?),
+            };
+            let callback = reader.saved(count)?;
+            snapshot.timers.push(SavedTimer { handle, clock, due, interval, callback });
+        }
+        if reader.pos != bytes.len() {
+            return Err(reader.error("unexpected bytes after the snapshot"));
+        }
+        Ok(snapshot)
+    }
+
+    fn empty() -> Self {
+        Snapshot {
+            time: 0.0,
+            frame: 0,
+            next_timer: 1,
+            random_state: 0,
+            objects: Vec::new(),
+            globals: Vec::new(),
+            timers: Vec::new(),
+        }
+    }
+}
+
+fn check_version(version: u32) -> Result<(), SnapshotError> {
+    if version == VERSION {
+        Ok(())
+    } else {
+        Err(SnapshotError::new(format!("unsupported snapshot version {} (this build reads {})", version, VERSION)))
+    }
+}
+
+fn write_object(out: &mut String, index: usize, object: &Object) {
+    let entries = match object {
+        Object::Table(entries) => {
+            out.push_str(&format!("table {}\n", index));
+            entries
+        }
+        Object::Function { name, line, captured } => {
+            out.push_str(&format!("function {} ", index));
+            json::write_string(out, name);
+            out.push_str(&format!(" {}\n", line));
+            captured
+        }
+    };
+    for (key, value) in entries {
+        out.push_str("  ");
+        json::write_string(out, key);
+        out.push(' ');
+        write_saved(out, value);
+        out.push('\n');
+    }
+}
+
+fn write_saved(out: &mut String, value: &Saved) {
+    match value {
+        Saved::Nil => out.push_str("nil"),
+        Saved::Bool(b) => out.push_str(&b.to_string()),
+        Saved::Int(i) => out.push_str(&i.to_string()),
+        Saved::Float(f) => out.push_str(&float_text(*f)),
+        Saved::String(s) => json::write_string(out, s),
+        Saved::Vec2(x, y) => out.push_str(&format!("vec2 {} {}", float_text(*x), float_text(*y))),
+        Saved::Vec3(x, y, z) => out.push_str(&format!("vec3 {} {} {}", float_text(*x), float_text(*y), float_text(*z))),
+        Saved::Builtin(name) => {
+            out.push_str("builtin ");
+            json::write_string(out, name);
+        }
+        Saved::Ref(index) => out.push_str(&format!("@{}", index)),
+    }
+}
+
+/// Debug formatting keeps the '.' (1.0) and round-trips exactly.
+fn float_text(f: f64) -> String {
+    format!("{:?}", f)
+}
+
+/// The words and JSON strings of one text line.
+struct Tokens<'a> {
+    tokens: std::vec::IntoIter<&'a str>,
+    peeked: Option<&'a str>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: &'a str) -> Result<Self, SnapshotError> {
+        let mut tokens = Vec::new();
+        let mut rest = line.trim();
+        while !rest.is_empty() {
+            let end = if rest.starts_with('"') {
+                let mut escaped = false;
+                let close = rest.char_indices().skip(1).find(|&(_, c)| {
+                    let found = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    found
+                });
+                match close {
+                    Some((i, _)) => i + 1,
+                    None => return Err(SnapshotError::new("unterminated string")),
+                }
+            } else {
+                rest.find(char::is_whitespace).unwrap_or(rest.len())
+            };
+            tokens.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+        let mut tokens = tokens.into_iter();
+        let peeked = tokens.next();
+        Ok(Self { tokens, peeked })
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.peeked
+    }
+
+    fn word(&mut self) -> Result<&'a str, SnapshotError> {
+        let token = self.peeked.ok_or_else(|| SnapshotError::new("line ends too early"))?;
+        self.peeked = self.tokens.next();
+        Ok(token)
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, SnapshotError> {
+        let word = self.word()?;
+        word.parse().map_err(|_| SnapshotError::new(format!("expected a number, found '{}'", word)))
+    }
+
+    fn float(&mut self) -> Result<f64, SnapshotError> {
+        self.number()
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        let word = self.word()?;
+        match json::decode(word) {
+            Ok(Value::String(s)) if word.starts_with('"') => Ok(s),
+            _ => Err(SnapshotError::new(format!("expected a string, found '{}'", word))),
+        }
+    }
+
+    /// A value that may refer to objects before `objects`.
+    fn value(&mut self, objects: usize) -> Result<Saved, SnapshotError> {
+        let word = self.peek().ok_or_else(|| SnapshotError::new("line ends too early"))?;
+        Ok(match word {
+            "nil" => Saved::Nil,
+            "true" => Saved::Bool(true),
+            "false" => Saved::Bool(false),
+            "vec2" => {
+                self.word()?;
+                return Ok(Saved::Vec2(self.float()?, self.float()?));
+            }
+            "vec3" => {
+                self.word()?;
+                return Ok(Saved::Vec3(self.float()?, self.float()?, self.float()?));
+            }
+            "builtin" => {
+                self.word()?;
+                return Ok(Saved::Builtin(self.string()?));
+            }
+            _ if word.starts_with('"') => return Ok(Saved::String(self.string()?)),
+            _ if word.starts_with('@') => {
+                let index = word[1..].parse().map_err(|_| SnapshotError::new(format!("bad reference '{}'", word)))?;
+                if index >= objects {
+                    return Err(SnapshotError::new(format!("object @{} is used before it's defined", index)));
+                }
+                Saved::Ref(index)
+            }
+            _ => match word.parse::<i64>() {
+                Ok(i) => Saved::Int(i),
+                Err(_) => return Ok(Saved::Float(self.float()?)),
+            },
+        })
+        .and_then(|value| self.word().map(|_| value))
+    }
+
+    fn end(&mut self) -> Result<(), SnapshotError> {
+        match self.peeked {
+            None => Ok(()),
+            Some(extra) => Err(SnapshotError::new(format!("unexpected '{}'", extra))),
+        }
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn put_int(out: &mut Vec<u8>, i: i64) {
+    put_varint(out, ((i << 1) ^ (i >> 63)) as u64);
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn put_entries(out: &mut Vec<u8>, entries: &[(String, Saved)]) {
+    put_varint(out, entries.len() as u64);
+    for (name, value) in entries {
+        put_str(out, name);
+        put_saved(out, value);
+    }
+}
+
+fn put_saved(out: &mut Vec<u8>, value: &Saved) {
+    match value {
+        Saved::Nil => out.push(0),
+        Saved::Bool(false) => out.push(1),
+        Saved::Bool(true) => out.push(2),
+        Saved::Int(i) => {
+            out.push(3);
+            put_int(out, *i);
+        }
+        Saved::Float(f) => {
+            out.push(4);
+            out.extend_from_slice(&f.to_le_bytes());
+        }
+        Saved::String(s) => {
+            out.push(5);
+            put_str(out, s);
+        }
+        Saved::Vec2(x, y) => {
+            out.push(6);
+            [x, y].iter().for_each(|f| out.extend_from_slice(&f.to_le_bytes()));
+        }
+        Saved::Vec3(x, y, z) => {
+            out.push(7);
+            [x, y, z].iter().for_each(|f| out.extend_from_slice(&f.to_le_bytes()));
+        }
+        Saved::Builtin(name) => {
+            out.push(8);
+            put_str(out, name);
+        }
+        Saved::Ref(index) => {
+            out.push(9);
+            put_varint(out, *index as u64);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> SnapshotError {
+        SnapshotError::new(format!("{} at byte {}", message, self.pos))
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        match self.bytes.get(self.pos..self.pos.saturating_add(n)) {
+            Some(slice) => {
+                self.pos += n;
+                Ok(slice)
+            }
+            None => Err(self.error("snapshot ends too early")),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, SnapshotError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(self.error("varint too long"))
+    }
+
+    /// A count or index, which can't be more than the bytes left.
+    fn count(&mut self) -> Result<usize, SnapshotError> {
+        let n = self.varint()?;
+        usize::try_from(n).ok().filter(|&n| n <= self.bytes.len()).ok_or_else(|| self.error("count too large"))
+    }
+
+    fn int(&mut self) -> Result<i64, SnapshotError> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn float(&mut self) -> Result<f64, SnapshotError> {
+        let bytes = self.take(8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().unwrap_or_default()))
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        let len = self.count()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error("string isn't UTF-8"))
+    }
+
+    fn entries(&mut self, objects: usize) -> Result<Vec<(String, Saved)>, SnapshotError> {
+        let count = self.count()?;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push((self.string()?, self.saved(objects)?));
+        }
+        Ok(entries)
+    }
+
+    /// A value that may refer to objects before `objects`.
+    fn saved(&mut self, objects: usize) -> Result<Saved, SnapshotError> {
+        Ok(match self.byte()? {
+            0 => Saved::Nil,
+            1 => Saved::Bool(false),
+            2 => Saved::Bool(true),
+            3 => Saved::Int(self.int()?),
+            4 => Saved::Float(self.float()?),
+            5 => Saved::String(self.string()?),
+            6 => Saved::Vec2(self.float()?, self.float()?),
+            7 => Saved::Vec3(self.float()?, self.float()?, self.float()?),
+            8 => Saved::Builtin(self.string()?),
+            9 => {
+                let index = self.count()?;
+                if index >= objects {
+                    return Err(self.error(&format!("object @{} is used before it's defined", index)));
+                }
+                Saved::Ref(index)
+            }
+            tag => return Err(self.error(&format!("unknown value tag {}", tag))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::sandbox::{Capability, Sandbox};
+
+    const GAME: &str = "
+        var player = {name: \"Hero\", hp: 100, pos: vec2(1.5, -2)};
+        var party = {leader: player, backup: player};
+        var kills = 0;
+        func make_counter(step): {
+            var count = 0;
+            func next(): {
+                count = count + step;
+                return count;
+            } end
+            return next;
+        } end
+        var counter = make_counter(5);
+        func bonus(): { return kills * 10 + len(player.name); } end
+        object Enemy: {
+            var hp = 30;
+            func hit(self, amount): { return self.hp - amount; } end
+        } end
+        func respawn(): { kills = kills + 1; } end
+        every(2.0, respawn);
+        after_frames(3, bonus);
+    ";
+
+    fn start(source: &str) -> (Interpreter, Program) {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let mut interp = Interpreter::new();
+        interp.eval_program(&program).unwrap();
+        (interp, program)
+    }
+
+    fn run(interp: &mut Interpreter, source: &str) -> Value {
+        interp.eval_in_frame(0, source).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_through_text_and_binary() {
+        let (mut interp, _) = start(GAME);
+        run(&mut interp, "kills = 3; player.hp = 75; Enemy.hp = 12;");
+        let snapshot = interp.snapshot().unwrap();
+
+        // party (twice) and bonus hold the player from before the hit, the
+        // player global the current one: two tables to store
+        let players = snapshot.objects.iter().filter(|o| matches!(o, Object::Table(e) if e.iter().any(|(k, _)| k == "pos"))).count();
+        assert_eq!(players, 2);
+
+        let text = snapshot.to_text();
+        assert!(text.starts_with("arcscript-snapshot 1\nclock 0.0 0 3\n"), "{}", text);
+        assert!(text.contains("\n  \"pos\" vec2 1.5 -2.0\n"), "{}", text);
+        assert_eq!(Snapshot::from_text(&text).unwrap(), snapshot);
+        let bytes = snapshot.to_bytes();
+        assert_eq!(Snapshot::from_bytes(&bytes).unwrap(), snapshot);
+        assert!(bytes.len() < text.len());
+    }
+
+    #[test]
+    fn test_restore_continues_the_game() {
+        let (mut interp, program) = start(GAME);
+        interp.seed_random(42);
+        run(&mut interp, "kills = 3; player.hp = 75; Enemy.hp = 12;");
+        interp.tick(0.5).unwrap();
+        let snapshot = interp.snapshot().unwrap();
+
+        let (mut fresh, _) = start(GAME);
+        fresh.restore(&Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), &program).unwrap();
+        // Functions show as <function name>, as their closures differ
+        let data = |interp: &Interpreter| -> Vec<(String, String)> {
+            interp.user_globals().into_iter().map(|(name, value)| (name, value.repr())).collect()
+        };
+        assert_eq!(data(&fresh), data(&interp));
+        assert_eq!((fresh.time(), fresh.frame(), fresh.pending_timers()), (0.5, 1, 2));
+
+        for source in ["party.backup.hp", "bonus()", "Enemy.hit(Enemy, 2)", "counter()", "random.int(1, 1000000)"] {
+            assert_eq!(run(&mut fresh, source), run(&mut interp, source), "{}", source);
+        }
+        // The repeating timer fires on schedule and new handles don't clash
+        fresh.tick(1.5).unwrap();
+        interp.tick(1.5).unwrap();
+        assert_eq!(run(&mut fresh, "kills"), run(&mut interp, "kills"));
+        assert_eq!(run(&mut fresh, "after(1.0, bonus)"), run(&mut interp, "after(1.0, bonus)"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let (interp, _) = start("func patrol(): { yield(1); } end var guard = coroutine(patrol);");
+        assert_eq!(interp.snapshot().unwrap_err().message, "global 'guard': coroutines can't be saved");
+
+        let (interp, _) = start("var hp = 3; func heal(): { return hp + 1; } end");
+        let snapshot = interp.snapshot().unwrap();
+        let patched = Parser::new(Lexer::new("var hp = 3;\n\nfunc cure(): { return 1; } end")).parse_program().unwrap();
+        let err = Interpreter::new().restore(&snapshot, &patched).unwrap_err();
+        assert_eq!(err.message, "function 'heal' (line 1) isn't in the script");
+
+        let text = snapshot.to_text().replace("arcscript-snapshot 1", "arcscript-snapshot 7");
+        assert_eq!(Snapshot::from_text(&text).unwrap_err().message, "unsupported snapshot version 7 (this build reads 1)");
+        let text = snapshot.to_text().replace("global \"hp\" 3", "global \"hp\" @9");
+        assert_eq!(Snapshot::from_text(&text).unwrap_err().message, "line 7: object @9 is used before it's defined");
+        let bytes = snapshot.to_bytes();
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err().message.starts_with("snapshot ends too early"));
+    }
+
+    #[test]
+    fn test_restore_respects_the_sandbox() {
+        let (interp, program) = start("var hp = 3; var hooks = {};");
+        let text = interp.snapshot().unwrap().to_text();
+        let pure = || Interpreter::with_sandbox(Sandbox::new(&[Capability::Pure]));
+
+        let forged = Snapshot::from_text(&text.replace("global \"hp\" 3", "global \"hp\" builtin \"println\"")).unwrap();
+        assert_eq!(forged.builtins(), ["println"]);
+        let err = pure().restore(&forged, &program).unwrap_err();
+        assert_eq!(err.message, "capability not granted: 'println' requires 'io'");
+        let mut full = Interpreter::new();
+        full.restore(&forged, &program).unwrap();
+        assert_eq!(full.get_global("hp"), Some(Value::BuiltinFunction("println".to_string())));
+
+        // Builtins inside tables are checked too, and so are made-up names
+        let mut nested = forged.clone();
+        nested.globals = vec![("hp".to_string(), Saved::Int(3)), ("hooks".to_string(), Saved::Ref(0))];
+        nested.objects = vec![Object::Table(vec![("log".to_string(), Saved::Builtin("print".to_string()))])];
+        assert_eq!(pure().restore(&nested, &program).unwrap_err().message, "capability not granted: 'print' requires 'io'");
+        nested.objects = vec![Object::Table(vec![("x".to_string(), Saved::Builtin("system".to_string()))])];
+        assert_eq!(Interpreter::new().restore(&nested, &program).unwrap_err().message, "'system' is not a builtin");
+    }
+}
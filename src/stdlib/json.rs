@@ -0,0 +1,491 @@
+// The `json` namespace and the matching host helpers: converting between JSON
+// text and ArcScript values
+//
+// Objects and arrays become tables (arrays as lists), null becomes nil, and
+// numbers stay ints unless they have a fraction or exponent. Functions and
+// coroutines can't be encoded.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::interpreter::{RuntimeError, Value};
+use crate::stdlib::{list_items, list_table, string_arg};
+
+pub const FUNCTIONS: &[&str] = &["encode", "decode"];
+
+/// Deepest nesting accepted either way. Tables are values, so a table can't
+/// contain itself, but deep nesting would still exhaust the stack.
+pub const MAX_DEPTH: usize = 128;
+
+/// Why a value couldn't be encoded or a text couldn't be decoded. Decode
+/// errors carry the 1-based line and column where parsing stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub message: String,
+    pub position: Option<(usize, usize)>,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{} at line {}, column {}", self.message, line, column),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// Calls `json.<function>`.
+pub(crate) fn call(function: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match function {
+        "encode" => {
+            let value = match args.first() {
+                Some(value) => value,
+                None => return Err(RuntimeError::new("json.encode() requires a value")),
+            };
+            let pretty = match args.get(1) {
+                None | Some(Value::Nil) => false,
+                Some(Value::Bool(pretty)) => *pretty,
+                _ => return Err(RuntimeError::new("json.encode() pretty flag must be a boolean")),
+            };
+            encode(value, pretty)
+                .map(Value::String)
+                .map_err(|e| RuntimeError::new(&format!("json.encode(): {}", e)))
+        }
+        "decode" => {
+            let text = string_arg(args, 0, "json.decode")?;
+            decode(text).map_err(|e| RuntimeError::new(&format!("json.decode(): {}", e)))
+        }
+        _ => Err(RuntimeError::new(&format!("unknown built-in function: json.{}", function))),
+    }
+}
+
+/// Encodes `value` as JSON, indented by two spaces per level if `pretty`.
+/// Object keys come out sorted so the same table always gives the same text.
+pub fn encode(value: &Value, pretty: bool) -> Result<String, JsonError> {
+    let mut out = String::new();
+    write_value(&mut out, value, pretty, 0)?;
+    Ok(out)
+}
+
+/// Parses JSON text into a value.
+pub fn decode(text: &str) -> Result<Value, JsonError> {
+    let mut parser = JsonParser { chars: text.chars().collect(), pos: 0 };
+    parser.skip_whitespace();
+    let value = parser.parse_value(0)?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("unexpected text after the value"));
+    }
+    Ok(value)
+}
+
+fn encode_error(message: &str) -> JsonError {
+    JsonError { message: message.to_string(), position: None }
+}
+
+fn write_value(out: &mut String, value: &Value, pretty: bool, depth: usize) -> Result<(), JsonError> {
+    if depth > MAX_DEPTH {
+        return Err(encode_error("value is nested too deeply"));
+    }
+    match value {
+        Value::Nil => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Int(i) => out.push_str(&i.to_string()),
+        Value::Float(f) => write_float(out, *f)?,
+        Value::String(s) => write_string(out, s),
+        // Vectors have no JSON form of their own; they come back as lists
+        Value::Vec2(x, y) => write_array(out, &[Value::Float(*x), Value::Float(*y)], pretty, depth)?,
+        Value::Vec3(x, y, z) => {
+            write_array(out, &[Value::Float(*x), Value::Float(*y), Value::Float(*z)], pretty, depth)?
+        }
+        Value::Table(table) if !table.is_empty() && list_items(table).len() == table.len() => {
+            write_array(out, &list_items(table), pretty, depth)?
+        }
+        Value::Table(table) => {
+            let mut keys: Vec<&String> = table.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, pretty, depth + 1);
+                write_string(out, key);
+                out.push_str(if pretty { ": " } else { ":" });
+                write_value(out, &table[*key], pretty, depth + 1)?;
+            }
+            if !keys.is_empty() {
+                newline(out, pretty, depth);
+            }
+            out.push('}');
+        }
+        Value::Function { .. } | Value::BuiltinFunction(_) => return Err(encode_error("cannot encode a function")),
+        Value::Coroutine(_) => return Err(encode_error("cannot encode a coroutine")),
+    }
+    Ok(())
+}
+
+fn write_array(out: &mut String, items: &[Value], pretty: bool, depth: usize) -> Result<(), JsonError> {
+    out.push('[');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(out, pretty, depth + 1);
+        write_value(out, item, pretty, depth + 1)?;
+    }
+    newline(out, pretty, depth);
+    out.push(']');
+    Ok(())
+}
+
+fn newline(out: &mut String, pretty: bool, depth: usize) {
+    if pretty {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+}
+
+fn write_float(out: &mut String, f: f64) -> Result<(), JsonError> {
+    if !f.is_finite() {
+        return Err(encode_error("cannot encode NaN or infinity"));
+    }
+    // Keep a decimal point on whole floats so they decode as floats again
+    let text = f.to_string();
+    out.push_str(&text);
+    if !text.contains(['.', 'e', 'E']) {
+        out.push_str(".0");
+    }
+    Ok(())
+}
+
+pub(crate) fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl JsonParser {
+    fn error(&self, message: &str) -> JsonError {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        JsonError { message: message.to_string(), position: Some((line, column)) }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), JsonError> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Value, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("value is nested too deeply"));
+        }
+        match self.peek() {
+            Some('{') => self.parse_object(depth),
+            Some('[') => self.parse_array(depth),
+            Some('"') => Ok(Value::String(self.parse_string()?)),
+            Some('-' | '0'..='9') => self.parse_number(),
+            Some(_) => {
+                for (word, value) in [("true", Value::Bool(true)), ("false", Value::Bool(false)), ("null", Value::Nil)] {
+                    if self.chars[self.pos..].starts_with(&word.chars().collect::<Vec<_>>()) {
+                        self.pos += word.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.error("unexpected character"))
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<Value, JsonError> {
+        self.expect('{')?;
+        let mut table = HashMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Table(table));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            let value = self.parse_value(depth + 1)?;
+            table.insert(key, value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Table(table));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<Value, JsonError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(list_table(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(list_table(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = match self.peek() {
+                        Some(c) => c,
+                        None => return Err(self.error("unterminated string")),
+                    };
+                    self.pos += 1;
+                    match escaped {
+                        '"' => s.push('"'),
+                        '\\' => s.push('\\'),
+                        '/' => s.push('/'),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => s.push(self.parse_unicode_escape()?),
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("invalid escape"));
+                        }
+                    }
+                }
+                c if (c as u32) < 0x20 => {
+                    self.pos -= 1;
+                    return Err(self.error("control character in string"));
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// The character after `\u`, combining a surrogate pair if needed.
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.parse_hex4()?;
+        if (0xD800..0xDC00).contains(&high) {
+            if self.chars[self.pos..].starts_with(&['\\', 'u']) {
+                self.pos += 2;
+                let low = self.parse_hex4()?;
+                if (0xDC00..0xE000).contains(&low) {
+                    let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                    return char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"));
+                }
+            }
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+        char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let digits: String = self.chars.iter().skip(self.pos).take(4).collect();
+        match u32::from_str_radix(&digits, 16) {
+            Ok(code) if digits.len() == 4 => {
+                self.pos += 4;
+                Ok(code)
+            }
+            _ => Err(self.error("invalid unicode escape")),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, JsonError> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        let digits_start = self.pos;
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.pos += 1;
+        }
+        let int_digits = self.pos - digits_start;
+        if int_digits == 0 || (int_digits > 1 && self.chars[digits_start] == '0') {
+            self.pos = start;
+            return Err(self.error("invalid number"));
+        }
+        let mut is_float = false;
+        if self.peek() == Some('.') {
+            is_float = true;
+            self.pos += 1;
+            if !self.skip_digits() {
+                return Err(self.error("expected digits after '.'"));
+            }
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            is_float = true;
+            self.pos += 1;
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.pos += 1;
+            }
+            if !self.skip_digits() {
+                return Err(self.error("expected digits in exponent"));
+            }
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        if !is_float {
+            if let Ok(i) = text.parse::<i64>() {
+                return Ok(Value::Int(i));
+            }
+        }
+        // Too big for an int, or written as a float
+        text.parse::<f64>().map(Value::Float).map_err(|_| self.error("invalid number"))
+    }
+
+    fn skip_digits(&mut self) -> bool {
+        let start = self.pos;
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::stdlib::run_script;
+
+    fn table(entries: &[(&str, Value)]) -> Value {
+        Value::Table(entries.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+    }
+
+    #[test]
+    fn test_round_trip_keeps_ints_and_floats() {
+        let level = table(&[
+            ("name", Value::String("Cave \"1\"\n".to_string())),
+            ("width", Value::Int(40)),
+            ("gravity", Value::Float(9.5)),
+            ("scale", Value::Float(2.0)),
+            ("spawns", list_table(vec![Value::Int(1), Value::Nil, Value::Bool(true)])),
+            ("meta", table(&[])),
+        ]);
+        let text = encode(&level, false).unwrap();
+        assert_eq!(
+            text,
+            r#"{"gravity":9.5,"meta":{},"name":"Cave \"1\"\n","scale":2.0,"spawns":[1,null,true],"width":40}"#
+        );
+        assert_eq!(decode(&text).unwrap(), level);
+        assert_eq!(decode(&encode(&level, true).unwrap()).unwrap(), level);
+    }
+
+    #[test]
+    fn test_pretty_output() {
+        let value = table(&[("hp", Value::Int(3)), ("tags", list_table(vec![Value::String("boss".to_string())]))]);
+        assert_eq!(encode(&value, true).unwrap(), "{\n  \"hp\": 3,\n  \"tags\": [\n    \"boss\"\n  ]\n}");
+    }
+
+    #[test]
+    fn test_decode_numbers_strings_and_errors() {
+        assert_eq!(decode("-12").unwrap(), Value::Int(-12));
+        assert_eq!(decode("1e3").unwrap(), Value::Float(1000.0));
+        assert_eq!(decode("99999999999999999999").unwrap(), Value::Float(1e20));
+        assert_eq!(decode(r#""\u00e9\ud83d\ude00\/""#).unwrap(), Value::String("é😀/".to_string()));
+        assert_eq!(decode(" [ ] ").unwrap(), list_table(vec![]));
+
+        let err = decode("{\n  \"a\": 1,\n  \"b\" 2\n}").unwrap_err();
+        assert_eq!(err.position, Some((3, 7)));
+        assert_eq!(err.to_string(), "expected ':' at line 3, column 7");
+        for bad in ["", "[1,]", "01", "{\"a\":1} x", "\"open", "tru", "1.", "\"\\ud800\""] {
+            assert!(decode(bad).is_err(), "{:?} should not decode", bad);
+        }
+        assert!(decode(&"[".repeat(MAX_DEPTH + 2)).unwrap_err().message.contains("too deeply"));
+    }
+
+    #[test]
+    fn test_encode_rejects_functions_and_non_finite() {
+        let with_fn = table(&[("f", Value::BuiltinFunction("print".to_string()))]);
+        assert_eq!(encode(&with_fn, false).unwrap_err().message, "cannot encode a function");
+        assert!(encode(&Value::Float(f64::NAN), false).is_err());
+        assert_eq!(encode(&Value::Vec2(1.0, 2.5), false).unwrap(), "[1.0,2.5]");
+    }
+
+    #[test]
+    fn test_script_encode_and_decode() {
+        let src = r#"
+            var save = {level: 3, pos: {x: 1.5, y: 2}};
+            var text = json.encode(save);
+            var back = json.decode(text);
+            var same = back.pos.x == 1.5 && back.level == 3;
+        "#;
+        let mut interp = run_script(src).expect("runtime error");
+        assert_eq!(
+            interp.get_global("text"),
+            Some(Value::String(r#"{"level":3,"pos":{"x":1.5,"y":2}}"#.to_string()))
+        );
+        assert_eq!(interp.get_global("same"), Some(Value::Bool(true)));
+
+        let program = Parser::new(Lexer::new("json.encode({f: print});")).parse_program().expect("parse failed");
+        let err = interp.eval_program(&program).unwrap_err();
+        assert!(err.message.contains("cannot encode a function"), "{}", err.message);
+    }
+}
@@ -0,0 +1,158 @@
+// Global math builtins beyond the basics: trigonometry, exp/log, clamp, sign
+// and variadic min/max
+
+use crate::interpreter::{RuntimeError, Value};
+
+pub const FUNCTIONS: &[&str] = &[
+    "min", "max", "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "exp", "log", "clamp", "sign",
+];
+
+/// Global constants defined next to the math builtins.
+pub const CONSTANTS: &[(&str, f64)] = &[
+    ("PI", std::f64::consts::PI),
+    ("TAU", std::f64::consts::TAU),
+    ("INF", f64::INFINITY),
+];
+
+fn number(value: &Value, name: &str) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Int(i) => Ok(*i as f64),
+        Value::Float(f) => Ok(*f),
+        _ => Err(RuntimeError::new(&format!("{}() requires numeric arguments", name))),
+    }
+}
+
+fn unary(args: &[Value], name: &str, f: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    match args.first() {
+        Some(x) => Ok(Value::Float(f(number(x, name)?))),
+        None => Err(RuntimeError::new(&format!("{}() requires 1 argument", name))),
+    }
+}
+
+pub(crate) fn call(name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match name {
+        "min" | "max" => {
+            let (first, rest) = match args.split_first() {
+                Some(split) => split,
+                None => return Err(RuntimeError::new(&format!("{}() requires at least 1 argument", name))),
+            };
+            let pick_min = name == "min";
+            let mut best = first.clone();
+            number(&best, name)?;
+            for arg in rest {
+                let (a, b) = (number(&best, name)?, number(arg, name)?);
+                if (pick_min && b < a) || (!pick_min && b > a) {
+                    best = arg.clone();
+                }
+            }
+            // Mixing ints and floats gives a float, like the other arithmetic
+            let all_ints = args.iter().all(|a| matches!(a, Value::Int(_)));
+            Ok(if all_ints { best } else { Value::Float(number(&best, name)?) })
+        }
+        "sin" => unary(args, name, f64::sin),
+        "cos" => unary(args, name, f64::cos),
+        "tan" => unary(args, name, f64::tan),
+        "asin" => unary(args, name, f64::asin),
+        "acos" => unary(args, name, f64::acos),
+        "atan" => unary(args, name, f64::atan),
+        "exp" => unary(args, name, f64::exp),
+        "atan2" => match args {
+            [y, x, ..] => Ok(Value::Float(number(y, name)?.atan2(number(x, name)?))),
+            _ => Err(RuntimeError::new("atan2() requires 2 arguments (y, x)")),
+        },
+        "log" => {
+            let x = match args.first() {
+                Some(x) => number(x, name)?,
+                None => return Err(RuntimeError::new("log() requires 1 argument")),
+            };
+            if x <= 0.0 {
+                return Err(RuntimeError::new("log() requires a positive argument"));
+            }
+            match args.get(1) {
+                Some(base) => {
+                    let base = number(base, name)?;
+                    if base <= 0.0 || base == 1.0 {
+                        return Err(RuntimeError::new("log() base must be positive and not 1"));
+                    }
+                    Ok(Value::Float(x.log(base)))
+                }
+                None => Ok(Value::Float(x.ln())),
+            }
+        }
+        "clamp" => match args {
+            [Value::Int(x), Value::Int(lo), Value::Int(hi), ..] if lo <= hi => Ok(Value::Int((*x).clamp(*lo, *hi))),
+            [x, lo, hi, ..] => {
+                let (x, lo, hi) = (number(x, name)?, number(lo, name)?, number(hi, name)?);
+                if lo > hi {
+                    return Err(RuntimeError::new("clamp() requires min <= max"));
+                }
+                Ok(Value::Float(x.clamp(lo, hi)))
+            }
+            _ => Err(RuntimeError::new("clamp() requires 3 arguments (value, min, max)")),
+        },
+        "sign" => match args.first() {
+            Some(Value::Int(i)) => Ok(Value::Int(i.signum())),
+            Some(Value::Float(f)) if *f == 0.0 || f.is_nan() => Ok(Value::Float(*f)),
+            Some(Value::Float(f)) => Ok(Value::Float(f.signum())),
+            Some(_) => Err(RuntimeError::new("sign() requires numeric argument")),
+            None => Err(RuntimeError::new("sign() requires 1 argument")),
+        },
+        _ => Err(RuntimeError::new(&format!("unknown built-in function: {}", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::{Interpreter, Value};
+    use crate::stdlib::run_script;
+
+    fn float(interp: &Interpreter, name: &str) -> f64 {
+        match interp.get_global(name) {
+            Some(Value::Float(f)) => f,
+            other => panic!("{} is not a float: {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn test_trig_exp_and_log() {
+        let interp = run_script(r#"
+            var s = sin(PI / 2);
+            var c = cos(TAU);
+            var t = tan(0);
+            var heading = atan2(1, 1);
+            var e = exp(1);
+            var ln = log(e);
+            var bits = log(1024, 2);
+        "#)
+        .expect("runtime error");
+        assert!((float(&interp, "s") - 1.0).abs() < 1e-12);
+        assert!((float(&interp, "c") - 1.0).abs() < 1e-12);
+        assert_eq!(float(&interp, "t"), 0.0);
+        assert!((float(&interp, "heading") - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+        assert!((float(&interp, "ln") - 1.0).abs() < 1e-12);
+        assert!((float(&interp, "bits") - 10.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_clamp_sign_and_variadic_min_max() {
+        let interp = run_script(r#"
+            var low = min(5, 3, 9, 4);
+            var high = max(2, 8.5, 7);
+            var single = max(7);
+            var clamped = clamp(150, 0, 100);
+            var fraction = clamp(-0.5, 0, 1);
+            var signs = sign(-12) + sign(0) + sign(3);
+            var fsign = sign(-2.5);
+            var infinite = INF > 1000000;
+        "#)
+        .expect("runtime error");
+        assert_eq!(interp.get_global("low"), Some(Value::Int(3)));
+        assert_eq!(interp.get_global("high"), Some(Value::Float(8.5)));
+        assert_eq!(interp.get_global("single"), Some(Value::Int(7)));
+        assert_eq!(interp.get_global("clamped"), Some(Value::Int(100)));
+        assert_eq!(interp.get_global("fraction"), Some(Value::Float(0.0)));
+        assert_eq!(interp.get_global("signs"), Some(Value::Int(0)));
+        assert_eq!(interp.get_global("fsign"), Some(Value::Float(-1.0)));
+        assert_eq!(interp.get_global("infinite"), Some(Value::Bool(true)));
+    }
+}
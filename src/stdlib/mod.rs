@@ -0,0 +1,52 @@
+// ArcScript standard library: math and the string, random, table and json namespaces
+//
+// Each namespace is a global table of builtins named "<namespace>.<function>".
+// The interpreter routes those names here from call_builtin.
+
+use std::collections::HashMap;
+
+use crate::interpreter::{RuntimeError, Value};
+
+pub mod json;
+pub mod math;
+pub mod random;
+pub mod signatures;
+pub mod string;
+pub mod table;
+
+/// Builds a list table: values under the positional keys "0", "1", ... like a
+/// table literal without keys.
+pub(crate) fn list_table(values: Vec<Value>) -> Value {
+    Value::Table(values.into_iter().enumerate().map(|(i, v)| (i.to_string(), v)).collect())
+}
+
+/// Reads a list table's positional entries in order, stopping at the first gap.
+pub(crate) fn list_items(table: &HashMap<String, Value>) -> Vec<Value> {
+    (0..).map_while(|i: usize| table.get(&i.to_string()).cloned()).collect()
+}
+
+/// The string argument at `index`, or an error naming the builtin.
+pub(crate) fn string_arg<'a>(args: &'a [Value], index: usize, name: &str) -> Result<&'a str, RuntimeError> {
+    match args.get(index) {
+        Some(Value::String(s)) => Ok(s),
+        _ => Err(RuntimeError::new(&format!("{}() requires a string as argument {}", name, index + 1))),
+    }
+}
+
+/// The integer argument at `index`, `default` if it was left out.
+pub(crate) fn int_arg(args: &[Value], index: usize, default: Option<i64>, name: &str) -> Result<i64, RuntimeError> {
+    match (args.get(index), default) {
+        (Some(Value::Int(i)), _) => Ok(*i),
+        (None | Some(Value::Nil), Some(default)) => Ok(default),
+        _ => Err(RuntimeError::new(&format!("{}() requires an integer as argument {}", name, index + 1))),
+    }
+}
+
+/// Parses and runs `src` in a fresh interpreter, for the namespaces' tests.
+#[cfg(test)]
+pub(crate) fn run_script(src: &str) -> Result<crate::interpreter::Interpreter, RuntimeError> {
+    let program = crate::parser::Parser::new(crate::lexer::Lexer::new(src)).parse_program().expect("parse failed");
+    let mut interp = crate::interpreter::Interpreter::new();
+    interp.eval_program(&program)?;
+    Ok(interp)
+}
@@ -0,0 +1,195 @@
+// The `random` namespace: a seedable generator so replays and tests can
+// reproduce every roll
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::interpreter::{Interpreter, RuntimeError, Value};
+use crate::stdlib::{list_items, list_table};
+
+pub const FUNCTIONS: &[&str] = &["seed", "int", "float", "choice", "shuffle"];
+
+/// SplitMix64: small, fast and fully determined by its seed, which is all a
+/// game needs. Not suitable for anything security related.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Default for Rng {
+    /// Seeded from the clock; call `seed` for reproducible sequences.
+    fn default() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Self::new(nanos)
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    /// The generator's position; seeding with it continues the same sequence.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [0, bound) without modulo bias. `bound` must be non-zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let n = self.next_u64();
+            if n < zone {
+                return n % bound;
+            }
+        }
+    }
+
+    /// Uniform integer in [lo, hi].
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        let span = hi.wrapping_sub(lo) as u64;
+        if span == u64::MAX {
+            return self.next_u64() as i64;
+        }
+        lo.wrapping_add(self.below(span + 1) as i64)
+    }
+}
+
+/// Calls `random.<function>`.
+pub(crate) fn call(interp: &mut Interpreter, function: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match function {
+        "seed" => match args.first() {
+            Some(Value::Int(seed)) => {
+                interp.rng().seed(*seed as u64);
+                Ok(Value::Nil)
+            }
+            _ => Err(RuntimeError::new("random.seed() requires an integer seed")),
+        },
+        "int" => match args {
+            [Value::Int(lo), Value::Int(hi), ..] if lo <= hi => Ok(Value::Int(interp.rng().range(*lo, *hi))),
+            [Value::Int(_), Value::Int(_), ..] => Err(RuntimeError::new("random.int() requires min <= max")),
+            _ => Err(RuntimeError::new("random.int() requires 2 integer arguments (min, max)")),
+        },
+        "float" => {
+            let bounds = match args {
+                [] => (0.0, 1.0),
+                [lo, hi, ..] => match (number(lo), number(hi)) {
+                    (Some(lo), Some(hi)) if lo <= hi => (lo, hi),
+                    (Some(_), Some(_)) => return Err(RuntimeError::new("random.float() requires min <= max")),
+                    _ => return Err(RuntimeError::new("random.float() requires numeric arguments")),
+                },
+                _ => return Err(RuntimeError::new("random.float() takes no arguments or (min, max)")),
+            };
+            let t = interp.rng().next_f64();
+            Ok(Value::Float(bounds.0 + (bounds.1 - bounds.0) * t))
+        }
+        "choice" => {
+            let items = list_arg(args, "random.choice")?;
+            if items.is_empty() {
+                return Err(RuntimeError::new("random.choice() requires a non-empty list"));
+            }
+            let index = interp.rng().below(items.len() as u64) as usize;
+            Ok(items[index].clone())
+        }
+        "shuffle" => {
+            // Returns a shuffled copy; tables are values, so the original is untouched
+            let mut items = list_arg(args, "random.shuffle")?;
+            let mut rng = interp.rng();
+            for i in (1..items.len()).rev() {
+                let j = rng.below(i as u64 + 1) as usize;
+                items.swap(i, j);
+            }
+            Ok(list_table(items))
+        }
+        _ => Err(RuntimeError::new(&format!("unknown built-in function: random.{}", function))),
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+fn list_arg(args: &[Value], name: &str) -> Result<Vec<Value>, RuntimeError> {
+    match args.first() {
+        Some(Value::Table(table)) => Ok(list_items(table)),
+        _ => Err(RuntimeError::new(&format!("{}() requires a list table", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::run_script;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        a.seed(7);
+        b.seed(8);
+        assert_ne!(a.next_u64(), b.next_u64());
+    }
+
+    #[test]
+    fn test_ranges_stay_in_bounds() {
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            let n = rng.range(-3, 3);
+            assert!((-3..=3).contains(&n));
+            let f = rng.next_f64();
+            assert!((0.0..1.0).contains(&f));
+        }
+        assert_eq!(rng.range(5, 5), 5);
+        rng.range(i64::MIN, i64::MAX);
+    }
+
+    #[test]
+    fn test_seeded_scripts_are_reproducible() {
+        let src = r#"
+            random.seed(1234);
+            var roll = random.int(1, 6);
+            var chance = random.float();
+            var spread = random.float(-2, 2);
+            var pick = random.choice({"red", "green", "blue"});
+            var deck = random.shuffle({1, 2, 3, 4, 5});
+        "#;
+        let run = || run_script(src).expect("runtime error");
+        let (a, b) = (run(), run());
+        for name in ["roll", "chance", "spread", "pick", "deck"] {
+            assert_eq!(a.get_global(name), b.get_global(name), "{} differs", name);
+        }
+
+        let mut dealt = match a.get_global("deck") {
+            Some(Value::Table(deck)) => list_items(&deck),
+            other => panic!("deck is not a table: {:?}", other),
+        };
+        dealt.sort_by_key(|card| match card {
+            Value::Int(i) => *i,
+            _ => 0,
+        });
+        assert_eq!(dealt, (1..=5).map(Value::Int).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,152 @@
+// Parameter lists for every builtin, for tools that check or describe calls
+// (`arcscript lint`, editor hover)
+//
+// In `params`, a trailing `?` marks an optional parameter and `...` one that
+// takes any number of values.
+
+pub struct Signature {
+    /// The name scripts call it by, e.g. "max" or "string.split"
+    pub name: &'static str,
+    pub params: &'static str,
+    pub summary: &'static str,
+}
+
+impl Signature {
+    /// The fewest and most arguments the builtin accepts; no maximum if it
+    /// is variadic.
+    pub fn arity(&self) -> (usize, Option<usize>) {
+        let mut min = 0;
+        let mut max = Some(0);
+        for param in self.params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if param.ends_with("...") {
+                max = None;
+            } else {
+                max = max.map(|m| m + 1);
+                if !param.ends_with('?') {
+                    min += 1;
+                }
+            }
+        }
+        (min, max)
+    }
+
+    /// `name(params)`, as shown to script authors.
+    pub fn display(&self) -> String {
+        format!("{}({})", self.name, self.params)
+    }
+}
+
+const fn sig(name: &'static str, params: &'static str, summary: &'static str) -> Signature {
+    Signature { name, params, summary }
+}
+
+pub const SIGNATURES: &[Signature] = &[
+    sig("print", "values...", "Print values separated by spaces, without a newline"),
+    sig("println", "values...", "Print values separated by spaces, then a newline"),
+    sig("error", "value?", "Throw value as an error"),
+    sig("assert", "cond, message?", "Fail with an assertion error unless cond is truthy"),
+    sig("assert_eq", "actual, expected, message?", "Fail with an assertion error unless the values are equal"),
+    sig("type", "value", "The type name of value: \"int\", \"string\", \"table\", ..."),
+    sig("len", "value", "Characters in a string or entries in a table"),
+    sig("str", "value", "Convert to a string"),
+    sig("int", "value", "Convert to an integer, truncating floats"),
+    sig("float", "value", "Convert to a float"),
+    sig("abs", "x", "Absolute value"),
+    sig("min", "value, values...", "Smallest of the values"),
+    sig("max", "value, values...", "Largest of the values"),
+    sig("floor", "x", "Round down"),
+    sig("ceil", "x", "Round up"),
+    sig("round", "x", "Round to nearest"),
+    sig("sqrt", "x", "Square root"),
+    sig("pow", "base, exp", "base to the power exp"),
+    sig("sin", "x", "Sine, in radians"),
+    sig("cos", "x", "Cosine, in radians"),
+    sig("tan", "x", "Tangent, in radians"),
+    sig("asin", "x", "Arcsine, in radians"),
+    sig("acos", "x", "Arccosine, in radians"),
+    sig("atan", "x", "Arctangent, in radians"),
+    sig("atan2", "y, x", "Angle of the point (x, y), in radians"),
+    sig("exp", "x", "e to the power x"),
+    sig("log", "x, base?", "Natural log, or log in base"),
+    sig("clamp", "x, lo, hi", "Keep x between lo and hi"),
+    sig("sign", "x", "-1, 0 or 1"),
+    sig("substring", "str, start, length", "length characters of str from start"),
+    sig("contains", "str, substr", "Whether str contains substr"),
+    sig("toUpper", "str", "Uppercase copy of str"),
+    sig("toLower", "str", "Lowercase copy of str"),
+    sig("vec2", "x, y", "A 2D vector"),
+    sig("vec3", "x, y, z", "A 3D vector"),
+    sig("length", "v", "Magnitude of a vector"),
+    sig("normalize", "v", "Unit vector in the direction of v"),
+    sig("dot", "a, b", "Dot product"),
+    sig("cross", "a, b", "Cross product (a number for vec2s)"),
+    sig("distance", "a, b", "Distance between two points"),
+    sig("lerp", "a, b, t", "Linear interpolation between numbers or vectors"),
+    sig("rotate", "v, axis_or_angle, angle?", "Rotate a vec2 by an angle, or a vec3 around an axis"),
+    sig("angle", "a, b?", "Heading of a vec2, or the angle between two vectors"),
+    sig("after", "seconds, function", "Call function once after a delay; returns a timer handle"),
+    sig("every", "seconds, function", "Call function repeatedly; returns a timer handle"),
+    sig("after_frames", "frames, function", "Call function once after some ticks"),
+    sig("every_frames", "frames, function", "Call function every few ticks"),
+    sig("cancel", "handle", "Stop a timer; true if it was pending"),
+    sig("coroutine", "function", "A coroutine that runs function when resumed"),
+    sig("resume", "co, args...", "Run a coroutine until it yields, waits or returns"),
+    sig("yield", "value?", "Pause the running coroutine, handing value to resume()"),
+    sig("status", "co", "\"suspended\", \"running\", \"waiting\" or \"dead\""),
+    sig("wait", "seconds", "Pause the running coroutine for some seconds of ticks"),
+    sig("wait_frames", "frames", "Pause the running coroutine for some ticks"),
+    sig("input.pressed", "action", "True only on the frame the action went down"),
+    sig("input.held", "action", "True while the action is down"),
+    sig("input.released", "action", "True only on the frame the action went up"),
+    sig("input.axis", "name, positive?", "An analog axis, or -1/0/1 from two buttons"),
+    sig("input.mouse", "", "Cursor position as a vec2"),
+    sig("input.bind", "action, button, buttons...", "Let any of the buttons trigger action"),
+    sig("input.unbind", "action", "Remove every binding for action"),
+    sig("string.split", "str, sep?", "Split on sep, or on whitespace"),
+    sig("string.join", "list, sep?", "Join a list of strings"),
+    sig("string.trim", "str", "Remove surrounding whitespace"),
+    sig("string.trimStart", "str", "Remove leading whitespace"),
+    sig("string.trimEnd", "str", "Remove trailing whitespace"),
+    sig("string.replace", "str, from, to, count?", "Replace occurrences of from, all unless count is given"),
+    sig("string.find", "str, needle, start?", "Character index of needle, or -1"),
+    sig("string.indexOf", "str, needle, start?", "Same as string.find"),
+    sig("string.startsWith", "str, prefix", "Whether str starts with prefix"),
+    sig("string.endsWith", "str, suffix", "Whether str ends with suffix"),
+    sig("string.repeat", "str, count", "str repeated count times"),
+    sig("string.padLeft", "str, width, fill?", "Pad on the left to width characters"),
+    sig("string.padRight", "str, width, fill?", "Pad on the right to width characters"),
+    sig("string.reverse", "str", "str backwards"),
+    sig("string.chars", "str", "A list of the characters in str"),
+    sig("string.charCode", "str, index?", "The character code at index"),
+    sig("string.fromCharCode", "codes...", "A string from character codes"),
+    sig("string.format", "template, values...", "Fill {} and {0} placeholders in template"),
+    sig("string.substring", "str, start, length", "length characters of str from start"),
+    sig("string.contains", "str, substr", "Whether str contains substr"),
+    sig("string.toUpper", "str", "Uppercase copy of str"),
+    sig("string.toLower", "str", "Lowercase copy of str"),
+    sig("random.seed", "seed", "Make the random sequence reproducible"),
+    sig("random.int", "min, max", "Random integer in [min, max]"),
+    sig("random.float", "min?, max?", "Random float in [0, 1) or [min, max)"),
+    sig("random.choice", "list", "A random item from a list"),
+    sig("random.shuffle", "list", "A shuffled copy of a list"),
+    sig("table.keys", "table", "A list of the table's keys"),
+    sig("table.values", "table", "A list of the table's values"),
+    sig("table.has", "table, key", "Whether the table has key"),
+    sig("table.remove", "table, key", "A copy without key; lists close the gap"),
+    sig("table.merge", "tables...", "One table from several; later tables win"),
+    sig("table.copy", "table, deep?", "An independent copy"),
+    sig("table.map", "table, function", "Apply function(value, key) to every entry"),
+    sig("table.filter", "table, function", "Entries where function(value, key) is truthy"),
+    sig("table.reduce", "table, function, initial?", "Fold the values with function(acc, value)"),
+    sig("table.find", "table, function", "First value where function(value, key) is truthy"),
+    sig("table.any", "table, function", "Whether function(value, key) is truthy for any entry"),
+    sig("table.all", "table, function", "Whether function(value, key) is truthy for every entry"),
+    sig("table.sort", "list, compare?", "A sorted copy of a list"),
+    sig("json.encode", "value, pretty?", "JSON text for a value"),
+    sig("json.decode", "text", "The value described by JSON text"),
+];
+
+/// The signature of the builtin called `name`.
+pub fn lookup(name: &str) -> Option<&'static Signature> {
+    SIGNATURES.iter().find(|sig| sig.name == name)
+}
@@ -0,0 +1,427 @@
+// The `string` namespace: string.split, string.format, ...
+//
+// Every index, length and width counts Unicode characters, matching
+// `substring` and `len`.
+
+use std::fmt::Write;
+
+use crate::interpreter::{Interpreter, RuntimeError, Value};
+use crate::stdlib::{int_arg, list_items, list_table, string_arg};
+
+pub const FUNCTIONS: &[&str] = &[
+    "split", "join", "trim", "trimStart", "trimEnd", "replace", "find", "indexOf", "startsWith", "endsWith",
+    "repeat", "padLeft", "padRight", "reverse", "chars", "charCode", "fromCharCode", "format", "substring",
+    "contains", "toUpper", "toLower",
+];
+
+/// Calls `string.<function>`.
+pub(crate) fn call(interp: &mut Interpreter, function: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    let name = format!("string.{}", function);
+    let name = name.as_str();
+    match function {
+        "split" => {
+            let s = string_arg(args, 0, name)?;
+            let parts: Vec<Value> = match args.get(1) {
+                None | Some(Value::Nil) => s.split_whitespace().map(|p| Value::String(p.to_string())).collect(),
+                Some(Value::String(sep)) if sep.is_empty() => s.chars().map(|c| Value::String(c.to_string())).collect(),
+                Some(Value::String(sep)) => s.split(sep.as_str()).map(|p| Value::String(p.to_string())).collect(),
+                _ => return Err(RuntimeError::new("string.split() separator must be a string")),
+            };
+            Ok(list_table(parts))
+        }
+        "join" => {
+            let items = match args.first() {
+                Some(Value::Table(table)) => list_items(table),
+                _ => return Err(RuntimeError::new("string.join() requires a list table as argument 1")),
+            };
+            let sep = match args.get(1) {
+                None | Some(Value::Nil) => "",
+                Some(Value::String(sep)) => sep,
+                _ => return Err(RuntimeError::new("string.join() separator must be a string")),
+            };
+            let mut parts = Vec::with_capacity(items.len());
+            for item in &items {
+                parts.push(interp.value_to_string(item)?);
+            }
+            Ok(Value::String(parts.join(sep)))
+        }
+        "trim" => Ok(Value::String(string_arg(args, 0, name)?.trim().to_string())),
+        "trimStart" => Ok(Value::String(string_arg(args, 0, name)?.trim_start().to_string())),
+        "trimEnd" => Ok(Value::String(string_arg(args, 0, name)?.trim_end().to_string())),
+        "replace" => {
+            let s = string_arg(args, 0, name)?;
+            let from = string_arg(args, 1, name)?;
+            let to = string_arg(args, 2, name)?;
+            if from.is_empty() {
+                return Err(RuntimeError::new("string.replace() cannot replace an empty string"));
+            }
+            let count = int_arg(args, 3, Some(-1), name)?;
+            Ok(Value::String(if count < 0 { s.replace(from, to) } else { s.replacen(from, to, count as usize) }))
+        }
+        "find" | "indexOf" => {
+            let s = string_arg(args, 0, name)?;
+            let needle = string_arg(args, 1, name)?;
+            let start = int_arg(args, 2, Some(0), name)?.max(0) as usize;
+            let offset = byte_offset(s, start);
+            let found = match s[offset..].find(needle) {
+                Some(pos) if start <= s.chars().count() => (start + s[offset..offset + pos].chars().count()) as i64,
+                _ => -1,
+            };
+            Ok(Value::Int(found))
+        }
+        "startsWith" => Ok(Value::Bool(string_arg(args, 0, name)?.starts_with(string_arg(args, 1, name)?))),
+        "endsWith" => Ok(Value::Bool(string_arg(args, 0, name)?.ends_with(string_arg(args, 1, name)?))),
+        "repeat" => {
+            let s = string_arg(args, 0, name)?;
+            match int_arg(args, 1, None, name)? {
+                n if n < 0 => Err(RuntimeError::new("string.repeat() count cannot be negative")),
+                _ if s.is_empty() => Ok(Value::String(String::new())),
+                n => {
+                    let n = usize::try_from(n).unwrap_or(usize::MAX);
+                    let mut out = string_with_capacity(interp, name, result_len(name, 0, s.len(), n)?)?;
+                    out.extend(std::iter::repeat_n(s, n));
+                    Ok(Value::String(out))
+                }
+            }
+        }
+        "padLeft" | "padRight" => {
+            let s = string_arg(args, 0, name)?;
+            let width = int_arg(args, 1, None, name)?.max(0) as usize;
+            let fill = match args.get(2) {
+                None | Some(Value::Nil) => ' ',
+                Some(Value::String(fill)) if fill.chars().count() == 1 => fill.chars().next().unwrap_or(' '),
+                _ => return Err(RuntimeError::new(&format!("{}() fill must be a single character", name))),
+            };
+            let pad = width.saturating_sub(s.chars().count());
+            let mut out = string_with_capacity(interp, name, result_len(name, s.len(), fill.len_utf8(), pad)?)?;
+            if function == "padRight" {
+                out.push_str(s);
+            }
+            out.extend(std::iter::repeat_n(fill, pad));
+            if function == "padLeft" {
+                out.push_str(s);
+            }
+            Ok(Value::String(out))
+        }
+        "reverse" => Ok(Value::String(string_arg(args, 0, name)?.chars().rev().collect())),
+        "chars" => {
+            let s = string_arg(args, 0, name)?;
+            Ok(list_table(s.chars().map(|c| Value::String(c.to_string())).collect()))
+        }
+        "charCode" => {
+            let s = string_arg(args, 0, name)?;
+            let index = int_arg(args, 1, Some(0), name)?;
+            match usize::try_from(index).ok().and_then(|i| s.chars().nth(i)) {
+                Some(c) => Ok(Value::Int(c as i64)),
+                None => Err(RuntimeError::new(&format!("string.charCode() index {} is out of range", index))),
+            }
+        }
+        "fromCharCode" => {
+            let mut out = String::with_capacity(args.len());
+            for code in args {
+                let code = match code {
+                    Value::Int(code) => *code,
+                    _ => return Err(RuntimeError::new("string.fromCharCode() requires integer character codes")),
+                };
+                match u32::try_from(code).ok().and_then(char::from_u32) {
+                    Some(c) => out.push(c),
+                    None => return Err(RuntimeError::new(&format!("string.fromCharCode() invalid character code {}", code))),
+                }
+            }
+            Ok(Value::String(out))
+        }
+        "format" => format(interp, string_arg(args, 0, name)?, &args[1..]),
+        // The original global string builtins, also reachable through the namespace
+        "substring" | "contains" | "toUpper" | "toLower" => interp.run_builtin(function, args),
+        _ => Err(RuntimeError::new(&format!("unknown built-in function: {}", name))),
+    }
+}
+
+/// Byte offset of the character at `index`, or the end of the string.
+fn byte_offset(s: &str, index: usize) -> usize {
+    s.char_indices().nth(index).map_or(s.len(), |(offset, _)| offset)
+}
+
+/// Size of `base` bytes plus `count` copies of `unit` bytes, or an error if it
+/// overflows.
+fn result_len(name: &str, base: usize, unit: usize, count: usize) -> Result<usize, RuntimeError> {
+    unit.checked_mul(count)
+        .and_then(|bytes| bytes.checked_add(base))
+        .filter(|&bytes| bytes <= isize::MAX as usize)
+        .ok_or_else(|| RuntimeError::new(&format!("{}() result is too large", name)))
+}
+
+/// An empty string with room for `bytes`, refused up front if it wouldn't fit
+/// under the memory cap or can't be allocated.
+fn string_with_capacity(interp: &mut Interpreter, name: &str, bytes: usize) -> Result<String, RuntimeError> {
+    interp.reserve_memory(bytes)?;
+    let mut out = String::new();
+    out.try_reserve_exact(bytes)
+        .map_err(|_| RuntimeError::new(&format!("{}() result is too large", name)))?;
+    Ok(out)
+}
+
+/// Placeholder options: `{[index][:[[fill]align][0][width][.precision]]}`.
+struct FormatSpec {
+    fill: char,
+    align: Option<char>,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Result<FormatSpec, RuntimeError> {
+        let invalid = || RuntimeError::new(&format!("string.format() invalid format spec '{}'", spec));
+        let chars: Vec<char> = spec.chars().collect();
+        let mut parsed = FormatSpec { fill: ' ', align: None, zero: false, width: 0, precision: None };
+        let mut i = 0;
+        let is_align = |c: Option<&char>| matches!(c, Some('<' | '>' | '^'));
+        if is_align(chars.get(1)) {
+            parsed.fill = chars[0];
+            parsed.align = Some(chars[1]);
+            i = 2;
+        } else if is_align(chars.first()) {
+            parsed.align = Some(chars[0]);
+            i = 1;
+        }
+        if chars.get(i) == Some(&'0') {
+            parsed.zero = true;
+            i += 1;
+        }
+        let digits = |i: &mut usize| -> Option<usize> {
+            let start = *i;
+            while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+                *i += 1;
+            }
+            chars[start..*i].iter().collect::<String>().parse().ok()
+        };
+        parsed.width = digits(&mut i).unwrap_or(0);
+        if chars.get(i) == Some(&'.') {
+            i += 1;
+            parsed.precision = Some(digits(&mut i).ok_or_else(invalid)?);
+        }
+        if i != chars.len() {
+            return Err(invalid());
+        }
+        Ok(parsed)
+    }
+}
+
+/// Fills `{}` placeholders in order, `{0}`-style ones by argument index.
+/// `{{` and `}}` produce literal braces.
+fn format(interp: &mut Interpreter, template: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    let mut next_arg = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => return Err(RuntimeError::new("string.format() unclosed '{' in format string")),
+                    }
+                }
+                let (index, spec) = field.split_once(':').unwrap_or((field.as_str(), ""));
+                let index = if index.is_empty() {
+                    next_arg += 1;
+                    next_arg - 1
+                } else {
+                    index.parse::<usize>().map_err(|_| {
+                        RuntimeError::new(&format!("string.format() invalid placeholder '{{{}}}'", field))
+                    })?
+                };
+                let value = args.get(index).ok_or_else(|| {
+                    RuntimeError::new(&format!("string.format() has no argument for placeholder {}", index))
+                })?;
+                let spec = FormatSpec::parse(spec)?;
+                out.push_str(&format_value(interp, value, &spec)?);
+            }
+            '}' => return Err(RuntimeError::new("string.format() unmatched '}' in format string")),
+            c => out.push(c),
+        }
+    }
+    Ok(Value::String(out))
+}
+
+/// Room for a float's sign, decimal point and up to 309 integral digits.
+const FLOAT_TEXT_BYTES: usize = 311;
+
+fn format_value(interp: &mut Interpreter, value: &Value, spec: &FormatSpec) -> Result<String, RuntimeError> {
+    const NAME: &str = "string.format";
+    let number = match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    };
+    let numeric = number.is_some();
+    let text = match (number, spec.precision) {
+        (Some(f), Some(p)) => {
+            let mut text = string_with_capacity(interp, NAME, result_len(NAME, FLOAT_TEXT_BYTES, 1, p)?)?;
+            let _ = write!(text, "{:.*}", p, f);
+            text
+        }
+        (_, Some(p)) => interp.value_to_string(value)?.chars().take(p).collect(),
+        (_, None) => interp.value_to_string(value)?,
+    };
+    let len = text.chars().count();
+    if len >= spec.width {
+        return Ok(text);
+    }
+    let pad = spec.width - len;
+    let mut out = string_with_capacity(interp, NAME, result_len(NAME, text.len(), spec.fill.len_utf8(), pad)?)?;
+    if spec.zero && numeric {
+        // Zeros go between the sign and the digits
+        let (sign, digits) = text.split_at(if text.starts_with('-') { 1 } else { 0 });
+        out.push_str(sign);
+        out.extend(std::iter::repeat_n('0', pad));
+        out.push_str(digits);
+        return Ok(out);
+    }
+    let before = match spec.align.unwrap_or(if numeric { '>' } else { '<' }) {
+        '<' => 0,
+        '^' => pad / 2,
+        _ => pad,
+    };
+    out.extend(std::iter::repeat_n(spec.fill, before));
+    out.push_str(&text);
+    out.extend(std::iter::repeat_n(spec.fill, pad - before));
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::ErrorKind;
+    use crate::lexer::Lexer;
+    use crate::limits::Limits;
+    use crate::parser::Parser;
+    use crate::stdlib::run_script;
+
+    fn global(interp: &Interpreter, name: &str) -> Value {
+        interp.get_global(name).unwrap_or(Value::Nil)
+    }
+
+    fn text(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_split_join_and_trim() {
+        let interp = run_script(r#"
+            var parts = string.split("a,b,,c", ",");
+            var words = string.split("  one two  three ");
+            var joined = string.join(parts, "-");
+            var count = len(words);
+            var first = words[0];
+            var trimmed = string.trim("  hi  ") + "|" + string.trimStart("  hi  ") + "|" + string.trimEnd("  hi  ");
+        "#)
+        .expect("runtime error");
+        assert_eq!(global(&interp, "joined"), text("a-b--c"));
+        assert_eq!(global(&interp, "count"), Value::Int(3));
+        assert_eq!(global(&interp, "first"), text("one"));
+        assert_eq!(global(&interp, "trimmed"), text("hi|hi  |  hi"));
+    }
+
+    #[test]
+    fn test_search_and_replace() {
+        let interp = run_script(r#"
+            var replaced = string.replace("a-b-c", "-", "+");
+            var once = string.replace("a-b-c", "-", "+", 1);
+            var at = string.find("héllo wörld", "wö");
+            var again = string.indexOf("abcabc", "b", 2);
+            var missing = string.find("abc", "z");
+            var starts = string.startsWith("player_1", "player");
+            var ends = string.endsWith("player_1", "_2");
+        "#)
+        .expect("runtime error");
+        assert_eq!(global(&interp, "replaced"), text("a+b+c"));
+        assert_eq!(global(&interp, "once"), text("a+b-c"));
+        assert_eq!(global(&interp, "at"), Value::Int(6));
+        assert_eq!(global(&interp, "again"), Value::Int(4));
+        assert_eq!(global(&interp, "missing"), Value::Int(-1));
+        assert_eq!(global(&interp, "starts"), Value::Bool(true));
+        assert_eq!(global(&interp, "ends"), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_unicode_character_counting() {
+        let interp = run_script(r#"
+            var size = len("héllo");
+            var padded = string.padLeft("é", 3, "*") + string.padRight("ñ", 3);
+            var reversed = string.reverse("añb");
+            var letters = string.chars("日本");
+            var second = letters[1];
+            var code = string.charCode("añb", 1);
+            var back = string.fromCharCode(72, 105, 233);
+            var echoes = string.repeat("ab", 3);
+        "#)
+        .expect("runtime error");
+        assert_eq!(global(&interp, "size"), Value::Int(5));
+        assert_eq!(global(&interp, "padded"), text("**éñ  "));
+        assert_eq!(global(&interp, "reversed"), text("bña"));
+        assert_eq!(global(&interp, "second"), text("本"));
+        assert_eq!(global(&interp, "code"), Value::Int(241));
+        assert_eq!(global(&interp, "back"), text("Hié"));
+        assert_eq!(global(&interp, "echoes"), text("ababab"));
+
+        let err = run_script("string.fromCharCode(72, 55296);").err().expect("expected error");
+        assert_eq!(err.message, "string.fromCharCode() invalid character code 55296");
+    }
+
+    #[test]
+    fn test_format() {
+        let interp = run_script(r#"
+            var plain = string.format("{} has {} hp", "Goblin", 12);
+            var indexed = string.format("{1} before {0}", "a", "b");
+            var numbers = string.format("[{:.2}] [{:6.1}] [{:05}] [{:<4}]", 3.14159, 2.25, -42, 7);
+            var aligned = string.format("[{:>5}] [{:*^7}] [{:.3}]", "ab", "mid", "truncate");
+            var braces = string.format("{{{}}}", "x");
+        "#)
+        .expect("runtime error");
+        assert_eq!(global(&interp, "plain"), text("Goblin has 12 hp"));
+        assert_eq!(global(&interp, "indexed"), text("b before a"));
+        assert_eq!(global(&interp, "numbers"), text("[3.14] [   2.2] [-0042] [7   ]"));
+        assert_eq!(global(&interp, "aligned"), text("[   ab] [**mid**] [tru]"));
+        assert_eq!(global(&interp, "braces"), text("{x}"));
+
+        let err = run_script(r#"string.format("{} {}", 1);"#).err().expect("expected error");
+        assert!(err.message.contains("no argument for placeholder 1"));
+        let err = run_script(r#"string.format("{:x}", 1);"#).err().expect("expected error");
+        assert!(err.message.contains("invalid format spec"));
+    }
+
+    #[test]
+    fn test_oversized_results_are_refused() {
+        let cases = [
+            (r#"string.repeat("ab", 9223372036854775807);"#, "string.repeat() result is too large"),
+            (r#"string.padLeft("x", 4611686018427387903);"#, "string.padLeft() result is too large"),
+            (r#"string.format("{:>99999999999999999}", 1);"#, "string.format() result is too large"),
+            (r#"string.format("{:.99999999999999999}", 1.5);"#, "string.format() result is too large"),
+        ];
+        for (src, message) in cases {
+            let err = run_script(src).err().expect("expected error");
+            assert_eq!(err.message, message);
+        }
+
+        let lexer = Lexer::new(r#"var s = string.padRight("x", 2000000);"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("parse failed");
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits { max_memory: Some(1 << 20), ..Limits::default() });
+        let err = interp.eval_program(&program).expect_err("expected memory limit");
+        assert_eq!(err.kind, ErrorKind::MemoryLimit);
+        assert!(interp.memory_usage().peak < 1 << 20);
+    }
+}
@@ -0,0 +1,365 @@
+// The `table` namespace: table.keys, table.map, table.sort, ...
+//
+// Tables are values, so nothing here changes its argument: every function that
+// "modifies" a table returns a new one. Entries are visited in key order, with
+// positional entries (0, 1, 2, ...) first, so results never depend on hashing.
+
+use std::collections::HashMap;
+
+use crate::interpreter::{Interpreter, RuntimeError, Value};
+use crate::stdlib::{list_items, list_table};
+
+pub const FUNCTIONS: &[&str] = &[
+    "keys", "values", "has", "remove", "merge", "copy", "map", "filter", "reduce", "find", "any", "all", "sort",
+];
+
+/// Calls `table.<function>`.
+pub(crate) fn call(interp: &mut Interpreter, function: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    let name = format!("table.{}", function);
+    let name = name.as_str();
+    match function {
+        "keys" => {
+            let table = table_arg(args, 0, name)?;
+            Ok(list_table(ordered_keys(table).iter().map(|k| key_value(k)).collect()))
+        }
+        "values" => {
+            let table = table_arg(args, 0, name)?;
+            Ok(list_table(ordered_keys(table).iter().map(|k| table[*k].clone()).collect()))
+        }
+        "has" => {
+            let table = table_arg(args, 0, name)?;
+            let key = key_arg(args, 1, name)?;
+            Ok(Value::Bool(table.contains_key(&key)))
+        }
+        "remove" => {
+            let table = table_arg(args, 0, name)?;
+            let key = key_arg(args, 1, name)?;
+            if is_list(table) {
+                // Removing from a list closes the gap, like a list would
+                let mut items = list_items(table);
+                if let Some(index) = key.parse::<usize>().ok().filter(|i| *i < items.len()) {
+                    items.remove(index);
+                }
+                return Ok(list_table(items));
+            }
+            let mut table = table.clone();
+            table.remove(&key);
+            Ok(Value::Table(table))
+        }
+        "merge" => {
+            // Later tables win on shared keys
+            let mut merged = HashMap::new();
+            for index in 0..args.len() {
+                merged.extend(table_arg(args, index, name)?.iter().map(|(k, v)| (k.clone(), v.clone())));
+            }
+            Ok(Value::Table(merged))
+        }
+        "copy" => {
+            // A nested table is a value too, so a shallow copy is already
+            // independent of the original; `deep` is accepted for clarity
+            let table = table_arg(args, 0, name)?;
+            match args.get(1) {
+                None | Some(Value::Nil | Value::Bool(_)) => Ok(Value::Table(table.clone())),
+                _ => Err(RuntimeError::new("table.copy() deep flag must be a boolean")),
+            }
+        }
+        "map" => {
+            let table = table_arg(args, 0, name)?;
+            let callback = callback_arg(args, 1, name)?;
+            let mut mapped = HashMap::with_capacity(table.len());
+            for key in ordered_keys(table) {
+                let value = interp.call_value(callback.clone(), vec![table[key].clone(), key_value(key)])?;
+                mapped.insert(key.clone(), value);
+            }
+            Ok(Value::Table(mapped))
+        }
+        "filter" => {
+            let table = table_arg(args, 0, name)?;
+            let callback = callback_arg(args, 1, name)?;
+            let mut kept = Vec::new();
+            for key in ordered_keys(table) {
+                let value = table[key].clone();
+                if call_predicate(interp, &callback, &value, key)? {
+                    kept.push((key.clone(), value));
+                }
+            }
+            // A filtered list is renumbered; other tables keep their keys
+            if is_list(table) {
+                Ok(list_table(kept.into_iter().map(|(_, v)| v).collect()))
+            } else {
+                Ok(Value::Table(kept.into_iter().collect()))
+            }
+        }
+        "reduce" => {
+            let table = table_arg(args, 0, name)?;
+            let callback = callback_arg(args, 1, name)?;
+            let mut keys = ordered_keys(table).into_iter();
+            let mut acc = match args.get(2) {
+                Some(initial) => initial.clone(),
+                None => match keys.next() {
+                    Some(first) => table[first].clone(),
+                    None => return Err(RuntimeError::new("table.reduce() of an empty table requires an initial value")),
+                },
+            };
+            for key in keys {
+                acc = interp.call_value(callback.clone(), vec![acc, table[key].clone(), key_value(key)])?;
+            }
+            Ok(acc)
+        }
+        "find" => {
+            let table = table_arg(args, 0, name)?;
+            let callback = callback_arg(args, 1, name)?;
+            for key in ordered_keys(table) {
+                if call_predicate(interp, &callback, &table[key], key)? {
+                    return Ok(table[key].clone());
+                }
+            }
+            Ok(Value::Nil)
+        }
+        "any" | "all" => {
+            let table = table_arg(args, 0, name)?;
+            let callback = callback_arg(args, 1, name)?;
+            let want_any = function == "any";
+            for key in ordered_keys(table) {
+                if call_predicate(interp, &callback, &table[key], key)? == want_any {
+                    return Ok(Value::Bool(want_any));
+                }
+            }
+            Ok(Value::Bool(!want_any))
+        }
+        "sort" => {
+            let table = table_arg(args, 0, name)?;
+            let comparator = match args.get(1) {
+                None | Some(Value::Nil) => None,
+                Some(_) => Some(callback_arg(args, 1, name)?),
+            };
+            let items = list_items(table);
+            Ok(list_table(merge_sort(interp, items, comparator.as_ref())?))
+        }
+        _ => Err(RuntimeError::new(&format!("unknown built-in function: table.{}", function))),
+    }
+}
+
+fn table_arg<'a>(args: &'a [Value], index: usize, name: &str) -> Result<&'a HashMap<String, Value>, RuntimeError> {
+    match args.get(index) {
+        Some(Value::Table(table)) => Ok(table),
+        _ => Err(RuntimeError::new(&format!("{}() requires a table as argument {}", name, index + 1))),
+    }
+}
+
+/// A key argument, accepted as a string or an integer like `t[key]`.
+fn key_arg(args: &[Value], index: usize, name: &str) -> Result<String, RuntimeError> {
+    match args.get(index) {
+        Some(Value::String(key)) => Ok(key.clone()),
+        Some(Value::Int(i)) => Ok(i.to_string()),
+        _ => Err(RuntimeError::new(&format!("{}() requires a string or integer key as argument {}", name, index + 1))),
+    }
+}
+
+fn callback_arg(args: &[Value], index: usize, name: &str) -> Result<Value, RuntimeError> {
+    match args.get(index) {
+        Some(callback @ (Value::Function { .. } | Value::BuiltinFunction(_) | Value::Table(_))) => Ok(callback.clone()),
+        _ => Err(RuntimeError::new(&format!("{}() requires a function as argument {}", name, index + 1))),
+    }
+}
+
+fn call_predicate(interp: &mut Interpreter, callback: &Value, value: &Value, key: &str) -> Result<bool, RuntimeError> {
+    let result = interp.call_value(callback.clone(), vec![value.clone(), key_value(key)])?;
+    Ok(!matches!(result, Value::Nil | Value::Bool(false)))
+}
+
+/// Positional keys are handed to scripts as integers, everything else as strings.
+fn key_value(key: &str) -> Value {
+    match position(key) {
+        Some(i) => Value::Int(i as i64),
+        None => Value::String(key.to_string()),
+    }
+}
+
+/// The index of a positional key: a plain decimal like "0" or "12", not "012".
+fn position(key: &str) -> Option<usize> {
+    if key.is_empty() || (key.len() > 1 && key.starts_with('0')) || !key.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    key.parse().ok()
+}
+
+/// Keys in visiting order: positions ascending, then the rest alphabetically.
+fn ordered_keys(table: &HashMap<String, Value>) -> Vec<&String> {
+    let mut keys: Vec<&String> = table.keys().collect();
+    keys.sort_by(|a, b| match (position(a), position(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.cmp(b),
+    });
+    keys
+}
+
+/// True if every entry is positional with no gaps.
+fn is_list(table: &HashMap<String, Value>) -> bool {
+    (0..table.len()).all(|i| table.contains_key(&i.to_string()))
+}
+
+/// Stable merge sort. The comparator is a script callback that can fail or
+/// be inconsistent, so this can't go through `slice::sort_by`.
+fn merge_sort(interp: &mut Interpreter, items: Vec<Value>, comparator: Option<&Value>) -> Result<Vec<Value>, RuntimeError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(interp, left, comparator)?;
+    let right = merge_sort(interp, right, comparator)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Take from the right only when it strictly comes first, keeping equal items in order
+        if less(interp, comparator, b, a)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn less(interp: &mut Interpreter, comparator: Option<&Value>, a: &Value, b: &Value) -> Result<bool, RuntimeError> {
+    if let Some(comparator) = comparator {
+        let result = interp.call_value(comparator.clone(), vec![a.clone(), b.clone()])?;
+        return Ok(!matches!(result, Value::Nil | Value::Bool(false)));
+    }
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(a < b),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => Ok(number(a) < number(b)),
+        (Value::String(a), Value::String(b)) => Ok(a < b),
+        _ => Err(RuntimeError::new("table.sort() can only compare numbers or strings without a comparator")),
+    }
+}
+
+fn number(value: &Value) -> f64 {
+    match value {
+        Value::Int(i) => *i as f64,
+        Value::Float(f) => *f,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Value;
+    use crate::stdlib::{list_items, list_table, run_script};
+
+    fn strings(items: &[&str]) -> Value {
+        list_table(items.iter().map(|s| Value::String(s.to_string())).collect())
+    }
+
+    #[test]
+    fn test_keys_values_has_remove_merge() {
+        let interp = run_script(r#"
+            var stats = {hp: 10, mp: 4, atk: 7};
+            var names = table.keys(stats);
+            var amounts = table.values(stats);
+            var has_hp = table.has(stats, "hp");
+            var has_xp = table.has(stats, "xp");
+            var no_mp = table.remove(stats, "mp");
+            var still = table.has(stats, "mp");
+            var list = table.remove({"a", "b", "c"}, 1);
+            var merged = table.merge({hp: 1, def: 2}, stats);
+            var copied = table.copy(stats, true);
+        "#)
+        .expect("runtime error");
+        assert_eq!(interp.get_global("names"), Some(strings(&["atk", "hp", "mp"])));
+        assert_eq!(interp.get_global("amounts"), Some(list_table(vec![Value::Int(7), Value::Int(10), Value::Int(4)])));
+        assert_eq!(interp.get_global("has_hp"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("has_xp"), Some(Value::Bool(false)));
+        assert_eq!(interp.get_global("still"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("list"), Some(strings(&["a", "c"])));
+        match interp.get_global("no_mp") {
+            Some(Value::Table(t)) => assert!(!t.contains_key("mp") && t.len() == 2),
+            other => panic!("no_mp is not a table: {:?}", other),
+        }
+        match interp.get_global("merged") {
+            Some(Value::Table(t)) => {
+                assert_eq!(t.get("hp"), Some(&Value::Int(10)));
+                assert_eq!(t.get("def"), Some(&Value::Int(2)));
+                assert_eq!(t.len(), 4);
+            }
+            other => panic!("merged is not a table: {:?}", other),
+        }
+        assert_eq!(interp.get_global("copied"), interp.get_global("stats"));
+    }
+
+    #[test]
+    fn test_higher_order_functions() {
+        let interp = run_script(r#"
+            var nums = {5, 2, 8, 1};
+            func double(x): { return x * 2; } end
+            func even(x): { return x % 2 == 0; } end
+            func add(acc, x): { return acc + x; } end
+            func big(x): { return x > 6; } end
+            func positive(x): { return x > 0; } end
+            var doubled = table.map(nums, double);
+            var evens = table.filter(nums, even);
+            var total = table.reduce(nums, add, 0);
+            func mul(acc, x): { return acc * x; } end
+            func huge(x): { return x > 100; } end
+            func index(v, i): { return i; } end
+            var product = table.reduce(nums, mul);
+            var first_big = table.find(nums, big);
+            var none = table.find(nums, huge);
+            var some_big = table.any(nums, big);
+            var all_positive = table.all(nums, positive);
+            var all_big = table.all(nums, big);
+            var indexes = table.map({"a", "b"}, index);
+        "#)
+        .expect("runtime error");
+        let ints = |v: &[i64]| list_table(v.iter().map(|i| Value::Int(*i)).collect());
+        assert_eq!(interp.get_global("doubled"), Some(ints(&[10, 4, 16, 2])));
+        assert_eq!(interp.get_global("evens"), Some(ints(&[2, 8])));
+        assert_eq!(interp.get_global("total"), Some(Value::Int(16)));
+        assert_eq!(interp.get_global("product"), Some(Value::Int(80)));
+        assert_eq!(interp.get_global("first_big"), Some(Value::Int(8)));
+        assert_eq!(interp.get_global("none"), Some(Value::Nil));
+        assert_eq!(interp.get_global("some_big"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("all_positive"), Some(Value::Bool(true)));
+        assert_eq!(interp.get_global("all_big"), Some(Value::Bool(false)));
+        assert_eq!(interp.get_global("indexes"), Some(ints(&[0, 1])));
+    }
+
+    #[test]
+    fn test_sort_default_and_comparator() {
+        let interp = run_script(r#"
+            var sorted = table.sort({3, 1.5, 2, -4});
+            var words = table.sort({"pear", "apple", "fig"});
+            func greater(a, b): { return a > b; } end
+            func weaker(a, b): { return a.hp < b.hp; } end
+            var desc = table.sort({3, 1, 2}, greater);
+            var units = {{name: "orc", hp: 5}, {name: "elf", hp: 3}, {name: "imp", hp: 5}};
+            var by_hp = table.sort(units, weaker);
+        "#)
+        .expect("runtime error");
+        assert_eq!(
+            interp.get_global("sorted"),
+            Some(list_table(vec![Value::Int(-4), Value::Float(1.5), Value::Int(2), Value::Int(3)]))
+        );
+        assert_eq!(interp.get_global("words"), Some(strings(&["apple", "fig", "pear"])));
+        assert_eq!(interp.get_global("desc"), Some(list_table(vec![Value::Int(3), Value::Int(2), Value::Int(1)])));
+        // Stable: the orc stays ahead of the imp
+        let names: Vec<Value> = match interp.get_global("by_hp") {
+            Some(Value::Table(t)) => list_items(&t)
+                .into_iter()
+                .map(|unit| match unit {
+                    Value::Table(unit) => unit["name"].clone(),
+                    other => other,
+                })
+                .collect(),
+            other => panic!("by_hp is not a table: {:?}", other),
+        };
+        assert_eq!(list_table(names), strings(&["elf", "orc", "imp"]));
+    }
+}
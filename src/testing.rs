@@ -0,0 +1,258 @@
+// ArcScript test runner: finds `test_*` functions in .arc files and runs each
+// one in a fresh interpreter
+//
+// Used by `arcscript test`; hosts can call it directly to run script tests
+// from their own harness.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::ast::{Program, StmtKind};
+use crate::interpreter::{ErrorKind, Interpreter, RuntimeError, Value};
+use crate::lexer::Lexer;
+use crate::limits::Limits;
+use crate::parser::Parser;
+use crate::resolver;
+
+/// Prefix that marks a top-level function as a test.
+pub const TEST_PREFIX: &str = "test_";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// The failure report: the error message, plus a diff for `assert_eq`
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+    pub duration: Duration,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+}
+
+/// The results for one file. `error` is set when the file couldn't be read,
+/// parsed or set up, in which case none of its tests ran.
+#[derive(Debug, Clone)]
+pub struct FileResult {
+    pub path: PathBuf,
+    pub tests: Vec<TestResult>,
+    pub error: Option<String>,
+}
+
+/// Every .arc file under `paths` (files are taken as given, directories are
+/// searched recursively), sorted so runs are repeatable.
+pub fn discover(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            collect_arc_files(path, &mut files)?;
+        } else if path.exists() {
+            files.push(path.clone());
+        } else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path.display())));
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn collect_arc_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            // Skip build output and hidden directories like .git
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if name != "target" && !name.starts_with('.') {
+                collect_arc_files(&path, files)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "arc") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Names of the top-level `test_*` functions, in source order.
+pub fn test_names(program: &Program) -> Vec<String> {
+    program
+        .body
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::FuncDecl(func) if func.name.starts_with(TEST_PREFIX) => Some(func.name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs every test in the file at `path`.
+pub fn run_file(path: &Path) -> FileResult {
+    let mut result = FileResult { path: path.to_path_buf(), tests: Vec::new(), error: None };
+    match fs::read_to_string(path) {
+        Ok(source) => match run_source(&source) {
+            Ok(tests) => result.tests = tests,
+            Err(error) => result.error = Some(error),
+        },
+        Err(e) => result.error = Some(format!("could not read file: {}", e)),
+    }
+    result
+}
+
+/// Runs every test in `source`. Each test gets a fresh interpreter that has
+/// run the file's top level first, so tests can't leak state into each other.
+pub fn run_source(source: &str) -> Result<Vec<TestResult>, String> {
+    let program = parse(source)?;
+    let mut results = Vec::new();
+    for name in test_names(&program) {
+        let start = Instant::now();
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits::recommended());
+        if let Err(e) = interp.eval_program(&program) {
+            return Err(format!("error while loading: {}", e));
+        }
+        let outcome = match interp.call_global(&name, Vec::new()) {
+            Ok(_) => Outcome::Passed,
+            Err(e) => Outcome::Failed(failure_report(&e)),
+        };
+        results.push(TestResult { name, outcome, duration: start.elapsed() });
+    }
+    Ok(results)
+}
+
+fn parse(source: &str) -> Result<Program, String> {
+    let lexer = Lexer::new(source);
+    let mut parser = Parser::new(lexer);
+    let mut program = parser.parse_program().map_err(|errors| {
+        let lines: Vec<String> = errors
+            .iter()
+            .map(|e| format!("parse error at {}:{}: {}", e.line, e.column, e.message))
+            .collect();
+        lines.join("\n")
+    })?;
+    resolver::resolve(&mut program).map_err(|errors| {
+        let lines: Vec<String> = errors.iter().map(|e| format!("error at line {}: {}", e.line, e.message)).collect();
+        lines.join("\n")
+    })?;
+    Ok(program)
+}
+
+/// The error message, followed by a line diff of both sides when an
+/// `assert_eq` failed.
+pub fn failure_report(err: &RuntimeError) -> String {
+    let mut report = err.to_string();
+    if err.kind != ErrorKind::Assertion {
+        return report;
+    }
+    if let Some(Value::Table(sides)) = &err.value {
+        if let (Some(left), Some(right)) = (sides.get("left"), sides.get("right")) {
+            report.push_str("\n- left\n+ right\n");
+            report.push_str(&diff(&left.repr_pretty(), &right.repr_pretty()));
+        }
+    }
+    report
+}
+
+/// A line diff: unchanged lines start with two spaces, lines only in `left`
+/// with "- " and lines only in `right` with "+ ".
+pub fn diff(left: &str, right: &str) -> String {
+    let a: Vec<&str> = left.lines().collect();
+    let b: Vec<&str> = right.lines().collect();
+    // Longest common subsequence table, filled from the end
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push(format!("  {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(format!("- {}", a[i]));
+            i += 1;
+        } else {
+            out.push(format!("+ {}", b[j]));
+            j += 1;
+        }
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discovers_and_runs_tests() {
+        let source = r#"
+            var base = 41;
+            func helper(): { return base; } end
+            func test_first(): {
+                assert_eq(helper() + 1, 42);
+            } end
+            func test_second(): {
+                assert(type(base) == "int", "setup didn't run");
+            } end
+            func test_failing(): {
+                assert_eq({hp: 10, name: "orc"}, {hp: 12, name: "orc"}, "stats differ");
+            } end
+            func test_plain_error(): {
+                var x = 1 / 0;
+            } end
+        "#;
+        let results = run_source(source).expect("file should load");
+        let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["test_first", "test_second", "test_failing", "test_plain_error"]);
+        assert!(results[0].passed() && results[1].passed());
+
+        match &results[2].outcome {
+            Outcome::Failed(report) => {
+                assert!(report.starts_with("Line 11: assert_eq failed: {hp: 10, name: \"orc\"} != {hp: 12, name: \"orc\"}: stats differ"));
+                assert!(report.ends_with("  {\n-   hp: 10,\n+   hp: 12,\n    name: \"orc\"\n  }"), "{}", report);
+            }
+            Outcome::Passed => panic!("test_failing passed"),
+        }
+        assert_eq!(results[3].outcome, Outcome::Failed("Line 14: division by zero".to_string()));
+    }
+
+    #[test]
+    fn test_load_errors_and_assert_messages() {
+        assert!(run_source("func test_x(: { } end").unwrap_err().contains("parse error"));
+        assert!(run_source("var bad = 1 / 0; func test_x(): { } end").unwrap_err().contains("error while loading"));
+
+        let results = run_source(r#"func test_msg(): { assert(false, "shields down"); } end"#).unwrap();
+        assert_eq!(results[0].outcome, Outcome::Failed("Line 1: assertion failed: shields down".to_string()));
+        // Assertions are ordinary errors, so try/catch can inspect them
+        let results = run_source(
+            r#"
+            func test_caught(): {
+                var seen = nil;
+                try { assert_eq(1, 2); } catch e { seen = e; } end
+                assert_eq(seen.kind, "assertion");
+                assert_eq(seen.right, 2);
+            } end
+            "#,
+        )
+        .unwrap();
+        assert!(results[0].passed(), "{:?}", results[0].outcome);
+    }
+
+    #[test]
+    fn test_diff_marks_changed_lines() {
+        assert_eq!(diff("a\nb\nc", "a\nx\nc"), "  a\n- b\n+ x\n  c");
+        assert_eq!(diff("3", "4"), "- 3\n+ 4");
+    }
+}
@@ -38,4 +38,22 @@ if ($LASTEXITCODE -ne 0) {
 Write-Host "All tests passed!" -ForegroundColor Green
 Write-Host ""
 
+# Test 4: Script tests
+Write-Host "Test 4: Running script tests..." -ForegroundColor Yellow
+cargo run --quiet -- test examples
+if ($LASTEXITCODE -ne 0) {
+    Write-Host "Script tests failed!" -ForegroundColor Red
+    exit 1
+}
+Write-Host ""
+
+# Test 5: Linting
+Write-Host "Test 5: Linting scripts..." -ForegroundColor Yellow
+cargo run --quiet -- lint examples
+if ($LASTEXITCODE -ne 0) {
+    Write-Host "Lint found errors!" -ForegroundColor Red
+    exit 1
+}
+Write-Host ""
+
 Write-Host "=== All tests complete ===" -ForegroundColor Cyan
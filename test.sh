@@ -24,4 +24,12 @@ done
 echo "Test 3: Running unit tests"
 cargo test
 
+# Test 4: Script tests
+echo "Test 4: Running script tests"
+cargo run -- test examples
+
+# Test 5: Linting
+echo "Test 5: Linting scripts"
+cargo run -- lint examples
+
 echo "=== All tests complete ==="
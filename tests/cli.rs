@@ -0,0 +1,66 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Writes `files` into a fresh directory under the system temp dir.
+fn scratch_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("arcscript-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, source) in files {
+        fs::write(dir.join(file), source).unwrap();
+    }
+    dir
+}
+
+fn arcscript(args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_arcscript")).args(args).output().unwrap()
+}
+
+#[test]
+fn test_runaway_recursion_is_an_error() {
+    let dir = scratch_dir("recursion", &[("main.arc", "func f(n): { return f(n + 1); } end\nf(0);\n")]);
+    let output = arcscript(&[dir.join("main.arc").as_os_str()]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // A stack overflow aborts the process, which leaves no exit code.
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr, "Runtime error: Line 1: maximum call depth exceeded (200)\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_runaway_recursion_fails_only_its_test() {
+    let source = "func runaway(n): { return runaway(n + 1); } end\n\
+                  func test_runaway(): { runaway(0); } end\n\
+                  func test_fine(): { assert_eq(1 + 1, 2); } end\n";
+    let dir = scratch_dir("test-recursion", &[("rec_test.arc", source)]);
+    let output = arcscript(&["test".as_ref(), dir.as_os_str()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("ok      test_fine"), "{}", stdout);
+    assert!(stdout.contains("maximum call depth exceeded (200)"), "{}", stdout);
+    assert!(stdout.contains("1 passed; 1 failed"), "{}", stdout);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_debugger_reports_runtime_errors_once() {
+    let dir = scratch_dir("debug-error", &[("main.arc", "print(\"start\");\nvar x = 1 / 0;\n")]);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_arcscript"))
+        .arg("debug")
+        .arg(dir.join("main.arc"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"continue\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Runtime error: Line 2: division by zero\n");
+    fs::remove_dir_all(&dir).unwrap();
+}